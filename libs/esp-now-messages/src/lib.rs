@@ -0,0 +1,102 @@
+//! The application messages the ROMs and host tools send each other over
+//! ESP-NOW, as typed enums rather than byte strings.
+//!
+//! ```text
+//! 0       4         5
+//! +-------+---------+-------------------
+//! | magic | version | postcard(Message)
+//! +-------+---------+-------------------
+//! ```
+//!
+//! The body is the [`Message`] serialized with `postcard`: the index of the
+//! variant as a varint, then its fields in order.
+//!
+//! Within a version, the schema only grows. New variants go at the end of
+//! [`Message`], and new fields at the end of their variant. An older node
+//! then gets [`Error::UnknownKind`] for a variant it doesn't know, which it
+//! can ignore, and decodes variants it does know as before, ignoring
+//! whatever was added after the fields it knows about. Anything else, like
+//! reordering or removing variants or fields, or changing their types, needs
+//! a new [`VERSION`].
+#![no_std]
+
+use serde::{Deserialize, Serialize};
+
+const MAGIC: &[u8; 4] = b"MSGS";
+const HEADER_LEN: usize = MAGIC.len() + 1;
+
+/// The version of the schema, see the module docs for when to bump it.
+pub const VERSION: u8 = 1;
+
+/// Which variants of [`Message`] there are. The match stops compiling when
+/// a variant is added, until it is listed and counted here.
+pub const KINDS: u32 = match (Message::Ping { seq: 0 }) {
+    Message::Ping { .. } | Message::Hello { .. } | Message::Text(_) => 3,
+};
+
+/// As large as an ESP-NOW payload gets.
+pub const MAX_MESSAGE_LEN: usize = 250;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Message<'a> {
+    /// Broadcast now and then by `esp-now-broadcast-sender`, counting up.
+    Ping { seq: u32 },
+    /// Sent by `esp-now-peering` to each paired peer it finds, with its
+    /// name.
+    Hello { name: &'a str },
+    /// Free text, e.g. sent from the host through `esp-now-bridge`.
+    Text(&'a str),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The payload is something else altogether, see [`is_typed_message`].
+    NotOurs,
+    /// The payload is from a version of the schema we don't speak.
+    Version(u8),
+    /// A variant that was added after ours, which can be ignored.
+    UnknownKind(u32),
+    /// The payload is cut short or otherwise damaged.
+    Malformed,
+    /// The message does not fit in the buffer.
+    TooLong,
+}
+
+impl Message<'_> {
+    /// Writes the message to `buf`, and returns how long it is.
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.len() < HEADER_LEN {
+            return Err(Error::TooLong);
+        }
+        buf[..4].copy_from_slice(MAGIC);
+        buf[4] = VERSION;
+        let body = postcard::to_slice(self, &mut buf[HEADER_LEN..]).map_err(|_| Error::TooLong)?;
+        Ok(HEADER_LEN + body.len())
+    }
+
+    /// Reads a message, which borrows its strings from `payload`.
+    pub fn decode(payload: &[u8]) -> Result<Message<'_>, Error> {
+        if !is_typed_message(payload) {
+            return Err(Error::NotOurs);
+        }
+        let Some(&version) = payload.get(4) else {
+            return Err(Error::Malformed);
+        };
+        if version != VERSION {
+            return Err(Error::Version(version));
+        }
+        let body = &payload[HEADER_LEN..];
+        let (kind, _) = postcard::take_from_bytes::<u32>(body).map_err(|_| Error::Malformed)?;
+        if kind >= KINDS {
+            return Err(Error::UnknownKind(kind));
+        }
+        // Whatever is left over was added by a newer node
+        let (message, _) = postcard::take_from_bytes(body).map_err(|_| Error::Malformed)?;
+        Ok(message)
+    }
+}
+
+/// Whether a payload is a typed message rather than anything else.
+pub fn is_typed_message(payload: &[u8]) -> bool {
+    payload.starts_with(MAGIC)
+}
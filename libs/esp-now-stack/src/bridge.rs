@@ -0,0 +1,230 @@
+//! The messages between the `esp-now-gateway` ROM and `tools/esp-now-bridge`
+//! on the host, over USB serial.
+//!
+//! Every message starts with its kind, and ends with a CRC-32 over
+//! everything before it, little endian. It is then COBS encoded and
+//! terminated by a zero byte, so that a reader that starts in the middle of
+//! a message finds the start of the next one.
+//!
+//! ```text
+//! Received    0x00 | src | dst | rssi (i8) | payload
+//! SendResult  0x01 | id (u16) | status
+//! Log         0x02 | UTF-8 text
+//! Send        0x80 | id (u16) | dst | payload
+//! ```
+//!
+//! The first three go to the host. `Send` goes to the gateway, which
+//! answers with a `SendResult` with the same ID. A `dst` of all ones
+//! broadcasts.
+
+use crate::{Mac, crc::crc32, header::MAX_FRAME_LEN};
+
+const MSG_RECEIVED: u8 = 0x00;
+const MSG_SEND_RESULT: u8 = 0x01;
+const MSG_LOG: u8 = 0x02;
+const MSG_SEND: u8 = 0x80;
+
+const CRC_LEN: usize = 4;
+
+/// The largest message before encoding: a received frame with the largest
+/// ESP-NOW payload.
+pub const MAX_MESSAGE_LEN: usize = 1 + 6 + 6 + 1 + MAX_FRAME_LEN + CRC_LEN;
+/// The largest message after COBS encoding, including the terminating zero.
+pub const MAX_ENCODED_LEN: usize = MAX_MESSAGE_LEN + MAX_MESSAGE_LEN / 254 + 2;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SendStatus {
+    /// Handed to the radio. For unicast, the peer acknowledged it.
+    Sent,
+    /// The peer did not acknowledge it.
+    Failed,
+    /// The payload does not fit in an ESP-NOW frame.
+    TooLong,
+    /// The gateway has no room for the peer.
+    NoPeer,
+}
+
+impl SendStatus {
+    fn to_byte(self) -> u8 {
+        match self {
+            SendStatus::Sent => 0,
+            SendStatus::Failed => 1,
+            SendStatus::TooLong => 2,
+            SendStatus::NoPeer => 3,
+        }
+    }
+
+    fn from_byte(b: u8) -> Option<Self> {
+        Some(match b {
+            0 => SendStatus::Sent,
+            1 => SendStatus::Failed,
+            2 => SendStatus::TooLong,
+            3 => SendStatus::NoPeer,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Message<'a> {
+    /// An ESP-NOW frame the gateway received.
+    Received {
+        src: Mac,
+        dst: Mac,
+        rssi: i8,
+        payload: &'a [u8],
+    },
+    /// What became of a `Send`.
+    SendResult { id: u16, status: SendStatus },
+    /// A line of text meant for a human.
+    Log(&'a str),
+    /// Asks the gateway to send `payload` to `dst`.
+    Send {
+        id: u16,
+        dst: Mac,
+        payload: &'a [u8],
+    },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Not valid COBS, most likely the tail end of a message we only saw
+    /// half of.
+    Framing,
+    Crc,
+    /// The CRC checks out, but the contents don't make sense.
+    Malformed,
+}
+
+impl Message<'_> {
+    /// Encodes the message, ready to be written out with its terminating
+    /// zero. Text and payloads that don't fit are cut off.
+    pub fn encode(&self, out: &mut [u8; MAX_ENCODED_LEN]) -> usize {
+        let mut message = heapless::Vec::<u8, MAX_MESSAGE_LEN>::new();
+        let max_body = MAX_MESSAGE_LEN - CRC_LEN;
+        let mut push = |bytes: &[u8]| {
+            let n = bytes.len().min(max_body - message.len());
+            let _ = message.extend_from_slice(&bytes[..n]);
+        };
+        match *self {
+            Message::Received {
+                src,
+                dst,
+                rssi,
+                payload,
+            } => {
+                push(&[MSG_RECEIVED]);
+                push(&src);
+                push(&dst);
+                push(&rssi.to_le_bytes());
+                push(payload);
+            }
+            Message::SendResult { id, status } => {
+                push(&[MSG_SEND_RESULT]);
+                push(&id.to_le_bytes());
+                push(&[status.to_byte()]);
+            }
+            Message::Log(text) => {
+                push(&[MSG_LOG]);
+                push(text.as_bytes());
+            }
+            Message::Send { id, dst, payload } => {
+                push(&[MSG_SEND]);
+                push(&id.to_le_bytes());
+                push(&dst);
+                push(payload);
+            }
+        }
+        let crc = crc32(&message);
+        message.extend_from_slice(&crc.to_le_bytes()).unwrap();
+
+        let n = cobs::encode(&message, out);
+        out[n] = 0;
+        n + 1
+    }
+}
+
+impl<'a> Message<'a> {
+    /// Decodes the bytes read up to, but not including, a zero byte. They
+    /// are decoded in place, so the message borrows them.
+    pub fn decode(frame: &'a mut [u8]) -> Result<Self, DecodeError> {
+        let n = cobs::decode_in_place(frame).map_err(|()| DecodeError::Framing)?;
+        let message = &frame[..n];
+        if message.len() < 1 + CRC_LEN {
+            return Err(DecodeError::Framing);
+        }
+        let (body, crc) = message.split_at(message.len() - CRC_LEN);
+        if crc32(body).to_le_bytes() != crc {
+            return Err(DecodeError::Crc);
+        }
+
+        let (&kind, rest) = body.split_first().unwrap();
+        let mac = |b: &[u8]| -> Mac { b.try_into().unwrap() };
+        match kind {
+            MSG_RECEIVED if rest.len() >= 13 => Ok(Message::Received {
+                src: mac(&rest[..6]),
+                dst: mac(&rest[6..12]),
+                rssi: rest[12] as i8,
+                payload: &rest[13..],
+            }),
+            MSG_SEND_RESULT if rest.len() == 3 => Ok(Message::SendResult {
+                id: u16::from_le_bytes([rest[0], rest[1]]),
+                status: SendStatus::from_byte(rest[2]).ok_or(DecodeError::Malformed)?,
+            }),
+            MSG_LOG => core::str::from_utf8(rest)
+                .map(Message::Log)
+                .map_err(|_| DecodeError::Malformed),
+            MSG_SEND if rest.len() >= 8 => Ok(Message::Send {
+                id: u16::from_le_bytes([rest[0], rest[1]]),
+                dst: mac(&rest[2..8]),
+                payload: &rest[8..],
+            }),
+            _ => Err(DecodeError::Malformed),
+        }
+    }
+}
+
+/// Collects bytes from a serial port into frames for [`Message::decode`].
+pub struct Deframer<const N: usize> {
+    buf: heapless::Vec<u8, N>,
+    /// Set when a frame didn't fit, until the end of it.
+    overflowed: bool,
+    /// Set when `buf` holds the frame we last returned.
+    complete: bool,
+}
+
+impl<const N: usize> Default for Deframer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Deframer<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: heapless::Vec::new(),
+            overflowed: false,
+            complete: false,
+        }
+    }
+
+    /// Takes the next byte, and returns a frame when it ends one. Frames
+    /// too large for the buffer are thrown away.
+    pub fn push(&mut self, byte: u8) -> Option<&mut [u8]> {
+        if core::mem::take(&mut self.complete) {
+            self.buf.clear();
+        }
+        if byte != 0 {
+            if self.buf.push(byte).is_err() {
+                self.overflowed = true;
+            }
+            return None;
+        }
+        if core::mem::take(&mut self.overflowed) || self.buf.is_empty() {
+            self.buf.clear();
+            return None;
+        }
+        self.complete = true;
+        Some(&mut self.buf)
+    }
+}
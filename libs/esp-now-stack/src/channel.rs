@@ -0,0 +1,248 @@
+//! Getting nodes onto the same channel.
+//!
+//! ESP-NOW only reaches nodes on the channel we are on. A node that is also
+//! connected to an AP as a station has no say in it: the AP picks the
+//! channel, and the node's channel is fixed. The others hop from channel to
+//! channel until they hear somebody, and then settle on the channel that
+//! node says it stays on, which discovery messages carry. Once a settled
+//! node hasn't heard from anybody for a while, it starts hopping again.
+//!
+//! Nodes that can move settle with each other wherever they meet, on the
+//! lower of their channels if they disagree, so that they don't chase each
+//! other around. Now and then they sweep the other channels for a node that
+//! can't move, and join it when they find one. Sweeps also bring together
+//! groups that settled on different channels. From then on they are
+//! anchored to it: only it keeps them on its channel, so when its AP moves
+//! it, they go looking for it rather than staying with each other.
+//!
+//! Hops go to a random channel rather than the next one, or two nodes
+//! hopping in step would never meet.
+
+/// The channels we hop between. 12 and 13 aren't allowed everywhere, but
+/// an AP that uses them is, and so are the nodes connected to it.
+pub const CHANNELS: core::ops::RangeInclusive<u8> = 1..=13;
+
+const FLAG_FIXED: u8 = 1 << 7;
+
+/// The channel a node stays on, as it tells the others.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ChannelInfo {
+    pub number: u8,
+    /// The node can't move, because its AP dictates the channel.
+    pub fixed: bool,
+}
+
+impl ChannelInfo {
+    /// One byte: the channel number, with the top bit set when it is fixed.
+    /// Zero means the node doesn't say.
+    pub fn to_byte(self) -> u8 {
+        self.number | if self.fixed { FLAG_FIXED } else { 0 }
+    }
+
+    pub fn from_byte(b: u8) -> Option<Self> {
+        let number = b & !FLAG_FIXED;
+        CHANNELS.contains(&number).then_some(Self {
+            number,
+            fixed: b & FLAG_FIXED != 0,
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// How long to stay on each channel while hopping or sweeping. It
+    /// should be long enough for a node that hears our hello to answer it.
+    pub dwell_ms: u64,
+    /// How long a settled node waits to hear from somebody before it starts
+    /// hopping again. It should be a few hello intervals.
+    pub settle_timeout_ms: u64,
+    /// How often a node that settled with nodes that can move sweeps the
+    /// other channels for one that can't.
+    pub sweep_interval_ms: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            dwell_ms: 300,
+            settle_timeout_ms: 10_000,
+            sweep_interval_ms: 30_000,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum State {
+    /// Our AP decides.
+    Fixed,
+    /// Looking for somebody to talk to.
+    Hopping,
+    /// Staying where we found somebody. `anchored` if that somebody's
+    /// channel is fixed, in which case only they keep us here.
+    Settled { anchored: bool },
+    /// Visiting the other channels, one after the other, to look for a
+    /// node whose channel is fixed. We go back to `home` afterwards.
+    Sweeping { home: u8, visited: u8 },
+}
+
+/// Decides which channel a node should be on.
+///
+/// Whenever a method returns a channel, the radio has to be switched to it,
+/// and a hello sent there right away.
+pub struct ChannelManager {
+    config: Config,
+    channel: u8,
+    state: State,
+    /// The next hop, or when a settled node gives up.
+    deadline_ms: u64,
+    /// When an unanchored node sweeps next, once it has picked a time.
+    next_sweep_ms: Option<u64>,
+}
+
+impl ChannelManager {
+    /// Starts out on `channel`, either staying there for good when `fixed`,
+    /// or hopping from there.
+    pub fn new(config: Config, now_ms: u64, channel: u8, fixed: bool) -> Self {
+        Self {
+            config,
+            channel,
+            state: if fixed { State::Fixed } else { State::Hopping },
+            deadline_ms: now_ms + config.dwell_ms,
+            next_sweep_ms: None,
+        }
+    }
+
+    pub fn channel(&self) -> u8 {
+        self.channel
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// What to tell the others in our discovery messages.
+    pub fn info(&self) -> ChannelInfo {
+        match self.state {
+            State::Sweeping { home, .. } => ChannelInfo {
+                number: home,
+                fixed: false,
+            },
+            state => ChannelInfo {
+                number: self.channel,
+                fixed: state == State::Fixed,
+            },
+        }
+    }
+
+    /// Our AP moved us to `channel`, or let go of us when it's `None`. In
+    /// the latter case we stay where we are until it's time to hop.
+    pub fn set_fixed(&mut self, now_ms: u64, channel: Option<u8>) {
+        match channel {
+            Some(channel) => {
+                self.channel = channel;
+                self.state = State::Fixed;
+            }
+            None if self.state == State::Fixed => self.settle(now_ms, false),
+            None => (),
+        }
+    }
+
+    fn settle(&mut self, now_ms: u64, anchored: bool) {
+        if !matches!(self.state, State::Settled { anchored: false }) {
+            self.next_sweep_ms = None;
+        }
+        self.state = State::Settled { anchored };
+        self.deadline_ms = now_ms + self.config.settle_timeout_ms;
+    }
+
+    /// Call for every discovery message, with the channel its sender stays
+    /// on. Returns the channel to switch to, if we have to move.
+    pub fn heard(&mut self, now_ms: u64, theirs: Option<ChannelInfo>) -> Option<u8> {
+        let fixed = theirs.is_some_and(|c| c.fixed);
+        let target = match (self.state, theirs) {
+            (State::Fixed, _) => return None,
+            (State::Settled { anchored: true }, _) if !fixed => return None,
+            // Two groups that settled apart join on the lower channel, like
+            // two nodes do
+            (State::Sweeping { home, .. }, Some(theirs)) if !fixed && theirs.number < home => {
+                theirs.number
+            }
+            (State::Sweeping { .. }, _) if !fixed => return None,
+            (_, None) => self.channel,
+            // We may hear a node on a channel next to its own
+            (State::Hopping, Some(theirs)) => theirs.number,
+            (_, Some(theirs)) if fixed => theirs.number,
+            (_, Some(theirs)) => theirs.number.min(self.channel),
+        };
+        self.settle(now_ms, fixed);
+        if target == self.channel {
+            return None;
+        }
+        self.channel = target;
+        Some(target)
+    }
+
+    /// When `poll` next has something to do.
+    pub fn next_deadline(&self) -> Option<u64> {
+        match self.state {
+            State::Fixed => None,
+            // Right away when it has yet to pick a time to sweep
+            State::Settled { anchored: false } => {
+                Some(self.next_sweep_ms.map_or(0, |t| t.min(self.deadline_ms)))
+            }
+            _ => Some(self.deadline_ms),
+        }
+    }
+
+    /// Hops when it's time to, starts hopping when we haven't heard from
+    /// anybody in too long, and sweeps. `random` picks the next channel
+    /// when hopping, and when to sweep next.
+    pub fn poll(&mut self, now_ms: u64, random: u32) -> Option<u8> {
+        match self.state {
+            State::Fixed => return None,
+            // Somewhere between half and one and a half intervals from now,
+            // or nodes that settled together would always sweep together,
+            // and never find each other's home
+            State::Settled { anchored: false } if self.next_sweep_ms.is_none() => {
+                let interval = self.config.sweep_interval_ms;
+                self.next_sweep_ms = Some(now_ms + interval / 2 + random as u64 % interval);
+                return None;
+            }
+            State::Settled { anchored: false }
+                if self.next_sweep_ms.is_some_and(|t| now_ms >= t) && now_ms < self.deadline_ms =>
+            {
+                self.state = State::Sweeping {
+                    home: self.channel,
+                    visited: 0,
+                };
+            }
+            _ if now_ms < self.deadline_ms => return None,
+            _ => (),
+        }
+        self.deadline_ms = now_ms + self.config.dwell_ms;
+
+        let next = match self.state {
+            State::Sweeping { home, visited } if visited as usize == CHANNELS.len() - 1 => {
+                self.settle(now_ms, false);
+                home
+            }
+            State::Sweeping { home, visited } => {
+                self.state = State::Sweeping {
+                    home,
+                    visited: visited + 1,
+                };
+                // The channels after home, wrapping around
+                (home + visited) % CHANNELS.end() + 1
+            }
+            _ => {
+                self.state = State::Hopping;
+                // Any channel but the one we are on
+                let others = CHANNELS.len() as u32 - 1;
+                let next = *CHANNELS.start() + (random % others) as u8;
+                if next >= self.channel { next + 1 } else { next }
+            }
+        };
+        self.channel = next;
+        Some(next)
+    }
+}
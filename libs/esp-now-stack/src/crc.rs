@@ -0,0 +1,26 @@
+//! Checksums for data that has to survive flash or a serial line.
+
+/// CRC-32 as used by Ethernet and zlib.
+pub fn crc32(data: &[u8]) -> u32 {
+    !update(!0, data)
+}
+
+/// The same CRC-32, as the ESP-IDF bootloader computes it for its OTA data:
+/// starting from zero rather than all ones.
+pub fn crc32_esp_idf(data: &[u8]) -> u32 {
+    !update(0, data)
+}
+
+fn update(mut crc: u32, data: &[u8]) -> u32 {
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
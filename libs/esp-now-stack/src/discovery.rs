@@ -0,0 +1,298 @@
+//! Finding out who else is around.
+//!
+//! Every node broadcasts a `Hello` now and then, and answers the ones it
+//! hears with a `HelloAck` sent straight back. Both carry the name of the
+//! node and what it can do:
+//!
+//! ```text
+//! 0       4      5              7         8          9
+//! +-------+------+--------------+---------+----------+------
+//! | magic | kind | capabilities | channel | name_len | name
+//! +-------+------+--------------+---------+----------+------
+//! ```
+//!
+//! The capabilities are little endian. The channel is the one the node
+//! stays on, see [`ChannelInfo`], so that nodes that hop channels to find
+//! each other know where to go. What we hear goes into a
+//! [`PeerTable`], which forgets nodes that have gone quiet, so that they can
+//! be removed from ESP-NOW's own peer list before it fills up.
+
+use crate::{Mac, channel::ChannelInfo};
+
+const MAGIC: &[u8; 4] = b"DISC";
+const KIND_HELLO: u8 = 0;
+const KIND_HELLO_ACK: u8 = 1;
+const FIXED_LEN: usize = MAGIC.len() + 5;
+
+pub const MAX_NAME_LEN: usize = 16;
+pub const MAX_MESSAGE_LEN: usize = FIXED_LEN + MAX_NAME_LEN;
+
+/// What a node can do, as a set of bits. Bits we don't know about are kept,
+/// so newer nodes can add some.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities(pub u16);
+
+impl Capabilities {
+    /// Acknowledges messages sent with `reliable`.
+    pub const RELIABLE: Self = Self(1 << 0);
+    /// Reassembles messages sent with `fragment`.
+    pub const FRAGMENTS: Self = Self(1 << 1);
+    /// Can be paired with, see `pairing`.
+    pub const PAIRING: Self = Self(1 << 2);
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+/// How a node describes itself.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Announcement {
+    pub name: heapless::String<MAX_NAME_LEN>,
+    pub capabilities: Capabilities,
+    /// `None` for nodes that don't say.
+    pub channel: Option<ChannelInfo>,
+}
+
+impl Announcement {
+    /// Names longer than [`MAX_NAME_LEN`] bytes are cut off.
+    pub fn new(name: &str, capabilities: Capabilities) -> Self {
+        let mut end = name.len().min(MAX_NAME_LEN);
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        Self {
+            name: heapless::String::try_from(&name[..end]).unwrap(),
+            capabilities,
+            channel: None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MessageKind {
+    Hello,
+    HelloAck,
+}
+
+pub fn encode(
+    kind: MessageKind,
+    announcement: &Announcement,
+) -> heapless::Vec<u8, MAX_MESSAGE_LEN> {
+    let mut out = heapless::Vec::new();
+    out.extend_from_slice(MAGIC).unwrap();
+    out.push(match kind {
+        MessageKind::Hello => KIND_HELLO,
+        MessageKind::HelloAck => KIND_HELLO_ACK,
+    })
+    .unwrap();
+    out.extend_from_slice(&announcement.capabilities.0.to_le_bytes())
+        .unwrap();
+    out.push(announcement.channel.map_or(0, ChannelInfo::to_byte))
+        .unwrap();
+    out.push(announcement.name.len() as u8).unwrap();
+    out.extend_from_slice(announcement.name.as_bytes()).unwrap();
+    out
+}
+
+/// Returns `None` for anything that isn't a well formed discovery message.
+pub fn decode(payload: &[u8]) -> Option<(MessageKind, Announcement)> {
+    let rest = payload.strip_prefix(MAGIC)?;
+    let (&[kind, caps_lo, caps_hi, channel, name_len], name) = rest.split_first_chunk::<5>()?;
+    let kind = match kind {
+        KIND_HELLO => MessageKind::Hello,
+        KIND_HELLO_ACK => MessageKind::HelloAck,
+        _ => return None,
+    };
+    if name.len() != name_len as usize || name.len() > MAX_NAME_LEN {
+        return None;
+    }
+    let name = core::str::from_utf8(name).ok()?;
+    Some((
+        kind,
+        Announcement {
+            name: heapless::String::try_from(name).ok()?,
+            capabilities: Capabilities(u16::from_le_bytes([caps_lo, caps_hi])),
+            channel: ChannelInfo::from_byte(channel),
+        },
+    ))
+}
+
+/// Whether a payload is a discovery message rather than anything else.
+pub fn is_discovery_message(payload: &[u8]) -> bool {
+    payload.starts_with(MAGIC)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Peer {
+    pub mac: Mac,
+    pub announcement: Announcement,
+    /// Signal strength of the last message we got from it, in dBm.
+    pub rssi: i8,
+    pub first_seen_ms: u64,
+    pub last_seen_ms: u64,
+}
+
+/// What happened to the table when we heard from a node.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Update {
+    /// We hadn't heard of it before. If the table was full, the node we
+    /// heard from longest ago made room for it.
+    New { evicted: Option<Mac> },
+    /// We already knew it.
+    Known,
+}
+
+/// The nodes we have heard from recently, up to `N` of them.
+pub struct PeerTable<const N: usize> {
+    /// Ordered from the least to the most recently seen.
+    peers: heapless::Vec<Peer, N>,
+    max_age_ms: u64,
+}
+
+impl<const N: usize> PeerTable<N> {
+    /// Nodes we haven't heard from in `max_age_ms` are forgotten. It should
+    /// be a few times the interval nodes say hello at.
+    pub const fn new(max_age_ms: u64) -> Self {
+        Self {
+            peers: heapless::Vec::new(),
+            max_age_ms,
+        }
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = &Peer> {
+        self.peers.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    pub fn get(&self, mac: &Mac) -> Option<&Peer> {
+        self.peers.iter().find(|p| p.mac == *mac)
+    }
+
+    /// Records that `mac` announced itself.
+    pub fn update(
+        &mut self,
+        now_ms: u64,
+        mac: Mac,
+        rssi: i8,
+        announcement: Announcement,
+    ) -> Update {
+        if let Some(i) = self.peers.iter().position(|p| p.mac == mac) {
+            let mut peer = self.peers.remove(i);
+            peer.announcement = announcement;
+            peer.rssi = rssi;
+            peer.last_seen_ms = now_ms;
+            let _ = self.peers.push(peer);
+            return Update::Known;
+        }
+        let evicted = if self.peers.is_full() {
+            Some(self.peers.remove(0).mac)
+        } else {
+            None
+        };
+        let _ = self.peers.push(Peer {
+            mac,
+            announcement,
+            rssi,
+            first_seen_ms: now_ms,
+            last_seen_ms: now_ms,
+        });
+        Update::New { evicted }
+    }
+
+    /// Records that we heard from `mac` in some other way than an
+    /// announcement. Returns false if we don't know it.
+    pub fn touch(&mut self, now_ms: u64, mac: &Mac, rssi: i8) -> bool {
+        let Some(i) = self.peers.iter().position(|p| p.mac == *mac) else {
+            return false;
+        };
+        let mut peer = self.peers.remove(i);
+        peer.rssi = rssi;
+        peer.last_seen_ms = now_ms;
+        let _ = self.peers.push(peer);
+        true
+    }
+
+    pub fn remove(&mut self, mac: &Mac) -> Option<Peer> {
+        let i = self.peers.iter().position(|p| p.mac == *mac)?;
+        Some(self.peers.remove(i))
+    }
+
+    /// Forgets one node we haven't heard from in too long, and returns it.
+    /// Call it until it returns `None`.
+    pub fn expire(&mut self, now_ms: u64) -> Option<Peer> {
+        let oldest = self.peers.first()?;
+        if now_ms.saturating_sub(oldest.last_seen_ms) < self.max_age_ms {
+            return None;
+        }
+        Some(self.peers.remove(0))
+    }
+
+    /// When `expire` will next have something to forget.
+    pub fn next_expiry(&self) -> Option<u64> {
+        self.peers.first().map(|p| p.last_seen_ms + self.max_age_ms)
+    }
+}
+
+/// What to do after handling a discovery message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Handled {
+    pub update: Update,
+    /// Send this back to the node, which has to be added as a peer first.
+    pub reply: Option<heapless::Vec<u8, MAX_MESSAGE_LEN>>,
+}
+
+/// Answers hellos and keeps track of who is around.
+pub struct Discovery<const N: usize> {
+    own: Announcement,
+    table: PeerTable<N>,
+}
+
+impl<const N: usize> Discovery<N> {
+    pub const fn new(own: Announcement, max_age_ms: u64) -> Self {
+        Self {
+            own,
+            table: PeerTable::new(max_age_ms),
+        }
+    }
+
+    pub fn table(&self) -> &PeerTable<N> {
+        &self.table
+    }
+
+    pub fn table_mut(&mut self) -> &mut PeerTable<N> {
+        &mut self.table
+    }
+
+    /// Tells the others which channel we are on from now on.
+    pub fn set_channel(&mut self, channel: ChannelInfo) {
+        self.own.channel = Some(channel);
+    }
+
+    /// The hello to broadcast every now and then.
+    pub fn hello(&self) -> heapless::Vec<u8, MAX_MESSAGE_LEN> {
+        encode(MessageKind::Hello, &self.own)
+    }
+
+    /// Returns `None` if the payload isn't a discovery message.
+    pub fn handle(&mut self, now_ms: u64, src: Mac, rssi: i8, payload: &[u8]) -> Option<Handled> {
+        let (kind, announcement) = decode(payload)?;
+        let update = self.table.update(now_ms, src, rssi, announcement);
+        let reply = match kind {
+            MessageKind::Hello => Some(encode(MessageKind::HelloAck, &self.own)),
+            MessageKind::HelloAck => None,
+        };
+        Some(Handled { update, reply })
+    }
+}
@@ -0,0 +1,365 @@
+//! Splits messages too large for a single frame into fragments, and puts
+//! them back together on the other end.
+//!
+//! Every fragment starts with a small header:
+//!
+//! ```text
+//! 0        2       3       4           6
+//! +--------+-------+-------+-----------+---------
+//! | msg id | index | count | total len | data
+//! +--------+-------+-------+-----------+---------
+//! ```
+//!
+//! The message id and total length are little endian. All fragments but the
+//! last carry exactly `FRAGMENT_DATA_LEN` bytes, so the receiver knows where
+//! each one goes no matter in which order they arrive.
+
+use crate::{Mac, header::MAX_PAYLOAD_LEN};
+
+pub const FRAGMENT_HEADER_LEN: usize = 6;
+pub const FRAGMENT_DATA_LEN: usize = MAX_PAYLOAD_LEN - FRAGMENT_HEADER_LEN;
+pub const MAX_FRAGMENTS: usize = u8::MAX as usize;
+/// The largest message that can be fragmented at all. Receivers usually
+/// accept a lot less, see [`Reassembler`].
+pub const MAX_MESSAGE_LEN: usize = FRAGMENT_DATA_LEN * MAX_FRAGMENTS;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct FragmentHeader {
+    msg_id: u16,
+    index: u8,
+    count: u8,
+    total_len: u16,
+}
+
+impl FragmentHeader {
+    fn encode(&self) -> [u8; FRAGMENT_HEADER_LEN] {
+        let [id_lo, id_hi] = self.msg_id.to_le_bytes();
+        let [len_lo, len_hi] = self.total_len.to_le_bytes();
+        [id_lo, id_hi, self.index, self.count, len_lo, len_hi]
+    }
+
+    fn decode(fragment: &[u8]) -> Option<(Self, &[u8])> {
+        let (header, data) = fragment.split_first_chunk::<FRAGMENT_HEADER_LEN>()?;
+        let [id_lo, id_hi, index, count, len_lo, len_hi] = *header;
+        let header = Self {
+            msg_id: u16::from_le_bytes([id_lo, id_hi]),
+            index,
+            count,
+            total_len: u16::from_le_bytes([len_lo, len_hi]),
+        };
+        Some((header, data))
+    }
+}
+
+/// The number of fragments a message of `len` bytes is split into. Even an
+/// empty message takes one.
+fn fragment_count(len: usize) -> usize {
+    len.div_ceil(FRAGMENT_DATA_LEN).max(1)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TooLong;
+
+/// The fragments of a message, ready to be sent in order. See [`split`].
+pub struct Fragments<'a> {
+    msg_id: u16,
+    data: &'a [u8],
+    count: u8,
+    next: u8,
+}
+
+/// Splits `data` into fragments. `msg_id` must be different for each
+/// message sent to the same peer, or at least for the last few.
+pub fn split(msg_id: u16, data: &[u8]) -> Result<Fragments<'_>, TooLong> {
+    if data.len() > MAX_MESSAGE_LEN || data.len() > u16::MAX as usize {
+        return Err(TooLong);
+    }
+    Ok(Fragments {
+        msg_id,
+        data,
+        count: fragment_count(data.len()) as u8,
+        next: 0,
+    })
+}
+
+impl Fragments<'_> {
+    pub fn count(&self) -> u8 {
+        self.count
+    }
+}
+
+impl Iterator for Fragments<'_> {
+    type Item = heapless::Vec<u8, MAX_PAYLOAD_LEN>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next == self.count {
+            return None;
+        }
+        let header = FragmentHeader {
+            msg_id: self.msg_id,
+            index: self.next,
+            count: self.count,
+            total_len: self.data.len() as u16,
+        };
+        let start = self.next as usize * FRAGMENT_DATA_LEN;
+        let end = (start + FRAGMENT_DATA_LEN).min(self.data.len());
+        self.next += 1;
+
+        let mut fragment = heapless::Vec::new();
+        fragment.extend_from_slice(&header.encode()).unwrap();
+        fragment.extend_from_slice(&self.data[start..end]).unwrap();
+        Some(fragment)
+    }
+}
+
+/// Why a message was given up on, see [`Incomplete`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IncompleteReason {
+    /// No fragment arrived for too long.
+    TimedOut,
+    /// The source started sending another message.
+    Superseded,
+    /// We needed the buffer for a message from another source.
+    Evicted,
+}
+
+/// A message that was dropped before all of its fragments arrived.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Incomplete {
+    pub src: Mac,
+    pub msg_id: u16,
+    pub received: u8,
+    pub count: u8,
+    pub reason: IncompleteReason,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Rejected {
+    /// The message is larger than the reassembly buffers.
+    TooLarge,
+    /// The fragment header doesn't add up.
+    Malformed,
+}
+
+/// What became of a fragment handed to [`Reassembler::handle`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome<'a> {
+    /// This was the last missing fragment.
+    Complete {
+        src: Mac,
+        msg_id: u16,
+        data: &'a [u8],
+    },
+    /// More fragments are needed.
+    Pending,
+    /// We already have this fragment.
+    Duplicate,
+    Rejected(Rejected),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum State {
+    Free,
+    Collecting,
+    /// Kept around so that late duplicates of the last fragments are not
+    /// taken for a new message.
+    Done,
+}
+
+struct Slot<const MAX_LEN: usize> {
+    state: State,
+    src: Mac,
+    msg_id: u16,
+    count: u8,
+    total_len: usize,
+    received: u8,
+    /// One bit per fragment.
+    have: [u32; MAX_FRAGMENTS.div_ceil(32)],
+    last_ms: u64,
+    data: [u8; MAX_LEN],
+}
+
+impl<const MAX_LEN: usize> Slot<MAX_LEN> {
+    fn has(&self, index: u8) -> bool {
+        self.have[index as usize / 32] & (1 << (index % 32)) != 0
+    }
+
+    fn incomplete(&self, reason: IncompleteReason) -> Incomplete {
+        Incomplete {
+            src: self.src,
+            msg_id: self.msg_id,
+            received: self.received,
+            count: self.count,
+            reason,
+        }
+    }
+}
+
+/// The number of dropped messages we remember until they are picked up with
+/// [`Reassembler::take_incomplete`].
+const MAX_INCOMPLETE: usize = 4;
+
+/// Puts fragmented messages back together.
+///
+/// There is a buffer of `MAX_LEN` bytes for each of `SOURCES` peers sending
+/// to us at the same time, which is all the memory this ever uses. Messages
+/// that don't complete within the timeout, or have to make room for others,
+/// are reported by [`Reassembler::take_incomplete`].
+pub struct Reassembler<const SOURCES: usize, const MAX_LEN: usize> {
+    timeout_ms: u64,
+    slots: [Slot<MAX_LEN>; SOURCES],
+    incomplete: heapless::Deque<Incomplete, MAX_INCOMPLETE>,
+}
+
+impl<const SOURCES: usize, const MAX_LEN: usize> Reassembler<SOURCES, MAX_LEN> {
+    /// Messages are dropped when no fragment for them has arrived for
+    /// `timeout_ms`.
+    pub fn new(timeout_ms: u64) -> Self {
+        Self {
+            timeout_ms,
+            slots: core::array::from_fn(|_| Slot {
+                state: State::Free,
+                src: [0; 6],
+                msg_id: 0,
+                count: 0,
+                total_len: 0,
+                received: 0,
+                have: [0; MAX_FRAGMENTS.div_ceil(32)],
+                last_ms: 0,
+                data: [0; MAX_LEN],
+            }),
+            incomplete: heapless::Deque::new(),
+        }
+    }
+
+    fn report(&mut self, incomplete: Incomplete) {
+        if self.incomplete.is_full() {
+            self.incomplete.pop_front();
+        }
+        let _ = self.incomplete.push_back(incomplete);
+    }
+
+    /// Picks the slot for a message from `src`, which is the one already
+    /// used by `src` if there is one.
+    fn slot_for(&mut self, src: Mac) -> usize {
+        if let Some(i) = self
+            .slots
+            .iter()
+            .position(|s| s.state != State::Free && s.src == src)
+        {
+            return i;
+        }
+        // Free slots first, then finished ones, then the one we heard from
+        // the longest time ago.
+        let i = (0..SOURCES)
+            .min_by_key(|&i| {
+                let slot = &self.slots[i];
+                (
+                    slot.state == State::Collecting,
+                    slot.state != State::Free,
+                    slot.last_ms,
+                )
+            })
+            .expect("at least one source");
+        if self.slots[i].state == State::Collecting {
+            let incomplete = self.slots[i].incomplete(IncompleteReason::Evicted);
+            self.report(incomplete);
+        }
+        self.slots[i].state = State::Free;
+        i
+    }
+
+    /// Handles a fragment received from `src`.
+    pub fn handle(&mut self, now_ms: u64, src: Mac, fragment: &[u8]) -> Outcome<'_> {
+        let Some((header, data)) = FragmentHeader::decode(fragment) else {
+            return Outcome::Rejected(Rejected::Malformed);
+        };
+        let total_len = header.total_len as usize;
+        if header.count as usize != fragment_count(total_len) || header.index >= header.count {
+            return Outcome::Rejected(Rejected::Malformed);
+        }
+        let offset = header.index as usize * FRAGMENT_DATA_LEN;
+        let expected_len = (total_len - offset).min(FRAGMENT_DATA_LEN);
+        if data.len() != expected_len {
+            return Outcome::Rejected(Rejected::Malformed);
+        }
+        if total_len > MAX_LEN {
+            return Outcome::Rejected(Rejected::TooLarge);
+        }
+
+        let i = self.slot_for(src);
+        let slot = &self.slots[i];
+        if slot.state != State::Free && slot.msg_id == header.msg_id {
+            if slot.count != header.count || slot.total_len != total_len {
+                return Outcome::Rejected(Rejected::Malformed);
+            }
+            if slot.state == State::Done || slot.has(header.index) {
+                return Outcome::Duplicate;
+            }
+        } else {
+            if slot.state == State::Collecting {
+                let incomplete = slot.incomplete(IncompleteReason::Superseded);
+                self.report(incomplete);
+            }
+            let slot = &mut self.slots[i];
+            slot.state = State::Collecting;
+            slot.src = src;
+            slot.msg_id = header.msg_id;
+            slot.count = header.count;
+            slot.total_len = total_len;
+            slot.received = 0;
+            slot.have = [0; MAX_FRAGMENTS.div_ceil(32)];
+        }
+
+        let slot = &mut self.slots[i];
+        slot.data[offset..][..data.len()].copy_from_slice(data);
+        slot.have[header.index as usize / 32] |= 1 << (header.index % 32);
+        slot.received += 1;
+        slot.last_ms = now_ms;
+        if slot.received < slot.count {
+            return Outcome::Pending;
+        }
+        slot.state = State::Done;
+        Outcome::Complete {
+            src,
+            msg_id: slot.msg_id,
+            data: &slot.data[..slot.total_len],
+        }
+    }
+
+    /// The last message completed from `src`, for as long as its buffer
+    /// hasn't been reused.
+    pub fn last_complete(&self, src: Mac) -> Option<&[u8]> {
+        self.slots
+            .iter()
+            .find(|s| s.state == State::Done && s.src == src)
+            .map(|s| &s.data[..s.total_len])
+    }
+
+    /// Drops messages that have timed out. They are reported by
+    /// `take_incomplete`.
+    pub fn expire(&mut self, now_ms: u64) {
+        for i in 0..SOURCES {
+            let slot = &mut self.slots[i];
+            if slot.state == State::Collecting && now_ms >= slot.last_ms + self.timeout_ms {
+                slot.state = State::Free;
+                let incomplete = slot.incomplete(IncompleteReason::TimedOut);
+                self.report(incomplete);
+            }
+        }
+    }
+
+    /// When `expire` needs to be called next, if there is anything to expire.
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.slots
+            .iter()
+            .filter(|s| s.state == State::Collecting)
+            .map(|s| s.last_ms + self.timeout_ms)
+            .min()
+    }
+
+    /// Returns the next message that was dropped before it was complete.
+    pub fn take_incomplete(&mut self) -> Option<Incomplete> {
+        self.incomplete.pop_front()
+    }
+}
@@ -0,0 +1,94 @@
+//! The header in front of every frame we send.
+//!
+//! ```text
+//! 0       1      2       4       5
+//! +-------+------+-------+-------+---------
+//! |version| kind |  seq  | flags | payload
+//! +-------+------+-------+-------+---------
+//! ```
+//!
+//! The sequence number is little endian.
+
+/// Bumped whenever the wire format changes in an incompatible way. Frames
+/// with a different version are dropped.
+pub const VERSION: u8 = 1;
+
+pub const HEADER_LEN: usize = 5;
+
+/// The largest payload ESP-NOW can carry in a single frame.
+pub const MAX_FRAME_LEN: usize = 250;
+pub const MAX_PAYLOAD_LEN: usize = MAX_FRAME_LEN - HEADER_LEN;
+
+/// The receiver should acknowledge this frame.
+pub const FLAG_ACK_REQUESTED: u8 = 1 << 0;
+/// The sender has not had anything acknowledged since it started, so its
+/// sequence numbers have nothing to do with what the receiver saw before.
+pub const FLAG_SYNC: u8 = 1 << 1;
+/// The payload is a fragment of a larger message, see [`crate::fragment`].
+pub const FLAG_FRAGMENT: u8 = 1 << 2;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Data,
+    Ack,
+}
+
+impl Kind {
+    fn to_u8(self) -> u8 {
+        match self {
+            Kind::Data => 0,
+            Kind::Ack => 1,
+        }
+    }
+
+    fn from_u8(kind: u8) -> Option<Self> {
+        match kind {
+            0 => Some(Kind::Data),
+            1 => Some(Kind::Ack),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub kind: Kind,
+    pub seq: u16,
+    pub flags: u8,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    TooShort,
+    UnsupportedVersion(u8),
+    UnknownKind(u8),
+}
+
+impl Header {
+    pub fn encode(&self) -> [u8; HEADER_LEN] {
+        let seq = self.seq.to_le_bytes();
+        [VERSION, self.kind.to_u8(), seq[0], seq[1], self.flags]
+    }
+
+    /// Splits a frame into its header and payload.
+    pub fn decode(frame: &[u8]) -> Result<(Header, &[u8]), DecodeError> {
+        let Some((header, payload)) = frame.split_first_chunk::<HEADER_LEN>() else {
+            return Err(DecodeError::TooShort);
+        };
+        let [version, kind, seq_lo, seq_hi, flags] = *header;
+        if version != VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+        let kind = Kind::from_u8(kind).ok_or(DecodeError::UnknownKind(kind))?;
+        let header = Header {
+            kind,
+            seq: u16::from_le_bytes([seq_lo, seq_hi]),
+            flags,
+        };
+        Ok((header, payload))
+    }
+
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+}
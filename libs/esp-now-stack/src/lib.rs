@@ -0,0 +1,27 @@
+//! Protocol layers on top of ESP-NOW, shared between the ROMs.
+//!
+//! None of the protocol logic touches the radio, it is handed frames and the
+//! current time, and tells the caller what to send. That way the same code
+//! runs on the host, see `tools/esp-now-sim`. The `esp-now` feature adds
+//! async wrappers that drive it with `esp-wifi`.
+#![no_std]
+
+pub mod bridge;
+pub mod channel;
+pub mod crc;
+pub mod discovery;
+pub mod fragment;
+pub mod header;
+pub mod mesh;
+pub mod ota;
+pub mod pairing;
+pub mod peer_store;
+pub mod proximity;
+pub mod reliable;
+pub mod remote;
+pub mod timesync;
+#[cfg(feature = "esp-now")]
+pub mod transport;
+
+/// The MAC address of an ESP-NOW node.
+pub type Mac = [u8; 6];
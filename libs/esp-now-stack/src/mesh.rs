@@ -0,0 +1,528 @@
+//! Multi-hop delivery, for nodes that are out of range of each other.
+//!
+//! Broadcasts are flooded: every node delivers and rebroadcasts a message the
+//! first time it sees it, until its TTL runs out. Unicast messages follow a
+//! routing table that is kept up to date with distance vectors, like RIP:
+//! every node broadcasts the destinations it can reach and in how many hops,
+//! and its neighbours pick the shortest way to each of them. Nodes can
+//! announce themselves as a gateway, so that the others can find the
+//! nearest one without knowing its address.
+//!
+//! Messages:
+//!
+//! ```text
+//! 0       4      5     6        12    18       20
+//! +-------+------+-----+--------+-----+--------+---------
+//! | magic | kind | ttl | origin | dst | msg_id | payload
+//! +-------+------+-----+--------+-----+--------+---------
+//! ```
+//!
+//! Route advertisements:
+//!
+//! ```text
+//! 0       4      5       6       7
+//! +-------+------+-------+-------+-----------------------------------------
+//! | magic | kind | flags | count | count * (dst, hops, flags, next_hop)
+//! +-------+------+-------+-------+-----------------------------------------
+//! ```
+//!
+//! The message ID is little endian. Routes are advertised along with their
+//! next hop, so that a neighbour doesn't take back a route that goes through
+//! itself (split horizon). Tables that don't fit in one frame are sent in
+//! several. Routes that aren't advertised again time out.
+//!
+//! Every hop is a separate ESP-NOW frame, so for unicast it should be sent
+//! with `reliable`, and a hop that fails reported with
+//! [`Router::link_failed`].
+
+use crate::{Mac, header::MAX_PAYLOAD_LEN};
+
+const MAGIC: &[u8; 4] = b"MESH";
+const KIND_DATA: u8 = 0;
+const KIND_ROUTES: u8 = 1;
+
+/// The node is a gateway.
+const FLAG_GATEWAY: u8 = 1 << 0;
+
+pub const BROADCAST: Mac = [0xff; 6];
+
+const DATA_HEADER_LEN: usize = MAGIC.len() + 2 + 6 + 6 + 2;
+/// The largest payload a mesh message can carry.
+pub const MAX_MESSAGE_LEN: usize = MAX_PAYLOAD_LEN - DATA_HEADER_LEN;
+
+const ROUTES_HEADER_LEN: usize = MAGIC.len() + 3;
+const ROUTE_ENTRY_LEN: usize = 6 + 1 + 1 + 6;
+/// Larger tables are advertised a part at a time.
+const MAX_ADVERTISED: usize = (MAX_PAYLOAD_LEN - ROUTES_HEADER_LEN) / ROUTE_ENTRY_LEN;
+
+/// A destination this many hops away can't be reached.
+pub const INFINITY: u8 = 16;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// How many hops a message may take.
+    pub ttl: u8,
+    /// Whether this node is a gateway.
+    pub gateway: bool,
+    pub advertise_interval_ms: u64,
+    /// When routes change, they are advertised this soon rather than
+    /// waiting for the interval.
+    pub triggered_delay_ms: u64,
+    /// Routes that haven't been advertised by their next hop in this long
+    /// are dropped. It should be a few advertisement intervals.
+    pub route_timeout_ms: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            ttl: 8,
+            gateway: false,
+            advertise_interval_ms: 2000,
+            triggered_delay_ms: 100,
+            route_timeout_ms: 7000,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SendError {
+    /// We don't know how to reach the destination.
+    NoRoute,
+    /// The payload is larger than [`MAX_MESSAGE_LEN`].
+    TooLong,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Route {
+    pub dst: Mac,
+    pub next_hop: Mac,
+    pub hops: u8,
+    pub gateway: bool,
+    updated_ms: u64,
+}
+
+/// Why a message wasn't passed on.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Dropped {
+    /// It ran out of hops.
+    TtlExpired,
+    /// We don't know how to reach its destination.
+    NoRoute,
+}
+
+/// What a received frame turned out to be, see [`Router::handle`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Event<'a> {
+    /// A message for us, or for everybody if `broadcast` is set.
+    Delivered {
+        origin: Mac,
+        msg_id: u16,
+        broadcast: bool,
+        /// The number of hops it took, going by how much of its TTL is
+        /// left. This assumes every node uses the same TTL.
+        hops: u8,
+        payload: &'a [u8],
+    },
+    /// A message for somebody else, passed on as `forward` tells.
+    Forwarded,
+    /// A message we have seen before.
+    Duplicate,
+    /// A message for somebody else that we couldn't pass on.
+    Dropped(Dropped),
+    /// Route advertisements, `changed` tells if our routes changed.
+    Routes { changed: bool },
+    /// Not a mesh frame.
+    Ignored,
+}
+
+/// The first `len` bytes of the buffer given to `handle` must be sent to
+/// `next_hop`, which is [`BROADCAST`] for floods.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Forward {
+    pub next_hop: Mac,
+    pub len: usize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Handled<'a> {
+    pub event: Event<'a>,
+    pub forward: Option<Forward>,
+}
+
+struct DataHeader {
+    ttl: u8,
+    origin: Mac,
+    dst: Mac,
+    msg_id: u16,
+}
+
+impl DataHeader {
+    fn encode(&self, payload: &[u8], out: &mut [u8; MAX_PAYLOAD_LEN]) -> usize {
+        out[..4].copy_from_slice(MAGIC);
+        out[4] = KIND_DATA;
+        out[5] = self.ttl;
+        out[6..12].copy_from_slice(&self.origin);
+        out[12..18].copy_from_slice(&self.dst);
+        out[18..20].copy_from_slice(&self.msg_id.to_le_bytes());
+        out[DATA_HEADER_LEN..][..payload.len()].copy_from_slice(payload);
+        DATA_HEADER_LEN + payload.len()
+    }
+
+    fn decode(frame: &[u8]) -> Option<(Self, &[u8])> {
+        if frame.len() < DATA_HEADER_LEN {
+            return None;
+        }
+        let (header, payload) = frame.split_at(DATA_HEADER_LEN);
+        Some((
+            Self {
+                ttl: header[5],
+                origin: header[6..12].try_into().unwrap(),
+                dst: header[12..18].try_into().unwrap(),
+                msg_id: u16::from_le_bytes([header[18], header[19]]),
+            },
+            payload,
+        ))
+    }
+}
+
+/// Whether a payload is a mesh frame rather than anything else.
+pub fn is_mesh_frame(payload: &[u8]) -> bool {
+    payload.starts_with(MAGIC)
+}
+
+/// Routes messages through the mesh for one node.
+///
+/// `ROUTES` is the number of destinations we can keep routes for, and
+/// `SEEN` the number of recent messages remembered to filter out
+/// duplicates. The latter has to cover the messages that can arrive while
+/// a flood is still going around.
+pub struct Router<const ROUTES: usize, const SEEN: usize> {
+    own: Mac,
+    config: Config,
+    next_msg_id: u16,
+    routes: heapless::Vec<Route, ROUTES>,
+    seen: heapless::Deque<(Mac, u16), SEEN>,
+    next_advertisement_ms: u64,
+    /// Where the rest of an advertisement that didn't fit in one frame
+    /// starts.
+    advertise_from: usize,
+}
+
+impl<const ROUTES: usize, const SEEN: usize> Router<ROUTES, SEEN> {
+    /// `first_msg_id` should be random, for the same reason as the first
+    /// sequence number in `reliable`.
+    pub fn new(own: Mac, config: Config, first_msg_id: u16) -> Self {
+        Self {
+            own,
+            config,
+            next_msg_id: first_msg_id,
+            routes: heapless::Vec::new(),
+            seen: heapless::Deque::new(),
+            next_advertisement_ms: 0,
+            advertise_from: 0,
+        }
+    }
+
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
+    pub fn route(&self, dst: &Mac) -> Option<&Route> {
+        self.routes.iter().find(|r| r.dst == *dst)
+    }
+
+    /// The route to the nearest gateway.
+    pub fn gateway(&self) -> Option<&Route> {
+        self.routes
+            .iter()
+            .filter(|r| r.gateway)
+            .min_by_key(|r| r.hops)
+    }
+
+    /// Remembers a message, returning false if we have seen it before.
+    fn remember(&mut self, origin: Mac, msg_id: u16) -> bool {
+        if self.seen.iter().any(|s| *s == (origin, msg_id)) {
+            return false;
+        }
+        if self.seen.is_full() {
+            self.seen.pop_front();
+        }
+        let _ = self.seen.push_back((origin, msg_id));
+        true
+    }
+
+    fn encode_new(
+        &mut self,
+        dst: Mac,
+        payload: &[u8],
+        out: &mut [u8; MAX_PAYLOAD_LEN],
+    ) -> Result<(u16, usize), SendError> {
+        if payload.len() > MAX_MESSAGE_LEN {
+            return Err(SendError::TooLong);
+        }
+        let msg_id = self.next_msg_id;
+        self.next_msg_id = self.next_msg_id.wrapping_add(1);
+        self.remember(self.own, msg_id);
+        let header = DataHeader {
+            ttl: self.config.ttl,
+            origin: self.own,
+            dst,
+            msg_id,
+        };
+        Ok((msg_id, header.encode(payload, out)))
+    }
+
+    /// Floods a message to every node. The first `len` bytes of `out` must
+    /// be broadcast.
+    pub fn broadcast(
+        &mut self,
+        payload: &[u8],
+        out: &mut [u8; MAX_PAYLOAD_LEN],
+    ) -> Result<(u16, usize), SendError> {
+        self.encode_new(BROADCAST, payload, out)
+    }
+
+    /// Sends a message to `dst`. On success, the first `len` bytes of `out`
+    /// must be sent to the returned next hop.
+    pub fn send(
+        &mut self,
+        dst: Mac,
+        payload: &[u8],
+        out: &mut [u8; MAX_PAYLOAD_LEN],
+    ) -> Result<(Mac, usize), SendError> {
+        let next_hop = self.route(&dst).ok_or(SendError::NoRoute)?.next_hop;
+        let (_, len) = self.encode_new(dst, payload, out)?;
+        Ok((next_hop, len))
+    }
+
+    /// Sends a message to the nearest gateway, see `send`.
+    pub fn send_to_gateway(
+        &mut self,
+        payload: &[u8],
+        out: &mut [u8; MAX_PAYLOAD_LEN],
+    ) -> Result<(Mac, usize), SendError> {
+        let dst = self.gateway().ok_or(SendError::NoRoute)?.dst;
+        self.send(dst, payload, out)
+    }
+
+    /// Drops the routes through a neighbour we failed to send to, and
+    /// advertises that soon.
+    pub fn link_failed(&mut self, now_ms: u64, neighbour: &Mac) {
+        let before = self.routes.len();
+        self.routes.retain(|r| r.next_hop != *neighbour);
+        if self.routes.len() != before {
+            self.trigger(now_ms);
+        }
+    }
+
+    fn trigger(&mut self, now_ms: u64) {
+        self.next_advertisement_ms = self
+            .next_advertisement_ms
+            .min(now_ms + self.config.triggered_delay_ms);
+    }
+
+    /// When `poll` next has something to do.
+    pub fn next_deadline(&self) -> u64 {
+        self.next_advertisement_ms
+    }
+
+    /// Drops stale routes and advertises ours when it's time to. When it
+    /// returns a length, that many bytes of `out` must be broadcast, and it
+    /// must be called again in case the routes didn't fit in one frame.
+    pub fn poll(&mut self, now_ms: u64, out: &mut [u8; MAX_PAYLOAD_LEN]) -> Option<usize> {
+        if now_ms < self.next_advertisement_ms {
+            return None;
+        }
+        if self.advertise_from == 0 {
+            let timeout = self.config.route_timeout_ms;
+            self.routes
+                .retain(|r| now_ms.saturating_sub(r.updated_ms) < timeout);
+        }
+
+        out[..4].copy_from_slice(MAGIC);
+        out[4] = KIND_ROUTES;
+        out[5] = if self.config.gateway { FLAG_GATEWAY } else { 0 };
+        let routes = self.routes.get(self.advertise_from..).unwrap_or_default();
+        let count = routes.len().min(MAX_ADVERTISED);
+        out[6] = count as u8;
+        for (route, entry) in routes[..count]
+            .iter()
+            .zip(out[ROUTES_HEADER_LEN..].chunks_exact_mut(ROUTE_ENTRY_LEN))
+        {
+            entry[..6].copy_from_slice(&route.dst);
+            entry[6] = route.hops;
+            entry[7] = if route.gateway { FLAG_GATEWAY } else { 0 };
+            entry[8..].copy_from_slice(&route.next_hop);
+        }
+
+        self.advertise_from += count;
+        if self.advertise_from >= self.routes.len() {
+            self.advertise_from = 0;
+            self.next_advertisement_ms = now_ms + self.config.advertise_interval_ms;
+        }
+        Some(ROUTES_HEADER_LEN + count * ROUTE_ENTRY_LEN)
+    }
+
+    /// Handles a frame received from the neighbour `src`. If `forward` is
+    /// set, it has been written to `out`.
+    pub fn handle<'a>(
+        &mut self,
+        now_ms: u64,
+        src: Mac,
+        frame: &'a [u8],
+        out: &mut [u8; MAX_PAYLOAD_LEN],
+    ) -> Handled<'a> {
+        let event = match frame.strip_prefix(MAGIC).and_then(|f| f.first()) {
+            Some(&KIND_DATA) => return self.handle_data(frame, out),
+            Some(&KIND_ROUTES) => match self.handle_routes(now_ms, src, frame) {
+                Some(changed) => Event::Routes { changed },
+                None => Event::Ignored,
+            },
+            _ => Event::Ignored,
+        };
+        Handled {
+            event,
+            forward: None,
+        }
+    }
+
+    fn handle_data<'a>(&mut self, frame: &'a [u8], out: &mut [u8; MAX_PAYLOAD_LEN]) -> Handled<'a> {
+        let handled = |event| Handled {
+            event,
+            forward: None,
+        };
+        let Some((header, payload)) = DataHeader::decode(frame) else {
+            return handled(Event::Ignored);
+        };
+        if !self.remember(header.origin, header.msg_id) {
+            return handled(Event::Duplicate);
+        }
+        let broadcast = header.dst == BROADCAST;
+        let delivered = Event::Delivered {
+            origin: header.origin,
+            msg_id: header.msg_id,
+            broadcast,
+            hops: self.config.ttl.saturating_sub(header.ttl) + 1,
+            payload,
+        };
+        if header.dst == self.own {
+            return handled(delivered);
+        }
+
+        let next_hop = if broadcast {
+            BROADCAST
+        } else {
+            match self.route(&header.dst) {
+                Some(route) => route.next_hop,
+                None => return handled(Event::Dropped(Dropped::NoRoute)),
+            }
+        };
+        let forward = if header.ttl > 1 {
+            let header = DataHeader {
+                ttl: header.ttl - 1,
+                ..header
+            };
+            Some(Forward {
+                next_hop,
+                len: header.encode(payload, out),
+            })
+        } else {
+            None
+        };
+        match (broadcast, forward) {
+            (true, forward) => Handled {
+                event: delivered,
+                forward,
+            },
+            (false, Some(forward)) => Handled {
+                event: Event::Forwarded,
+                forward: Some(forward),
+            },
+            (false, None) => handled(Event::Dropped(Dropped::TtlExpired)),
+        }
+    }
+
+    /// Returns whether our routes changed, or `None` if the frame is
+    /// malformed.
+    fn handle_routes(&mut self, now_ms: u64, src: Mac, frame: &[u8]) -> Option<bool> {
+        if frame.len() < ROUTES_HEADER_LEN {
+            return None;
+        }
+        let (header, entries) = frame.split_at(ROUTES_HEADER_LEN);
+        let count = header[6] as usize;
+        if entries.len() != count * ROUTE_ENTRY_LEN {
+            return None;
+        }
+
+        // The neighbour itself is one hop away
+        let mut changed = self.learn(now_ms, src, src, 1, header[5] & FLAG_GATEWAY != 0);
+        for entry in entries.chunks_exact(ROUTE_ENTRY_LEN) {
+            let dst: Mac = entry[..6].try_into().unwrap();
+            let next_hop: Mac = entry[8..].try_into().unwrap();
+            if dst == self.own {
+                continue;
+            }
+            // A route through us is of no use to us, and taking it would
+            // make a loop
+            let hops = if next_hop == self.own {
+                INFINITY
+            } else {
+                entry[6].saturating_add(1).min(INFINITY)
+            };
+            changed |= self.learn(now_ms, dst, src, hops, entry[7] & FLAG_GATEWAY != 0);
+        }
+        if changed {
+            self.trigger(now_ms);
+        }
+        Some(changed)
+    }
+
+    /// Takes in a route to `dst` through `next_hop`, and returns whether our
+    /// routes changed.
+    fn learn(&mut self, now_ms: u64, dst: Mac, next_hop: Mac, hops: u8, gateway: bool) -> bool {
+        let new = Route {
+            dst,
+            next_hop,
+            hops,
+            gateway,
+            updated_ms: now_ms,
+        };
+        if let Some(i) = self.routes.iter().position(|r| r.dst == dst) {
+            let route = &mut self.routes[i];
+            if route.next_hop == next_hop {
+                // Whatever the next hop says goes, even if it got worse
+                if hops >= INFINITY {
+                    self.routes.remove(i);
+                    return true;
+                }
+                let changed = route.hops != hops || route.gateway != gateway;
+                *route = new;
+                return changed;
+            }
+            if hops < route.hops {
+                *route = new;
+                return true;
+            }
+            return false;
+        }
+        if hops >= INFINITY {
+            return false;
+        }
+        if self.routes.is_full() {
+            // Make room by dropping the longest route, if it is longer
+            let (i, longest) = self
+                .routes
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, r)| r.hops)
+                .unwrap();
+            if longest.hops <= hops {
+                return false;
+            }
+            self.routes.swap_remove(i);
+        }
+        let _ = self.routes.push(new);
+        true
+    }
+}
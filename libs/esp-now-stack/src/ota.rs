@@ -0,0 +1,593 @@
+//! Handing firmware images from node to node.
+//!
+//! A seeder broadcasts an `Offer` now and then, with the manifest of the
+//! image it runs: its version, length, SHA-256, and an Ed25519 signature
+//! over those. A node that runs an older version, and trusts the key that
+//! signed it, asks the seeder for a window of chunks at a time, and writes
+//! them to its other OTA slot in order. Every chunk carries a CRC-32, so a
+//! damaged one is dropped, and asked for again along with the rest of the
+//! window once it's clear it isn't coming.
+//!
+//! ```text
+//! Offer    magic | 0 | version | len | sha256 | signature
+//! Request  magic | 1 | id | first | count (u8)
+//! Chunk    magic | 2 | id | index | data | crc
+//! ```
+//!
+//! Numbers are little endian, and four bytes unless noted. The ID is the
+//! start of the SHA-256, so that chunks of different images don't mix. The
+//! CRC is over everything before it.
+//!
+//! Each slot ends with a trailer sector, which holds the manifest of the
+//! image in it, whether that image was checked, and which of its sectors
+//! have been written, one word per sector that is cleared once it is. An
+//! interrupted transfer, even by a reboot, carries on from the first
+//! sector that wasn't. Flash can clear bits without erasing, so none of
+//! this needs the sector erased more than once per transfer.
+//!
+//! ```text
+//! 0       4          108        112
+//! +-------+----------+----------+----------------
+//! | magic | manifest | verified | sector written..
+//! +-------+----------+----------+----------------
+//! ```
+//!
+//! The ESP-IDF bootloader picks the slot to boot from the OTA data
+//! partition, see [`select_entry`].
+
+use core::ops::Range;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    Mac,
+    crc::{crc32, crc32_esp_idf},
+};
+
+const MAGIC: &[u8; 4] = b"FIRM";
+const KIND_OFFER: u8 = 0;
+const KIND_REQUEST: u8 = 1;
+const KIND_CHUNK: u8 = 2;
+
+/// What the signature is over, ahead of the rest of the manifest.
+const SIGNATURE_CONTEXT: &[u8] = b"esp-now-ota";
+
+pub const DIGEST_LEN: usize = 32;
+pub const SIGNATURE_LEN: usize = 64;
+pub const KEY_LEN: usize = 32;
+pub const MANIFEST_LEN: usize = 4 + 4 + DIGEST_LEN + SIGNATURE_LEN;
+
+/// A power of two, so that chunks don't straddle flash sectors.
+pub const CHUNK_LEN: usize = 128;
+pub const SECTOR_LEN: u32 = 4096;
+const CHUNKS_PER_SECTOR: u32 = SECTOR_LEN / CHUNK_LEN as u32;
+
+const CRC_LEN: usize = 4;
+const CHUNK_HEADER_LEN: usize = MAGIC.len() + 1 + 4 + 4;
+/// A full chunk is the largest message.
+pub const MAX_MESSAGE_LEN: usize = CHUNK_HEADER_LEN + CHUNK_LEN + CRC_LEN;
+
+pub type Frame = heapless::Vec<u8, MAX_MESSAGE_LEN>;
+
+const TRAILER_MAGIC: &[u8; 4] = b"OTAM";
+/// Where in the trailer the verified word is. Everything before it is
+/// written when a transfer starts.
+pub const TRAILER_VERIFIED: u32 = (TRAILER_MAGIC.len() + MANIFEST_LEN) as u32;
+const TRAILER_PROGRESS: u32 = TRAILER_VERIFIED + 4;
+/// As many sectors as there are progress words in the trailer, almost 4 MB.
+pub const MAX_SECTORS: u32 = (SECTOR_LEN - TRAILER_PROGRESS) / 4;
+
+/// Whether a payload is an OTA message rather than anything else.
+pub fn is_ota_message(payload: &[u8]) -> bool {
+    payload.starts_with(MAGIC)
+}
+
+/// The public key that goes with a secret one, to build into the nodes.
+pub fn public_key(secret: &[u8; KEY_LEN]) -> [u8; KEY_LEN] {
+    SigningKey::from_bytes(secret).verifying_key().to_bytes()
+}
+
+/// Parses a key given as 64 hex digits, such as `OTA_PUBLIC_KEY`.
+pub fn parse_key(hex: &str) -> Option<[u8; KEY_LEN]> {
+    let hex = hex.trim().as_bytes();
+    if hex.len() != 2 * KEY_LEN {
+        return None;
+    }
+    let digit = |c: u8| (c as char).to_digit(16).map(|d| d as u8);
+    let mut key = [0; KEY_LEN];
+    for (k, pair) in key.iter_mut().zip(hex.chunks(2)) {
+        *k = digit(pair[0])? << 4 | digit(pair[1])?;
+    }
+    Some(key)
+}
+
+/// Describes an image, and vouches for it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Manifest {
+    /// Nodes only take images with a higher version than the one they run.
+    pub version: u32,
+    pub len: u32,
+    pub digest: [u8; DIGEST_LEN],
+    pub signature: [u8; SIGNATURE_LEN],
+}
+
+impl Manifest {
+    /// Hashes and signs `image`.
+    pub fn sign(version: u32, image: &[u8], secret: &[u8; KEY_LEN]) -> Self {
+        let mut manifest = Self {
+            version,
+            len: image.len() as u32,
+            digest: Sha256::digest(image).into(),
+            signature: [0; SIGNATURE_LEN],
+        };
+        let message = manifest.signed_message();
+        manifest.signature = SigningKey::from_bytes(secret).sign(&message).to_bytes();
+        manifest
+    }
+
+    fn signed_message(&self) -> [u8; SIGNATURE_CONTEXT.len() + 8 + DIGEST_LEN] {
+        let mut out = [0; SIGNATURE_CONTEXT.len() + 8 + DIGEST_LEN];
+        let (context, rest) = out.split_at_mut(SIGNATURE_CONTEXT.len());
+        context.copy_from_slice(SIGNATURE_CONTEXT);
+        rest[..4].copy_from_slice(&self.version.to_le_bytes());
+        rest[4..8].copy_from_slice(&self.len.to_le_bytes());
+        rest[8..].copy_from_slice(&self.digest);
+        out
+    }
+
+    /// Whether the manifest was signed by the secret key that goes with
+    /// `public_key`. The image itself is checked against the digest once it
+    /// is all there.
+    pub fn verify(&self, public_key: &[u8; KEY_LEN]) -> bool {
+        let Ok(key) = VerifyingKey::from_bytes(public_key) else {
+            return false;
+        };
+        let signature = Signature::from_bytes(&self.signature);
+        key.verify_strict(&self.signed_message(), &signature)
+            .is_ok()
+    }
+
+    pub fn id(&self) -> u32 {
+        u32::from_le_bytes(self.digest[..4].try_into().unwrap())
+    }
+
+    pub fn chunks(&self) -> u32 {
+        self.len.div_ceil(CHUNK_LEN as u32)
+    }
+
+    pub fn sectors(&self) -> u32 {
+        self.len.div_ceil(SECTOR_LEN)
+    }
+
+    /// Where chunk `index` is in the image.
+    pub fn chunk_span(&self, index: u32) -> Range<u32> {
+        let start = index * CHUNK_LEN as u32;
+        start..(start + CHUNK_LEN as u32).min(self.len)
+    }
+
+    pub fn encode(&self) -> [u8; MANIFEST_LEN] {
+        let mut out = [0; MANIFEST_LEN];
+        out[..4].copy_from_slice(&self.version.to_le_bytes());
+        out[4..8].copy_from_slice(&self.len.to_le_bytes());
+        out[8..8 + DIGEST_LEN].copy_from_slice(&self.digest);
+        out[8 + DIGEST_LEN..].copy_from_slice(&self.signature);
+        out
+    }
+
+    pub fn decode(bytes: &[u8; MANIFEST_LEN]) -> Self {
+        Self {
+            version: u32::from_le_bytes(bytes[..4].try_into().unwrap()),
+            len: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+            digest: bytes[8..8 + DIGEST_LEN].try_into().unwrap(),
+            signature: bytes[8 + DIGEST_LEN..].try_into().unwrap(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Message<'a> {
+    /// A seeder has this image.
+    Offer(Manifest),
+    /// Asks a seeder for `count` chunks, starting with `first`.
+    Request {
+        id: u32,
+        first: u32,
+        count: u8,
+    },
+    Chunk {
+        id: u32,
+        index: u32,
+        data: &'a [u8],
+    },
+}
+
+impl Message<'_> {
+    pub fn encode(&self) -> Frame {
+        let mut out = Frame::new();
+        out.extend_from_slice(MAGIC).unwrap();
+        match *self {
+            Message::Offer(manifest) => {
+                out.push(KIND_OFFER).unwrap();
+                out.extend_from_slice(&manifest.encode()).unwrap();
+            }
+            Message::Request { id, first, count } => {
+                out.push(KIND_REQUEST).unwrap();
+                out.extend_from_slice(&id.to_le_bytes()).unwrap();
+                out.extend_from_slice(&first.to_le_bytes()).unwrap();
+                out.push(count).unwrap();
+            }
+            Message::Chunk { id, index, data } => {
+                out.push(KIND_CHUNK).unwrap();
+                out.extend_from_slice(&id.to_le_bytes()).unwrap();
+                out.extend_from_slice(&index.to_le_bytes()).unwrap();
+                out.extend_from_slice(data).unwrap();
+                let crc = crc32(&out);
+                out.extend_from_slice(&crc.to_le_bytes()).unwrap();
+            }
+        }
+        out
+    }
+
+    /// Returns `None` for anything that isn't a well formed OTA message,
+    /// including chunks that got damaged on the way.
+    pub fn decode(payload: &[u8]) -> Option<Message<'_>> {
+        let rest = payload.strip_prefix(MAGIC)?;
+        let (&kind, rest) = rest.split_first()?;
+        match kind {
+            KIND_OFFER => Some(Message::Offer(Manifest::decode(rest.try_into().ok()?))),
+            KIND_REQUEST => {
+                let (&[i0, i1, i2, i3, f0, f1, f2, f3, count], []) = rest.split_first_chunk()?
+                else {
+                    return None;
+                };
+                Some(Message::Request {
+                    id: u32::from_le_bytes([i0, i1, i2, i3]),
+                    first: u32::from_le_bytes([f0, f1, f2, f3]),
+                    count,
+                })
+            }
+            KIND_CHUNK => {
+                let (covered, crc) = payload.split_last_chunk::<CRC_LEN>()?;
+                if covered.len() < CHUNK_HEADER_LEN
+                    || covered.len() > CHUNK_HEADER_LEN + CHUNK_LEN
+                    || crc32(covered) != u32::from_le_bytes(*crc)
+                {
+                    return None;
+                }
+                let (&[i0, i1, i2, i3, n0, n1, n2, n3], data) =
+                    covered[MAGIC.len() + 1..].split_first_chunk()?;
+                Some(Message::Chunk {
+                    id: u32::from_le_bytes([i0, i1, i2, i3]),
+                    index: u32::from_le_bytes([n0, n1, n2, n3]),
+                    data,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The chunks a seeder should send for a request, if it's for `manifest`.
+/// Asking for more than a window's worth gets a window's worth.
+pub fn serve(manifest: &Manifest, max_window: u8, request: &Message<'_>) -> Option<Range<u32>> {
+    let &Message::Request { id, first, count } = request else {
+        return None;
+    };
+    if id != manifest.id() || first >= manifest.chunks() {
+        return None;
+    }
+    let end = first + count.min(max_window) as u32;
+    Some(first..end.min(manifest.chunks()))
+}
+
+/// What the trailer of a slot says, see the module docs.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Trailer {
+    pub manifest: Manifest,
+    /// The image was checked against the manifest, and may be booted, or
+    /// handed on.
+    pub verified: bool,
+    /// The sectors written so far, counted from the start.
+    pub sectors_written: u32,
+}
+
+impl Trailer {
+    /// Everything up to the verified word, to write when a transfer starts.
+    pub fn header(manifest: &Manifest) -> [u8; TRAILER_VERIFIED as usize] {
+        let mut out = [0; TRAILER_VERIFIED as usize];
+        out[..TRAILER_MAGIC.len()].copy_from_slice(TRAILER_MAGIC);
+        out[TRAILER_MAGIC.len()..].copy_from_slice(&manifest.encode());
+        out
+    }
+
+    /// The trailer of an image that was flashed over USB rather than
+    /// received, so that it can be handed on.
+    pub fn flashed(manifest: &Manifest) -> [u8; TRAILER_PROGRESS as usize] {
+        let mut out = [0; TRAILER_PROGRESS as usize];
+        out[..TRAILER_VERIFIED as usize].copy_from_slice(&Self::header(manifest));
+        out
+    }
+
+    /// Where the word that marks `sector` as written goes.
+    pub fn progress_offset(sector: u32) -> u32 {
+        TRAILER_PROGRESS + 4 * sector
+    }
+
+    /// Reads a trailer sector. Erased or foreign sectors read as `None`.
+    pub fn decode(sector: &[u8]) -> Option<Self> {
+        let rest = sector.strip_prefix(TRAILER_MAGIC)?;
+        let (manifest, _) = rest.split_first_chunk::<MANIFEST_LEN>()?;
+        let manifest = Manifest::decode(manifest);
+        if manifest.sectors() > MAX_SECTORS {
+            return None;
+        }
+        let word = |offset: u32| {
+            let offset = offset as usize;
+            sector.get(offset..offset + 4) == Some(&[0; 4])
+        };
+        Some(Self {
+            manifest,
+            verified: word(TRAILER_VERIFIED),
+            sectors_written: (0..manifest.sectors())
+                .take_while(|&s| word(Self::progress_offset(s)))
+                .count() as u32,
+        })
+    }
+}
+
+/// An entry in the OTA data partition, for the bootloader to boot slot
+/// `(seq - 1) % slots` from. The entry with the highest sequence number
+/// wins.
+pub const SELECT_ENTRY_LEN: usize = 32;
+
+pub fn select_entry(seq: u32) -> [u8; SELECT_ENTRY_LEN] {
+    let mut out = [0xff; SELECT_ENTRY_LEN];
+    out[..4].copy_from_slice(&seq.to_le_bytes());
+    // The label stays erased, and so does the state, which leaves rollback
+    // out of it
+    out[28..].copy_from_slice(&crc32_esp_idf(&seq.to_le_bytes()).to_le_bytes());
+    out
+}
+
+/// The sequence number in an entry, unless it's erased or damaged.
+pub fn parse_select_entry(entry: &[u8; SELECT_ENTRY_LEN]) -> Option<u32> {
+    let seq = u32::from_le_bytes(entry[..4].try_into().unwrap());
+    let crc = u32::from_le_bytes(entry[28..].try_into().unwrap());
+    (seq != u32::MAX && crc == crc32_esp_idf(&entry[..4])).then_some(seq)
+}
+
+/// The lowest sequence number above `current` that boots `slot`.
+pub fn next_seq(current: Option<u32>, slot: u32, slots: u32) -> u32 {
+    let mut seq = current.unwrap_or(0) + 1;
+    while (seq - 1) % slots != slot {
+        seq += 1;
+    }
+    seq
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// How many chunks to ask for at a time.
+    pub window: u8,
+    /// How long to wait for the next chunk before asking again.
+    pub chunk_timeout_ms: u64,
+    /// How many times in a row to ask again before giving up on a seeder.
+    pub max_retries: u8,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            window: 16,
+            chunk_timeout_ms: 300,
+            max_retries: 10,
+        }
+    }
+}
+
+/// What a received frame turned out to be, see [`Receiver::handle`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event<'a> {
+    /// A newer image than ours, signed by the right key. Find out how much
+    /// of it we already have, and call [`Receiver::start`].
+    Offered(Manifest),
+    /// An offer that wasn't signed by the right key.
+    BadSignature { src: Mac },
+    /// The next chunk, to write at `offset` into the slot. The first chunk
+    /// of a sector is the time to erase it.
+    Chunk {
+        offset: u32,
+        data: &'a [u8],
+        /// A sector that is now written in full, to mark in the trailer.
+        sector_written: Option<u32>,
+        /// The image is all there, and has to be checked against the
+        /// manifest.
+        complete: bool,
+        /// Asks for the next window.
+        request: Option<Frame>,
+    },
+    /// The last chunk of the window arrived, but some before it didn't.
+    /// Asks for them again right away, rather than once they are overdue.
+    Missed { request: Frame },
+    /// Anything else, including chunks out of order.
+    Ignored,
+}
+
+struct Transfer {
+    seeder: Mac,
+    manifest: Manifest,
+    next: u32,
+    window_end: u32,
+    deadline_ms: u64,
+    retries: u8,
+}
+
+/// Downloads images newer than the one we run, one at a time.
+pub struct Receiver {
+    config: Config,
+    public_key: [u8; KEY_LEN],
+    running_version: u32,
+    transfer: Option<Transfer>,
+    complete: bool,
+}
+
+impl Receiver {
+    pub fn new(config: Config, public_key: [u8; KEY_LEN], running_version: u32) -> Self {
+        Self {
+            config,
+            public_key,
+            running_version,
+            transfer: None,
+            complete: false,
+        }
+    }
+
+    /// The seeder we are downloading from, and how far we got.
+    pub fn progress(&self) -> Option<(Mac, &Manifest, u32)> {
+        self.transfer
+            .as_ref()
+            .map(|t| (t.seeder, &t.manifest, t.next))
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    fn request(&mut self, now_ms: u64) -> Frame {
+        let t = self.transfer.as_mut().unwrap();
+        let count = (t.manifest.chunks() - t.next).min(self.config.window as u32);
+        t.window_end = t.next + count;
+        t.deadline_ms = now_ms + self.config.chunk_timeout_ms;
+        Message::Request {
+            id: t.manifest.id(),
+            first: t.next,
+            count: count as u8,
+        }
+        .encode()
+    }
+
+    /// Starts downloading from `seeder`, from chunk `first` on, and returns
+    /// the first request to send it. If there is nothing left to download,
+    /// the image is complete right away, and has to be checked against the
+    /// manifest like after the last chunk.
+    pub fn start(
+        &mut self,
+        now_ms: u64,
+        seeder: Mac,
+        manifest: Manifest,
+        first: u32,
+    ) -> Option<Frame> {
+        if first >= manifest.chunks() {
+            self.complete = true;
+            return None;
+        }
+        self.transfer = Some(Transfer {
+            seeder,
+            manifest,
+            next: first,
+            window_end: first,
+            deadline_ms: now_ms,
+            retries: 0,
+        });
+        Some(self.request(now_ms))
+    }
+
+    /// Where a transfer would resume from, given the trailer of the slot
+    /// we'd write to. That is past the last chunk when it was all written,
+    /// but we lost power before it was verified.
+    pub fn resume_from(manifest: &Manifest, trailer: Option<&Trailer>) -> Option<u32> {
+        trailer
+            .filter(|t| t.manifest == *manifest && !t.verified)
+            .map(|t| (t.sectors_written * CHUNKS_PER_SECTOR).min(manifest.chunks()))
+    }
+
+    /// When `poll` next has something to do.
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.transfer.as_ref().map(|t| t.deadline_ms)
+    }
+
+    /// Asks again when chunks are overdue, or gives up on the seeder after
+    /// too many tries, in which case we wait for the next offer.
+    pub fn poll(&mut self, now_ms: u64) -> Option<(Mac, Frame)> {
+        let t = self.transfer.as_mut()?;
+        if now_ms < t.deadline_ms {
+            return None;
+        }
+        if t.retries >= self.config.max_retries {
+            self.transfer = None;
+            return None;
+        }
+        t.retries += 1;
+        let seeder = t.seeder;
+        Some((seeder, self.request(now_ms)))
+    }
+
+    /// Handles a frame from `src` received at `now_ms`.
+    pub fn handle<'a>(&mut self, now_ms: u64, src: Mac, payload: &'a [u8]) -> Event<'a> {
+        let Some(message) = Message::decode(payload) else {
+            return Event::Ignored;
+        };
+        match message {
+            Message::Offer(manifest) => {
+                if self.complete
+                    || self.transfer.is_some()
+                    || manifest.version <= self.running_version
+                    || manifest.sectors() > MAX_SECTORS
+                {
+                    Event::Ignored
+                } else if !manifest.verify(&self.public_key) {
+                    Event::BadSignature { src }
+                } else {
+                    Event::Offered(manifest)
+                }
+            }
+            Message::Chunk { id, index, data } => {
+                let Some(t) = self.transfer.as_mut() else {
+                    return Event::Ignored;
+                };
+                let span = t.manifest.chunk_span(index);
+                if src != t.seeder || id != t.manifest.id() || data.len() != span.len() {
+                    return Event::Ignored;
+                }
+                if index != t.next {
+                    if index > t.next && index + 1 == t.window_end {
+                        return Event::Missed {
+                            request: self.request(now_ms),
+                        };
+                    }
+                    return Event::Ignored;
+                }
+                t.next += 1;
+                t.retries = 0;
+                t.deadline_ms = now_ms + self.config.chunk_timeout_ms;
+                let complete = t.next == t.manifest.chunks();
+                let sector_written = (t.next % CHUNKS_PER_SECTOR == 0 || complete)
+                    .then_some(index / CHUNKS_PER_SECTOR);
+                let window_done = t.next == t.window_end;
+                let request = if complete {
+                    self.transfer = None;
+                    self.complete = true;
+                    None
+                } else {
+                    window_done.then(|| self.request(now_ms))
+                };
+                Event::Chunk {
+                    offset: span.start,
+                    data,
+                    sector_written,
+                    complete,
+                    request,
+                }
+            }
+            Message::Request { .. } => Event::Ignored,
+        }
+    }
+
+    /// Forgets a completed image that didn't check out, so that it can be
+    /// downloaded again.
+    pub fn reset(&mut self) {
+        self.transfer = None;
+        self.complete = false;
+    }
+}
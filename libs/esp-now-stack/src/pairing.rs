@@ -0,0 +1,244 @@
+//! Pairing two nodes, so that they can talk over encrypted ESP-NOW.
+//!
+//! ESP-NOW encrypts unicast frames with a per-peer local master key (LMK),
+//! which is itself encrypted with a primary master key (PMK) shared by all
+//! nodes. Pairing agrees on an LMK without ever sending it:
+//!
+//! 1. Both nodes broadcast an `Offer` with a fresh X25519 public key.
+//! 2. On an offer, a node computes the shared secret and broadcasts a
+//!    `Confirm` addressed to the other node. The tag in it proves that the
+//!    sender knows both the shared secret and the pairing code.
+//! 3. A node that receives a valid confirmation derives the LMK from the
+//!    shared secret, and is paired.
+//!
+//! Somebody who only listens learns nothing. Somebody actively in the middle
+//! gets one confirmation tag per attempt to guess the pairing code with, so
+//! the code has to be long enough to not be guessed offline within the
+//! pairing window. The PMK is derived from the pairing code alone.
+
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac as _};
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::Mac;
+
+pub const KEY_LEN: usize = 16;
+pub const PUBLIC_KEY_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+
+/// Every pairing message starts with this, followed by the message type.
+const MAGIC: &[u8; 4] = b"PAIR";
+const MSG_OFFER: u8 = 0;
+const MSG_CONFIRM: u8 = 1;
+
+/// The offer is the larger of the two messages.
+pub const MAX_MESSAGE_LEN: usize = MAGIC.len() + 1 + PUBLIC_KEY_LEN;
+
+/// The primary master key, which has to be the same on every node.
+pub fn pmk(pairing_code: &[u8]) -> [u8; KEY_LEN] {
+    let mut pmk = [0; KEY_LEN];
+    Hkdf::<Sha256>::new(None, pairing_code)
+        .expand(b"esp-now pmk", &mut pmk)
+        .unwrap();
+    pmk
+}
+
+/// Whether a payload is a pairing message rather than anything else.
+pub fn is_pairing_message(payload: &[u8]) -> bool {
+    payload.starts_with(MAGIC)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Message {
+    Offer { public: [u8; PUBLIC_KEY_LEN] },
+    Confirm { to: Mac, tag: [u8; TAG_LEN] },
+}
+
+impl Message {
+    fn encode(&self) -> heapless::Vec<u8, MAX_MESSAGE_LEN> {
+        let mut out = heapless::Vec::new();
+        out.extend_from_slice(MAGIC).unwrap();
+        match self {
+            Message::Offer { public } => {
+                out.push(MSG_OFFER).unwrap();
+                out.extend_from_slice(public).unwrap();
+            }
+            Message::Confirm { to, tag } => {
+                out.push(MSG_CONFIRM).unwrap();
+                out.extend_from_slice(to).unwrap();
+                out.extend_from_slice(tag).unwrap();
+            }
+        }
+        out
+    }
+
+    fn decode(payload: &[u8]) -> Option<Self> {
+        let rest = payload.strip_prefix(MAGIC)?;
+        match rest.split_first()? {
+            (&MSG_OFFER, public) => Some(Message::Offer {
+                public: public.try_into().ok()?,
+            }),
+            (&MSG_CONFIRM, rest) => {
+                let (to, tag) = rest.split_first_chunk::<6>()?;
+                Some(Message::Confirm {
+                    to: *to,
+                    tag: tag.try_into().ok()?,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// What to do after handling a pairing message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    None,
+    /// Broadcast this message.
+    Reply(heapless::Vec<u8, MAX_MESSAGE_LEN>),
+    /// We are paired with `peer`, and should add it as an encrypted peer
+    /// with `lmk`.
+    Paired {
+        peer: Mac,
+        lmk: [u8; KEY_LEN],
+    },
+    /// `peer` sent a confirmation that doesn't check out, most likely
+    /// because it was given a different pairing code.
+    Rejected {
+        peer: Mac,
+    },
+}
+
+/// Keys agreed with a node whose offer we have seen.
+struct Candidate {
+    peer: Mac,
+    public: [u8; PUBLIC_KEY_LEN],
+    confirm_key: [u8; 32],
+    lmk: [u8; KEY_LEN],
+}
+
+/// The number of nodes we can be pairing with at the same time.
+const MAX_CANDIDATES: usize = 4;
+
+/// One pairing session. It should only live for as long as the user asked
+/// to pair, and be started over with a new secret every time.
+pub struct Pairing {
+    own_mac: Mac,
+    secret: StaticSecret,
+    public: PublicKey,
+    pairing_code: heapless::Vec<u8, 64>,
+    candidates: heapless::Vec<Candidate, MAX_CANDIDATES>,
+}
+
+impl Pairing {
+    /// `secret` must come from a proper random number generator. Pairing
+    /// codes longer than 64 bytes are cut off.
+    pub fn new(own_mac: Mac, secret: [u8; 32], pairing_code: &[u8]) -> Self {
+        let secret = StaticSecret::from(secret);
+        let code = &pairing_code[..pairing_code.len().min(64)];
+        Self {
+            own_mac,
+            public: PublicKey::from(&secret),
+            secret,
+            pairing_code: heapless::Vec::from_slice(code).unwrap(),
+            candidates: heapless::Vec::new(),
+        }
+    }
+
+    /// The offer to broadcast every now and then while pairing.
+    pub fn offer(&self) -> heapless::Vec<u8, MAX_MESSAGE_LEN> {
+        Message::Offer {
+            public: self.public.to_bytes(),
+        }
+        .encode()
+    }
+
+    /// The MAC for the tag `from` sends to `to` to prove it knows the keys.
+    fn tag_mac(candidate: &Candidate, from: (Mac, &[u8]), to: (Mac, &[u8])) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&candidate.confirm_key).unwrap();
+        mac.update(&from.0);
+        mac.update(&to.0);
+        mac.update(from.1);
+        mac.update(to.1);
+        mac
+    }
+
+    pub fn handle(&mut self, src: Mac, payload: &[u8]) -> Action {
+        match Message::decode(payload) {
+            Some(Message::Offer { public }) => self.handle_offer(src, public),
+            Some(Message::Confirm { to, tag }) if to == self.own_mac => {
+                self.handle_confirm(src, tag)
+            }
+            _ => Action::None,
+        }
+    }
+
+    fn handle_offer(&mut self, src: Mac, public: [u8; PUBLIC_KEY_LEN]) -> Action {
+        if !self
+            .candidates
+            .iter()
+            .any(|c| c.peer == src && c.public == public)
+        {
+            let shared = self.secret.diffie_hellman(&PublicKey::from(public));
+            // Rejects keys that would make the shared secret predictable
+            if !shared.was_contributory() {
+                return Action::None;
+            }
+            // Both sides have to derive the same keys, so the MAC addresses
+            // go in sorted
+            let (low, high) = if src < self.own_mac {
+                (src, self.own_mac)
+            } else {
+                (self.own_mac, src)
+            };
+            let hkdf = Hkdf::<Sha256>::new(Some(&self.pairing_code), shared.as_bytes());
+            let mut confirm_key = [0; 32];
+            let mut lmk = [0; KEY_LEN];
+            hkdf.expand_multi_info(&[b"esp-now confirm", &low, &high], &mut confirm_key)
+                .unwrap();
+            hkdf.expand_multi_info(&[b"esp-now lmk", &low, &high], &mut lmk)
+                .unwrap();
+
+            self.candidates.retain(|c| c.peer != src);
+            if self.candidates.is_full() {
+                self.candidates.remove(0);
+            }
+            let _ = self.candidates.push(Candidate {
+                peer: src,
+                public,
+                confirm_key,
+                lmk,
+            });
+        }
+
+        let candidate = self.candidates.iter().find(|c| c.peer == src).unwrap();
+        let mac = Self::tag_mac(
+            candidate,
+            (self.own_mac, self.public.as_bytes()),
+            (src, &candidate.public),
+        );
+        let mut tag = [0; TAG_LEN];
+        tag.copy_from_slice(&mac.finalize().into_bytes()[..TAG_LEN]);
+        Action::Reply(Message::Confirm { to: src, tag }.encode())
+    }
+
+    fn handle_confirm(&mut self, src: Mac, tag: [u8; TAG_LEN]) -> Action {
+        // We can't check anything before we have seen the offer
+        let Some(candidate) = self.candidates.iter().find(|c| c.peer == src) else {
+            return Action::None;
+        };
+        let mac = Self::tag_mac(
+            candidate,
+            (src, &candidate.public),
+            (self.own_mac, self.public.as_bytes()),
+        );
+        if mac.verify_truncated_left(&tag).is_err() {
+            return Action::Rejected { peer: src };
+        }
+        Action::Paired {
+            peer: src,
+            lmk: candidate.lmk,
+        }
+    }
+}
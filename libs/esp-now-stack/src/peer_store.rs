@@ -0,0 +1,111 @@
+//! The peers we are paired with, in a form that can be kept in flash.
+//!
+//! ```text
+//! 0       4         5       6
+//! +-------+---------+-------+-----------------------+--------
+//! | magic | version | count | count * (mac, lmk)    | crc32
+//! +-------+---------+-------+-----------------------+--------
+//! ```
+//!
+//! The CRC covers everything before it, and is little endian. Anything that
+//! doesn't check out is read as an empty store.
+
+use crate::{Mac, crc::crc32, pairing::KEY_LEN};
+
+/// ESP-NOW can only encrypt traffic to a handful of peers.
+pub const MAX_PAIRED: usize = 6;
+
+const MAGIC: &[u8; 4] = b"ENPS";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = MAGIC.len() + 2;
+const ENTRY_LEN: usize = 6 + KEY_LEN;
+pub const STORE_LEN: usize = HEADER_LEN + MAX_PAIRED * ENTRY_LEN + 4;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PairedPeer {
+    pub mac: Mac,
+    pub lmk: [u8; KEY_LEN],
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PeerStore {
+    /// Ordered from the oldest to the most recent pairing.
+    peers: heapless::Vec<PairedPeer, MAX_PAIRED>,
+}
+
+impl PeerStore {
+    pub const fn new() -> Self {
+        Self {
+            peers: heapless::Vec::new(),
+        }
+    }
+
+    pub fn peers(&self) -> &[PairedPeer] {
+        &self.peers
+    }
+
+    pub fn get(&self, mac: &Mac) -> Option<&PairedPeer> {
+        self.peers.iter().find(|p| p.mac == *mac)
+    }
+
+    /// Adds or updates a peer. When the store is full, the peer we paired
+    /// with the longest time ago is forgotten and returned, so it can be
+    /// removed from ESP-NOW as well.
+    pub fn insert(&mut self, peer: PairedPeer) -> Option<PairedPeer> {
+        self.remove(&peer.mac);
+        let evicted = if self.peers.is_full() {
+            Some(self.peers.remove(0))
+        } else {
+            None
+        };
+        let _ = self.peers.push(peer);
+        evicted
+    }
+
+    pub fn remove(&mut self, mac: &Mac) -> Option<PairedPeer> {
+        let i = self.peers.iter().position(|p| p.mac == *mac)?;
+        Some(self.peers.remove(i))
+    }
+
+    pub fn clear(&mut self) {
+        self.peers.clear();
+    }
+
+    pub fn encode(&self) -> [u8; STORE_LEN] {
+        let mut out = [0xff; STORE_LEN];
+        out[..MAGIC.len()].copy_from_slice(MAGIC);
+        out[MAGIC.len()] = VERSION;
+        out[MAGIC.len() + 1] = self.peers.len() as u8;
+        for (peer, entry) in self
+            .peers
+            .iter()
+            .zip(out[HEADER_LEN..].chunks_exact_mut(ENTRY_LEN))
+        {
+            entry[..6].copy_from_slice(&peer.mac);
+            entry[6..].copy_from_slice(&peer.lmk);
+        }
+        let crc_at = STORE_LEN - 4;
+        let crc = crc32(&out[..crc_at]);
+        out[crc_at..].copy_from_slice(&crc.to_le_bytes());
+        out
+    }
+
+    pub fn decode(data: &[u8; STORE_LEN]) -> Self {
+        let mut store = Self::new();
+        let (body, crc) = data.split_at(STORE_LEN - 4);
+        if crc32(body).to_le_bytes() != crc
+            || !body.starts_with(MAGIC)
+            || body[MAGIC.len()] != VERSION
+        {
+            return store;
+        }
+        let count = (body[MAGIC.len() + 1] as usize).min(MAX_PAIRED);
+        for entry in body[HEADER_LEN..].chunks_exact(ENTRY_LEN).take(count) {
+            let _ = store.peers.push(PairedPeer {
+                mac: entry[..6].try_into().unwrap(),
+                lmk: entry[6..].try_into().unwrap(),
+            });
+        }
+        store
+    }
+}
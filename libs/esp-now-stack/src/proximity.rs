@@ -0,0 +1,198 @@
+//! How close other nodes are, going by how strong their frames arrive.
+//!
+//! Any frame will do, but the nodes should send something now and then,
+//! like the pings of `esp-now-broadcast-sender`. The RSSI of a single frame
+//! jumps around by several dB as people walk by and the node turns, so it's
+//! smoothed with a one dimensional Kalman filter: the estimate is trusted
+//! less the longer it has been since the last frame, and a frame moves it
+//! more the less it is trusted.
+//!
+//! The estimate puts a node in a [`Zone`]. It only moves to another one when
+//! the estimate is past the boundary by the hysteresis, so that a node that
+//! sits right on it doesn't flap between the two. A node we haven't heard
+//! from for a while is gone.
+
+use crate::Mac;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Config {
+    /// The boundary between [`Zone::Near`] and [`Zone::Far`], in dBm.
+    pub near_dbm: f32,
+    /// How far past the boundary the estimate has to get to change zones.
+    pub hysteresis_db: f32,
+    /// How much we expect the RSSI to change by itself, as a variance per
+    /// second, in dB². Higher follows a node that moves faster, but lets
+    /// more noise through.
+    pub process_noise: f32,
+    /// How noisy a single frame is, as a variance in dB².
+    pub measurement_noise: f32,
+    /// Without a frame for this long, a node is gone.
+    pub timeout_ms: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            near_dbm: -60.0,
+            hysteresis_db: 4.0,
+            process_noise: 2.0,
+            measurement_noise: 16.0,
+            timeout_ms: 10_000,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Zone {
+    Near,
+    Far,
+}
+
+/// The RSSI of a node, smoothed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Filter {
+    estimate: f32,
+    /// How far off `estimate` might be, as a variance.
+    variance: f32,
+    updated_ms: u64,
+}
+
+impl Filter {
+    /// Starts off at the first frame's RSSI, trusting it no more than a
+    /// frame.
+    pub fn new(now_ms: u64, rssi: i8, config: &Config) -> Self {
+        Self {
+            estimate: rssi as f32,
+            variance: config.measurement_noise,
+            updated_ms: now_ms,
+        }
+    }
+
+    pub fn update(&mut self, now_ms: u64, rssi: i8, config: &Config) {
+        let elapsed_s = now_ms.saturating_sub(self.updated_ms) as f32 / 1000.0;
+        let predicted = self.variance + config.process_noise * elapsed_s;
+        let gain = predicted / (predicted + config.measurement_noise);
+        self.estimate += gain * (rssi as f32 - self.estimate);
+        self.variance = (1.0 - gain) * predicted;
+        self.updated_ms = now_ms;
+    }
+
+    /// In dBm.
+    pub fn estimate(&self) -> f32 {
+        self.estimate
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Tracked {
+    pub mac: Mac,
+    pub filter: Filter,
+    pub zone: Zone,
+    /// The RSSI of the last frame, in dBm.
+    pub last_rssi: i8,
+    pub last_seen_ms: u64,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// We hadn't heard from the node, or not for a while. If we were
+    /// tracking as many as we can, the one we heard from longest ago made
+    /// room for it, without a `Left`.
+    Entered {
+        mac: Mac,
+        zone: Zone,
+        evicted: Option<Mac>,
+    },
+    /// The node moved to another zone.
+    Moved { mac: Mac, zone: Zone },
+    /// We haven't heard from the node in too long.
+    Left { mac: Mac },
+}
+
+/// The zone for an estimate, given the one it was in.
+fn zone(config: &Config, current: Option<Zone>, estimate: f32) -> Zone {
+    let boundary = match current {
+        Some(Zone::Near) => config.near_dbm - config.hysteresis_db,
+        Some(Zone::Far) => config.near_dbm + config.hysteresis_db,
+        None => config.near_dbm,
+    };
+    if estimate >= boundary {
+        Zone::Near
+    } else {
+        Zone::Far
+    }
+}
+
+/// Up to `N` nodes, and how close they are.
+pub struct Tracker<const N: usize> {
+    config: Config,
+    /// Ordered from the least to the most recently seen.
+    tracked: heapless::Vec<Tracked, N>,
+}
+
+impl<const N: usize> Tracker<N> {
+    pub const fn new(config: Config) -> Self {
+        Self {
+            config,
+            tracked: heapless::Vec::new(),
+        }
+    }
+
+    pub fn tracked(&self) -> impl Iterator<Item = &Tracked> {
+        self.tracked.iter()
+    }
+
+    pub fn get(&self, mac: &Mac) -> Option<&Tracked> {
+        self.tracked.iter().find(|t| t.mac == *mac)
+    }
+
+    /// Records a frame from `mac`, and tells if that changed anything.
+    pub fn handle(&mut self, now_ms: u64, mac: Mac, rssi: i8) -> Option<Event> {
+        let config = &self.config;
+        if let Some(i) = self.tracked.iter().position(|t| t.mac == mac) {
+            let mut tracked = self.tracked.remove(i);
+            tracked.filter.update(now_ms, rssi, config);
+            tracked.last_rssi = rssi;
+            tracked.last_seen_ms = now_ms;
+            let zone = zone(config, Some(tracked.zone), tracked.filter.estimate());
+            let moved = zone != tracked.zone;
+            tracked.zone = zone;
+            let _ = self.tracked.push(tracked);
+            return moved.then_some(Event::Moved { mac, zone });
+        }
+
+        let evicted = if self.tracked.is_full() {
+            Some(self.tracked.remove(0).mac)
+        } else {
+            None
+        };
+        let filter = Filter::new(now_ms, rssi, config);
+        let zone = zone(config, None, filter.estimate());
+        let _ = self.tracked.push(Tracked {
+            mac,
+            filter,
+            zone,
+            last_rssi: rssi,
+            last_seen_ms: now_ms,
+        });
+        Some(Event::Entered { mac, zone, evicted })
+    }
+
+    /// Forgets one node we haven't heard from in too long. Call it until it
+    /// returns `None`.
+    pub fn expire(&mut self, now_ms: u64) -> Option<Event> {
+        let oldest = self.tracked.first()?;
+        if now_ms.saturating_sub(oldest.last_seen_ms) < self.config.timeout_ms {
+            return None;
+        }
+        let mac = self.tracked.remove(0).mac;
+        Some(Event::Left { mac })
+    }
+
+    /// When `expire` will next have something to forget.
+    pub fn next_expiry(&self) -> Option<u64> {
+        self.tracked
+            .first()
+            .map(|t| t.last_seen_ms + self.config.timeout_ms)
+    }
+}
@@ -0,0 +1,360 @@
+//! Reliable unicast: every data frame is acknowledged by the receiver and
+//! retransmitted with exponential backoff until it is, or until we give up.
+//! Retransmissions and lost acknowledgements lead to duplicates, which the
+//! receiving side filters out by sequence number.
+//!
+//! Only one message is in flight at a time. ESP-NOW is slow enough that a
+//! window would buy us little, and it keeps the receiving side simple.
+
+use crate::{
+    Mac,
+    header::{
+        FLAG_ACK_REQUESTED, FLAG_FRAGMENT, FLAG_SYNC, HEADER_LEN, Header, Kind, MAX_FRAME_LEN,
+        MAX_PAYLOAD_LEN,
+    },
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// How many times a frame is sent, including the first time.
+    pub max_attempts: u8,
+    /// How long to wait for the first acknowledgement. The timeout doubles
+    /// with every retransmission.
+    pub initial_timeout_ms: u64,
+    pub max_timeout_ms: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_attempts: 6,
+            initial_timeout_ms: 20,
+            max_timeout_ms: 320,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SendError {
+    /// The previous message is still waiting for its acknowledgement.
+    Busy,
+    /// The payload does not fit in a single frame.
+    TooLong,
+}
+
+/// What the endpoint wants done next, see [`Endpoint::poll`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Poll {
+    /// Nothing is in flight.
+    Idle,
+    /// Waiting for an acknowledgement. `poll` should be called again at
+    /// `until_ms`, or when a frame arrives.
+    Wait { until_ms: u64 },
+    /// The first `len` bytes of the buffer given to `poll` must be sent to
+    /// `dst` again.
+    Retransmit { dst: Mac, len: usize },
+    /// The message with sequence number `seq` was acknowledged.
+    Delivered { seq: u16, attempts: u8 },
+    /// The message with sequence number `seq` was never acknowledged.
+    Failed { seq: u16, attempts: u8 },
+}
+
+/// What a received frame turned out to be, see [`Endpoint::handle`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Event<'a> {
+    /// A new message. `reliable` tells if it was sent with
+    /// [`Endpoint::send`] rather than [`Endpoint::send_unreliable`], and
+    /// `fragment` if it was sent with [`Endpoint::send_fragment`].
+    Received {
+        seq: u16,
+        reliable: bool,
+        fragment: bool,
+        payload: &'a [u8],
+    },
+    /// A message we already received, retransmitted because our
+    /// acknowledgement got lost.
+    Duplicate { seq: u16 },
+    /// The acknowledgement for our message in flight. It is reported by the
+    /// next call to `poll`.
+    Acked { seq: u16 },
+    /// Not for us, or not understood.
+    Ignored,
+}
+
+/// The result of handling a received frame. If `ack` is set it must be
+/// sent back to the source of the frame.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Handled<'a> {
+    pub event: Event<'a>,
+    pub ack: Option<[u8; HEADER_LEN]>,
+}
+
+struct Outgoing {
+    dst: Mac,
+    seq: u16,
+    frame: heapless::Vec<u8, MAX_FRAME_LEN>,
+    attempts: u8,
+    deadline_ms: u64,
+    acked: bool,
+}
+
+/// The last sequence number we accepted from a peer.
+#[derive(Copy, Clone)]
+struct Seen {
+    src: Mac,
+    seq: u16,
+}
+
+/// One end of a reliable link, talking to any number of peers.
+///
+/// `PEERS` is the number of peers we remember sequence numbers for. When a
+/// new peer shows up, the one we heard from longest ago is forgotten.
+pub struct Endpoint<const PEERS: usize> {
+    config: Config,
+    next_seq: u16,
+    /// Cleared once anything we sent has been acknowledged.
+    sync: bool,
+    outgoing: Option<Outgoing>,
+    /// Ordered from least to most recently heard from.
+    seen: heapless::Vec<Seen, PEERS>,
+}
+
+/// How far behind the last accepted sequence number a frame with
+/// `FLAG_SYNC` has to be, to be taken as a new message.
+const SYNC_WINDOW: u16 = 64;
+
+/// Whether `a` comes after `b`, taking wrap-around into account.
+fn is_newer(a: u16, b: u16) -> bool {
+    (a.wrapping_sub(b) as i16) > 0
+}
+
+impl<const PEERS: usize> Endpoint<PEERS> {
+    /// `first_seq` should be random, so that a peer that remembers our
+    /// sequence numbers from before a reboot doesn't take our first messages
+    /// for duplicates.
+    pub fn new(config: Config, first_seq: u16) -> Self {
+        Self {
+            config,
+            next_seq: first_seq,
+            sync: true,
+            outgoing: None,
+            seen: heapless::Vec::new(),
+        }
+    }
+
+    fn encode(
+        &mut self,
+        flags: u8,
+        payload: &[u8],
+        out: &mut [u8; MAX_FRAME_LEN],
+    ) -> Result<(u16, usize), SendError> {
+        if payload.len() > MAX_PAYLOAD_LEN {
+            return Err(SendError::TooLong);
+        }
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        let header = Header {
+            kind: Kind::Data,
+            seq,
+            flags,
+        };
+        out[..HEADER_LEN].copy_from_slice(&header.encode());
+        out[HEADER_LEN..][..payload.len()].copy_from_slice(payload);
+        Ok((seq, HEADER_LEN + payload.len()))
+    }
+
+    /// Starts sending a message to `dst`. On success, the first `len` bytes
+    /// of `out` must be sent right away, and `poll` tells what happens next.
+    pub fn send(
+        &mut self,
+        now_ms: u64,
+        dst: Mac,
+        payload: &[u8],
+        out: &mut [u8; MAX_FRAME_LEN],
+    ) -> Result<(u16, usize), SendError> {
+        self.start(now_ms, dst, FLAG_ACK_REQUESTED, payload, out)
+    }
+
+    /// Like `send`, but marks the payload as a fragment of a larger message,
+    /// see [`crate::fragment`].
+    pub fn send_fragment(
+        &mut self,
+        now_ms: u64,
+        dst: Mac,
+        fragment: &[u8],
+        out: &mut [u8; MAX_FRAME_LEN],
+    ) -> Result<(u16, usize), SendError> {
+        self.start(
+            now_ms,
+            dst,
+            FLAG_ACK_REQUESTED | FLAG_FRAGMENT,
+            fragment,
+            out,
+        )
+    }
+
+    fn start(
+        &mut self,
+        now_ms: u64,
+        dst: Mac,
+        mut flags: u8,
+        payload: &[u8],
+        out: &mut [u8; MAX_FRAME_LEN],
+    ) -> Result<(u16, usize), SendError> {
+        if self.outgoing.is_some() {
+            return Err(SendError::Busy);
+        }
+        if self.sync {
+            flags |= FLAG_SYNC;
+        }
+        let (seq, len) = self.encode(flags, payload, out)?;
+        self.outgoing = Some(Outgoing {
+            dst,
+            seq,
+            frame: heapless::Vec::from_slice(&out[..len]).unwrap(),
+            attempts: 1,
+            deadline_ms: now_ms + self.config.initial_timeout_ms,
+            acked: false,
+        });
+        Ok((seq, len))
+    }
+
+    /// Frames a message that is sent once and never acknowledged, e.g. for
+    /// broadcasts. The first `len` bytes of `out` must be sent.
+    pub fn send_unreliable(
+        &mut self,
+        payload: &[u8],
+        out: &mut [u8; MAX_FRAME_LEN],
+    ) -> Result<usize, SendError> {
+        self.encode(0, payload, out).map(|(_, len)| len)
+    }
+
+    /// Moves the message in flight along. Must be called after `send`, when
+    /// the time returned in `Poll::Wait` has passed and after handling
+    /// an acknowledgement.
+    pub fn poll(&mut self, now_ms: u64, out: &mut [u8; MAX_FRAME_LEN]) -> Poll {
+        let Some(outgoing) = &mut self.outgoing else {
+            return Poll::Idle;
+        };
+        if outgoing.acked {
+            let poll = Poll::Delivered {
+                seq: outgoing.seq,
+                attempts: outgoing.attempts,
+            };
+            self.outgoing = None;
+            return poll;
+        }
+        if now_ms < outgoing.deadline_ms {
+            return Poll::Wait {
+                until_ms: outgoing.deadline_ms,
+            };
+        }
+        if outgoing.attempts >= self.config.max_attempts {
+            let poll = Poll::Failed {
+                seq: outgoing.seq,
+                attempts: outgoing.attempts,
+            };
+            self.outgoing = None;
+            return poll;
+        }
+
+        let timeout = self
+            .config
+            .initial_timeout_ms
+            .checked_shl(outgoing.attempts as u32)
+            .unwrap_or(u64::MAX)
+            .min(self.config.max_timeout_ms);
+        outgoing.attempts += 1;
+        outgoing.deadline_ms = now_ms + timeout;
+        let len = outgoing.frame.len();
+        out[..len].copy_from_slice(&outgoing.frame);
+        Poll::Retransmit {
+            dst: outgoing.dst,
+            len,
+        }
+    }
+
+    /// Handles a frame received from `src`.
+    pub fn handle<'a>(&mut self, src: Mac, frame: &'a [u8]) -> Handled<'a> {
+        let ignored = Handled {
+            event: Event::Ignored,
+            ack: None,
+        };
+        let Ok((header, payload)) = Header::decode(frame) else {
+            return ignored;
+        };
+
+        match header.kind {
+            Kind::Ack => {
+                let Some(outgoing) = &mut self.outgoing else {
+                    return ignored;
+                };
+                if outgoing.dst != src || outgoing.seq != header.seq || outgoing.acked {
+                    return ignored;
+                }
+                outgoing.acked = true;
+                self.sync = false;
+                Handled {
+                    event: Event::Acked { seq: header.seq },
+                    ack: None,
+                }
+            }
+            Kind::Data if !header.has_flag(FLAG_ACK_REQUESTED) => Handled {
+                event: Event::Received {
+                    seq: header.seq,
+                    reliable: false,
+                    fragment: header.has_flag(FLAG_FRAGMENT),
+                    payload,
+                },
+                ack: None,
+            },
+            Kind::Data => {
+                let ack = Header {
+                    kind: Kind::Ack,
+                    seq: header.seq,
+                    flags: 0,
+                };
+                let event = if self.accept(src, header.seq, header.has_flag(FLAG_SYNC)) {
+                    Event::Received {
+                        seq: header.seq,
+                        reliable: true,
+                        fragment: header.has_flag(FLAG_FRAGMENT),
+                        payload,
+                    }
+                } else {
+                    Event::Duplicate { seq: header.seq }
+                };
+                Handled {
+                    event,
+                    ack: Some(ack.encode()),
+                }
+            }
+        }
+    }
+
+    /// Records `seq` as seen from `src`, returning false if it is a
+    /// duplicate.
+    fn accept(&mut self, src: Mac, seq: u16, sync: bool) -> bool {
+        let Some(i) = self.seen.iter().position(|s| s.src == src) else {
+            if self.seen.is_full() {
+                self.seen.remove(0);
+            }
+            let _ = self.seen.push(Seen { src, seq });
+            return true;
+        };
+        let mut seen = self.seen.remove(i);
+        // After a reboot the sender starts over at a random sequence number,
+        // which may well look older than what we have. Only the last few are
+        // taken to be stale retransmissions.
+        let new = if sync {
+            seen.seq.wrapping_sub(seq) >= SYNC_WINDOW
+        } else {
+            is_newer(seq, seen.seq)
+        };
+        if new {
+            seen.seq = seq;
+        }
+        let _ = self.seen.push(seen);
+        new
+    }
+}
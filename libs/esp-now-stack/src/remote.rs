@@ -0,0 +1,159 @@
+//! Commands from a remote to an actuator, and the state it replies with.
+//!
+//! A remote sends a command when one of its buttons is pressed: toggle the
+//! output, set its level, or set its color. The actuator applies it to its
+//! [`State`] and answers with the whole of it, so the remote knows where
+//! things stand even when some of its commands went to somebody else. The
+//! actuator also broadcasts its state when it starts, which is how remotes
+//! find it.
+//!
+//! ```text
+//! Toggle  magic | 0
+//! Level   magic | 1 | level
+//! Color   magic | 2 | r | g | b
+//! State   magic | 3 | on | level | r | g | b
+//! ```
+//!
+//! Everything is a byte. Levels are in percent, and `on` is 0 or 1.
+
+const MAGIC: &[u8; 4] = b"CTRL";
+const KIND_TOGGLE: u8 = 0;
+const KIND_LEVEL: u8 = 1;
+const KIND_COLOR: u8 = 2;
+const KIND_STATE: u8 = 3;
+
+const HEADER_LEN: usize = MAGIC.len() + 1;
+pub const MAX_MESSAGE_LEN: usize = HEADER_LEN + 5;
+
+/// The brightest level, in percent.
+pub const MAX_LEVEL: u8 = 100;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const WHITE: Rgb = Rgb::new(255, 255, 255);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// Dims each channel to `level` percent.
+    pub fn scaled(self, level: u8) -> Self {
+        let scale = |c: u8| (c as u16 * level.min(MAX_LEVEL) as u16 / MAX_LEVEL as u16) as u8;
+        Self::new(scale(self.r), scale(self.g), scale(self.b))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Toggle,
+    /// Turns the output on at `0 < level <= MAX_LEVEL` percent, or off at 0,
+    /// in which case the level it had is kept for the next toggle.
+    Level(u8),
+    /// Turns the output on in this color. An output that is a plain pin
+    /// keeps it, but only cares about being on.
+    Color(Rgb),
+}
+
+/// What the actuator puts out.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct State {
+    pub on: bool,
+    /// In percent, never 0.
+    pub level: u8,
+    pub color: Rgb,
+}
+
+impl Default for State {
+    /// Off, but at full brightness in white once toggled on.
+    fn default() -> Self {
+        Self {
+            on: false,
+            level: MAX_LEVEL,
+            color: Rgb::WHITE,
+        }
+    }
+}
+
+impl State {
+    pub fn apply(&mut self, command: Command) {
+        match command {
+            Command::Toggle => self.on = !self.on,
+            Command::Level(0) => self.on = false,
+            Command::Level(level) => {
+                self.on = true;
+                self.level = level.min(MAX_LEVEL);
+            }
+            Command::Color(color) => {
+                self.on = true;
+                self.color = color;
+            }
+        }
+    }
+
+    /// How bright the output is, in percent.
+    pub fn brightness(&self) -> u8 {
+        if self.on { self.level } else { 0 }
+    }
+
+    /// The color to show, dimmed to the level.
+    pub fn pixel(&self) -> Rgb {
+        self.color.scaled(self.brightness())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    Command(Command),
+    State(State),
+}
+
+impl Message {
+    pub fn encode(&self) -> heapless::Vec<u8, MAX_MESSAGE_LEN> {
+        let mut out = heapless::Vec::new();
+        out.extend_from_slice(MAGIC).unwrap();
+        let (kind, body): (u8, &[u8]) = match self {
+            Message::Command(Command::Toggle) => (KIND_TOGGLE, &[]),
+            Message::Command(Command::Level(level)) => (KIND_LEVEL, &[*level]),
+            Message::Command(Command::Color(c)) => (KIND_COLOR, &[c.r, c.g, c.b]),
+            Message::State(s) => (
+                KIND_STATE,
+                &[s.on as u8, s.level, s.color.r, s.color.g, s.color.b],
+            ),
+        };
+        out.push(kind).unwrap();
+        out.extend_from_slice(body).unwrap();
+        out
+    }
+
+    pub fn decode(payload: &[u8]) -> Option<Self> {
+        if !is_remote_message(payload) || payload.len() < HEADER_LEN {
+            return None;
+        }
+        let body = &payload[HEADER_LEN..];
+        let message = match (payload[4], body) {
+            (KIND_TOGGLE, []) => Message::Command(Command::Toggle),
+            (KIND_LEVEL, &[level]) if level <= MAX_LEVEL => Message::Command(Command::Level(level)),
+            (KIND_COLOR, &[r, g, b]) => Message::Command(Command::Color(Rgb::new(r, g, b))),
+            (KIND_STATE, &[on @ (0 | 1), level, r, g, b]) if (1..=MAX_LEVEL).contains(&level) => {
+                Message::State(State {
+                    on: on == 1,
+                    level,
+                    color: Rgb::new(r, g, b),
+                })
+            }
+            _ => return None,
+        };
+        Some(message)
+    }
+}
+
+/// Whether a payload is a remote control message rather than anything else.
+pub fn is_remote_message(payload: &[u8]) -> bool {
+    payload.starts_with(MAGIC)
+}
@@ -0,0 +1,371 @@
+//! A common clock for all nodes in range.
+//!
+//! One node is the leader, and broadcasts a beacon with its time now and
+//! then. The others note when each beacon arrived by their own clock, and
+//! fit a line through the differences: where it is now is the offset
+//! between the clocks, and its slope how fast they drift apart. That way
+//! they keep in step between beacons, and for a while after the leader is
+//! gone.
+//!
+//! The node with the lowest MAC address leads. A node that hears nobody
+//! lower than itself for a while takes over, using the time it was synced
+//! to, so the common clock carries on rather than jumping to its own.
+//!
+//! ```text
+//! 0       4      5
+//! +-------+------+---------
+//! | magic | kind | time_us
+//! +-------+------+---------
+//! ```
+//!
+//! The time is little endian, in microseconds. Beacons are stamped when they
+//! are handed to the radio and when they are received, so the time it takes
+//! to get from one to the other is not accounted for. It's about the same
+//! for every beacon, which puts every follower behind by about the same few
+//! hundred microseconds.
+
+use crate::Mac;
+
+const MAGIC: &[u8; 4] = b"TIME";
+const KIND_BEACON: u8 = 0;
+
+pub const BEACON_LEN: usize = MAGIC.len() + 1 + 8;
+
+/// Samples are only checked against the line once there are this many.
+const MIN_SAMPLES_TO_REJECT: usize = 3;
+/// This many outliers in a row means the leader's clock jumped, rather than
+/// its beacons being late.
+const MAX_OUTLIERS_IN_A_ROW: u8 = 3;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub beacon_interval_ms: u64,
+    /// Without a beacon from the leader for this long, we take over. It
+    /// should be a few beacon intervals.
+    pub leader_timeout_ms: u64,
+    /// Beacons that are further than this off the line are taken to have
+    /// been held up on the way, and are ignored.
+    pub max_residual_us: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            beacon_interval_ms: 1000,
+            leader_timeout_ms: 3500,
+            max_residual_us: 2000,
+        }
+    }
+}
+
+pub fn encode_beacon(time_us: u64) -> [u8; BEACON_LEN] {
+    let mut out = [0; BEACON_LEN];
+    out[..4].copy_from_slice(MAGIC);
+    out[4] = KIND_BEACON;
+    out[5..].copy_from_slice(&time_us.to_le_bytes());
+    out
+}
+
+/// Returns the time in a beacon.
+pub fn decode_beacon(frame: &[u8]) -> Option<u64> {
+    if frame.len() != BEACON_LEN || !is_timesync_message(frame) || frame[4] != KIND_BEACON {
+        return None;
+    }
+    Some(u64::from_le_bytes(frame[5..].try_into().unwrap()))
+}
+
+/// Whether a payload is a time sync message rather than anything else.
+pub fn is_timesync_message(payload: &[u8]) -> bool {
+    payload.starts_with(MAGIC)
+}
+
+/// `f64::round` needs `std`.
+fn round(x: f64) -> i64 {
+    if x < 0.0 {
+        (x - 0.5) as i64
+    } else {
+        (x + 0.5) as i64
+    }
+}
+
+/// The line fitted through the samples: at our time `at_us`, the other
+/// clock is `offset_us` ahead, and it gains `drift` microseconds on ours
+/// every microsecond.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Model {
+    pub at_us: u64,
+    pub offset_us: i64,
+    pub drift: f64,
+}
+
+impl Model {
+    pub fn offset_at(&self, local_us: u64) -> i64 {
+        let elapsed = local_us as i64 - self.at_us as i64;
+        self.offset_us + round(self.drift * elapsed as f64)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Sample {
+    local_us: u64,
+    offset_us: i64,
+}
+
+/// How well the line fits, see [`Estimator::stats`].
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub samples: usize,
+    /// How far the furthest sample is off the line.
+    pub max_residual_us: u64,
+    pub drift_ppm: f64,
+    /// Samples ignored since the last reset.
+    pub outliers: u32,
+}
+
+/// Fits a line through the last `N` (local time, offset) samples, by least
+/// squares.
+pub struct Estimator<const N: usize> {
+    samples: heapless::Deque<Sample, N>,
+    model: Option<Model>,
+    max_residual_us: u64,
+    outliers_in_a_row: u8,
+    outliers: u32,
+}
+
+impl<const N: usize> Default for Estimator<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Estimator<N> {
+    pub const fn new() -> Self {
+        Self {
+            samples: heapless::Deque::new(),
+            model: None,
+            max_residual_us: 0,
+            outliers_in_a_row: 0,
+            outliers: 0,
+        }
+    }
+
+    pub fn model(&self) -> Option<&Model> {
+        self.model.as_ref()
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            samples: self.samples.len(),
+            max_residual_us: self.max_residual_us,
+            drift_ppm: self.model.map_or(0.0, |m| m.drift * 1e6),
+            outliers: self.outliers,
+        }
+    }
+
+    /// Forgets everything, for when the other clock is a different one.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Adds a sample: the other clock read `remote_us` when ours read
+    /// `local_us`. Samples too far off the line are left out, and the
+    /// residual is returned instead.
+    pub fn add(&mut self, local_us: u64, remote_us: u64, max_residual_us: u64) -> Result<(), i64> {
+        let offset_us = remote_us as i64 - local_us as i64;
+        let model = self
+            .model
+            .filter(|_| self.samples.len() >= MIN_SAMPLES_TO_REJECT);
+        if let Some(model) = model {
+            let residual = offset_us - model.offset_at(local_us);
+            if residual.unsigned_abs() > max_residual_us {
+                self.outliers += 1;
+                self.outliers_in_a_row += 1;
+                if self.outliers_in_a_row < MAX_OUTLIERS_IN_A_ROW {
+                    return Err(residual);
+                }
+                self.samples.clear();
+            }
+        }
+        self.outliers_in_a_row = 0;
+        if self.samples.is_full() {
+            self.samples.pop_front();
+        }
+        let _ = self.samples.push_back(Sample {
+            local_us,
+            offset_us,
+        });
+        self.fit();
+        Ok(())
+    }
+
+    fn fit(&mut self) {
+        let Some(last) = self.samples.back().copied() else {
+            self.model = None;
+            return;
+        };
+        // Relative to the last sample, to keep the sums small
+        let n = self.samples.len() as f64;
+        let points = self.samples.iter().map(|s| {
+            (
+                s.local_us as f64 - last.local_us as f64,
+                (s.offset_us - last.offset_us) as f64,
+            )
+        });
+        let (sum_t, sum_o) = points
+            .clone()
+            .fold((0.0, 0.0), |(t, o), p| (t + p.0, o + p.1));
+        let (mean_t, mean_o) = (sum_t / n, sum_o / n);
+        let (sxx, sxy) = points.fold((0.0, 0.0), |(xx, xy), (t, o)| {
+            (
+                xx + (t - mean_t) * (t - mean_t),
+                xy + (t - mean_t) * (o - mean_o),
+            )
+        });
+        let drift = if sxx > 0.0 { sxy / sxx } else { 0.0 };
+        let model = Model {
+            at_us: last.local_us,
+            offset_us: last.offset_us + round(mean_o - drift * mean_t),
+            drift,
+        };
+        self.max_residual_us = self
+            .samples
+            .iter()
+            .map(|s| (s.offset_us - model.offset_at(s.local_us)).unsigned_abs())
+            .max()
+            .unwrap_or(0);
+        self.model = Some(model);
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Role {
+    /// Waiting to hear from a leader.
+    Listening,
+    Follower {
+        leader: Mac,
+    },
+    Leader,
+}
+
+/// What a received frame turned out to be, see [`TimeSync::handle`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A beacon from our leader, which now is `offset_us` ahead of us.
+    Synced { offset_us: i64 },
+    /// A beacon from our leader that was too far off, see
+    /// [`Config::max_residual_us`].
+    Outlier { residual_us: i64 },
+    /// A beacon from somebody that isn't our leader.
+    NotOurLeader,
+    /// Not a time sync frame.
+    Ignored,
+}
+
+/// Keeps one node's clock in step with the leader's.
+///
+/// All times given to it are our own clock in microseconds, such as
+/// `Instant::now().as_micros()`. `N` is the number of beacons the line is
+/// fitted through.
+pub struct TimeSync<const N: usize> {
+    own: Mac,
+    config: Config,
+    role: Role,
+    /// When we started listening, or last heard from the leader.
+    heard_us: u64,
+    next_beacon_us: u64,
+    estimator: Estimator<N>,
+}
+
+impl<const N: usize> TimeSync<N> {
+    pub fn new(own: Mac, config: Config, now_us: u64) -> Self {
+        Self {
+            own,
+            config,
+            role: Role::Listening,
+            heard_us: now_us,
+            next_beacon_us: 0,
+            estimator: Estimator::new(),
+        }
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    pub fn estimator(&self) -> &Estimator<N> {
+        &self.estimator
+    }
+
+    /// Whether [`TimeSync::now`] is the common clock yet.
+    pub fn is_synced(&self) -> bool {
+        self.role == Role::Leader || self.estimator.model().is_some()
+    }
+
+    /// The common clock, given ours. Until we are synced, that's our own.
+    pub fn now(&self, local_us: u64) -> u64 {
+        let offset = self.estimator.model().map_or(0, |m| m.offset_at(local_us));
+        (local_us as i64 + offset) as u64
+    }
+
+    fn lead(&mut self, now_us: u64) {
+        self.role = Role::Leader;
+        self.next_beacon_us = now_us;
+    }
+
+    /// When `poll` next has something to do.
+    pub fn next_deadline(&self) -> u64 {
+        let timeout = self.config.leader_timeout_ms * 1000;
+        match self.role {
+            Role::Leader => self.next_beacon_us,
+            Role::Listening | Role::Follower { .. } => self.heard_us + timeout,
+        }
+    }
+
+    /// Takes over when the leader has gone quiet, and returns a beacon to
+    /// broadcast when it's time to.
+    pub fn poll(&mut self, now_us: u64) -> Option<[u8; BEACON_LEN]> {
+        if self.role != Role::Leader && now_us >= self.next_deadline() {
+            self.lead(now_us);
+        }
+        if self.role != Role::Leader || now_us < self.next_beacon_us {
+            return None;
+        }
+        self.next_beacon_us = now_us + self.config.beacon_interval_ms * 1000;
+        Some(encode_beacon(self.now(now_us)))
+    }
+
+    /// Handles a frame from `src` received at `now_us`.
+    pub fn handle(&mut self, now_us: u64, src: Mac, frame: &[u8]) -> Event {
+        let Some(remote_us) = decode_beacon(frame) else {
+            return Event::Ignored;
+        };
+        let follow = match self.role {
+            Role::Listening => true,
+            Role::Follower { leader } => src <= leader,
+            Role::Leader => src < self.own,
+        };
+        if !follow {
+            return Event::NotOurLeader;
+        }
+        if self.role != (Role::Follower { leader: src }) {
+            self.role = Role::Follower { leader: src };
+            self.estimator.reset();
+        }
+        self.heard_us = now_us;
+
+        let event = match self
+            .estimator
+            .add(now_us, remote_us, self.config.max_residual_us)
+        {
+            Ok(()) => Event::Synced {
+                offset_us: self.estimator.model().unwrap().offset_at(now_us),
+            },
+            Err(residual_us) => Event::Outlier { residual_us },
+        };
+        // We should be leading, now we know what time it is
+        if self.own < src {
+            self.lead(now_us);
+        }
+        event
+    }
+}
@@ -0,0 +1,273 @@
+//! Drives [`reliable::Endpoint`](crate::reliable::Endpoint) over `esp-wifi`'s
+//! ESP-NOW driver.
+
+use core::cell::{Cell, RefCell};
+
+use embassy_futures::select::{Either, select};
+use embassy_sync::{
+    blocking_mutex::{NoopMutex, raw::NoopRawMutex},
+    mutex::Mutex,
+    signal::Signal,
+};
+use embassy_time::{Instant, Timer};
+use esp_wifi::esp_now::{BROADCAST_ADDRESS, EspNowReceiver, EspNowSender, ReceivedData};
+
+use crate::{
+    Mac,
+    fragment::{self, Incomplete, Outcome, Reassembler},
+    header::{MAX_FRAME_LEN, MAX_PAYLOAD_LEN},
+    reliable::{Config, Endpoint, Event, Poll, SendError},
+};
+
+/// The number of peers we filter duplicates for.
+pub const PEERS: usize = 16;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TransportError {
+    /// The payload does not fit in a single frame, or the message is too
+    /// large to be fragmented.
+    TooLong,
+    /// The peer never acknowledged the message.
+    NotAcknowledged { attempts: u8 },
+}
+
+/// A message received by [`Transport::receive`].
+#[derive(Clone, Debug)]
+pub struct Message {
+    pub src: Mac,
+    pub dst: Mac,
+    pub rssi: i8,
+    /// False for broadcasts and other messages sent with
+    /// [`Transport::send_unreliable`].
+    pub reliable: bool,
+    /// Set for fragments of larger messages, which
+    /// [`Transport::receive_reassembled`] puts back together.
+    pub fragment: bool,
+    pub payload: heapless::Vec<u8, MAX_PAYLOAD_LEN>,
+}
+
+/// Something received by [`Transport::receive_reassembled`].
+#[derive(Debug)]
+pub enum Received<'r> {
+    /// A message that fit in a single frame.
+    Message(Message),
+    /// A message that was sent with [`Transport::send_large`].
+    Reassembled { src: Mac, data: &'r [u8] },
+    /// A message sent with `send_large` that we had to give up on.
+    Incomplete(Incomplete),
+}
+
+/// Reliable unicast over ESP-NOW.
+///
+/// Acknowledgements are picked up by [`Transport::receive`], so that has to
+/// be running while [`Transport::send`] is waiting for one. Messages are
+/// sent one at a time, concurrent calls to `send` wait their turn.
+///
+/// Peers still have to be added with the `EspNowManager`, both to send to
+/// them and to acknowledge what they send us.
+pub struct Transport<'d> {
+    endpoint: NoopMutex<RefCell<Endpoint<PEERS>>>,
+    sender: Mutex<NoopRawMutex, EspNowSender<'d>>,
+    /// Held for the whole time a reliable message is in flight.
+    in_flight: Mutex<NoopRawMutex, ()>,
+    acked: Signal<NoopRawMutex, ()>,
+    next_msg_id: Cell<u16>,
+}
+
+fn now_ms() -> u64 {
+    Instant::now().as_millis()
+}
+
+impl<'d> Transport<'d> {
+    /// `first_seq` should be random, see [`Endpoint::new`].
+    pub fn new(sender: EspNowSender<'d>, config: Config, first_seq: u16) -> Self {
+        Self {
+            endpoint: NoopMutex::new(RefCell::new(Endpoint::new(config, first_seq))),
+            sender: Mutex::new(sender),
+            in_flight: Mutex::new(()),
+            acked: Signal::new(),
+            next_msg_id: Cell::new(first_seq),
+        }
+    }
+
+    async fn transmit(&self, dst: &Mac, frame: &[u8]) {
+        // A failed send is no different from a lost frame, which is retried
+        // when the acknowledgement doesn't show up.
+        let _ = self.sender.lock().await.send_async(dst, frame).await;
+    }
+
+    /// Sends `payload` to `dst` and waits for it to be acknowledged. Returns
+    /// how many times it had to be sent.
+    pub async fn send(&self, dst: &Mac, payload: &[u8]) -> Result<u8, TransportError> {
+        let _in_flight = self.in_flight.lock().await;
+        self.send_in_flight(dst, payload, false).await
+    }
+
+    /// Sends a message of any size up to [`fragment::MAX_MESSAGE_LEN`] to
+    /// `dst`, fragmenting it as needed. Each fragment is sent like with
+    /// `send`, and we give up on the first one that isn't acknowledged.
+    /// Returns the number of fragments.
+    pub async fn send_large(&self, dst: &Mac, data: &[u8]) -> Result<u8, TransportError> {
+        let msg_id = self.next_msg_id.get();
+        self.next_msg_id.set(msg_id.wrapping_add(1));
+        let fragments = fragment::split(msg_id, data).map_err(|_| TransportError::TooLong)?;
+        let count = fragments.count();
+
+        let _in_flight = self.in_flight.lock().await;
+        for fragment in fragments {
+            self.send_in_flight(dst, &fragment, true).await?;
+        }
+        Ok(count)
+    }
+
+    /// Sends a single frame. The caller must hold `in_flight`.
+    async fn send_in_flight(
+        &self,
+        dst: &Mac,
+        payload: &[u8],
+        fragment: bool,
+    ) -> Result<u8, TransportError> {
+        let mut frame = [0; MAX_FRAME_LEN];
+        let sent = self.endpoint.lock(|e| {
+            let mut e = e.borrow_mut();
+            if fragment {
+                e.send_fragment(now_ms(), *dst, payload, &mut frame)
+            } else {
+                e.send(now_ms(), *dst, payload, &mut frame)
+            }
+        });
+        let len = match sent {
+            Ok((_, len)) => len,
+            Err(SendError::TooLong) => return Err(TransportError::TooLong),
+            Err(SendError::Busy) => unreachable!("only one message is in flight"),
+        };
+        self.acked.reset();
+        self.transmit(dst, &frame[..len]).await;
+
+        loop {
+            let poll = self
+                .endpoint
+                .lock(|e| e.borrow_mut().poll(now_ms(), &mut frame));
+            match poll {
+                Poll::Wait { until_ms } => {
+                    let timeout = Timer::at(Instant::from_millis(until_ms));
+                    select(self.acked.wait(), timeout).await;
+                }
+                Poll::Retransmit { dst, len } => self.transmit(&dst, &frame[..len]).await,
+                Poll::Delivered { attempts, .. } => return Ok(attempts),
+                Poll::Failed { attempts, .. } => {
+                    return Err(TransportError::NotAcknowledged { attempts });
+                }
+                Poll::Idle => unreachable!("a message is in flight"),
+            }
+        }
+    }
+
+    /// Sends `payload` once, without waiting for an acknowledgement.
+    pub async fn send_unreliable(&self, dst: &Mac, payload: &[u8]) -> Result<(), TransportError> {
+        let mut frame = [0; MAX_FRAME_LEN];
+        let len = self
+            .endpoint
+            .lock(|e| e.borrow_mut().send_unreliable(payload, &mut frame))
+            .map_err(|_| TransportError::TooLong)?;
+        self.transmit(dst, &frame[..len]).await;
+        Ok(())
+    }
+
+    pub async fn broadcast(&self, payload: &[u8]) -> Result<(), TransportError> {
+        self.send_unreliable(&BROADCAST_ADDRESS, payload).await
+    }
+
+    /// Waits for the next new message. Acknowledgements are sent and
+    /// duplicates dropped along the way.
+    pub async fn receive(&self, receiver: &mut EspNowReceiver<'_>) -> Message {
+        loop {
+            let received = receiver.receive_async().await;
+            if let Some(message) = self.handle(&received).await {
+                return message;
+            }
+        }
+    }
+
+    /// Handles a received frame, sending the acknowledgement for it, and
+    /// returns the message in it if it is a new one. Once the endpoint has
+    /// seen the frame, a retransmission of it counts as a duplicate, so this
+    /// must run to the end rather than be cancelled.
+    async fn handle(&self, received: &ReceivedData) -> Option<Message> {
+        let src = received.info.src_address;
+        let handled = self
+            .endpoint
+            .lock(|e| e.borrow_mut().handle(src, received.data()));
+
+        if let Some(ack) = handled.ack {
+            self.transmit(&src, &ack).await;
+        }
+        match handled.event {
+            Event::Received {
+                reliable,
+                fragment,
+                payload,
+                ..
+            } => Some(Message {
+                src,
+                dst: received.info.dst_address,
+                rssi: received.info.rx_control.rssi as i8,
+                reliable,
+                fragment,
+                payload: heapless::Vec::from_slice(payload).ok()?,
+            }),
+            Event::Acked { .. } => {
+                self.acked.signal(());
+                None
+            }
+            Event::Duplicate { .. } | Event::Ignored => None,
+        }
+    }
+
+    /// Like `receive`, but puts fragmented messages back together. Messages
+    /// that are only partially received are reported once they time out, or
+    /// have to make room for others.
+    pub async fn receive_reassembled<'r, const SOURCES: usize, const MAX_LEN: usize>(
+        &self,
+        receiver: &mut EspNowReceiver<'_>,
+        reassembler: &'r mut Reassembler<SOURCES, MAX_LEN>,
+    ) -> Received<'r> {
+        loop {
+            reassembler.expire(now_ms());
+            if let Some(incomplete) = reassembler.take_incomplete() {
+                return Received::Incomplete(incomplete);
+            }
+
+            // Only the wait for a frame may time out, see `handle`
+            let received = match reassembler.next_deadline() {
+                Some(deadline) => {
+                    let timeout = Timer::at(Instant::from_millis(deadline));
+                    match select(receiver.receive_async(), timeout).await {
+                        Either::First(received) => received,
+                        Either::Second(()) => continue,
+                    }
+                }
+                None => receiver.receive_async().await,
+            };
+            let Some(message) = self.handle(&received).await else {
+                continue;
+            };
+            if !message.fragment {
+                return Received::Message(message);
+            }
+
+            // Getting the data out of the reassembler ties it to the lifetime
+            // of `reassembler`, which the borrow checker won't let us do in
+            // a loop. Instead we go back in for it once it is complete.
+            let src = message.src;
+            match reassembler.handle(now_ms(), src, &message.payload) {
+                Outcome::Complete { .. } => (),
+                Outcome::Pending | Outcome::Duplicate | Outcome::Rejected(_) => continue,
+            }
+            return Received::Reassembled {
+                src,
+                data: reassembler.last_complete(src).unwrap(),
+            };
+        }
+    }
+}
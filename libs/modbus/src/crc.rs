@@ -0,0 +1,18 @@
+//! The CRC at the end of every RTU frame.
+
+/// CRC-16/MODBUS: polynomial 0x8005 reflected, starting from all ones. It
+/// goes on the wire low byte first, unlike everything else in Modbus.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xffff;
+    for &b in data {
+        crc ^= b as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xa001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
@@ -0,0 +1,185 @@
+//! A Modbus RTU bus on the async UART of `esp_hal`, through an RS-485
+//! transceiver.
+
+use embassy_time::{Duration, Instant, Timer, with_deadline};
+use embedded_io_async::Write;
+use esp_hal::{
+    Async,
+    gpio::Output,
+    uart::{RxConfig, Uart},
+};
+
+use crate::{
+    pdu::{MAX_PDU_LEN, Request, Response, ResponseError},
+    rtu::{self, BROADCAST, FrameError, Framer, MAX_FRAME_LEN, Timing},
+    slave::{self, DataModel},
+};
+
+/// How long to leave devices to act on a broadcast, as they don't answer.
+const BROADCAST_TURNAROUND: Duration = Duration::from_millis(100);
+
+/// The `RxConfig` for the UART of an [`Rtu`]. The gaps in between frames
+/// are timed from when the UART hands over what it received, so it has to
+/// hand over every byte as it comes in, rather than wait for its FIFO to
+/// fill up or for the line to go quiet.
+pub fn rx_config() -> RxConfig {
+    RxConfig::default().with_fifo_full_threshold(1)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// No answer in time.
+    Timeout,
+    Frame(FrameError),
+    Response(ResponseError),
+    /// The UART failed to send.
+    Uart,
+}
+
+/// What [`Rtu::serve`] did with a frame.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Served {
+    Answered {
+        exception: bool,
+    },
+    /// Acted on a broadcast, without answering.
+    Broadcast,
+    /// The frame was for another device.
+    OtherDevice,
+}
+
+pub struct Rtu {
+    uart: Uart<'static, Async>,
+    /// Drives DE and /RE of the transceiver, which are tied together: high
+    /// to send, low to receive. Without one, the UART is taken to be on a
+    /// bus that doesn't need switching.
+    de: Option<Output<'static>>,
+    framer: Framer,
+}
+
+impl Rtu {
+    /// Takes over a UART set up with [`rx_config`].
+    pub fn new(uart: Uart<'static, Async>, de: Option<Output<'static>>, timing: Timing) -> Self {
+        let mut rtu = Self {
+            uart,
+            de,
+            framer: Framer::new(timing),
+        };
+        rtu.listen();
+        rtu
+    }
+
+    fn listen(&mut self) {
+        if let Some(de) = &mut self.de {
+            de.set_low();
+        }
+    }
+
+    /// Sends a frame, driving the transceiver for as long as it takes.
+    pub async fn send(&mut self, address: u8, pdu: &[u8]) -> Result<(), Error> {
+        let mut frame = [0; MAX_FRAME_LEN];
+        let len = rtu::encode(address, pdu, &mut frame);
+        if let Some(de) = &mut self.de {
+            de.set_high();
+        }
+        // Waits for the last stop bit to go out, not just for the FIFO to
+        // take the bytes, so that the transceiver isn't switched too early
+        let sent = match self.uart.write_all(&frame[..len]).await {
+            Ok(()) => self.uart.flush_async().await.map_err(|_| Error::Uart),
+            Err(_) => Err(Error::Uart),
+        };
+        self.listen();
+        // Whatever came in while sending is our own echo, or a collision
+        self.framer.reset();
+        sent
+    }
+
+    /// Waits for a frame to end, until `deadline` if it doesn't start
+    /// before. It is left in the framer.
+    async fn receive(&mut self, deadline: Option<Instant>) -> Result<(), Error> {
+        let mut buf = [0; 64];
+        loop {
+            let until = match (self.framer.deadline(), deadline) {
+                (Some(end_us), _) => Instant::from_micros(end_us),
+                (None, Some(deadline)) => deadline,
+                (None, None) => Instant::MAX,
+            };
+            let read = with_deadline(until, self.uart.read_async(&mut buf)).await;
+            let now_us = Instant::now().as_micros();
+            match read {
+                Ok(Ok(n)) => self.framer.push_chunk(now_us, &buf[..n]),
+                Ok(Err(_)) => self.framer.interrupt(now_us),
+                Err(_) => {
+                    return match self.framer.poll(now_us) {
+                        Some(result) => result.map_err(Error::Frame),
+                        None => Err(Error::Timeout),
+                    };
+                }
+            }
+        }
+    }
+
+    /// Sends a request PDU to `address`, and waits up to `timeout` for the
+    /// response PDU. Anything from other devices in the meantime is
+    /// ignored. A broadcast has no response.
+    pub async fn transact(
+        &mut self,
+        address: u8,
+        pdu: &[u8],
+        timeout: Duration,
+    ) -> Result<&[u8], Error> {
+        self.send(address, pdu).await?;
+        if address == BROADCAST {
+            Timer::after(BROADCAST_TURNAROUND).await;
+            return Ok(&[]);
+        }
+        let deadline = Instant::now() + timeout;
+        loop {
+            self.receive(Some(deadline)).await?;
+            match rtu::decode(self.framer.frame()) {
+                Ok((from, _)) if from == address => break,
+                Ok(_) => (),
+                Err(e) => return Err(Error::Frame(e)),
+            }
+        }
+        let (_, response) = rtu::decode(self.framer.frame()).map_err(Error::Frame)?;
+        Ok(response)
+    }
+
+    /// Sends a request to `address`, and parses the response.
+    pub async fn request(
+        &mut self,
+        address: u8,
+        request: &Request<'_>,
+        timeout: Duration,
+    ) -> Result<Response<'_>, Error> {
+        let mut pdu = [0; MAX_PDU_LEN];
+        let len = request.encode(&mut pdu);
+        let response = self.transact(address, &pdu[..len], timeout).await?;
+        if address == BROADCAST {
+            return Ok(Response::Written);
+        }
+        Response::decode(request, response).map_err(Error::Response)
+    }
+
+    /// Waits for the next frame, and answers it from `model` if it is for
+    /// `address`.
+    pub async fn serve(
+        &mut self,
+        address: u8,
+        model: &mut impl DataModel,
+    ) -> Result<Served, Error> {
+        self.receive(None).await?;
+        let (to, pdu) = rtu::decode(self.framer.frame()).map_err(Error::Frame)?;
+        if to != address && to != BROADCAST {
+            return Ok(Served::OtherDevice);
+        }
+        let mut response = [0; MAX_PDU_LEN];
+        let (len, exception) = slave::handle(model, pdu, &mut response);
+        if to == BROADCAST {
+            return Ok(Served::Broadcast);
+        }
+        self.send(address, &response[..len]).await?;
+        Ok(Served::Answered { exception })
+    }
+}
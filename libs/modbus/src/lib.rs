@@ -0,0 +1,23 @@
+//! Modbus, for the ROMs that sit on an RS-485 bus, and for the host tools
+//! that check them.
+//!
+//! [`pdu`] encodes and decodes requests and responses, [`rtu`] frames them
+//! for a serial line, with the CRC and the timing between frames, and
+//! [`slave`] answers requests from a [`slave::DataModel`]. [`tcp`] puts
+//! them behind the MBAP header of Modbus TCP instead, and routes them from
+//! there to a bus as a gateway. None of that touches the hardware, so it
+//! runs on the host as well, see `tools/modbus-sim`. The `esp-hal` feature
+//! adds [`Rtu`], which runs a bus as the master or as a slave with the
+//! async UART.
+#![no_std]
+
+pub mod crc;
+#[cfg(feature = "esp-hal")]
+mod hal;
+pub mod pdu;
+pub mod rtu;
+pub mod slave;
+pub mod tcp;
+
+#[cfg(feature = "esp-hal")]
+pub use hal::{Error, Rtu, Served, rx_config};
@@ -0,0 +1,370 @@
+//! Protocol data units: a function code and what goes with it, the part of
+//! a Modbus message that is the same over RTU and TCP. Addresses, counts and
+//! registers are big endian.
+//!
+//! Only the functions for the four data tables are supported: coils,
+//! discrete inputs, holding registers and input registers.
+
+/// A PDU has to fit in an RTU frame along with the address and the CRC.
+pub const MAX_PDU_LEN: usize = 253;
+
+pub const READ_COILS: u8 = 0x01;
+pub const READ_DISCRETE_INPUTS: u8 = 0x02;
+pub const READ_HOLDING_REGISTERS: u8 = 0x03;
+pub const READ_INPUT_REGISTERS: u8 = 0x04;
+pub const WRITE_SINGLE_COIL: u8 = 0x05;
+pub const WRITE_SINGLE_REGISTER: u8 = 0x06;
+pub const WRITE_MULTIPLE_COILS: u8 = 0x0f;
+pub const WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+
+/// Set in the function code of a response that is an exception.
+pub const EXCEPTION_FLAG: u8 = 0x80;
+
+/// The most that can be read or written in one request, so that it fits in
+/// a PDU.
+pub const MAX_READ_BITS: u16 = 2000;
+pub const MAX_READ_REGISTERS: u16 = 125;
+pub const MAX_WRITE_BITS: u16 = 1968;
+pub const MAX_WRITE_REGISTERS: u16 = 123;
+
+const COIL_ON: u16 = 0xff00;
+const COIL_OFF: u16 = 0x0000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Exception {
+    IllegalFunction,
+    IllegalDataAddress,
+    IllegalDataValue,
+    ServerDeviceFailure,
+    Acknowledge,
+    ServerDeviceBusy,
+    /// From a gateway that can't reach the bus the device is on.
+    GatewayPathUnavailable,
+    /// From a gateway, when the device didn't answer.
+    GatewayTargetFailedToRespond,
+    Other(u8),
+}
+
+impl Exception {
+    pub fn code(self) -> u8 {
+        match self {
+            Exception::IllegalFunction => 0x01,
+            Exception::IllegalDataAddress => 0x02,
+            Exception::IllegalDataValue => 0x03,
+            Exception::ServerDeviceFailure => 0x04,
+            Exception::Acknowledge => 0x05,
+            Exception::ServerDeviceBusy => 0x06,
+            Exception::GatewayPathUnavailable => 0x0a,
+            Exception::GatewayTargetFailedToRespond => 0x0b,
+            Exception::Other(code) => code,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            0x01 => Exception::IllegalFunction,
+            0x02 => Exception::IllegalDataAddress,
+            0x03 => Exception::IllegalDataValue,
+            0x04 => Exception::ServerDeviceFailure,
+            0x05 => Exception::Acknowledge,
+            0x06 => Exception::ServerDeviceBusy,
+            0x0a => Exception::GatewayPathUnavailable,
+            0x0b => Exception::GatewayTargetFailedToRespond,
+            code => Exception::Other(code),
+        }
+    }
+}
+
+/// Writes the exception response to a request with `function` to `out`,
+/// and returns its length.
+pub fn encode_exception(function: u8, exception: Exception, out: &mut [u8]) -> usize {
+    out[0] = function | EXCEPTION_FLAG;
+    out[1] = exception.code();
+    2
+}
+
+/// Packed bits, the first one in the lowest bit of the first byte.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Bits<'a> {
+    bytes: &'a [u8],
+    count: u16,
+}
+
+impl<'a> Bits<'a> {
+    /// `None` unless `bytes` is just long enough for `count` bits.
+    pub fn new(bytes: &'a [u8], count: u16) -> Option<Self> {
+        (bytes.len() == (count as usize).div_ceil(8)).then_some(Self { bytes, count })
+    }
+
+    /// Packs `values` into `buf`.
+    pub fn pack(values: &[bool], buf: &'a mut [u8]) -> Self {
+        let bytes = &mut buf[..values.len().div_ceil(8)];
+        bytes.fill(0);
+        for (i, _) in values.iter().enumerate().filter(|(_, v)| **v) {
+            bytes[i / 8] |= 1 << (i % 8);
+        }
+        Self {
+            bytes,
+            count: values.len() as u16,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.count as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn get(&self, i: usize) -> Option<bool> {
+        (i < self.len()).then(|| self.bytes[i / 8] & (1 << (i % 8)) != 0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = bool> + '_ {
+        (0..self.len()).map(|i| self.bytes[i / 8] & (1 << (i % 8)) != 0)
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+}
+
+/// Registers as they are on the wire, two bytes each.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Registers<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Registers<'a> {
+    /// `None` if `bytes` is an odd number of bytes.
+    pub fn new(bytes: &'a [u8]) -> Option<Self> {
+        bytes.len().is_multiple_of(2).then_some(Self { bytes })
+    }
+
+    /// Packs `values` into `buf`.
+    pub fn pack(values: &[u16], buf: &'a mut [u8]) -> Self {
+        let bytes = &mut buf[..values.len() * 2];
+        for (chunk, value) in bytes.chunks_exact_mut(2).zip(values) {
+            chunk.copy_from_slice(&value.to_be_bytes());
+        }
+        Self { bytes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len() / 2
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn get(&self, i: usize) -> Option<u16> {
+        let b = self.bytes.get(i * 2..i * 2 + 2)?;
+        Some(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        self.bytes
+            .chunks_exact(2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Request<'a> {
+    ReadCoils { address: u16, count: u16 },
+    ReadDiscreteInputs { address: u16, count: u16 },
+    ReadHoldingRegisters { address: u16, count: u16 },
+    ReadInputRegisters { address: u16, count: u16 },
+    WriteSingleCoil { address: u16, value: bool },
+    WriteSingleRegister { address: u16, value: u16 },
+    WriteMultipleCoils { address: u16, values: Bits<'a> },
+    WriteMultipleRegisters { address: u16, values: Registers<'a> },
+}
+
+fn be16(b: &[u8]) -> u16 {
+    u16::from_be_bytes([b[0], b[1]])
+}
+
+/// Checks that `count` items starting at `address` are within the limit,
+/// and don't run past the end of the address space.
+fn check_range(address: u16, count: u16, max: u16) -> Result<(), Exception> {
+    if !(1..=max).contains(&count) {
+        return Err(Exception::IllegalDataValue);
+    }
+    if address as u32 + count as u32 > 0x1_0000 {
+        return Err(Exception::IllegalDataAddress);
+    }
+    Ok(())
+}
+
+impl<'a> Request<'a> {
+    pub fn function(&self) -> u8 {
+        match self {
+            Request::ReadCoils { .. } => READ_COILS,
+            Request::ReadDiscreteInputs { .. } => READ_DISCRETE_INPUTS,
+            Request::ReadHoldingRegisters { .. } => READ_HOLDING_REGISTERS,
+            Request::ReadInputRegisters { .. } => READ_INPUT_REGISTERS,
+            Request::WriteSingleCoil { .. } => WRITE_SINGLE_COIL,
+            Request::WriteSingleRegister { .. } => WRITE_SINGLE_REGISTER,
+            Request::WriteMultipleCoils { .. } => WRITE_MULTIPLE_COILS,
+            Request::WriteMultipleRegisters { .. } => WRITE_MULTIPLE_REGISTERS,
+        }
+    }
+
+    /// Parses a request, or tells which exception to answer it with.
+    pub fn decode(pdu: &'a [u8]) -> Result<Self, Exception> {
+        let (&function, data) = pdu.split_first().ok_or(Exception::IllegalFunction)?;
+        let fixed = |len| {
+            if data.len() == len {
+                Ok((be16(data), be16(&data[2..])))
+            } else {
+                Err(Exception::IllegalDataValue)
+            }
+        };
+        let request = match function {
+            READ_COILS | READ_DISCRETE_INPUTS => {
+                let (address, count) = fixed(4)?;
+                check_range(address, count, MAX_READ_BITS)?;
+                if function == READ_COILS {
+                    Request::ReadCoils { address, count }
+                } else {
+                    Request::ReadDiscreteInputs { address, count }
+                }
+            }
+            READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS => {
+                let (address, count) = fixed(4)?;
+                check_range(address, count, MAX_READ_REGISTERS)?;
+                if function == READ_HOLDING_REGISTERS {
+                    Request::ReadHoldingRegisters { address, count }
+                } else {
+                    Request::ReadInputRegisters { address, count }
+                }
+            }
+            WRITE_SINGLE_COIL => {
+                let (address, value) = fixed(4)?;
+                let value = match value {
+                    COIL_ON => true,
+                    COIL_OFF => false,
+                    _ => return Err(Exception::IllegalDataValue),
+                };
+                Request::WriteSingleCoil { address, value }
+            }
+            WRITE_SINGLE_REGISTER => {
+                let (address, value) = fixed(4)?;
+                Request::WriteSingleRegister { address, value }
+            }
+            WRITE_MULTIPLE_COILS | WRITE_MULTIPLE_REGISTERS => {
+                if data.len() < 5 || data.len() != 5 + data[4] as usize {
+                    return Err(Exception::IllegalDataValue);
+                }
+                let (address, count) = (be16(data), be16(&data[2..]));
+                let values = &data[5..];
+                if function == WRITE_MULTIPLE_COILS {
+                    check_range(address, count, MAX_WRITE_BITS)?;
+                    let values = Bits::new(values, count).ok_or(Exception::IllegalDataValue)?;
+                    Request::WriteMultipleCoils { address, values }
+                } else {
+                    check_range(address, count, MAX_WRITE_REGISTERS)?;
+                    if values.len() != count as usize * 2 {
+                        return Err(Exception::IllegalDataValue);
+                    }
+                    let values = Registers { bytes: values };
+                    Request::WriteMultipleRegisters { address, values }
+                }
+            }
+            _ => return Err(Exception::IllegalFunction),
+        };
+        Ok(request)
+    }
+
+    /// Writes the request to `out`, which must have room for
+    /// [`MAX_PDU_LEN`] bytes, and returns its length.
+    pub fn encode(&self, out: &mut [u8]) -> usize {
+        out[0] = self.function();
+        let (address, second) = match *self {
+            Request::ReadCoils { address, count }
+            | Request::ReadDiscreteInputs { address, count }
+            | Request::ReadHoldingRegisters { address, count }
+            | Request::ReadInputRegisters { address, count } => (address, count),
+            Request::WriteSingleCoil { address, value } => {
+                (address, if value { COIL_ON } else { COIL_OFF })
+            }
+            Request::WriteSingleRegister { address, value } => (address, value),
+            Request::WriteMultipleCoils { address, values } => (address, values.count),
+            Request::WriteMultipleRegisters { address, values } => (address, values.len() as u16),
+        };
+        out[1..3].copy_from_slice(&address.to_be_bytes());
+        out[3..5].copy_from_slice(&second.to_be_bytes());
+        let values = match self {
+            Request::WriteMultipleCoils { values, .. } => values.as_bytes(),
+            Request::WriteMultipleRegisters { values, .. } => values.as_bytes(),
+            _ => return 5,
+        };
+        out[5] = values.len() as u8;
+        out[6..][..values.len()].copy_from_slice(values);
+        6 + values.len()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Response<'a> {
+    /// Coils or discrete inputs, as many as were asked for.
+    Bits(Bits<'a>),
+    /// Holding or input registers.
+    Registers(Registers<'a>),
+    /// A write went through.
+    Written,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ResponseError {
+    Exception(Exception),
+    /// Not a valid response to the request.
+    Malformed,
+}
+
+impl<'a> Response<'a> {
+    /// Parses the response to `request`, checking that it fits.
+    pub fn decode(request: &Request, pdu: &'a [u8]) -> Result<Self, ResponseError> {
+        let (&function, data) = pdu.split_first().ok_or(ResponseError::Malformed)?;
+        if function == request.function() | EXCEPTION_FLAG {
+            return match data {
+                &[code] => Err(ResponseError::Exception(Exception::from_code(code))),
+                _ => Err(ResponseError::Malformed),
+            };
+        }
+        if function != request.function() {
+            return Err(ResponseError::Malformed);
+        }
+        let (&byte_count, values) = data.split_first().ok_or(ResponseError::Malformed)?;
+        let response = match *request {
+            Request::ReadCoils { count, .. } | Request::ReadDiscreteInputs { count, .. } => {
+                Bits::new(values, count)
+                    .filter(|_| byte_count as usize == values.len())
+                    .map(Response::Bits)
+            }
+            Request::ReadHoldingRegisters { count, .. }
+            | Request::ReadInputRegisters { count, .. } => (byte_count as usize == values.len()
+                && values.len() == count as usize * 2)
+                .then_some(Response::Registers(Registers { bytes: values })),
+            // The writes are echoed back, at least the address and count
+            Request::WriteSingleCoil { .. } | Request::WriteSingleRegister { .. } => {
+                let mut expected = [0; MAX_PDU_LEN];
+                let len = request.encode(&mut expected);
+                (pdu == &expected[..len]).then_some(Response::Written)
+            }
+            Request::WriteMultipleCoils { .. } | Request::WriteMultipleRegisters { .. } => {
+                let mut expected = [0; MAX_PDU_LEN];
+                request.encode(&mut expected);
+                (pdu == &expected[..5]).then_some(Response::Written)
+            }
+        };
+        response.ok_or(ResponseError::Malformed)
+    }
+}
@@ -0,0 +1,224 @@
+//! Modbus RTU framing: a device address, the PDU and a CRC, with silences
+//! on the line in between frames.
+//!
+//! ```text
+//! +---------+-----------------------+-----------------+
+//! | address | PDU, up to 253 bytes  | CRC16, LE       |
+//! +---------+-----------------------+-----------------+
+//! ```
+//!
+//! A frame ends when the line stays quiet for 3.5 characters. A gap of more
+//! than 1.5 characters in the middle of one means it got mangled, and it is
+//! dropped as a whole.
+
+use crate::{crc::crc16, pdu::MAX_PDU_LEN};
+
+pub const MAX_FRAME_LEN: usize = 1 + MAX_PDU_LEN + 2;
+
+/// Every device acts on requests to this address, and none answers.
+pub const BROADCAST: u8 = 0;
+
+/// The addresses devices can have.
+pub const MIN_ADDRESS: u8 = 1;
+pub const MAX_ADDRESS: u8 = 247;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FrameError {
+    /// Shorter than an address, a function code and a CRC.
+    TooShort,
+    TooLong,
+    BadCrc,
+    /// The line went quiet in the middle of the frame, or the UART reported
+    /// a framing or parity error.
+    Interrupted,
+}
+
+/// Writes a frame to `out`, which must have room for [`MAX_FRAME_LEN`]
+/// bytes, and returns its length.
+pub fn encode(address: u8, pdu: &[u8], out: &mut [u8]) -> usize {
+    out[0] = address;
+    out[1..][..pdu.len()].copy_from_slice(pdu);
+    let len = 1 + pdu.len();
+    let crc = crc16(&out[..len]);
+    out[len..len + 2].copy_from_slice(&crc.to_le_bytes());
+    len + 2
+}
+
+/// Checks the CRC of a frame, and splits it into the address and the PDU.
+pub fn decode(frame: &[u8]) -> Result<(u8, &[u8]), FrameError> {
+    if frame.len() < 4 {
+        return Err(FrameError::TooShort);
+    }
+    if frame.len() > MAX_FRAME_LEN {
+        return Err(FrameError::TooLong);
+    }
+    let (data, crc) = frame.split_at(frame.len() - 2);
+    if crc16(data).to_le_bytes() != crc {
+        return Err(FrameError::BadCrc);
+    }
+    Ok((data[0], &data[1..]))
+}
+
+/// The silences that matter, in µs.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Timing {
+    /// How long a character takes to send.
+    pub char_us: u32,
+    /// The longest gap allowed within a frame.
+    pub t1_5_us: u32,
+    /// The shortest gap between frames.
+    pub t3_5_us: u32,
+}
+
+impl Timing {
+    /// The timing at `baud`, with `frame_bits` bits to a character, 11 as
+    /// the spec has it. Above 19200 baud the gaps are fixed, as they'd get
+    /// too short to time reliably.
+    pub fn new(baud: u32, frame_bits: u32) -> Self {
+        let char_us = (frame_bits * 1_000_000).div_ceil(baud);
+        let (t1_5_us, t3_5_us) = if baud > 19_200 {
+            (750, 1750)
+        } else {
+            ((char_us * 3).div_ceil(2), (char_us * 7).div_ceil(2))
+        };
+        Self {
+            char_us,
+            t1_5_us,
+            t3_5_us,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum State {
+    /// Nothing received since the last frame.
+    Idle,
+    Receiving,
+    /// The frame was too long, or has a gap in it. The rest of it is
+    /// dropped.
+    Broken(FrameError),
+    /// The frame ended, and is there for [`Framer::frame`] until more comes
+    /// in.
+    Complete,
+}
+
+/// Splits what comes in over the line into frames, by the silences in
+/// between.
+///
+/// Bytes go in with the time they arrived. Whoever drives it calls
+/// [`Framer::poll`] once the line went quiet for [`Framer::deadline`],
+/// before pushing anything more.
+#[derive(Clone, Debug)]
+pub struct Framer {
+    timing: Timing,
+    frame: heapless::Vec<u8, MAX_FRAME_LEN>,
+    state: State,
+    /// When the last byte arrived, in µs.
+    last_us: u64,
+}
+
+impl Framer {
+    pub const fn new(timing: Timing) -> Self {
+        Self {
+            timing,
+            frame: heapless::Vec::new(),
+            state: State::Idle,
+            last_us: 0,
+        }
+    }
+
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+        self.reset();
+    }
+
+    /// Forgets whatever came in so far.
+    pub fn reset(&mut self) {
+        self.frame.clear();
+        self.state = State::Idle;
+    }
+
+    /// Takes a byte that arrived at `at_us`.
+    pub fn push(&mut self, at_us: u64, byte: u8) {
+        let gap = at_us.saturating_sub(self.last_us);
+        self.last_us = at_us;
+        match self.state {
+            State::Idle | State::Complete => {
+                self.frame.clear();
+                self.state = State::Receiving;
+            }
+            // A frame that wasn't polled in time, which is the same as the
+            // start of a new one
+            _ if gap >= self.timing.t3_5_us as u64 => {
+                self.frame.clear();
+                self.state = State::Receiving;
+            }
+            State::Receiving if gap > self.timing.t1_5_us as u64 => {
+                self.state = State::Broken(FrameError::Interrupted);
+            }
+            State::Receiving | State::Broken(_) => (),
+        }
+        if self.state == State::Receiving && self.frame.push(byte).is_err() {
+            self.state = State::Broken(FrameError::TooLong);
+        }
+    }
+
+    /// Takes a chunk of bytes that finished arriving at `end_us`, as a UART
+    /// hands them over. They are taken to have come in back to back.
+    pub fn push_chunk(&mut self, end_us: u64, bytes: &[u8]) {
+        let n = bytes.len() as u64;
+        for (i, &b) in bytes.iter().enumerate() {
+            let at_us = end_us.saturating_sub((n - 1 - i as u64) * self.timing.char_us as u64);
+            self.push(at_us.max(self.last_us), b);
+        }
+    }
+
+    /// Marks the frame that is coming in as broken, e.g. on a UART error.
+    pub fn interrupt(&mut self, at_us: u64) {
+        self.last_us = at_us;
+        if self.state != State::Complete {
+            self.state = State::Broken(FrameError::Interrupted);
+        }
+    }
+
+    /// When the frame that is coming in ends, unless more arrives.
+    pub fn deadline(&self) -> Option<u64> {
+        match self.state {
+            State::Receiving | State::Broken(_) => Some(self.last_us + self.timing.t3_5_us as u64),
+            State::Idle | State::Complete => None,
+        }
+    }
+
+    /// Whether the frame that was coming in has ended by `now_us`, and if
+    /// so, whether it came in whole. It is in [`Framer::frame`] if it did.
+    /// The CRC is left to [`decode`].
+    pub fn poll(&mut self, now_us: u64) -> Option<Result<(), FrameError>> {
+        if now_us < self.deadline()? {
+            return None;
+        }
+        let result = match self.state {
+            State::Broken(e) => {
+                self.frame.clear();
+                self.state = State::Idle;
+                Err(e)
+            }
+            _ => {
+                self.state = State::Complete;
+                Ok(())
+            }
+        };
+        Some(result)
+    }
+
+    /// The last frame [`Framer::poll`] found complete.
+    pub fn frame(&self) -> &[u8] {
+        match self.state {
+            State::Complete => &self.frame,
+            _ => &[],
+        }
+    }
+}
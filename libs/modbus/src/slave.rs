@@ -0,0 +1,130 @@
+//! Answering requests from what a device exposes.
+
+use crate::pdu::{
+    Bits, Exception, MAX_PDU_LEN, Registers, Request, WRITE_MULTIPLE_COILS,
+    WRITE_MULTIPLE_REGISTERS, encode_exception,
+};
+
+/// The four data tables of a device. Anything not overridden isn't there,
+/// and reading or writing it answers with an exception.
+pub trait DataModel {
+    fn read_coil(&mut self, _address: u16) -> Result<bool, Exception> {
+        Err(Exception::IllegalDataAddress)
+    }
+
+    fn read_discrete_input(&mut self, _address: u16) -> Result<bool, Exception> {
+        Err(Exception::IllegalDataAddress)
+    }
+
+    fn read_holding_register(&mut self, _address: u16) -> Result<u16, Exception> {
+        Err(Exception::IllegalDataAddress)
+    }
+
+    fn read_input_register(&mut self, _address: u16) -> Result<u16, Exception> {
+        Err(Exception::IllegalDataAddress)
+    }
+
+    fn write_coil(&mut self, _address: u16, _value: bool) -> Result<(), Exception> {
+        Err(Exception::IllegalDataAddress)
+    }
+
+    fn write_register(&mut self, _address: u16, _value: u16) -> Result<(), Exception> {
+        Err(Exception::IllegalDataAddress)
+    }
+
+    /// Writes consecutive coils. By default one at a time, so a write that
+    /// fails part way leaves the ones before written; override this to
+    /// check the whole range first.
+    fn write_coils(&mut self, address: u16, values: Bits) -> Result<(), Exception> {
+        for (i, value) in values.iter().enumerate() {
+            self.write_coil(address + i as u16, value)?;
+        }
+        Ok(())
+    }
+
+    /// Writes consecutive registers, see [`DataModel::write_coils`].
+    fn write_registers(&mut self, address: u16, values: Registers) -> Result<(), Exception> {
+        for (i, value) in values.iter().enumerate() {
+            self.write_register(address + i as u16, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Answers the request in `pdu` from `model`, writing the response to
+/// `out`, which must have room for [`MAX_PDU_LEN`] bytes. Returns its
+/// length, and whether it is an exception.
+pub fn handle(model: &mut impl DataModel, pdu: &[u8], out: &mut [u8]) -> (usize, bool) {
+    let function = pdu.first().copied().unwrap_or(0);
+    match respond(model, pdu, &mut out[..MAX_PDU_LEN]) {
+        Ok(len) => (len, false),
+        Err(e) => (encode_exception(function, e, out), true),
+    }
+}
+
+fn respond(model: &mut impl DataModel, pdu: &[u8], out: &mut [u8]) -> Result<usize, Exception> {
+    let request = Request::decode(pdu)?;
+    out[0] = request.function();
+    match request {
+        Request::ReadCoils { address, count } | Request::ReadDiscreteInputs { address, count } => {
+            let bytes = (count as usize).div_ceil(8);
+            out[1] = bytes as u8;
+            out[2..][..bytes].fill(0);
+            for i in 0..count {
+                let bit = match request {
+                    Request::ReadCoils { .. } => model.read_coil(address + i)?,
+                    _ => model.read_discrete_input(address + i)?,
+                };
+                out[2 + i as usize / 8] |= (bit as u8) << (i % 8);
+            }
+            Ok(2 + bytes)
+        }
+        Request::ReadHoldingRegisters { address, count }
+        | Request::ReadInputRegisters { address, count } => {
+            out[1] = (count * 2) as u8;
+            for i in 0..count {
+                let value = match request {
+                    Request::ReadHoldingRegisters { .. } => {
+                        model.read_holding_register(address + i)?
+                    }
+                    _ => model.read_input_register(address + i)?,
+                };
+                out[2 + i as usize * 2..][..2].copy_from_slice(&value.to_be_bytes());
+            }
+            Ok(2 + count as usize * 2)
+        }
+        Request::WriteSingleCoil { address, value } => {
+            model.write_coil(address, value)?;
+            // Echoes the request
+            out[..pdu.len()].copy_from_slice(pdu);
+            Ok(pdu.len())
+        }
+        Request::WriteSingleRegister { address, value } => {
+            model.write_register(address, value)?;
+            out[..pdu.len()].copy_from_slice(pdu);
+            Ok(pdu.len())
+        }
+        Request::WriteMultipleCoils { address, values } => {
+            model.write_coils(address, values)?;
+            Ok(echo_write(WRITE_MULTIPLE_COILS, address, values.len(), out))
+        }
+        Request::WriteMultipleRegisters { address, values } => {
+            model.write_registers(address, values)?;
+            Ok(echo_write(
+                WRITE_MULTIPLE_REGISTERS,
+                address,
+                values.len(),
+                out,
+            ))
+        }
+    }
+}
+
+/// The response to a write of several coils or registers: the address and
+/// the count.
+fn echo_write(function: u8, address: u16, count: usize, out: &mut [u8]) -> usize {
+    out[0] = function;
+    out[1..3].copy_from_slice(&address.to_be_bytes());
+    out[3..5].copy_from_slice(&(count as u16).to_be_bytes());
+    5
+}
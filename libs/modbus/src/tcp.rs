@@ -0,0 +1,227 @@
+//! Modbus TCP: the PDU behind an MBAP header, and what a gateway between it
+//! and an RTU bus does with the requests.
+//!
+//! ```text
+//! +-------------+-------------+-------------+---------+------------------+
+//! | transaction | protocol, 0 | length      | unit    | PDU              |
+//! | 2 bytes     | 2 bytes     | 2 bytes     | 1 byte  | up to 253 bytes  |
+//! +-------------+-------------+-------------+---------+------------------+
+//! ```
+//!
+//! All big-endian, with the length counting the unit and the PDU. A client
+//! picks the transaction ids, and they come back with the response.
+//!
+//! A gateway serves each client connection with [`serve`], and lines the
+//! requests up in a [`Queue`] for the [`Bus`], which [`Queue::run`] hands
+//! them to one at a time.
+
+use embassy_sync::{blocking_mutex::raw::RawMutex, channel::Channel, signal::Signal};
+use embedded_io_async::{Read, ReadExactError, Write};
+
+use crate::{
+    pdu::{EXCEPTION_FLAG, Exception, MAX_PDU_LEN, encode_exception},
+    rtu::{MAX_ADDRESS, MIN_ADDRESS},
+};
+
+pub const PORT: u16 = 502;
+
+pub const HEADER_LEN: usize = 7;
+pub const MAX_ADU_LEN: usize = HEADER_LEN + MAX_PDU_LEN;
+
+const PROTOCOL: u16 = 0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub transaction: u16,
+    /// The device behind a gateway, its RTU address.
+    pub unit: u8,
+    /// How long the PDU after the header is.
+    pub pdu_len: usize,
+}
+
+/// A header that can't be Modbus. There is no telling where the next one
+/// would start, so the connection is best closed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HeaderError {
+    Protocol(u16),
+    /// Too short for a function code, or too long for a PDU.
+    Length(u16),
+}
+
+impl Header {
+    pub fn decode(bytes: &[u8; HEADER_LEN]) -> Result<Self, HeaderError> {
+        let protocol = u16::from_be_bytes([bytes[2], bytes[3]]);
+        if protocol != PROTOCOL {
+            return Err(HeaderError::Protocol(protocol));
+        }
+        let length = u16::from_be_bytes([bytes[4], bytes[5]]);
+        if !(2..=1 + MAX_PDU_LEN as u16).contains(&length) {
+            return Err(HeaderError::Length(length));
+        }
+        Ok(Self {
+            transaction: u16::from_be_bytes([bytes[0], bytes[1]]),
+            unit: bytes[6],
+            pdu_len: length as usize - 1,
+        })
+    }
+
+    /// Writes an ADU with this header and `pdu` to `out`, which must have
+    /// room for [`MAX_ADU_LEN`] bytes, and returns its length. The length
+    /// is that of `pdu`, whatever `pdu_len` says.
+    pub fn encode(&self, pdu: &[u8], out: &mut [u8]) -> usize {
+        out[0..2].copy_from_slice(&self.transaction.to_be_bytes());
+        out[2..4].copy_from_slice(&PROTOCOL.to_be_bytes());
+        out[4..6].copy_from_slice(&(1 + pdu.len() as u16).to_be_bytes());
+        out[6] = self.unit;
+        out[HEADER_LEN..][..pdu.len()].copy_from_slice(pdu);
+        HEADER_LEN + pdu.len()
+    }
+}
+
+/// The RTU address a gateway forwards a request for `unit` to, which is
+/// the same. Unit 0 would be a broadcast, which the client would never
+/// hear back from, and anything above 247 isn't an address, so those are
+/// answered with [`Exception::GatewayPathUnavailable`].
+pub fn route(unit: u8) -> Result<u8, Exception> {
+    if (MIN_ADDRESS..=MAX_ADDRESS).contains(&unit) {
+        Ok(unit)
+    } else {
+        Err(Exception::GatewayPathUnavailable)
+    }
+}
+
+/// Writes the PDU a gateway answers the `request` PDU with to `out`, which
+/// must have room for [`MAX_PDU_LEN`] bytes, and returns its length. That
+/// is the `response` PDU from the bus if there is one for the same
+/// function, the device's exceptions included, or
+/// [`Exception::GatewayTargetFailedToRespond`] otherwise.
+pub fn respond(request: &[u8], response: Option<&[u8]>, out: &mut [u8]) -> usize {
+    let function = request.first().copied().unwrap_or(0);
+    match response {
+        Some(response)
+            if !response.is_empty()
+                && response.len() <= MAX_PDU_LEN
+                && response[0] & !EXCEPTION_FLAG == function =>
+        {
+            out[..response.len()].copy_from_slice(response);
+            response.len()
+        }
+        _ => encode_exception(function, Exception::GatewayTargetFailedToRespond, out),
+    }
+}
+
+pub type Pdu = heapless::Vec<u8, MAX_PDU_LEN>;
+
+/// The bus behind a gateway, which carries one request at a time.
+// Only ever used with concrete types, so nothing needs the futures to be
+// `Send`
+#[allow(async_fn_in_trait)]
+pub trait Bus {
+    /// Sends the request `pdu` to the device at `address`, and returns the
+    /// PDU it answered with, if it did in time.
+    async fn transact(&mut self, address: u8, pdu: &[u8]) -> Option<&[u8]>;
+}
+
+/// A request from a client, for the device at `address`.
+struct Job {
+    client: usize,
+    address: u8,
+    pdu: Pdu,
+}
+
+/// Where the clients line up for the bus. Each client waits for its answer
+/// before it sends another request, so there is always room for one from
+/// every client, and they are served in the order they came in.
+pub struct Queue<M: RawMutex, const CLIENTS: usize> {
+    jobs: Channel<M, Job, CLIENTS>,
+    answers: [Signal<M, Pdu>; CLIENTS],
+}
+
+impl<M: RawMutex, const CLIENTS: usize> Queue<M, CLIENTS> {
+    pub const fn new() -> Self {
+        Self {
+            jobs: Channel::new(),
+            answers: [const { Signal::new() }; CLIENTS],
+        }
+    }
+
+    /// How many requests are waiting for the bus, besides the one it is
+    /// busy with.
+    pub fn waiting(&self) -> usize {
+        self.jobs.len()
+    }
+
+    /// Forwards a request over the bus, and returns the PDU to answer the
+    /// client with.
+    async fn forward(&self, client: usize, address: u8, pdu: &[u8]) -> Pdu {
+        let job = Job {
+            client,
+            address,
+            pdu: Pdu::from_slice(pdu).unwrap(),
+        };
+        self.jobs.send(job).await;
+        self.answers[client].wait().await
+    }
+
+    /// Hands the requests to `bus` one after the other, and the answers
+    /// back to the clients.
+    pub async fn run(&self, bus: &mut impl Bus) -> ! {
+        let mut answer = [0; MAX_PDU_LEN];
+        loop {
+            let job = self.jobs.receive().await;
+            let response = bus.transact(job.address, &job.pdu).await;
+            let len = respond(&job.pdu, response, &mut answer);
+            self.answers[job.client].signal(Pdu::from_slice(&answer[..len]).unwrap());
+        }
+    }
+}
+
+impl<M: RawMutex, const CLIENTS: usize> Default for Queue<M, CLIENTS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Why [`serve`] gave up on a client.
+#[derive(Debug)]
+pub enum ServeError<E> {
+    Read(ReadExactError<E>),
+    Write(E),
+    Header(HeaderError),
+}
+
+/// Answers the requests a client sends over `conn` one after the other,
+/// until it hangs up. `client` tells it apart from the others in `queue`,
+/// and has to be below `CLIENTS`.
+pub async fn serve<C: Read + Write, M: RawMutex, const CLIENTS: usize>(
+    conn: &mut C,
+    queue: &Queue<M, CLIENTS>,
+    client: usize,
+) -> Result<(), ServeError<C::Error>> {
+    let mut header = [0; HEADER_LEN];
+    let mut pdu = [0; MAX_PDU_LEN];
+    let mut adu = [0; MAX_ADU_LEN];
+    loop {
+        match conn.read_exact(&mut header).await {
+            Ok(()) => (),
+            Err(ReadExactError::UnexpectedEof) => return Ok(()),
+            Err(e) => return Err(ServeError::Read(e)),
+        }
+        let header = Header::decode(&header).map_err(ServeError::Header)?;
+        let request = &mut pdu[..header.pdu_len];
+        conn.read_exact(request).await.map_err(ServeError::Read)?;
+
+        let answer = match route(header.unit) {
+            Ok(address) => queue.forward(client, address, request).await,
+            Err(e) => {
+                let mut answer = [0; 2];
+                let len = encode_exception(request[0], e, &mut answer);
+                Pdu::from_slice(&answer[..len]).unwrap()
+            }
+        };
+        let len = header.encode(&answer, &mut adu);
+        conn.write_all(&adu[..len])
+            .await
+            .map_err(ServeError::Write)?;
+    }
+}
@@ -0,0 +1,281 @@
+//! The commands a ROM registers, and parsing a line into one of them with
+//! its arguments checked against their types.
+//!
+//! ```ignore
+//! const COMMANDS: &[Command<Ctx>] = &[Command {
+//!     name: "led",
+//!     args: &[Arg::bool("on"), Arg::int("brightness", 0, 255).optional()],
+//!     help: "Turn the LED on or off",
+//!     run: led,
+//! }];
+//!
+//! fn led(ctx: &mut Ctx, args: &Args, out: &mut Output) -> Result<(), &'static str> {
+//!     ctx.set_led(args.bool(0).unwrap(), args.int(1).unwrap_or(255) as u8);
+//!     Ok(())
+//! }
+//! ```
+
+use core::fmt;
+
+use crate::Output;
+
+/// The most arguments a command can take.
+pub const MAX_ARGS: usize = 8;
+
+/// Runs a command, writing what it has to say to the output. An error is
+/// shown to the user as is.
+pub type Handler<C> = fn(&mut C, &Args, &mut Output) -> Result<(), &'static str>;
+
+pub struct Command<C> {
+    pub name: &'static str,
+    pub args: &'static [Arg],
+    /// A short line for `help`.
+    pub help: &'static str,
+    pub run: Handler<C>,
+}
+
+impl<C> Command<C> {
+    /// Something like `name <a> [b]`.
+    pub fn usage(&self) -> impl fmt::Display + '_ {
+        Usage(self.name, self.args)
+    }
+}
+
+struct Usage(&'static str, &'static [Arg]);
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)?;
+        for arg in self.1 {
+            match (arg.optional, arg.kind) {
+                (false, ArgKind::Rest) => write!(f, " <{}...>", arg.name)?,
+                (true, ArgKind::Rest) => write!(f, " [{}...]", arg.name)?,
+                (false, _) => write!(f, " <{}>", arg.name)?,
+                (true, _) => write!(f, " [{}]", arg.name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ArgKind {
+    /// An integer in `min..=max`, in decimal, or in hex with `0x`.
+    Int { min: i64, max: i64 },
+    /// `on` or `off`, also taking `1`/`0` and `true`/`false`.
+    Bool,
+    /// One of a fixed set of words.
+    Choice(&'static [&'static str]),
+    /// Any single word.
+    Word,
+    /// The rest of the line, spaces and all. Only makes sense last.
+    Rest,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Arg {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    /// Optional arguments can only be followed by other optional ones.
+    pub optional: bool,
+}
+
+impl Arg {
+    pub const fn int(name: &'static str, min: i64, max: i64) -> Self {
+        Self::new(name, ArgKind::Int { min, max })
+    }
+
+    pub const fn bool(name: &'static str) -> Self {
+        Self::new(name, ArgKind::Bool)
+    }
+
+    pub const fn choice(name: &'static str, choices: &'static [&'static str]) -> Self {
+        Self::new(name, ArgKind::Choice(choices))
+    }
+
+    pub const fn word(name: &'static str) -> Self {
+        Self::new(name, ArgKind::Word)
+    }
+
+    pub const fn rest(name: &'static str) -> Self {
+        Self::new(name, ArgKind::Rest)
+    }
+
+    pub const fn optional(self) -> Self {
+        Self {
+            optional: true,
+            ..self
+        }
+    }
+
+    const fn new(name: &'static str, kind: ArgKind) -> Self {
+        Self {
+            name,
+            kind,
+            optional: false,
+        }
+    }
+
+    /// The words Tab can complete this argument to.
+    pub fn completions(&self) -> &'static [&'static str] {
+        match self.kind {
+            ArgKind::Bool => &["on", "off"],
+            ArgKind::Choice(choices) => choices,
+            ArgKind::Int { .. } | ArgKind::Word | ArgKind::Rest => &[],
+        }
+    }
+
+    fn parse<'a>(&'static self, s: &'a str) -> Result<Value<'a>, ParseError<'a>> {
+        match self.kind {
+            ArgKind::Int { min, max } => {
+                let n = match s.strip_prefix("0x") {
+                    Some(hex) => i64::from_str_radix(hex, 16),
+                    None => s.parse(),
+                }
+                .map_err(|_| ParseError::NotInt(self))?;
+                if !(min..=max).contains(&n) {
+                    return Err(ParseError::OutOfRange(self));
+                }
+                Ok(Value::Int(n))
+            }
+            ArgKind::Bool => match s {
+                "on" | "1" | "true" => Ok(Value::Bool(true)),
+                "off" | "0" | "false" => Ok(Value::Bool(false)),
+                _ => Err(ParseError::NotBool(self)),
+            },
+            ArgKind::Choice(choices) => match choices.iter().find(|&&c| c == s) {
+                Some(c) => Ok(Value::Str(c)),
+                None => Err(ParseError::NotChoice(self)),
+            },
+            ArgKind::Word | ArgKind::Rest => Ok(Value::Str(s)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Value<'a> {
+    Int(i64),
+    Bool(bool),
+    /// A `Word`, `Choice` or `Rest`.
+    Str(&'a str),
+}
+
+/// The arguments a command was given, in the order it declares them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Args<'a> {
+    /// `None` for optional arguments that were left out.
+    values: heapless::Vec<Option<Value<'a>>, MAX_ARGS>,
+}
+
+impl<'a> Args<'a> {
+    pub fn get(&self, i: usize) -> Option<Value<'a>> {
+        self.values.get(i).copied().flatten()
+    }
+
+    pub fn int(&self, i: usize) -> Option<i64> {
+        match self.get(i) {
+            Some(Value::Int(n)) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn bool(&self, i: usize) -> Option<bool> {
+        match self.get(i) {
+            Some(Value::Bool(b)) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn str(&self, i: usize) -> Option<&'a str> {
+        match self.get(i) {
+            Some(Value::Str(s)) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ParseError<'a> {
+    UnknownCommand(&'a str),
+    Missing(&'static Arg),
+    TooMany,
+    NotInt(&'static Arg),
+    OutOfRange(&'static Arg),
+    NotBool(&'static Arg),
+    NotChoice(&'static Arg),
+}
+
+impl fmt::Display for ParseError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnknownCommand(name) => write!(f, "unknown command `{name}`, try `help`"),
+            ParseError::Missing(arg) => write!(f, "<{}> is missing", arg.name),
+            ParseError::TooMany => write!(f, "too many arguments"),
+            ParseError::NotInt(arg) => write!(f, "<{}> must be a number", arg.name),
+            ParseError::OutOfRange(arg) => match arg.kind {
+                ArgKind::Int { min, max } => {
+                    write!(f, "<{}> must be between {min} and {max}", arg.name)
+                }
+                _ => write!(f, "<{}> is out of range", arg.name),
+            },
+            ParseError::NotBool(arg) => write!(f, "<{}> must be on or off", arg.name),
+            ParseError::NotChoice(arg) => {
+                write!(f, "<{}> must be one of ", arg.name)?;
+                for (i, choice) in arg.completions().iter().enumerate() {
+                    let sep = if i == 0 { "" } else { ", " };
+                    write!(f, "{sep}{choice}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Splits the first word off `s`, skipping the whitespace before it.
+pub fn next_word(s: &str) -> Option<(&str, &str)> {
+    let s = s.trim_start();
+    if s.is_empty() {
+        return None;
+    }
+    Some(s.split_once(char::is_whitespace).unwrap_or((s, "")))
+}
+
+/// Finds the command a line is for, and parses its arguments. A blank line
+/// is `Ok(None)`.
+pub fn parse<'c, 'a, C>(
+    commands: &'c [Command<C>],
+    line: &'a str,
+) -> Result<Option<(&'c Command<C>, Args<'a>)>, ParseError<'a>> {
+    let Some((name, mut rest)) = next_word(line) else {
+        return Ok(None);
+    };
+    let command = commands
+        .iter()
+        .find(|c| c.name == name)
+        .ok_or(ParseError::UnknownCommand(name))?;
+
+    let mut args = Args::default();
+    for arg in command.args {
+        let word = if arg.kind == ArgKind::Rest {
+            let trimmed = rest.trim();
+            rest = "";
+            (!trimmed.is_empty()).then_some(trimmed)
+        } else {
+            next_word(rest).map(|(word, r)| {
+                rest = r;
+                word
+            })
+        };
+        let value = match word {
+            Some(word) => Some(arg.parse(word)?),
+            None if arg.optional => None,
+            None => return Err(ParseError::Missing(arg)),
+        };
+        // Only a command declaring more than `MAX_ARGS` gets here
+        args.values.push(value).map_err(|_| ParseError::TooMany)?;
+    }
+    if next_word(rest).is_some() {
+        return Err(ParseError::TooMany);
+    }
+    Ok(Some((command, args)))
+}
@@ -0,0 +1,358 @@
+//! Editing the line being typed, the way a terminal expects.
+//!
+//! Keys come in as the bytes a VT100-style terminal sends, and what the
+//! terminal has to show in turn goes to an [`Output`]. Lines are ASCII only,
+//! so every byte is a column on the screen.
+
+use crate::{
+    Output,
+    history::{History, Line},
+};
+
+/// The longest line that can be typed.
+pub const MAX_LINE_LEN: usize = 96;
+
+const BELL: &str = "\x07";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Key {
+    Char(u8),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Home,
+    End,
+    Up,
+    Down,
+    Tab,
+    /// Ctrl-U.
+    KillToStart,
+    /// Ctrl-K.
+    KillToEnd,
+    /// Ctrl-W.
+    KillWord,
+    /// Ctrl-C.
+    Cancel,
+    /// Ctrl-L.
+    ClearScreen,
+}
+
+/// Where we are in an escape sequence.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// After ESC.
+    Started,
+    /// After ESC [, with the number read so far.
+    Csi(u8),
+    /// After ESC O.
+    Ss3,
+}
+
+/// What the caller has to do after a key.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    None,
+    /// Enter was pressed, [`Editor::take_line`] has the line.
+    Line,
+    /// Tab was pressed, see [`Editor::before_cursor`] and
+    /// [`Editor::insert`].
+    Complete,
+}
+
+#[derive(Clone, Debug)]
+pub struct Editor {
+    line: Line,
+    cursor: usize,
+    history: History,
+    /// How far back in the history Up and Down went, if they did.
+    browsing: Option<usize>,
+    /// The line that was being typed before going back in the history.
+    draft: Line,
+    escape: Escape,
+    /// A CR followed by an LF is a single Enter.
+    after_cr: bool,
+}
+
+impl Default for Editor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Editor {
+    pub const fn new() -> Self {
+        Self {
+            line: Line::new(),
+            cursor: 0,
+            history: History::new(),
+            browsing: None,
+            draft: Line::new(),
+            escape: Escape::None,
+            after_cr: false,
+        }
+    }
+
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn before_cursor(&self) -> &str {
+        &self.line[..self.cursor]
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Handles a byte from the terminal.
+    pub fn feed(&mut self, byte: u8, prompt: &str, out: &mut Output) -> Event {
+        match self.decode(byte) {
+            Some(key) => self.key(key, prompt, out),
+            None => Event::None,
+        }
+    }
+
+    /// Turns the bytes a terminal sends into keys. Anything we don't know
+    /// is dropped, escape sequences as a whole.
+    fn decode(&mut self, byte: u8) -> Option<Key> {
+        let after_cr = core::mem::replace(&mut self.after_cr, byte == b'\r');
+        match self.escape {
+            Escape::None => (),
+            Escape::Started => {
+                self.escape = match byte {
+                    b'[' => Escape::Csi(0),
+                    b'O' => Escape::Ss3,
+                    _ => Escape::None,
+                };
+                return None;
+            }
+            Escape::Csi(n) => {
+                if byte.is_ascii_digit() {
+                    self.escape = Escape::Csi(n.saturating_mul(10).saturating_add(byte - b'0'));
+                    return None;
+                }
+                // Parameters, until the final byte
+                if !(0x40..=0x7e).contains(&byte) {
+                    return None;
+                }
+                self.escape = Escape::None;
+                return match (byte, n) {
+                    (b'A', _) => Some(Key::Up),
+                    (b'B', _) => Some(Key::Down),
+                    (b'C', _) => Some(Key::Right),
+                    (b'D', _) => Some(Key::Left),
+                    (b'H', _) | (b'~', 1 | 7) => Some(Key::Home),
+                    (b'F', _) | (b'~', 4 | 8) => Some(Key::End),
+                    (b'~', 3) => Some(Key::Delete),
+                    _ => None,
+                };
+            }
+            Escape::Ss3 => {
+                self.escape = Escape::None;
+                return match byte {
+                    b'A' => Some(Key::Up),
+                    b'B' => Some(Key::Down),
+                    b'C' => Some(Key::Right),
+                    b'D' => Some(Key::Left),
+                    b'H' => Some(Key::Home),
+                    b'F' => Some(Key::End),
+                    _ => None,
+                };
+            }
+        }
+        match byte {
+            0x1b => {
+                self.escape = Escape::Started;
+                None
+            }
+            b'\r' => Some(Key::Enter),
+            b'\n' if after_cr => None,
+            b'\n' => Some(Key::Enter),
+            0x01 => Some(Key::Home),
+            0x02 => Some(Key::Left),
+            0x03 => Some(Key::Cancel),
+            0x04 => Some(Key::Delete),
+            0x05 => Some(Key::End),
+            0x06 => Some(Key::Right),
+            0x08 | 0x7f => Some(Key::Backspace),
+            b'\t' => Some(Key::Tab),
+            0x0b => Some(Key::KillToEnd),
+            0x0c => Some(Key::ClearScreen),
+            0x0e => Some(Key::Down),
+            0x10 => Some(Key::Up),
+            0x15 => Some(Key::KillToStart),
+            0x17 => Some(Key::KillWord),
+            b' '..=b'~' => Some(Key::Char(byte)),
+            _ => None,
+        }
+    }
+
+    /// Handles a key, and writes what changes on the screen to `out`.
+    pub fn key(&mut self, key: Key, prompt: &str, out: &mut Output) -> Event {
+        match key {
+            Key::Char(c) => {
+                let mut buf = [0; 4];
+                self.insert((c as char).encode_utf8(&mut buf), prompt, out);
+            }
+            Key::Enter => {
+                out.write_str("\n");
+                self.browsing = None;
+                return Event::Line;
+            }
+            Key::Backspace if self.cursor == 0 => out.write_str(BELL),
+            Key::Backspace if self.cursor == self.line.len() => {
+                self.line.pop();
+                self.cursor -= 1;
+                out.write_str("\x08 \x08");
+            }
+            Key::Backspace => {
+                self.cursor -= 1;
+                self.remove(self.cursor..self.cursor + 1);
+                self.redraw(prompt, out);
+            }
+            Key::Delete if self.cursor == self.line.len() => (),
+            Key::Delete => {
+                self.remove(self.cursor..self.cursor + 1);
+                self.redraw(prompt, out);
+            }
+            Key::Left if self.cursor > 0 => {
+                self.cursor -= 1;
+                out.write_str("\x1b[D");
+            }
+            Key::Right if self.cursor < self.line.len() => {
+                self.cursor += 1;
+                out.write_str("\x1b[C");
+            }
+            Key::Left | Key::Right => (),
+            Key::Home => {
+                self.cursor = 0;
+                self.redraw(prompt, out);
+            }
+            Key::End => {
+                self.cursor = self.line.len();
+                self.redraw(prompt, out);
+            }
+            Key::Up => {
+                let back = self.browsing.map_or(0, |b| b + 1);
+                let Some(line) = self.history.get(back) else {
+                    out.write_str(BELL);
+                    return Event::None;
+                };
+                if self.browsing.is_none() {
+                    self.draft = self.line.clone();
+                }
+                self.browsing = Some(back);
+                self.line = Line::try_from(line).unwrap_or_default();
+                self.cursor = self.line.len();
+                self.redraw(prompt, out);
+            }
+            Key::Down => {
+                match self.browsing {
+                    None => {
+                        out.write_str(BELL);
+                        return Event::None;
+                    }
+                    Some(0) => {
+                        self.browsing = None;
+                        self.line = core::mem::take(&mut self.draft);
+                    }
+                    Some(back) => {
+                        self.browsing = Some(back - 1);
+                        let line = self.history.get(back - 1).unwrap_or_default();
+                        self.line = Line::try_from(line).unwrap_or_default();
+                    }
+                }
+                self.cursor = self.line.len();
+                self.redraw(prompt, out);
+            }
+            Key::Tab => return Event::Complete,
+            Key::KillToStart => {
+                self.remove(0..self.cursor);
+                self.cursor = 0;
+                self.redraw(prompt, out);
+            }
+            Key::KillToEnd => {
+                self.line.truncate(self.cursor);
+                self.redraw(prompt, out);
+            }
+            Key::KillWord => {
+                let before = self.before_cursor();
+                let start = before.trim_end().rfind(' ').map_or(0, |i| i + 1);
+                self.remove(start..self.cursor);
+                self.cursor = start;
+                self.redraw(prompt, out);
+            }
+            Key::Cancel => {
+                out.write_str("^C\n");
+                self.clear();
+                out.write_str(prompt);
+            }
+            Key::ClearScreen => {
+                out.write_str("\x1b[2J\x1b[H");
+                self.redraw(prompt, out);
+            }
+        }
+        Event::None
+    }
+
+    /// Inserts `s` at the cursor, or rings the bell if it doesn't fit.
+    pub fn insert(&mut self, s: &str, prompt: &str, out: &mut Output) {
+        if self.line.len() + s.len() > MAX_LINE_LEN {
+            out.write_str(BELL);
+            return;
+        }
+        let at_end = self.cursor == self.line.len();
+        let tail = Line::try_from(&self.line[self.cursor..]).unwrap_or_default();
+        self.line.truncate(self.cursor);
+        let _ = self.line.push_str(s);
+        let _ = self.line.push_str(&tail);
+        self.cursor += s.len();
+        if at_end {
+            out.write_str(s);
+        } else {
+            self.redraw(prompt, out);
+        }
+    }
+
+    /// The line as it was typed, which goes into the history. The editor
+    /// starts on a new one.
+    pub fn take_line(&mut self) -> Line {
+        let line = core::mem::take(&mut self.line);
+        self.history.push(&line);
+        self.clear();
+        line
+    }
+
+    fn clear(&mut self) {
+        self.line.clear();
+        self.cursor = 0;
+        self.browsing = None;
+        self.draft.clear();
+    }
+
+    fn remove(&mut self, range: core::ops::Range<usize>) {
+        let tail = Line::try_from(&self.line[range.end..]).unwrap_or_default();
+        self.line.truncate(range.start);
+        let _ = self.line.push_str(&tail);
+    }
+
+    /// Writes the prompt and the line again, over what was there, and puts
+    /// the cursor back.
+    pub fn redraw(&self, prompt: &str, out: &mut Output) {
+        out.write_str("\r");
+        out.write_str(prompt);
+        out.write_str(&self.line);
+        out.write_str("\x1b[K");
+        let left = self.line.len() - self.cursor;
+        if left > 0 {
+            write!(out, "\x1b[{left}D");
+        }
+    }
+}
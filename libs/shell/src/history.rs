@@ -0,0 +1,55 @@
+//! The lines entered before, for Up and Down to bring back.
+
+use crate::editor::MAX_LINE_LEN;
+
+/// How many lines are remembered.
+pub const HISTORY_LEN: usize = 8;
+
+pub type Line = heapless::String<MAX_LINE_LEN>;
+
+#[derive(Clone, Debug, Default)]
+pub struct History {
+    /// Ordered from the oldest to the most recent line.
+    lines: heapless::Deque<Line, HISTORY_LEN>,
+}
+
+impl History {
+    pub const fn new() -> Self {
+        Self {
+            lines: heapless::Deque::new(),
+        }
+    }
+
+    /// Remembers a line, unless it is blank or the same as the last one.
+    /// When full, the oldest line is forgotten.
+    pub fn push(&mut self, line: &str) {
+        if line.trim().is_empty() || self.get(0) == Some(line) {
+            return;
+        }
+        if self.lines.is_full() {
+            self.lines.pop_front();
+        }
+        let _ = self
+            .lines
+            .push_back(Line::try_from(line).unwrap_or_default());
+    }
+
+    /// The line entered `back` lines ago, 0 being the last one.
+    pub fn get(&self, back: usize) -> Option<&str> {
+        let i = self.lines.len().checked_sub(back + 1)?;
+        self.lines.iter().nth(i).map(|l| l.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// From the oldest to the most recent line.
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().map(|l| l.as_str())
+    }
+}
@@ -0,0 +1,342 @@
+//! A line-oriented command shell for the consoles of the ROMs, over anything
+//! that implements `embedded_io_async::{Read, Write}`: a UART, or the
+//! USB-Serial-JTAG.
+//!
+//! Lines are edited the way a terminal expects, with the arrow keys, Home
+//! and End, Ctrl-A/E/K/U/W/C/L, Up and Down through the history, and Tab to
+//! complete command names and arguments. A ROM registers its commands as a
+//! table of [`Command`]s, and their arguments are checked against their
+//! types before the handler sees them. `help` and `history` are built in.
+//!
+//! Nothing in here depends on esp-hal, so the same code runs on the host,
+//! see `tools/shell-sim`. Besides [`Shell::run`], the shell can be fed a
+//! byte at a time with [`Shell::feed`] and [`Shell::execute`].
+#![no_std]
+
+use core::fmt;
+
+use embedded_io_async::{Read, Write};
+
+pub mod command;
+pub mod editor;
+pub mod history;
+
+pub use command::{Arg, ArgKind, Args, Command, Value};
+use command::{next_word, parse};
+use editor::{Editor, Event, MAX_LINE_LEN};
+
+/// How much a command can write in one go.
+pub const OUTPUT_LEN: usize = 1024;
+
+pub const MAX_PROMPT_LEN: usize = 16;
+
+const TRUNCATED: &str = "\r\n(output truncated)\r\n";
+
+/// Room for telling the user the output was truncated, and the prompt.
+const SPARE_LEN: usize = TRUNCATED.len() + MAX_PROMPT_LEN;
+
+/// Column the help text of each command starts at in `help`.
+const HELP_COLUMN: usize = 24;
+
+/// What goes to the terminal, with every `\n` turned into `\r\n`.
+///
+/// Writing to it can't fail, so commands don't need to deal with errors:
+/// what doesn't fit is dropped, and the user told so.
+#[derive(Clone, Debug, Default)]
+pub struct Output {
+    buf: heapless::Vec<u8, { OUTPUT_LEN + SPARE_LEN }>,
+    truncated: bool,
+}
+
+impl Output {
+    pub const fn new() -> Self {
+        Self {
+            buf: heapless::Vec::new(),
+            truncated: false,
+        }
+    }
+
+    pub fn write_str(&mut self, s: &str) {
+        for &b in s.as_bytes() {
+            let len = if b == b'\n' { 2 } else { 1 };
+            if self.truncated || self.buf.len() + len > OUTPUT_LEN {
+                self.truncated = true;
+                return;
+            }
+            if b == b'\n' {
+                let _ = self.buf.push(b'\r');
+            }
+            let _ = self.buf.push(b);
+        }
+    }
+
+    /// For `write!` and `writeln!`, which don't need their result checked
+    /// this way.
+    pub fn write_fmt(&mut self, args: fmt::Arguments<'_>) {
+        let _ = fmt::Write::write_fmt(self, args);
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.truncated = false;
+    }
+
+    /// Tells the user if anything was dropped, and shows the prompt.
+    fn finish(&mut self, prompt: &str) {
+        if self.truncated {
+            let _ = self.buf.extend_from_slice(TRUNCATED.as_bytes());
+            self.truncated = false;
+        }
+        let _ = self.buf.extend_from_slice(prompt.as_bytes());
+    }
+}
+
+impl fmt::Write for Output {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        Output::write_str(self, s);
+        Ok(())
+    }
+}
+
+/// The built-in commands, which go before any of the same name.
+const BUILTINS: [(&str, &str, &str); 2] = [
+    (
+        "help",
+        "help [command]",
+        "List the commands, or explain one",
+    ),
+    ("history", "history", "List the lines entered before"),
+];
+
+pub struct Shell<C: 'static> {
+    commands: &'static [Command<C>],
+    prompt: &'static str,
+    editor: Editor,
+    out: Output,
+    started: bool,
+}
+
+impl<C> Shell<C> {
+    pub const fn new(commands: &'static [Command<C>], prompt: &'static str) -> Self {
+        assert!(prompt.len() <= MAX_PROMPT_LEN, "prompt is too long");
+        Self {
+            commands,
+            prompt,
+            editor: Editor::new(),
+            out: Output::new(),
+            started: false,
+        }
+    }
+
+    pub fn editor(&self) -> &Editor {
+        &self.editor
+    }
+
+    /// What has to go to the terminal.
+    pub fn output(&self) -> &[u8] {
+        self.out.as_bytes()
+    }
+
+    pub fn clear_output(&mut self) {
+        self.out.clear();
+    }
+
+    /// Shows the first prompt, unless it was already.
+    pub fn start(&mut self) {
+        if !self.started {
+            self.started = true;
+            self.out.write_str(self.prompt);
+        }
+    }
+
+    /// Handles a byte from the terminal. Returns whether a line was
+    /// entered, for [`Shell::execute`] to run.
+    pub fn feed(&mut self, byte: u8) -> bool {
+        self.start();
+        match self.editor.feed(byte, self.prompt, &mut self.out) {
+            Event::None => false,
+            Event::Line => true,
+            Event::Complete => {
+                self.complete();
+                false
+            }
+        }
+    }
+
+    /// Runs the line that was entered, and shows the prompt again.
+    pub fn execute(&mut self, ctx: &mut C) {
+        let line = self.editor.take_line();
+        let out = &mut self.out;
+        match next_word(&line) {
+            Some(("help", rest)) => self.help(rest.trim()),
+            Some(("history", rest)) if rest.trim().is_empty() => {
+                for (i, line) in self.editor.history().iter().enumerate() {
+                    writeln!(out, "{:>3}  {line}", i + 1);
+                }
+            }
+            Some(("history", _)) => writeln!(out, "Error: too many arguments"),
+            _ => match parse(self.commands, &line) {
+                Ok(Some((command, args))) => {
+                    if let Err(e) = (command.run)(ctx, &args, out) {
+                        writeln!(out, "Error: {e}");
+                    }
+                }
+                Ok(None) => (),
+                Err(e) => writeln!(out, "Error: {e}"),
+            },
+        }
+        self.out.finish(self.prompt);
+    }
+
+    /// Reads from `io` and writes back to it until it reports the end of
+    /// input, if it ever does. It can be called again after an error, and
+    /// carries on where it left off.
+    pub async fn run<T: Read + Write>(&mut self, io: &mut T, ctx: &mut C) -> Result<(), T::Error> {
+        self.start();
+        let mut buf = [0; 64];
+        loop {
+            self.flush(io).await?;
+            let n = io.read(&mut buf).await?;
+            if n == 0 {
+                return Ok(());
+            }
+            for &b in &buf[..n] {
+                // A redraw takes at most a line and the prompt
+                if self.out.len() > OUTPUT_LEN / 2 {
+                    self.flush(io).await?;
+                }
+                if self.feed(b) {
+                    // The command gets all of the output to itself
+                    self.flush(io).await?;
+                    self.execute(ctx);
+                    self.flush(io).await?;
+                }
+            }
+        }
+    }
+
+    async fn flush<T: Write>(&mut self, io: &mut T) -> Result<(), T::Error> {
+        if !self.out.is_empty() {
+            io.write_all(self.out.as_bytes()).await?;
+            io.flush().await?;
+            self.out.clear();
+        }
+        Ok(())
+    }
+
+    fn help(&mut self, name: &str) {
+        let out = &mut self.out;
+        if name.is_empty() {
+            for (_, usage, help) in BUILTINS {
+                writeln!(out, "  {usage:<HELP_COLUMN$}{help}");
+            }
+            for command in self.commands {
+                let mut usage = heapless::String::<MAX_LINE_LEN>::new();
+                let _ = fmt::Write::write_fmt(&mut usage, format_args!("{}", command.usage()));
+                writeln!(out, "  {usage:<HELP_COLUMN$}{}", command.help);
+            }
+            return;
+        }
+        if let Some((_, usage, help)) = BUILTINS.iter().find(|b| b.0 == name) {
+            writeln!(out, "{usage}\n  {help}");
+            return;
+        }
+        let Some(command) = self.commands.iter().find(|c| c.name == name) else {
+            writeln!(out, "Error: unknown command `{name}`");
+            return;
+        };
+        writeln!(out, "{}\n  {}", command.usage(), command.help);
+        for arg in command.args {
+            write!(out, "  <{}>  ", arg.name);
+            match arg.kind {
+                ArgKind::Int { min, max } => writeln!(out, "{min} to {max}"),
+                ArgKind::Bool => writeln!(out, "on or off"),
+                ArgKind::Choice(choices) => {
+                    for (i, choice) in choices.iter().enumerate() {
+                        let sep = if i == 0 { "" } else { ", " };
+                        write!(out, "{sep}{choice}");
+                    }
+                    writeln!(out);
+                }
+                ArgKind::Word => writeln!(out, "a word"),
+                ArgKind::Rest => writeln!(out, "the rest of the line"),
+            }
+        }
+    }
+
+    /// Completes the word before the cursor as far as it is unambiguous,
+    /// or lists what it could be.
+    fn complete(&mut self) {
+        let before = self.editor.before_cursor();
+        let start = before.rfind(' ').map_or(0, |i| i + 1);
+        let (words, partial) = before.split_at(start);
+        let mut words = words.split_whitespace();
+        let first = words.next();
+        let index = first.map_or(0, |_| 1 + words.count());
+
+        let names = index == 0 || (first == Some("help") && index == 1);
+        let arg_words = match first.and_then(|f| self.commands.iter().find(|c| c.name == f)) {
+            Some(command) if index > 0 && !BUILTINS.iter().any(|b| Some(b.0) == first) => {
+                let args = &command.args[..command.args.len().min(index)];
+                match args.get(index - 1) {
+                    // Past a `Rest`, it's all one argument
+                    Some(arg) if !args.iter().any(|a| a.kind == ArgKind::Rest) => arg.completions(),
+                    _ => &[],
+                }
+            }
+            _ => &[],
+        };
+        let commands = self.commands;
+        let candidates = move || {
+            BUILTINS
+                .iter()
+                .map(|b| b.0)
+                .chain(commands.iter().map(|c| c.name))
+                .filter(move |_| names)
+                .chain(arg_words.iter().copied())
+                .filter(move |c| c.starts_with(partial))
+        };
+
+        let Some(first) = candidates().next() else {
+            self.out.write_str("\x07");
+            return;
+        };
+        let common = candidates().fold(first.len(), |len, c| {
+            first
+                .bytes()
+                .zip(c.bytes())
+                .take(len)
+                .take_while(|(a, b)| a == b)
+                .count()
+        });
+        let partial_len = partial.len();
+        if candidates().nth(1).is_none() {
+            self.editor
+                .insert(&first[partial_len..], self.prompt, &mut self.out);
+            self.editor.insert(" ", self.prompt, &mut self.out);
+        } else if common > partial_len {
+            self.editor
+                .insert(&first[partial_len..common], self.prompt, &mut self.out);
+        } else {
+            self.out.write_str("\n");
+            for (i, c) in candidates().enumerate() {
+                let sep = if i == 0 { "" } else { "  " };
+                write!(self.out, "{sep}{c}");
+            }
+            self.out.write_str("\n");
+            self.editor.redraw(self.prompt, &mut self.out);
+        }
+    }
+}
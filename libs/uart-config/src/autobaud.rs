@@ -0,0 +1,99 @@
+//! Working out the baud rate of a line from the pulses on it.
+//!
+//! Whatever is sent, some of the pulses are a single bit long: every byte
+//! with two neighbouring bits that differ has one. So the shortest pulses
+//! give away the bit time, and the others, being whole multiples of it,
+//! help to pin it down. The rate that comes out is then rounded to the
+//! closest one people actually use.
+//!
+//! With the `esp-hal` feature, [`detect`] measures the pulses with the RMT,
+//! and makes sure the UART reads the line without framing errors at the
+//! rate it settles on.
+
+/// The rates [`closest_standard`] picks from. Slower lines can't be
+/// measured with the RMT, see [`detect`].
+pub const STANDARD_RATES: [u32; 19] = [
+    2400, 4800, 9600, 14_400, 19_200, 28_800, 38_400, 57_600, 74_880, 115_200, 230_400, 250_000,
+    460_800, 500_000, 921_600, 1_000_000, 1_500_000, 2_000_000, 3_000_000,
+];
+
+/// How far off a measured rate may be from a standard one, in percent.
+pub const TOLERANCE_PCT: u32 = 5;
+
+/// Fewer pulses than this might not include a single bit.
+pub const MIN_PULSES: usize = 16;
+
+/// How many pulses about as short as the shortest one there need to be,
+/// so that a single glitch doesn't pass for a bit.
+const MIN_SINGLE_BITS: usize = 3;
+
+/// Pulses longer than this many bits are the line idling between bytes.
+const MAX_BITS: u32 = 10;
+
+/// The baud rate that `pulses_ns`, the lengths of consecutive pulses in
+/// ns, were sent at, as measured.
+pub fn estimate(pulses_ns: &mut [u32]) -> Option<u32> {
+    if pulses_ns.len() < MIN_PULSES {
+        return None;
+    }
+    pulses_ns.sort_unstable();
+    // The single bits are the pulses about as short as the shortest one
+    let singles = (0..pulses_ns.len()).find_map(|i| {
+        let p = pulses_ns[i];
+        let n = pulses_ns[i..]
+            .iter()
+            .take_while(|&&q| q <= p + p / 4)
+            .count();
+        (p > 0 && n >= MIN_SINGLE_BITS).then_some(&pulses_ns[i..i + n])
+    })?;
+    let mut bit_ns = singles.iter().map(|&p| p as u64).sum::<u64>() / singles.len() as u64;
+
+    // Every pulse is a whole number of bits long, so the long ones pin the
+    // bit time down more precisely. Counting the bits in them takes a good
+    // guess at it to start with, and the better one that gives counts them
+    // more reliably again.
+    for _ in 0..2 {
+        let (mut total_ns, mut total_bits) = (0, 0);
+        for &p in pulses_ns.iter() {
+            let bits = (p as u64 + bit_ns / 2) / bit_ns;
+            if (1..=MAX_BITS as u64).contains(&bits) {
+                total_ns += p as u64;
+                total_bits += bits;
+            }
+        }
+        bit_ns = total_ns.checked_div(total_bits)?.max(1);
+    }
+    Some((1_000_000_000 / bit_ns) as u32)
+}
+
+/// The standard rate within [`TOLERANCE_PCT`] of `baud`, if any.
+pub fn closest_standard(baud: u32) -> Option<u32> {
+    STANDARD_RATES
+        .into_iter()
+        .min_by_key(|&rate| rate.abs_diff(baud) as u64 * 1000 / rate as u64)
+        .filter(|&rate| rate.abs_diff(baud) as u64 * 100 <= rate as u64 * TOLERANCE_PCT as u64)
+}
+
+/// The standard rates to try for a measured one, most likely first. If no
+/// pulse happened to be a single bit, the rate is really twice as high.
+pub fn candidates(measured: u32) -> impl Iterator<Item = u32> {
+    [measured, measured.saturating_mul(2)]
+        .into_iter()
+        .filter_map(closest_standard)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// Too few edges on the line to go by.
+    NoSignal,
+    /// The line looks like it runs at this rate, which is no standard one.
+    NotStandard(u32),
+    /// Every rate we tried made for framing errors.
+    FramingErrors,
+    /// The rate looks right, but the line went quiet before we could make
+    /// sure. It is applied nonetheless.
+    Unverified(u32),
+}
+
+#[cfg(feature = "esp-hal")]
+pub use crate::hal::{detect, rmt_config};
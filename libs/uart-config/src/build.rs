@@ -0,0 +1,28 @@
+//! The check of `UART_CONFIG` that the ROMs run in their `build.rs`.
+
+extern crate std;
+
+use std::{env, format, println};
+
+use crate::UartParams;
+
+/// Applies `UART_CONFIG` on top of the ROM's `default` commands, and fails
+/// the build if one of them is wrong, or the UART ends up on one of
+/// `reserved_pins`, each with what the ROM uses it for.
+///
+/// The ROM applies the same commands at boot, from
+/// `env!("UART_BUILD_CONFIG")`, which this sets.
+pub fn check_build_config(default: &str, reserved_pins: &[(u8, &str)]) {
+    println!("cargo:rerun-if-env-changed=UART_CONFIG");
+    let config = format!("{default}; {}", env::var("UART_CONFIG").unwrap_or_default());
+    let params = match UartParams::default().from_config(&config) {
+        Ok(params) => params,
+        Err((command, e)) => panic!("Invalid UART_CONFIG command {command:?}: {e}"),
+    };
+    for (pin, used_for) in reserved_pins {
+        if [params.tx_pin, params.rx_pin].contains(pin) {
+            panic!("Invalid UART_CONFIG, GPIO{pin} is {used_for}");
+        }
+    }
+    println!("cargo:rustc-env=UART_BUILD_CONFIG={config}");
+}
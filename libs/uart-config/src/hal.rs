@@ -0,0 +1,178 @@
+//! The parameters in the types of `esp_hal::uart`, and measuring the baud
+//! rate with the RMT.
+
+use embassy_time::{Duration, Instant, with_deadline};
+use esp_hal::{
+    Async,
+    gpio::AnyPin,
+    rmt::{PulseCode, RxChannelAsync, RxChannelConfig},
+    uart::{
+        Config, DataBits, Parity as HalParity, RxConfig, RxError, StopBits as HalStopBits, UartRx,
+    },
+};
+
+use crate::{
+    Parity, StopBits, UartParams,
+    autobaud::{Error, candidates, closest_standard, estimate},
+};
+
+impl UartParams {
+    /// Everything but the pins, for `Uart::new` and `apply_config`.
+    pub fn uart_config(&self) -> Config {
+        let data_bits = match self.data_bits {
+            5 => DataBits::_5,
+            6 => DataBits::_6,
+            7 => DataBits::_7,
+            _ => DataBits::_8,
+        };
+        let parity = match self.parity {
+            Parity::None => HalParity::None,
+            Parity::Even => HalParity::Even,
+            Parity::Odd => HalParity::Odd,
+        };
+        let stop_bits = match self.stop_bits {
+            StopBits::One => HalStopBits::_1,
+            StopBits::OneAndHalf => HalStopBits::_1p5,
+            StopBits::Two => HalStopBits::_2,
+        };
+        Config::default()
+            .with_baudrate(self.baud)
+            .with_data_bits(data_bits)
+            .with_parity(parity)
+            .with_stop_bits(stop_bits)
+            .with_rx(RxConfig::default().with_fifo_full_threshold(self.rx_fifo_threshold))
+    }
+
+    /// The TX and RX pins.
+    ///
+    /// # Safety
+    ///
+    /// Nothing else in the ROM may use them. The pins are only known once
+    /// the config has been parsed, so they can't be moved out of the
+    /// peripherals like usual.
+    pub unsafe fn pins(&self) -> (AnyPin, AnyPin) {
+        unsafe { (AnyPin::steal(self.tx_pin), AnyPin::steal(self.rx_pin)) }
+    }
+}
+
+/// The RMT counts in ticks of 80 MHz divided by this: 50 ns, fine enough
+/// for a bit at 3 Mbaud. A pulse code and the idle threshold top out at
+/// 0x7fff ticks, about 1.6 ms, and a capture ends on any pulse longer than
+/// that. At 2400 baud that still leaves pulses of up to 3 bits, at 1200 it
+/// would only be single ones, which is why that isn't a standard rate.
+const RMT_DIVIDER: u8 = 4;
+const TICK_NS: u32 = 50;
+
+/// How many pulses to go by. More make for a better estimate, but take
+/// longer to come by on a line that's quiet most of the time.
+const WANTED_PULSES: usize = 128;
+
+/// How long to wait for the pulses, and then for bytes to verify the rate
+/// with.
+const LISTEN_TIME: Duration = Duration::from_secs(5);
+const VERIFY_TIME: Duration = Duration::from_secs(2);
+
+/// How many bytes have to come in without framing errors.
+const VERIFY_BYTES: usize = 16;
+
+/// An RMT channel config for [`detect`], to be set up on the RX pin of the
+/// UART.
+pub fn rmt_config() -> RxChannelConfig {
+    RxChannelConfig::default()
+        .with_clk_divider(RMT_DIVIDER)
+        // The longest a line idles between bytes that still ends up as a
+        // pulse, rather than the end of what we received
+        .with_idle_threshold(0x7fff)
+}
+
+/// Measures the baud rate of the line the UART receives on, with `rmt` on
+/// the same pin, and applies it to `params` and the UART. The RX half is
+/// enough, the config it takes goes for the TX half as well.
+///
+/// The rest of `params` has to be right already, or the rate can't be
+/// verified. If it can't be found, the UART is left as it was. Lines slower
+/// than 2400 baud are out of reach, see `RMT_DIVIDER`.
+pub async fn detect(
+    uart: &mut UartRx<'static, Async>,
+    rmt: &mut impl RxChannelAsync,
+    params: &mut UartParams,
+) -> Result<u32, Error> {
+    let mut pulses = [0; WANTED_PULSES];
+    let n = listen(rmt, &mut pulses).await;
+    let measured = estimate(&mut pulses[..n]).ok_or(Error::NoSignal)?;
+
+    let original = params.baud;
+    let mut unverified = None;
+    for baud in candidates(measured) {
+        params.baud = baud;
+        let _ = uart.apply_config(&params.uart_config());
+        match verify(uart).await {
+            Some(true) => return Ok(baud),
+            Some(false) => (),
+            None => {
+                unverified.get_or_insert(baud);
+            }
+        }
+    }
+    params.baud = unverified.unwrap_or(original);
+    let _ = uart.apply_config(&params.uart_config());
+    match unverified {
+        Some(baud) => Err(Error::Unverified(baud)),
+        None if closest_standard(measured).is_none() => Err(Error::NotStandard(measured)),
+        None => Err(Error::FramingErrors),
+    }
+}
+
+/// Collects the lengths of the pulses on the line in ns, and returns how
+/// many it got.
+async fn listen(rmt: &mut impl RxChannelAsync, pulses: &mut [u32]) -> usize {
+    let deadline = Instant::now() + LISTEN_TIME;
+    let mut n = 0;
+    while n < pulses.len() {
+        let mut codes = [0u32; 48];
+        match with_deadline(deadline, rmt.receive(&mut codes)).await {
+            Ok(Ok(())) => (),
+            Ok(Err(_)) => continue,
+            Err(_) => break,
+        }
+        // The first pulse started before we were listening, and the last
+        // one is the line going idle
+        let mut lengths = [0; 96];
+        let mut received = 0;
+        for length in codes
+            .iter()
+            .flat_map(|c| [c.length1(), c.length2()])
+            .take_while(|&l| l != 0)
+        {
+            lengths[received] = length as u32 * TICK_NS;
+            received += 1;
+        }
+        for &length in lengths[..received.saturating_sub(1)].iter().skip(1) {
+            if n == pulses.len() {
+                break;
+            }
+            pulses[n] = length;
+            n += 1;
+        }
+    }
+    n
+}
+
+/// Whether the UART reads the line without framing errors, or `None` if
+/// too little came in to tell.
+async fn verify(uart: &mut UartRx<'static, Async>) -> Option<bool> {
+    let deadline = Instant::now() + VERIFY_TIME;
+    let mut buf = [0; 64];
+    // Whatever is in the FIFO came in at the old rate
+    let _ = with_deadline(deadline, uart.read_async(&mut buf)).await;
+    let mut clean = 0;
+    while clean < VERIFY_BYTES {
+        match with_deadline(deadline, uart.read_async(&mut buf)).await {
+            Ok(Ok(n)) => clean += n,
+            Ok(Err(RxError::FrameFormatViolated | RxError::ParityMismatch)) => return Some(false),
+            Ok(Err(_)) => (),
+            Err(_) => return None,
+        }
+    }
+    Some(true)
+}
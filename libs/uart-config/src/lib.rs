@@ -0,0 +1,267 @@
+//! UART parameters for the ROMs that talk to another device over a UART, and
+//! the console commands that change them.
+//!
+//! A ROM starts from its own defaults, and applies `UART_CONFIG` on top at
+//! build time: a `;` separated list of the same commands it takes at
+//! runtime, e.g. `UART_CONFIG="baud 9600; format 8E1; pins 4 5"`. Its
+//! `build.rs` runs them through `check_build_config` from the `build`
+//! feature as well, so a typo fails the build rather than the boot. The
+//! `esp-hal` feature turns the parameters into what `esp_hal::uart` takes.
+//!
+//! When the baud rate isn't known up front, [`autobaud`] finds it.
+#![no_std]
+
+use core::fmt;
+
+pub mod autobaud;
+#[cfg(feature = "build")]
+mod build;
+#[cfg(feature = "esp-hal")]
+mod hal;
+
+#[cfg(feature = "build")]
+pub use build::check_build_config;
+
+/// The UART runs off the 80 MHz APB clock, divided down to at least this
+/// many times the baud rate.
+pub const MAX_BAUD: u32 = 5_000_000;
+pub const MIN_BAUD: u32 = 300;
+
+/// The RX FIFO holds 128 bytes, and the threshold has to be below that.
+pub const MAX_RX_FIFO_THRESHOLD: u16 = 127;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    OneAndHalf,
+    Two,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct UartParams {
+    pub baud: u32,
+    /// 5 to 8.
+    pub data_bits: u8,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub tx_pin: u8,
+    pub rx_pin: u8,
+    /// How many bytes the RX FIFO collects before a read returns, unless
+    /// the line goes quiet first.
+    pub rx_fifo_threshold: u16,
+}
+
+impl Default for UartParams {
+    /// 115200 8N1 on the pins of the UART0 console.
+    fn default() -> Self {
+        Self {
+            baud: 115_200,
+            data_bits: 8,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            tx_pin: 21,
+            rx_pin: 20,
+            rx_fifo_threshold: 64,
+        }
+    }
+}
+
+impl UartParams {
+    /// Applies a `;` separated list of commands, and tells which one was
+    /// wrong if any.
+    pub fn from_config(mut self, config: &str) -> Result<Self, (&str, &'static str)> {
+        for command in config.split(';') {
+            Command::parse(command)
+                .and_then(|c| self.apply(c))
+                .map_err(|e| (command.trim(), e))?;
+        }
+        Ok(self)
+    }
+
+    /// Applies a command. Commands that don't change the parameters are
+    /// ignored.
+    pub fn apply(&mut self, command: Command) -> Result<(), &'static str> {
+        match command {
+            Command::Baud(baud) => self.baud = baud,
+            Command::Format {
+                data_bits,
+                parity,
+                stop_bits,
+            } => {
+                self.data_bits = data_bits;
+                self.parity = parity;
+                self.stop_bits = stop_bits;
+            }
+            Command::Pins { tx, rx } => {
+                if tx == rx {
+                    return Err("TX and RX must be different pins");
+                }
+                self.tx_pin = tx;
+                self.rx_pin = rx;
+            }
+            Command::RxThreshold(threshold) => self.rx_fifo_threshold = threshold,
+            Command::Nothing | Command::Show | Command::Help => (),
+        }
+        Ok(())
+    }
+
+    /// How many bits a character takes on the line: the start bit, the data,
+    /// the parity bit if any, and the stop bits, 1.5 counting as 2.
+    pub fn frame_bits(&self) -> u32 {
+        let parity = match self.parity {
+            Parity::None => 0,
+            Parity::Even | Parity::Odd => 1,
+        };
+        let stop = match self.stop_bits {
+            StopBits::One => 1,
+            StopBits::OneAndHalf | StopBits::Two => 2,
+        };
+        1 + self.data_bits as u32 + parity + stop
+    }
+
+    /// Runs a command typed into a console, and writes the reply to `out`.
+    /// Returns whether the parameters changed, in which case the UART
+    /// needs them applied.
+    pub fn execute(&mut self, line: &str, out: &mut impl fmt::Write) -> Result<bool, fmt::Error> {
+        match Command::parse(line) {
+            Ok(Command::Nothing) => Ok(false),
+            Ok(Command::Help) => writeln!(out, "{HELP}").map(|_| false),
+            Ok(Command::Show) => writeln!(out, "{self}").map(|_| false),
+            // The pins are handed to the UART once at boot
+            Ok(Command::Pins { .. }) => {
+                writeln!(out, "Error: pins can only be set at build time").map(|_| false)
+            }
+            Ok(command) => match self.apply(command) {
+                Ok(()) => writeln!(out, "{self}").map(|_| true),
+                Err(e) => writeln!(out, "Error: {e}").map(|_| false),
+            },
+            Err(e) => writeln!(out, "Error: {e}").map(|_| false),
+        }
+    }
+}
+
+impl fmt::Display for UartParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parity = match self.parity {
+            Parity::None => 'N',
+            Parity::Even => 'E',
+            Parity::Odd => 'O',
+        };
+        let stop_bits = match self.stop_bits {
+            StopBits::One => "1",
+            StopBits::OneAndHalf => "1.5",
+            StopBits::Two => "2",
+        };
+        write!(
+            f,
+            "baud={} format={}{parity}{stop_bits} tx=GPIO{} rx=GPIO{} rx-threshold={}",
+            self.baud, self.data_bits, self.tx_pin, self.rx_pin, self.rx_fifo_threshold
+        )
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// An empty line.
+    Nothing,
+    Baud(u32),
+    Format {
+        data_bits: u8,
+        parity: Parity,
+        stop_bits: StopBits,
+    },
+    Pins {
+        tx: u8,
+        rx: u8,
+    },
+    RxThreshold(u16),
+    /// Print the current parameters.
+    Show,
+    Help,
+}
+
+pub const HELP: &str = "\
+Commands:
+  baud <n>              Baud rate, 300 to 5000000
+  format <d><p><s>      Data bits 5-8, parity N/E/O and stop bits 1/1.5/2, e.g. `format 8E1`
+  pins <tx> <rx>        TX and RX GPIOs, only at build time
+  rx-threshold <bytes>  How many bytes to collect before passing them on, 1 to 127
+  show                  Show the current parameters";
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Self, &'static str> {
+        let line = line.trim();
+        let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+        let args = args.trim();
+
+        match name {
+            "" => Ok(Command::Nothing),
+            "baud" => match args.parse() {
+                Ok(baud @ MIN_BAUD..=MAX_BAUD) => Ok(Command::Baud(baud)),
+                Ok(_) => Err("baud rate must be between 300 and 5000000"),
+                Err(_) => Err("invalid baud rate"),
+            },
+            "format" => parse_format(args),
+            "pins" => {
+                let mut args = args.split_whitespace();
+                let (Some(tx), Some(rx), None) = (args.next(), args.next(), args.next()) else {
+                    return Err("pins takes a TX and an RX pin");
+                };
+                Ok(Command::Pins {
+                    tx: parse_pin(tx)?,
+                    rx: parse_pin(rx)?,
+                })
+            }
+            "rx-threshold" => match args.parse() {
+                Ok(threshold @ 1..=MAX_RX_FIFO_THRESHOLD) => Ok(Command::RxThreshold(threshold)),
+                _ => Err("RX threshold must be between 1 and 127"),
+            },
+            "show" => Ok(Command::Show),
+            "help" => Ok(Command::Help),
+            _ => Err("unknown command, try `help`"),
+        }
+    }
+}
+
+/// Parses a format like `8E1`, as the `format` command takes it.
+pub fn parse_format(s: &str) -> Result<Command, &'static str> {
+    let mut chars = s.chars();
+    let data_bits = match chars.next() {
+        Some(c @ '5'..='8') => c as u8 - b'0',
+        _ => return Err("data bits must be between 5 and 8"),
+    };
+    let parity = match chars.next().map(|c| c.to_ascii_uppercase()) {
+        Some('N') => Parity::None,
+        Some('E') => Parity::Even,
+        Some('O') => Parity::Odd,
+        _ => return Err("parity must be one of N, E or O"),
+    };
+    let stop_bits = match chars.as_str() {
+        "1" => StopBits::One,
+        "1.5" => StopBits::OneAndHalf,
+        "2" => StopBits::Two,
+        _ => return Err("stop bits must be one of 1, 1.5 or 2"),
+    };
+    Ok(Command::Format {
+        data_bits,
+        parity,
+        stop_bits,
+    })
+}
+
+/// GPIO12 to GPIO17 are wired to the flash, and GPIO18 and GPIO19 to USB.
+fn parse_pin(s: &str) -> Result<u8, &'static str> {
+    let s = s.strip_prefix("GPIO").unwrap_or(s);
+    match s.parse() {
+        Ok(pin @ (0..=11 | 20 | 21)) => Ok(pin),
+        Ok(12..=19) => Err("GPIO12 to GPIO19 are taken by the flash and USB"),
+        _ => Err("pins must be between 0 and 21"),
+    }
+}
@@ -0,0 +1,238 @@
+//! Heuristics for spotting attacks on our networks. Nothing in here touches
+//! the radio, frames are fed in together with the time they were received.
+
+use core::fmt;
+
+use crate::{Mac, frame::Frame};
+
+/// More deauthentications/disassociations than this within
+/// `DEAUTH_WINDOW_MS` is considered a flood. A handful is normal when
+/// clients roam or APs restart.
+pub const DEAUTH_THRESHOLD: u32 = 20;
+pub const DEAUTH_WINDOW_MS: u64 = 1000;
+
+/// More distinct BSSIDs beaconing than this within `BEACON_WINDOW_MS` is
+/// considered a flood. This has to be tuned to the environment; an office
+/// building can easily have 50 real access points in range.
+pub const BEACON_THRESHOLD: usize = 60;
+pub const BEACON_WINDOW_MS: u64 = 10_000;
+
+pub const MAX_OUR_SSIDS: usize = 4;
+pub const MAX_KNOWN_BSSIDS: usize = 16;
+/// We only report each evil twin once, but have to remember them to do so.
+pub const MAX_REPORTED_TWINS: usize = 16;
+
+/// The networks we are responsible for: the SSIDs, and the BSSIDs of the
+/// access points that are allowed to announce them.
+#[derive(Clone, Debug, Default)]
+pub struct Networks {
+    pub ssids: heapless::Vec<heapless::String<32>, MAX_OUR_SSIDS>,
+    pub bssids: heapless::Vec<[u8; 6], MAX_KNOWN_BSSIDS>,
+}
+
+impl Networks {
+    /// Reads the networks from comma separated lists of SSIDs and of
+    /// BSSIDs, as `MONITOR_SSIDS` and `MONITOR_BSSIDS` give them. Both are
+    /// trimmed and empty ones skipped. Tells which one was wrong if any.
+    pub fn from_config<'a>(
+        ssids: &'a str,
+        bssids: &'a str,
+    ) -> Result<Self, (&'a str, &'static str)> {
+        let mut networks = Self::default();
+        for s in ssids.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let ssid = s
+                .try_into()
+                .map_err(|_| (s, "SSID is longer than 32 bytes"))?;
+            networks
+                .ssids
+                .push(ssid)
+                .map_err(|_| (s, "too many SSIDs"))?;
+        }
+        for s in bssids.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let bssid = parse_mac(s).ok_or((s, "invalid BSSID"))?;
+            networks
+                .bssids
+                .push(bssid)
+                .map_err(|_| (s, "too many BSSIDs"))?;
+        }
+        Ok(networks)
+    }
+
+    fn is_our_ssid(&self, ssid: &[u8]) -> bool {
+        self.ssids.iter().any(|s| s.as_bytes() == ssid)
+    }
+}
+
+/// Parses a MAC address written as `aa:bb:cc:dd:ee:ff`.
+pub fn parse_mac(s: &str) -> Option<[u8; 6]> {
+    let mut mac = [0; 6];
+    let mut parts = s.split(':');
+    for byte in &mut mac {
+        *byte = u8::from_str_radix(parts.next()?, 16).ok()?;
+    }
+    parts.next().is_none().then_some(mac)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Alert {
+    DeauthFlood {
+        count: u32,
+        /// The last frame we saw, which is likely to be representative.
+        src: [u8; 6],
+        dst: [u8; 6],
+        reason: u16,
+    },
+    BeaconFlood {
+        bssids: usize,
+    },
+    EvilTwin {
+        ssid: heapless::String<32>,
+        bssid: [u8; 6],
+    },
+}
+
+/// Counts events in fixed windows of time.
+struct Window {
+    start_ms: u64,
+    count: u32,
+    reported: bool,
+}
+
+impl Window {
+    const fn new() -> Self {
+        Self {
+            start_ms: 0,
+            count: 0,
+            reported: false,
+        }
+    }
+
+    /// Starts a new window if the current one has run out. Returns true if
+    /// a new window was started.
+    fn roll(&mut self, now_ms: u64, length_ms: u64) -> bool {
+        if now_ms.saturating_sub(self.start_ms) < length_ms {
+            return false;
+        }
+        self.start_ms = now_ms;
+        self.count = 0;
+        self.reported = false;
+        true
+    }
+}
+
+pub struct Detector {
+    networks: Networks,
+    deauths: Window,
+    beacons: Window,
+    beacon_bssids: heapless::Vec<[u8; 6], BEACON_THRESHOLD>,
+    reported_twins: heapless::Vec<[u8; 6], MAX_REPORTED_TWINS>,
+}
+
+impl Detector {
+    pub fn new(networks: Networks) -> Self {
+        Self {
+            networks,
+            deauths: Window::new(),
+            beacons: Window::new(),
+            beacon_bssids: heapless::Vec::new(),
+            reported_twins: heapless::Vec::new(),
+        }
+    }
+
+    /// Looks at a single frame. Each kind of alert is only raised once per
+    /// window, or once per BSSID for evil twins.
+    pub fn on_frame(&mut self, now_ms: u64, frame: &Frame<'_>) -> Option<Alert> {
+        match *frame {
+            Frame::Kick {
+                src, dst, reason, ..
+            } => {
+                self.deauths.roll(now_ms, DEAUTH_WINDOW_MS);
+                self.deauths.count += 1;
+                if self.deauths.count > DEAUTH_THRESHOLD && !self.deauths.reported {
+                    self.deauths.reported = true;
+                    return Some(Alert::DeauthFlood {
+                        count: self.deauths.count,
+                        src,
+                        dst,
+                        reason,
+                    });
+                }
+                None
+            }
+            Frame::Announcement {
+                bssid,
+                ssid,
+                beacon,
+            } => {
+                // A twin whose beacon tips off a flood is reported with its
+                // next one
+                let flood = match beacon {
+                    true => self.count_beacon(now_ms, bssid),
+                    false => None,
+                };
+                flood.or_else(|| self.check_twin(bssid, ssid))
+            }
+            Frame::Other => None,
+        }
+    }
+
+    fn count_beacon(&mut self, now_ms: u64, bssid: [u8; 6]) -> Option<Alert> {
+        if self.beacons.roll(now_ms, BEACON_WINDOW_MS) {
+            self.beacon_bssids.clear();
+        }
+        if self.beacon_bssids.contains(&bssid) {
+            return None;
+        }
+        self.beacons.count += 1;
+        // Once the list is full we are past the threshold, and only need to
+        // keep counting
+        let _ = self.beacon_bssids.push(bssid);
+        if self.beacons.count as usize > BEACON_THRESHOLD && !self.beacons.reported {
+            self.beacons.reported = true;
+            return Some(Alert::BeaconFlood {
+                bssids: self.beacons.count as usize,
+            });
+        }
+        None
+    }
+
+    fn check_twin(&mut self, bssid: [u8; 6], ssid: &[u8]) -> Option<Alert> {
+        if !self.networks.is_our_ssid(ssid)
+            || self.networks.bssids.contains(&bssid)
+            || self.reported_twins.contains(&bssid)
+        {
+            return None;
+        }
+        if self.reported_twins.is_full() {
+            self.reported_twins.remove(0);
+        }
+        let _ = self.reported_twins.push(bssid);
+        let ssid = core::str::from_utf8(ssid).ok()?.try_into().ok()?;
+        Some(Alert::EvilTwin { ssid, bssid })
+    }
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Alert::DeauthFlood {
+                count,
+                src,
+                dst,
+                reason,
+            } => write!(
+                f,
+                "deauth-flood count={count} within {DEAUTH_WINDOW_MS}ms, last src={} dst={} reason={reason}",
+                Mac(src),
+                Mac(dst),
+            ),
+            Alert::BeaconFlood { bssids } => write!(
+                f,
+                "beacon-flood bssids={bssids} within {BEACON_WINDOW_MS}ms"
+            ),
+            Alert::EvilTwin { ssid, bssid } => {
+                write!(f, "evil-twin ssid={ssid:?} bssid={}", Mac(bssid))
+            }
+        }
+    }
+}
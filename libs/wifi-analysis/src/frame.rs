@@ -0,0 +1,98 @@
+//! Just enough 802.11 parsing to tell the management frames we care about
+//! apart.
+
+const TYPE_MGMT: u8 = 0;
+
+const SUBTYPE_PROBE_RESPONSE: u8 = 5;
+const SUBTYPE_BEACON: u8 = 8;
+const SUBTYPE_DISASSOC: u8 = 10;
+const SUBTYPE_DEAUTH: u8 = 12;
+
+const HEADER_LEN: usize = 24;
+/// Timestamp, beacon interval and capabilities come before the elements in
+/// beacons and probe responses.
+const BEACON_FIXED_LEN: usize = 12;
+
+const ELEMENT_SSID: u8 = 0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Frame<'a> {
+    /// A beacon or a probe response, both of which announce a network.
+    Announcement {
+        bssid: [u8; 6],
+        ssid: &'a [u8],
+        beacon: bool,
+    },
+    /// A deauthentication or disassociation.
+    Kick {
+        src: [u8; 6],
+        dst: [u8; 6],
+        bssid: [u8; 6],
+        reason: u16,
+        deauth: bool,
+    },
+    Other,
+}
+
+fn mac(data: &[u8], offset: usize) -> [u8; 6] {
+    data[offset..offset + 6].try_into().unwrap()
+}
+
+/// Parses a frame, starting with the frame control field. Truncated or
+/// otherwise malformed frames are returned as `Frame::Other`.
+pub fn parse(data: &[u8]) -> Frame<'_> {
+    if data.len() < HEADER_LEN {
+        return Frame::Other;
+    }
+    let frame_type = (data[0] >> 2) & 0b11;
+    let subtype = data[0] >> 4;
+    if frame_type != TYPE_MGMT {
+        return Frame::Other;
+    }
+
+    let dst = mac(data, 4);
+    let src = mac(data, 10);
+    let bssid = mac(data, 16);
+    let body = &data[HEADER_LEN..];
+
+    match subtype {
+        SUBTYPE_BEACON | SUBTYPE_PROBE_RESPONSE => {
+            let Some(elements) = body.get(BEACON_FIXED_LEN..) else {
+                return Frame::Other;
+            };
+            let Some(ssid) = find_element(elements, ELEMENT_SSID) else {
+                return Frame::Other;
+            };
+            Frame::Announcement {
+                bssid,
+                ssid,
+                beacon: subtype == SUBTYPE_BEACON,
+            }
+        }
+        SUBTYPE_DEAUTH | SUBTYPE_DISASSOC => {
+            let Some(reason) = body.get(..2) else {
+                return Frame::Other;
+            };
+            Frame::Kick {
+                src,
+                dst,
+                bssid,
+                reason: u16::from_le_bytes([reason[0], reason[1]]),
+                deauth: subtype == SUBTYPE_DEAUTH,
+            }
+        }
+        _ => Frame::Other,
+    }
+}
+
+fn find_element(mut elements: &[u8], id: u8) -> Option<&[u8]> {
+    while let [element_id, len, rest @ ..] = elements {
+        let len = *len as usize;
+        let value = rest.get(..len)?;
+        if *element_id == id {
+            return Some(value);
+        }
+        elements = &rest[len..];
+    }
+    None
+}
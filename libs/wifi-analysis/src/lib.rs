@@ -0,0 +1,26 @@
+//! What the Wi-Fi ROMs make of the radio, without touching it.
+//!
+//! [`link`] watches the signal of the access point a station is connected
+//! to, and tells it when to roam. [`tracker`] follows access points across
+//! scans, and tells when they come, go and change. [`frame`] picks apart the
+//! management frames a sniffer captures, and [`detect`] looks for attacks in
+//! them. The ROMs hand them what they read from the driver, so the same code
+//! runs on the host as well, see `tools/wifi-sim`.
+#![no_std]
+
+use core::fmt;
+
+pub mod detect;
+pub mod frame;
+pub mod link;
+pub mod tracker;
+
+/// Shows a MAC address as `aa:bb:cc:dd:ee:ff`.
+struct Mac<'a>(&'a [u8; 6]);
+
+impl fmt::Display for Mac<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
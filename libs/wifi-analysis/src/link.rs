@@ -0,0 +1,129 @@
+use heapless::HistoryBuffer;
+
+/// Number of samples kept around for computing the average signal strength.
+pub const HISTORY_LEN: usize = 16;
+
+/// Signal strength (in dBm) below which the link is considered weak.
+pub const WEAK_RSSI: i8 = -75;
+
+/// How many dB above `WEAK_RSSI` the signal has to climb before the link
+/// is considered good again. This keeps us from spamming warnings when the
+/// signal hovers around the threshold.
+pub const HYSTERESIS: i8 = 5;
+
+/// How many weak samples in a row before we try to find a better access point.
+pub const ROAM_AFTER: usize = 6;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PhyMode {
+    B,
+    G,
+    N,
+    LongRange,
+    Unknown,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LinkSample {
+    pub rssi: i8,
+    pub channel: u8,
+    pub phy: PhyMode,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LinkEvent {
+    /// The signal dropped below `WEAK_RSSI`.
+    Degraded { rssi: i8 },
+    /// The signal is back above `WEAK_RSSI + HYSTERESIS`.
+    Recovered { rssi: i8 },
+    /// The AP moved to a different channel while we were connected.
+    ChannelChanged { from: u8, to: u8 },
+    /// The signal has been weak for `ROAM_AFTER` samples in a row.
+    Roam,
+}
+
+/// Keeps track of the signal quality of the access point we are connected to.
+///
+/// This does not touch the radio at all, it only looks at the samples it is
+/// given, so it can be driven from anywhere.
+pub struct LinkMonitor {
+    history: HistoryBuffer<LinkSample, HISTORY_LEN>,
+    weak: bool,
+    weak_count: usize,
+}
+
+impl Default for LinkMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LinkMonitor {
+    pub const fn new() -> Self {
+        Self {
+            history: HistoryBuffer::new(),
+            weak: false,
+            weak_count: 0,
+        }
+    }
+
+    /// Forget everything we know, e.g. after reconnecting.
+    pub fn reset(&mut self) {
+        self.history.clear();
+        self.weak = false;
+        self.weak_count = 0;
+    }
+
+    pub fn average_rssi(&self) -> Option<i8> {
+        if self.history.is_empty() {
+            return None;
+        }
+        let sum: i32 = self.history.as_slice().iter().map(|s| s.rssi as i32).sum();
+        Some((sum / self.history.len() as i32) as i8)
+    }
+
+    pub fn min_rssi(&self) -> Option<i8> {
+        self.history.as_slice().iter().map(|s| s.rssi).min()
+    }
+
+    pub fn max_rssi(&self) -> Option<i8> {
+        self.history.as_slice().iter().map(|s| s.rssi).max()
+    }
+
+    /// Record a new sample. At most one event is returned per sample, with
+    /// the most important one winning.
+    pub fn record(&mut self, sample: LinkSample) -> Option<LinkEvent> {
+        let previous_channel = self.history.recent().map(|s| s.channel);
+        self.history.write(sample);
+
+        let mut event = match previous_channel {
+            Some(from) if from != sample.channel => Some(LinkEvent::ChannelChanged {
+                from,
+                to: sample.channel,
+            }),
+            _ => None,
+        };
+
+        if self.weak {
+            if sample.rssi >= WEAK_RSSI.saturating_add(HYSTERESIS) {
+                self.weak = false;
+                self.weak_count = 0;
+                event = Some(LinkEvent::Recovered { rssi: sample.rssi });
+            } else if sample.rssi < WEAK_RSSI {
+                self.weak_count += 1;
+                if self.weak_count >= ROAM_AFTER {
+                    // Start counting from scratch, so we only ask for a roam
+                    // once every `ROAM_AFTER` samples.
+                    self.weak_count = 0;
+                    event = Some(LinkEvent::Roam);
+                }
+            }
+        } else if sample.rssi < WEAK_RSSI {
+            self.weak = true;
+            self.weak_count = 1;
+            event = Some(LinkEvent::Degraded { rssi: sample.rssi });
+        }
+
+        event
+    }
+}
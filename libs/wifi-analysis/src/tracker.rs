@@ -0,0 +1,245 @@
+use core::fmt::{self, Write};
+
+use crate::Mac;
+
+/// How many scans in a row an access point has to be missing before we
+/// consider it gone. Scans regularly miss an AP or two, so reporting them as
+/// gone right away would be very noisy.
+pub const DISAPPEAR_AFTER_SCANS: u32 = 3;
+
+/// An access point as seen in a single scan.
+#[derive(Copy, Clone, Debug)]
+pub struct Sighting<'a> {
+    pub bssid: [u8; 6],
+    pub ssid: &'a [u8],
+    pub channel: u8,
+    pub rssi: i8,
+    pub auth: &'static str,
+}
+
+/// Everything we know about an access point across scans.
+#[derive(Clone, Debug)]
+struct ApEntry {
+    bssid: [u8; 6],
+    ssid: heapless::Vec<u8, 32>,
+    channel: u8,
+    auth: &'static str,
+    first_seen_ms: u64,
+    last_seen_ms: u64,
+    min_rssi: i8,
+    max_rssi: i8,
+    rssi_sum: i64,
+    samples: u32,
+    last_scan: u32,
+}
+
+impl ApEntry {
+    fn avg_rssi(&self) -> i8 {
+        (self.rssi_sum / self.samples as i64) as i8
+    }
+
+    fn disappeared(&self) -> TrackEvent {
+        TrackEvent::Disappeared {
+            bssid: self.bssid,
+            first_seen_ms: self.first_seen_ms,
+            last_seen_ms: self.last_seen_ms,
+            min_rssi: self.min_rssi,
+            max_rssi: self.max_rssi,
+            avg_rssi: self.avg_rssi(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TrackEvent {
+    Appeared {
+        bssid: [u8; 6],
+        ssid: heapless::Vec<u8, 32>,
+        channel: u8,
+        rssi: i8,
+    },
+    /// Includes a summary of what we saw while the AP was around. Also sent
+    /// for the AP that makes room when the table is full.
+    Disappeared {
+        bssid: [u8; 6],
+        first_seen_ms: u64,
+        last_seen_ms: u64,
+        min_rssi: i8,
+        max_rssi: i8,
+        avg_rssi: i8,
+    },
+    ChannelChanged {
+        bssid: [u8; 6],
+        from: u8,
+        to: u8,
+    },
+    SecurityChanged {
+        bssid: [u8; 6],
+        from: &'static str,
+        to: &'static str,
+    },
+}
+
+/// Keeps a table of up to `N` access points across scans, and reports when
+/// they come and go or change in interesting ways.
+///
+/// Each scan is fed to the tracker by calling `observe` for every access
+/// point, followed by a single call to `end_scan`. If the table is full,
+/// the access point that was seen the longest time ago is reported as gone
+/// to make room for new ones.
+pub struct Tracker<const N: usize> {
+    entries: heapless::Vec<ApEntry, N>,
+    scan: u32,
+}
+
+impl<const N: usize> Default for Tracker<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Tracker<N> {
+    pub const fn new() -> Self {
+        Self {
+            entries: heapless::Vec::new(),
+            scan: 0,
+        }
+    }
+
+    /// Forget all access points, without reporting them as gone.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn observe(
+        &mut self,
+        now_ms: u64,
+        sighting: &Sighting<'_>,
+        mut emit: impl FnMut(TrackEvent),
+    ) {
+        let scan = self.scan;
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.bssid == sighting.bssid) {
+            if entry.channel != sighting.channel {
+                emit(TrackEvent::ChannelChanged {
+                    bssid: entry.bssid,
+                    from: entry.channel,
+                    to: sighting.channel,
+                });
+                entry.channel = sighting.channel;
+            }
+            if entry.auth != sighting.auth {
+                emit(TrackEvent::SecurityChanged {
+                    bssid: entry.bssid,
+                    from: entry.auth,
+                    to: sighting.auth,
+                });
+                entry.auth = sighting.auth;
+            }
+            // Hidden networks sometimes reveal their SSID in probe responses
+            if !sighting.ssid.is_empty() {
+                entry.ssid = heapless::Vec::from_slice(sighting.ssid).unwrap_or_default();
+            }
+            entry.last_seen_ms = now_ms;
+            entry.min_rssi = entry.min_rssi.min(sighting.rssi);
+            entry.max_rssi = entry.max_rssi.max(sighting.rssi);
+            entry.rssi_sum += sighting.rssi as i64;
+            entry.samples += 1;
+            entry.last_scan = scan;
+            return;
+        }
+
+        if self.entries.is_full() {
+            let oldest = self
+                .entries
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, e)| e.last_seen_ms)
+                .map(|(i, _)| i)
+                .unwrap();
+            emit(self.entries.swap_remove(oldest).disappeared());
+        }
+
+        let entry = ApEntry {
+            bssid: sighting.bssid,
+            ssid: heapless::Vec::from_slice(sighting.ssid).unwrap_or_default(),
+            channel: sighting.channel,
+            auth: sighting.auth,
+            first_seen_ms: now_ms,
+            last_seen_ms: now_ms,
+            min_rssi: sighting.rssi,
+            max_rssi: sighting.rssi,
+            rssi_sum: sighting.rssi as i64,
+            samples: 1,
+            last_scan: scan,
+        };
+        emit(TrackEvent::Appeared {
+            bssid: entry.bssid,
+            ssid: entry.ssid.clone(),
+            channel: entry.channel,
+            rssi: sighting.rssi,
+        });
+        // There is room, see above
+        let _ = self.entries.push(entry);
+    }
+
+    /// Reports access points that have now been missing for too long, and
+    /// removes them from the table.
+    pub fn end_scan(&mut self, mut emit: impl FnMut(TrackEvent)) {
+        let scan = self.scan;
+        self.entries.retain(|entry| {
+            let gone = scan.wrapping_sub(entry.last_scan) >= DISAPPEAR_AFTER_SCANS;
+            if gone {
+                emit(entry.disappeared());
+            }
+            !gone
+        });
+        self.scan = self.scan.wrapping_add(1);
+    }
+}
+
+impl fmt::Display for TrackEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrackEvent::Appeared {
+                bssid,
+                ssid,
+                channel,
+                rssi,
+            } => {
+                write!(f, "appeared bssid={} ssid=\"", Mac(bssid))?;
+                for &c in ssid {
+                    for e in core::ascii::escape_default(c) {
+                        f.write_char(e as char)?;
+                    }
+                }
+                write!(f, "\" channel={channel} rssi={rssi}")
+            }
+            TrackEvent::Disappeared {
+                bssid,
+                first_seen_ms,
+                last_seen_ms,
+                min_rssi,
+                max_rssi,
+                avg_rssi,
+            } => write!(
+                f,
+                "disappeared bssid={} first_seen={first_seen_ms}ms last_seen={last_seen_ms}ms rssi={min_rssi}/{avg_rssi}/{max_rssi}",
+                Mac(bssid)
+            ),
+            TrackEvent::ChannelChanged { bssid, from, to } => {
+                write!(
+                    f,
+                    "channel-changed bssid={} from={from} to={to}",
+                    Mac(bssid)
+                )
+            }
+            TrackEvent::SecurityChanged { bssid, from, to } => {
+                write!(
+                    f,
+                    "security-changed bssid={} from={from} to={to}",
+                    Mac(bssid)
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,24 @@
+//! The settings of the Wi-Fi ROMs, and the console commands that change
+//! them.
+//!
+//! [`scan`] is for `wifi-scanner` and [`sniff`] for `wifi-sniffer`. Each ROM
+//! starts from the defaults here, and applies a `;` separated list of the
+//! same commands it takes at runtime on top at build time. Its `build.rs`
+//! runs them through the same parser, so a typo fails the build rather than
+//! the boot.
+#![no_std]
+
+use core::fmt;
+
+pub mod scan;
+pub mod sniff;
+
+/// Shows a MAC address as `aa:bb:cc:dd:ee:ff`.
+struct Mac<'a>(&'a [u8; 6]);
+
+impl fmt::Display for Mac<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
@@ -0,0 +1,266 @@
+//! What `wifi-scanner` scans for, and how it prints what it finds.
+
+use core::fmt;
+
+use crate::Mac;
+
+/// Scan parameters, applied at the start of every scan.
+///
+/// The defaults can be overridden at build time by setting `SCAN_CONFIG` to
+/// a `;` separated list of console commands, e.g.
+/// `SCAN_CONFIG="channels 1,6,11; passive 200; interval 10000"`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScanParams {
+    pub channels: ChannelSet,
+    pub scan_type: ScanType,
+    pub ssid: Option<heapless::String<32>>,
+    pub bssid: Option<[u8; 6]>,
+    pub interval_ms: u32,
+    pub format: Format,
+}
+
+impl Default for ScanParams {
+    fn default() -> Self {
+        Self {
+            channels: ChannelSet::ALL,
+            scan_type: ScanType::Active {
+                min_ms: 10,
+                max_ms: 20,
+            },
+            ssid: None,
+            bssid: None,
+            interval_ms: 5000,
+            format: Format::Text,
+        }
+    }
+}
+
+impl ScanParams {
+    /// Applies a `;` separated list of commands, and tells which one was
+    /// wrong if any.
+    pub fn from_config(mut self, config: &str) -> Result<Self, (&str, &'static str)> {
+        for command in config.split(';') {
+            let command = command.trim();
+            self.apply(Command::parse(command).map_err(|e| (command, e))?);
+        }
+        Ok(self)
+    }
+
+    /// Applies a command. Commands that don't change the parameters are
+    /// ignored.
+    pub fn apply(&mut self, command: Command) {
+        match command {
+            Command::Channels(channels) => self.channels = channels,
+            Command::ScanType(scan_type) => self.scan_type = scan_type,
+            Command::Ssid(ssid) => self.ssid = ssid,
+            Command::Bssid(bssid) => self.bssid = bssid,
+            Command::Interval(interval_ms) => self.interval_ms = interval_ms,
+            Command::Format(format) => self.format = format,
+            Command::Nothing | Command::Show | Command::Help | Command::Scan => (),
+        }
+    }
+}
+
+impl fmt::Display for ScanParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "channels={} scan={}", self.channels, self.scan_type)?;
+        if let Some(ssid) = &self.ssid {
+            write!(f, " ssid={ssid:?}")?;
+        }
+        if let Some(bssid) = &self.bssid {
+            write!(f, " bssid={}", Mac(bssid))?;
+        }
+        write!(
+            f,
+            " interval={}ms format={:?}",
+            self.interval_ms, self.format
+        )
+    }
+}
+
+/// How scan results are printed over the serial console.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Human readable, one access point per line.
+    Text,
+    /// One JSON object per line. Every object has a `type` field, which is
+    /// either `"scan"` (one per scan, before the results) or `"ap"`.
+    JsonLines,
+    /// Comma separated values, with a header line printed once at startup.
+    Csv,
+}
+
+/// A set of 2.4 GHz channels, 1 through 14.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ChannelSet(u16);
+
+impl ChannelSet {
+    pub const MAX_CHANNEL: u8 = 14;
+    pub const ALL: Self = Self(((1 << Self::MAX_CHANNEL) - 1) << 1);
+
+    pub fn is_all(&self) -> bool {
+        *self == Self::ALL
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u8> {
+        let bits = self.0;
+        (1..=Self::MAX_CHANNEL).filter(move |c| bits & (1 << c) != 0)
+    }
+}
+
+impl fmt::Display for ChannelSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_all() {
+            return f.write_str("all");
+        }
+        for (i, channel) in self.iter().enumerate() {
+            if i != 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{channel}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ScanType {
+    /// Send probe requests, and wait between `min_ms` and `max_ms` on each
+    /// channel for responses.
+    Active { min_ms: u32, max_ms: u32 },
+    /// Only listen for beacons, for `dwell_ms` on each channel.
+    Passive { dwell_ms: u32 },
+}
+
+/// The driver does not allow staying on a channel for longer than this.
+const MAX_DWELL_MS: u32 = 1500;
+
+impl fmt::Display for ScanType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanType::Active { min_ms, max_ms } => write!(f, "active({min_ms}-{max_ms}ms)"),
+            ScanType::Passive { dwell_ms } => write!(f, "passive({dwell_ms}ms)"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// An empty line.
+    Nothing,
+    Channels(ChannelSet),
+    ScanType(ScanType),
+    Ssid(Option<heapless::String<32>>),
+    Bssid(Option<[u8; 6]>),
+    Interval(u32),
+    Format(Format),
+    /// Print the current parameters.
+    Show,
+    Help,
+    /// Start a scan right away.
+    Scan,
+}
+
+pub const HELP: &str = "\
+Commands:
+  channels all|<n>[,<n>...]   Channels to scan, e.g. `channels 1,6,11`
+  active [<min ms> [<max ms>]] Send probe requests, waiting this long per channel
+  passive [<ms>]              Only listen for beacons, this long per channel
+  ssid [<ssid>]               Only report this SSID, or all SSIDs if left out
+  bssid [<xx:xx:xx:xx:xx:xx>] Only report this BSSID, or all BSSIDs if left out
+  interval <ms>               Time between scans
+  format text|json|csv        Output format
+  scan                        Scan right away
+  show                        Show the current parameters";
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Self, &'static str> {
+        let line = line.trim();
+        let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+        let args = args.trim();
+
+        match name {
+            "" => Ok(Command::Nothing),
+            "channels" => parse_channels(args).map(Command::Channels),
+            "active" => {
+                let mut args = args.split_whitespace();
+                let min_ms = match args.next() {
+                    Some(s) => parse_ms(s)?,
+                    None => 10,
+                };
+                let max_ms = match args.next() {
+                    Some(s) => parse_ms(s)?,
+                    None => (min_ms * 2).min(MAX_DWELL_MS),
+                };
+                if min_ms > max_ms {
+                    return Err("min must not be larger than max");
+                }
+                Ok(Command::ScanType(ScanType::Active { min_ms, max_ms }))
+            }
+            "passive" => {
+                let dwell_ms = if args.is_empty() {
+                    360
+                } else {
+                    parse_ms(args)?
+                };
+                Ok(Command::ScanType(ScanType::Passive { dwell_ms }))
+            }
+            "ssid" if args.is_empty() => Ok(Command::Ssid(None)),
+            "ssid" => args
+                .try_into()
+                .map(|ssid| Command::Ssid(Some(ssid)))
+                .map_err(|_| "ssid is longer than 32 bytes"),
+            "bssid" if args.is_empty() => Ok(Command::Bssid(None)),
+            "bssid" => parse_mac(args).map(|bssid| Command::Bssid(Some(bssid))),
+            "interval" => args
+                .parse()
+                .map(Command::Interval)
+                .map_err(|_| "invalid interval"),
+            "format" => match args {
+                "text" => Ok(Command::Format(Format::Text)),
+                "json" => Ok(Command::Format(Format::JsonLines)),
+                "csv" => Ok(Command::Format(Format::Csv)),
+                _ => Err("format must be one of text, json or csv"),
+            },
+            "show" => Ok(Command::Show),
+            "help" => Ok(Command::Help),
+            "scan" => Ok(Command::Scan),
+            _ => Err("unknown command, try `help`"),
+        }
+    }
+}
+
+fn parse_ms(s: &str) -> Result<u32, &'static str> {
+    match s.parse() {
+        Ok(ms) if ms <= MAX_DWELL_MS => Ok(ms),
+        Ok(_) => Err("dwell time must be at most 1500 ms"),
+        Err(_) => Err("invalid dwell time"),
+    }
+}
+
+fn parse_channels(s: &str) -> Result<ChannelSet, &'static str> {
+    if s == "all" {
+        return Ok(ChannelSet::ALL);
+    }
+    let mut bits = 0u16;
+    for channel in s.split(',') {
+        match channel.trim().parse::<u8>() {
+            Ok(c @ 1..=ChannelSet::MAX_CHANNEL) => bits |= 1 << c,
+            _ => return Err("channels must be between 1 and 14"),
+        }
+    }
+    Ok(ChannelSet(bits))
+}
+
+pub fn parse_mac(s: &str) -> Result<[u8; 6], &'static str> {
+    let mut mac = [0; 6];
+    let mut parts = s.split(':');
+    for byte in &mut mac {
+        let part = parts.next().ok_or("MAC address is too short")?;
+        *byte = u8::from_str_radix(part, 16).map_err(|_| "invalid MAC address")?;
+    }
+    if parts.next().is_some() {
+        return Err("MAC address is too long");
+    }
+    Ok(mac)
+}
@@ -0,0 +1,116 @@
+//! Which channels `wifi-sniffer` listens on, and which frames it keeps.
+
+/// What the sniffer listens to.
+///
+/// The defaults can be overridden at build time by setting `SNIFF_CONFIG`
+/// to a `;` separated list of commands, e.g. `SNIFF_CONFIG="channel 6; filter mgmt"`.
+/// The same commands can be sent over the USB serial, one per line.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Settings {
+    pub channel: ChannelMode,
+    pub filter: FrameTypes,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChannelMode {
+    Fixed(u8),
+    /// Go through channels 1 to 13, staying on each for `dwell_ms`.
+    Hop {
+        dwell_ms: u32,
+    },
+}
+
+/// Which kinds of 802.11 frames to capture.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FrameTypes {
+    pub mgmt: bool,
+    pub ctrl: bool,
+    pub data: bool,
+    pub misc: bool,
+}
+
+impl FrameTypes {
+    pub const ALL: Self = Self {
+        mgmt: true,
+        ctrl: true,
+        data: true,
+        misc: true,
+    };
+}
+
+pub const MAX_CHANNEL: u8 = 13;
+const DEFAULT_DWELL_MS: u32 = 250;
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            channel: ChannelMode::Fixed(1),
+            filter: FrameTypes {
+                mgmt: true,
+                ctrl: false,
+                data: true,
+                misc: false,
+            },
+        }
+    }
+}
+
+impl Settings {
+    /// Applies a `;` separated list of commands, and tells which one was
+    /// wrong if any.
+    pub fn from_config(mut self, config: &str) -> Result<Self, (&str, &'static str)> {
+        for command in config.split(';') {
+            self.apply(command).map_err(|e| (command.trim(), e))?;
+        }
+        Ok(self)
+    }
+
+    /// Parses a single command and applies it. Empty commands are ignored.
+    pub fn apply(&mut self, command: &str) -> Result<(), &'static str> {
+        let command = command.trim();
+        let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+        let args = args.trim();
+
+        match name {
+            "" => (),
+            "channel" => match args.parse() {
+                Ok(channel @ 1..=MAX_CHANNEL) => self.channel = ChannelMode::Fixed(channel),
+                _ => return Err("channel must be between 1 and 13"),
+            },
+            "hop" if args.is_empty() => {
+                self.channel = ChannelMode::Hop {
+                    dwell_ms: DEFAULT_DWELL_MS,
+                }
+            }
+            "hop" => match args.parse() {
+                Ok(dwell_ms) if dwell_ms > 0 => self.channel = ChannelMode::Hop { dwell_ms },
+                _ => return Err("invalid dwell time"),
+            },
+            "filter" => self.filter = parse_filter(args)?,
+            _ => return Err("unknown command"),
+        }
+        Ok(())
+    }
+}
+
+fn parse_filter(s: &str) -> Result<FrameTypes, &'static str> {
+    if s == "all" {
+        return Ok(FrameTypes::ALL);
+    }
+    let mut filter = FrameTypes {
+        mgmt: false,
+        ctrl: false,
+        data: false,
+        misc: false,
+    };
+    for kind in s.split(',') {
+        match kind.trim() {
+            "mgmt" => filter.mgmt = true,
+            "ctrl" => filter.ctrl = true,
+            "data" => filter.data = true,
+            "misc" => filter.misc = true,
+            _ => return Err("frame types must be some of mgmt, ctrl, data and misc"),
+        }
+    }
+    Ok(filter)
+}
@@ -0,0 +1,142 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+mod macros;
+mod output;
+
+use core::cell::Cell;
+
+use embassy_executor::Spawner;
+use embassy_sync::{
+    blocking_mutex::{NoopMutex, raw::NoopRawMutex},
+    channel::Channel,
+};
+use esp_backtrace as _;
+use esp_hal::{clock::CpuClock, rng::Rng, timer::timg::TimerGroup};
+use esp_now_stack::{
+    Mac,
+    reliable::Config,
+    remote::{Message, State},
+    transport::Transport,
+};
+use esp_println::println;
+use esp_wifi::{
+    EspWifiController,
+    esp_now::{EspNowManager, PeerInfo},
+};
+
+use output::Output;
+
+/// What we put out, shared with `reply_task`.
+type SharedState = NoopMutex<Cell<State>>;
+
+/// Remotes waiting to hear the state their command left us in.
+type Replies = Channel<NoopRawMutex, Mac, 4>;
+
+#[esp_hal_embassy::main]
+async fn main(spawner: Spawner) -> ! {
+    esp_println::logger::init_logger_from_env();
+    let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
+    let peripherals = esp_hal::init(config);
+
+    esp_alloc::heap_allocator!(size: 72 * 1024);
+
+    let timg0 = TimerGroup::new(peripherals.TIMG0);
+    let timg1 = TimerGroup::new(peripherals.TIMG1);
+    let mut rng = Rng::new(peripherals.RNG);
+
+    esp_hal_embassy::init(timg1.timer0);
+
+    let init = &*mk_static!(
+        EspWifiController<'static>,
+        esp_wifi::init(timg0.timer0, rng, peripherals.RADIO_CLK).unwrap()
+    );
+
+    let esp_now = esp_wifi::esp_now::EspNow::new(&init, peripherals.WIFI).unwrap();
+    let (manager, sender, mut receiver) = esp_now.split();
+    let manager = &*mk_static!(EspNowManager<'static>, manager);
+    let transport = &*mk_static!(
+        Transport<'static>,
+        Transport::new(sender, Config::default(), rng.random() as u16)
+    );
+
+    #[cfg(not(feature = "ws2812"))]
+    let mut output = Output::new(peripherals.LEDC, peripherals.GPIO8);
+    #[cfg(feature = "ws2812")]
+    let mut output = Output::new(peripherals.RMT, peripherals.GPIO10);
+
+    let state = &*mk_static!(SharedState, NoopMutex::new(Cell::new(State::default())));
+    output.show(&State::default()).await;
+
+    let replies = &*mk_static!(Replies, Channel::new());
+    spawner
+        .spawn(reply_task(transport, state, replies))
+        .unwrap();
+
+    // Lets remotes that are already running find us
+    let hello = Message::State(State::default()).encode();
+    if let Err(e) = transport.broadcast(&hello).await {
+        println!("Error broadcasting our state: {e:?}");
+    }
+    println!("Waiting for commands");
+
+    loop {
+        // This also sends the acknowledgements `reply_task` is waiting for
+        let r = transport.receive(&mut receiver).await;
+        let Some(Message::Command(command)) = Message::decode(&r.payload) else {
+            continue;
+        };
+        // Its first command can't be acknowledged until it's a peer, but
+        // the retries can
+        if !add_peer(manager, r.src) {
+            continue;
+        }
+        let new = state.lock(|s| {
+            let mut new = s.get();
+            new.apply(command);
+            s.set(new);
+            new
+        });
+        println!("{command:?} from {:x?}, now {new:?}", r.src);
+        output.show(&new).await;
+        if replies.try_send(r.src).is_err() {
+            println!("Too many replies queued, {:x?} goes without", r.src);
+        }
+    }
+}
+
+fn add_peer(manager: &EspNowManager<'static>, mac: Mac) -> bool {
+    if manager.peer_exists(&mac) {
+        return true;
+    }
+    let peer = PeerInfo {
+        peer_address: mac,
+        lmk: None,
+        channel: None,
+        encrypt: false,
+    };
+    match manager.add_peer(peer) {
+        Ok(()) => true,
+        Err(e) => {
+            println!("Error adding peer {mac:x?}: {e:?}");
+            false
+        }
+    }
+}
+
+/// Tells each remote that sent us a command what state we are in now.
+#[embassy_executor::task]
+async fn reply_task(
+    transport: &'static Transport<'static>,
+    state: &'static SharedState,
+    replies: &'static Replies,
+) {
+    loop {
+        let remote = replies.receive().await;
+        let reply = Message::State(state.lock(Cell::get)).encode();
+        if let Err(e) = transport.send(&remote, &reply).await {
+            println!("State to {remote:x?} failed: {e:?}");
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Where the state goes: the LED on GPIO8, dimmed with the LEDC, or with the
+//! `ws2812` feature a strip on GPIO10, driven by the RMT like in the
+//! `ws2812b-rmt` ROM.
+
+use esp_now_stack::remote::State;
+
+#[cfg(not(feature = "ws2812"))]
+mod led {
+    use esp_hal::{
+        gpio::GpioPin,
+        ledc::{
+            LSGlobalClkSource, Ledc, LowSpeed,
+            channel::{self, ChannelIFace},
+            timer::{self, TimerIFace},
+        },
+        peripherals::LEDC,
+        time::Rate,
+    };
+    use esp_println::println;
+
+    use super::State;
+
+    pub(crate) struct Output {
+        channel: channel::Channel<'static, LowSpeed>,
+    }
+
+    impl Output {
+        pub(crate) fn new(ledc: LEDC, pin: GpioPin<8>) -> Self {
+            let ledc = &*mk_static!(Ledc<'static>, Ledc::new(ledc));
+            ledc.set_global_slow_clock(LSGlobalClkSource::APBClk);
+            let timer = mk_static!(
+                timer::Timer<'static, LowSpeed>,
+                ledc.timer::<LowSpeed>(timer::Number::Timer0)
+            );
+            timer
+                .configure(timer::config::Config {
+                    duty: timer::config::Duty::Duty8Bit,
+                    clock_source: timer::LSClockSource::APBClk,
+                    frequency: Rate::from_khz(1),
+                })
+                .unwrap();
+            let mut channel = ledc.channel(channel::Number::Channel0, pin);
+            channel
+                .configure(channel::config::Config {
+                    timer: &*timer,
+                    duty_pct: 0,
+                    pin_config: channel::config::PinConfig::PushPull,
+                })
+                .unwrap();
+            Self { channel }
+        }
+
+        /// The LED has one color, only the level counts.
+        pub(crate) async fn show(&mut self, state: &State) {
+            if let Err(e) = self.channel.set_duty(state.brightness()) {
+                println!("Error setting the duty cycle: {e:?}");
+            }
+        }
+    }
+}
+
+#[cfg(feature = "ws2812")]
+mod strip {
+    use esp_hal::{
+        Async,
+        gpio::{GpioPin, Level},
+        peripherals::RMT,
+        rmt::{Channel, PulseCode, Rmt, TxChannelAsync, TxChannelConfig, TxChannelCreatorAsync},
+        time::Rate,
+    };
+    use esp_now_stack::remote::Rgb;
+    use esp_println::println;
+
+    use super::State;
+
+    const NUM_PIXELS: usize = 16;
+
+    pub(crate) struct Output {
+        channel: Channel<Async, 0>,
+    }
+
+    impl Output {
+        pub(crate) fn new(rmt: RMT, pin: GpioPin<10>) -> Self {
+            let rmt = Rmt::new(rmt, Rate::from_mhz(80)).unwrap().into_async();
+            let channel = rmt
+                .channel0
+                .configure(
+                    pin,
+                    TxChannelConfig::default()
+                        .with_clk_divider(1)
+                        .with_idle_output_level(Level::Low)
+                        .with_idle_output(true)
+                        .with_carrier_modulation(false),
+                )
+                .unwrap();
+            Self { channel }
+        }
+
+        /// Every pixel gets the same color. The RMT only has room for one
+        /// pixel at a time, see `ws2812b-rmt`.
+        pub(crate) async fn show(&mut self, state: &State) {
+            let pulses = pixel_to_pulsecodes(state.pixel());
+            for _ in 0..NUM_PIXELS {
+                if let Err(e) = self.channel.transmit(&pulses).await {
+                    println!("Error sending to the strip: {e:?}");
+                    return;
+                }
+            }
+        }
+    }
+
+    /// The pulse codes for one pixel, in the order the WS2812 wants them,
+    /// and the end code.
+    fn pixel_to_pulsecodes(pixel: Rgb) -> [u32; 25] {
+        let mut codes = [PulseCode::empty(); 25];
+        for (i, byte) in [pixel.g, pixel.r, pixel.b].into_iter().enumerate() {
+            codes[i * 8..(i + 1) * 8].copy_from_slice(&byte_to_pulsecodes(byte));
+        }
+        codes
+    }
+
+    fn byte_to_pulsecodes(byte: u8) -> [u32; 8] {
+        // 350 ns high and 600 ns low for a zero, 700 ns high for a one, at
+        // 80 MHz
+        let zero = PulseCode::new(Level::High, 28, Level::Low, 48);
+        let one = PulseCode::new(Level::High, 56, Level::Low, 48);
+        core::array::from_fn(|i| if byte & (0x80 >> i) != 0 { one } else { zero })
+    }
+}
+
+#[cfg(not(feature = "ws2812"))]
+pub(crate) use led::Output;
+#[cfg(feature = "ws2812")]
+pub(crate) use strip::Output;
@@ -5,11 +5,21 @@
 mod macros;
 
 use embassy_executor::Spawner;
+use embassy_futures::select::{Either, select};
+use embassy_time::{Instant, Timer};
 use esp_backtrace as _;
 use esp_hal::{clock::CpuClock, rng::Rng, timer::timg::TimerGroup};
+use esp_now_messages::Message;
+use esp_now_stack::{
+    header::Header,
+    proximity::{self, Event, Tracker},
+};
 use esp_println::println;
 use esp_wifi::EspWifiController;
 
+/// How many nodes we keep track of.
+const MAX_TRACKED: usize = 16;
+
 #[esp_hal_embassy::main]
 async fn main(_spawner: Spawner) -> ! {
     esp_println::logger::init_logger_from_env();
@@ -32,8 +42,34 @@ async fn main(_spawner: Spawner) -> ! {
     let mut esp_now = esp_wifi::esp_now::EspNow::new(&init, peripherals.WIFI).unwrap();
     println!("ESP-NOW version: {:?}", esp_now.version().unwrap());
 
+    let mut tracker = Tracker::<MAX_TRACKED>::new(proximity::Config::default());
     loop {
-        let received = esp_now.receive_async().await;
-        println!("{received:?}");
+        let expiry = tracker
+            .next_expiry()
+            .map_or(Instant::MAX, Instant::from_millis);
+        let received = match select(esp_now.receive_async(), Timer::at(expiry)).await {
+            Either::First(received) => received,
+            Either::Second(()) => {
+                while let Some(event) = tracker.expire(Instant::now().as_millis()) {
+                    println!("{event:x?}");
+                }
+                continue;
+            }
+        };
+        let src = received.info.src_address;
+        let rssi = received.info.rx_control.rssi as i8;
+        // The senders frame their messages for `Transport`, but anything
+        // unframed is worth a look as well
+        let payload = Header::decode(received.data()).map_or(received.data(), |(_, p)| p);
+        match Message::decode(payload) {
+            Ok(message) => println!("{message:?} from {src:x?}, {rssi} dBm"),
+            Err(e) => println!("{received:?} ({e:?})"),
+        }
+        if let Some(event) = tracker.handle(Instant::now().as_millis(), src, rssi) {
+            let estimate = tracker
+                .get(&src)
+                .map_or(rssi as f32, |t| t.filter.estimate());
+            println!("{event:x?}, at {estimate:.1} dBm");
+        }
     }
 }
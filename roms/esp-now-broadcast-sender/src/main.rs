@@ -8,8 +8,10 @@ use embassy_executor::Spawner;
 use embassy_time::Timer;
 use esp_backtrace as _;
 use esp_hal::{clock::CpuClock, rng::Rng, timer::timg::TimerGroup};
+use esp_now_messages::Message;
+use esp_now_stack::{header::MAX_PAYLOAD_LEN, reliable::Config, transport::Transport};
 use esp_println::println;
-use esp_wifi::{EspWifiController, esp_now::BROADCAST_ADDRESS};
+use esp_wifi::EspWifiController;
 
 #[esp_hal_embassy::main]
 async fn main(_spawner: Spawner) -> ! {
@@ -21,7 +23,7 @@ async fn main(_spawner: Spawner) -> ! {
 
     let timg0 = TimerGroup::new(peripherals.TIMG0);
     let timg1 = TimerGroup::new(peripherals.TIMG1);
-    let rng = Rng::new(peripherals.RNG);
+    let mut rng = Rng::new(peripherals.RNG);
 
     esp_hal_embassy::init(timg1.timer0);
 
@@ -30,15 +32,23 @@ async fn main(_spawner: Spawner) -> ! {
         esp_wifi::init(timg0.timer0, rng, peripherals.RADIO_CLK).unwrap()
     );
 
-    let mut esp_now = esp_wifi::esp_now::EspNow::new(&init, peripherals.WIFI).unwrap();
+    let esp_now = esp_wifi::esp_now::EspNow::new(&init, peripherals.WIFI).unwrap();
     println!("ESP-NOW version: {:?}", esp_now.version().unwrap());
+    // Framed like everything else, so `esp-now-peering` doesn't drop the
+    // pings. Broadcasts aren't acknowledged, so nothing has to receive.
+    let (_manager, sender, _receiver) = esp_now.split();
+    let transport = Transport::new(sender, Config::default(), rng.random() as u16);
 
+    let mut buf = [0; MAX_PAYLOAD_LEN];
+    let mut seq: u32 = 0;
     loop {
-        if let Err(e) = esp_now.send_async(&BROADCAST_ADDRESS, b"test").await {
+        let n = Message::Ping { seq }.encode(&mut buf).unwrap();
+        if let Err(e) = transport.broadcast(&buf[..n]).await {
             println!("Error while sending: {e:?}");
         } else {
-            println!("Ping sent");
+            println!("Ping {seq} sent");
         }
+        seq = seq.wrapping_add(1);
         Timer::after_secs(1).await;
     }
 }
@@ -0,0 +1,220 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+mod macros;
+
+use core::{
+    cell::Cell,
+    fmt::{self, Write as _},
+};
+
+use embassy_executor::Spawner;
+use embassy_futures::select::{Either3, select3};
+use embassy_sync::{
+    blocking_mutex::{self, raw::CriticalSectionRawMutex},
+    channel::Channel,
+};
+use embedded_io_async::{Read, Write};
+use esp_backtrace as _;
+use esp_hal::{
+    Async,
+    clock::CpuClock,
+    efuse::Efuse,
+    rng::Rng,
+    timer::timg::TimerGroup,
+    usb_serial_jtag::{UsbSerialJtag, UsbSerialJtagRx},
+};
+use esp_now_stack::{
+    Mac,
+    bridge::{Deframer, MAX_ENCODED_LEN, Message, SendStatus},
+    header::MAX_FRAME_LEN,
+};
+use esp_wifi::{
+    EspWifiController,
+    esp_now::{BROADCAST_ADDRESS, EspNowManager, EspNowReceiver, EspNowSender, PeerInfo},
+};
+
+/// ESP-NOW allows 20 peers, one of which is the broadcast address.
+const MAX_PEERS: usize = 19;
+
+struct Received {
+    src: Mac,
+    dst: Mac,
+    rssi: i8,
+    payload: heapless::Vec<u8, MAX_FRAME_LEN>,
+}
+
+struct SendRequest {
+    id: u16,
+    dst: Mac,
+    payload: heapless::Vec<u8, MAX_FRAME_LEN>,
+}
+
+static RECEIVED: Channel<CriticalSectionRawMutex, Received, 8> = Channel::new();
+static REQUESTS: Channel<CriticalSectionRawMutex, SendRequest, 4> = Channel::new();
+static RESULTS: Channel<CriticalSectionRawMutex, (u16, SendStatus), 4> = Channel::new();
+static LOGS: Channel<CriticalSectionRawMutex, heapless::String<96>, 4> = Channel::new();
+
+/// Frames we had to throw away because the host did not keep up.
+static DROPPED: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<u32>> =
+    blocking_mutex::Mutex::new(Cell::new(0));
+
+/// Everything on the USB serial port is a `bridge` message, so log messages
+/// are sent as those too rather than printed.
+fn log(args: fmt::Arguments<'_>) {
+    let mut s = heapless::String::new();
+    let _ = s.write_fmt(args);
+    let _ = LOGS.try_send(s);
+}
+
+#[esp_hal_embassy::main]
+async fn main(spawner: Spawner) -> ! {
+    let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
+    let peripherals = esp_hal::init(config);
+
+    esp_alloc::heap_allocator!(size: 72 * 1024);
+
+    let timg0 = TimerGroup::new(peripherals.TIMG0);
+    let timg1 = TimerGroup::new(peripherals.TIMG1);
+    let rng = Rng::new(peripherals.RNG);
+
+    esp_hal_embassy::init(timg1.timer0);
+
+    let (usb_rx, mut usb_tx) = UsbSerialJtag::new(peripherals.USB_DEVICE)
+        .into_async()
+        .split();
+
+    let init = &*mk_static!(
+        EspWifiController<'static>,
+        esp_wifi::init(timg0.timer0, rng, peripherals.RADIO_CLK).unwrap()
+    );
+
+    let esp_now = esp_wifi::esp_now::EspNow::new(&init, peripherals.WIFI).unwrap();
+    let (manager, sender, receiver) = esp_now.split();
+    let manager = &*mk_static!(EspNowManager<'static>, manager);
+
+    spawner.spawn(host_task(usb_rx)).unwrap();
+    spawner.spawn(send_task(manager, sender)).unwrap();
+    spawner.spawn(radio_task(receiver)).unwrap();
+    log(format_args!(
+        "Gateway {:x?} started",
+        Efuse::read_base_mac_address()
+    ));
+
+    // Everything for the host goes out from here, one message at a time
+    let encoded = mk_static!([u8; MAX_ENCODED_LEN], [0; MAX_ENCODED_LEN]);
+    loop {
+        let n = match select3(RECEIVED.receive(), RESULTS.receive(), LOGS.receive()).await {
+            Either3::First(r) => Message::Received {
+                src: r.src,
+                dst: r.dst,
+                rssi: r.rssi,
+                payload: &r.payload,
+            }
+            .encode(encoded),
+            Either3::Second((id, status)) => Message::SendResult { id, status }.encode(encoded),
+            Either3::Third(line) => Message::Log(&line).encode(encoded),
+        };
+        let Ok(()) = usb_tx.write_all(&encoded[..n]).await;
+
+        let dropped = DROPPED.lock(|d| d.replace(0));
+        if dropped > 0 {
+            log(format_args!("Dropped {dropped} frames"));
+        }
+    }
+}
+
+/// Passes every frame we receive on to the host.
+#[embassy_executor::task]
+async fn radio_task(mut receiver: EspNowReceiver<'static>) {
+    loop {
+        let r = receiver.receive_async().await;
+        let received = Received {
+            src: r.info.src_address,
+            dst: r.info.dst_address,
+            rssi: r.info.rx_control.rssi as i8,
+            payload: heapless::Vec::from_slice(r.data()).unwrap(),
+        };
+        if RECEIVED.try_send(received).is_err() {
+            DROPPED.lock(|d| d.set(d.get() + 1));
+        }
+    }
+}
+
+/// Reads `Send` messages from the host.
+#[embassy_executor::task]
+async fn host_task(mut usb_rx: UsbSerialJtagRx<'static, Async>) {
+    let mut buf = [0; 64];
+    let mut deframer = Deframer::<MAX_ENCODED_LEN>::new();
+    loop {
+        let Ok(n) = usb_rx.read(&mut buf).await;
+        for &b in &buf[..n] {
+            let Some(frame) = deframer.push(b) else {
+                continue;
+            };
+            match Message::decode(frame) {
+                Ok(Message::Send { id, dst, payload }) => {
+                    let Ok(payload) = heapless::Vec::from_slice(payload) else {
+                        let _ = RESULTS.try_send((id, SendStatus::TooLong));
+                        continue;
+                    };
+                    REQUESTS.send(SendRequest { id, dst, payload }).await;
+                }
+                Ok(_) => log(format_args!("Ignoring a message meant for the host")),
+                Err(e) => log(format_args!("Ignoring a bad message: {e:?}")),
+            }
+        }
+    }
+}
+
+/// Sends what the host asks us to.
+#[embassy_executor::task]
+async fn send_task(manager: &'static EspNowManager<'static>, mut sender: EspNowSender<'static>) {
+    let mut peers = heapless::Deque::<Mac, MAX_PEERS>::new();
+    loop {
+        let request = REQUESTS.receive().await;
+        let status = if !add_peer(manager, &mut peers, request.dst) {
+            SendStatus::NoPeer
+        } else {
+            // For unicast the driver waits for the peer's acknowledgement
+            match sender.send_async(&request.dst, &request.payload).await {
+                Ok(()) => SendStatus::Sent,
+                Err(_) => SendStatus::Failed,
+            }
+        };
+        RESULTS.send((request.id, status)).await;
+    }
+}
+
+/// Makes sure we can send to `dst`. When there is no room for another peer,
+/// the one we added first makes way.
+fn add_peer(
+    manager: &EspNowManager<'_>,
+    peers: &mut heapless::Deque<Mac, MAX_PEERS>,
+    dst: Mac,
+) -> bool {
+    if dst == BROADCAST_ADDRESS || manager.peer_exists(&dst) {
+        return true;
+    }
+    if peers.is_full() {
+        let oldest = peers.pop_front().unwrap();
+        let _ = manager.remove_peer(&oldest);
+    }
+    let peer = PeerInfo {
+        peer_address: dst,
+        lmk: None,
+        channel: None,
+        encrypt: false,
+    };
+    match manager.add_peer(peer) {
+        Ok(()) => {
+            let _ = peers.push_back(dst);
+            true
+        }
+        Err(e) => {
+            log(format_args!("Error adding peer {dst:x?}: {e:?}"));
+            false
+        }
+    }
+}
@@ -0,0 +1,259 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+mod macros;
+
+use core::{cell::RefCell, fmt::Write};
+
+use embassy_executor::Spawner;
+use embassy_futures::select::{Either, select};
+use embassy_sync::{
+    blocking_mutex::{NoopMutex, raw::NoopRawMutex},
+    channel::Channel,
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant, Ticker, Timer};
+use esp_backtrace as _;
+use esp_hal::{
+    clock::CpuClock,
+    efuse::Efuse,
+    gpio::{Input, InputConfig, Pull},
+    rng::Rng,
+    timer::timg::TimerGroup,
+};
+use esp_now_stack::{
+    Mac,
+    header::MAX_PAYLOAD_LEN,
+    mesh::{self, BROADCAST, Event, Router},
+    reliable::Config,
+    transport::Transport,
+};
+use esp_println::println;
+use esp_wifi::{
+    EspWifiController,
+    esp_now::{EspNowManager, PeerInfo},
+};
+
+/// Build with `MESH_GATEWAY` set for the node that collects the readings.
+/// The others send it a reading every few seconds, and flood an alarm to
+/// everybody when the BOOT button is pressed.
+const GATEWAY: bool = option_env!("MESH_GATEWAY").is_some();
+const READING_INTERVAL: Duration = Duration::from_secs(5);
+
+type SharedRouter = NoopMutex<RefCell<Router<32, 64>>>;
+/// Frames waiting to be sent to their next hop, which is [`BROADCAST`] for
+/// floods and route advertisements.
+type Outbox = Channel<NoopRawMutex, (Mac, heapless::Vec<u8, MAX_PAYLOAD_LEN>), 8>;
+
+#[esp_hal_embassy::main]
+async fn main(spawner: Spawner) -> ! {
+    esp_println::logger::init_logger_from_env();
+    let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
+    let peripherals = esp_hal::init(config);
+
+    esp_alloc::heap_allocator!(size: 128 * 1024);
+
+    let timg0 = TimerGroup::new(peripherals.TIMG0);
+    let timg1 = TimerGroup::new(peripherals.TIMG1);
+    let mut rng = Rng::new(peripherals.RNG);
+
+    esp_hal_embassy::init(timg1.timer0);
+
+    let init = &*mk_static!(
+        EspWifiController<'static>,
+        esp_wifi::init(timg0.timer0, rng, peripherals.RADIO_CLK).unwrap()
+    );
+
+    let esp_now = esp_wifi::esp_now::EspNow::new(&init, peripherals.WIFI).unwrap();
+    let (manager, sender, mut receiver) = esp_now.split();
+    let manager = &*mk_static!(EspNowManager<'static>, manager);
+
+    let own_mac = Efuse::read_base_mac_address();
+    println!(
+        "Mesh node {own_mac:x?}{}",
+        if GATEWAY { ", gateway" } else { "" }
+    );
+
+    let transport = &*mk_static!(
+        Transport<'static>,
+        Transport::new(sender, Config::default(), rng.random() as u16)
+    );
+    let router = &*mk_static!(
+        SharedRouter,
+        NoopMutex::new(RefCell::new(Router::new(
+            own_mac,
+            mesh::Config {
+                gateway: GATEWAY,
+                ..mesh::Config::default()
+            },
+            rng.random() as u16,
+        )))
+    );
+    let outbox = &*mk_static!(Outbox, Channel::new());
+    let routes_changed = &*mk_static!(Signal<NoopRawMutex, ()>, Signal::new());
+
+    spawner
+        .spawn(advertise_task(router, outbox, routes_changed))
+        .unwrap();
+    spawner
+        .spawn(send_task(
+            manager,
+            transport,
+            router,
+            outbox,
+            routes_changed,
+        ))
+        .unwrap();
+    if !GATEWAY {
+        let button = Input::new(
+            peripherals.GPIO9,
+            InputConfig::default().with_pull(Pull::Up),
+        );
+        spawner.spawn(sensor_task(router, outbox, button)).unwrap();
+    }
+
+    loop {
+        // This also sends the acknowledgements `send_task` is waiting for
+        let r = transport.receive(&mut receiver).await;
+        if !mesh::is_mesh_frame(&r.payload) {
+            continue;
+        }
+        // Anybody we hear from may become a next hop
+        if !manager.peer_exists(&r.src) {
+            let peer = PeerInfo {
+                peer_address: r.src,
+                lmk: None,
+                channel: None,
+                encrypt: false,
+            };
+            if let Err(e) = manager.add_peer(peer) {
+                println!("Error adding peer {:x?}: {e:?}", r.src);
+            }
+        }
+
+        let mut out = [0; MAX_PAYLOAD_LEN];
+        let now = Instant::now().as_millis();
+        router.lock(|router| {
+            let handled = router.borrow_mut().handle(now, r.src, &r.payload, &mut out);
+            match handled.event {
+                Event::Delivered {
+                    origin,
+                    broadcast,
+                    hops,
+                    payload,
+                    ..
+                } => {
+                    let mut s = heapless::String::<64>::new();
+                    for &c in payload {
+                        for e in core::ascii::escape_default(c) {
+                            let _ = s.push(e as char);
+                        }
+                    }
+                    println!(
+                        "{} from {origin:x?} after {hops} hop(s): {s:?}",
+                        if broadcast { "Flood" } else { "Message" }
+                    );
+                }
+                Event::Dropped(reason) => println!("Dropped a message: {reason:?}"),
+                Event::Routes { changed: true } => routes_changed.signal(()),
+                _ => (),
+            }
+            if let Some(forward) = handled.forward {
+                let frame = heapless::Vec::from_slice(&out[..forward.len]).unwrap();
+                if outbox.try_send((forward.next_hop, frame)).is_err() {
+                    println!("Outbox full, dropped a message");
+                }
+            }
+        });
+    }
+}
+
+/// Advertises our routes when it's time to, or sooner when they change.
+#[embassy_executor::task]
+async fn advertise_task(
+    router: &'static SharedRouter,
+    outbox: &'static Outbox,
+    routes_changed: &'static Signal<NoopRawMutex, ()>,
+) {
+    let mut out = [0; MAX_PAYLOAD_LEN];
+    loop {
+        let now = Instant::now().as_millis();
+        while let Some(len) = router.lock(|r| r.borrow_mut().poll(now, &mut out)) {
+            let frame = heapless::Vec::from_slice(&out[..len]).unwrap();
+            outbox.send((BROADCAST, frame)).await;
+        }
+        let deadline = router.lock(|r| r.borrow().next_deadline());
+        select(
+            Timer::at(Instant::from_millis(deadline)),
+            routes_changed.wait(),
+        )
+        .await;
+    }
+}
+
+/// Sends every frame to its next hop. Hops are acknowledged, and a hop that
+/// isn't drops the routes through it.
+#[embassy_executor::task]
+async fn send_task(
+    manager: &'static EspNowManager<'static>,
+    transport: &'static Transport<'static>,
+    router: &'static SharedRouter,
+    outbox: &'static Outbox,
+    routes_changed: &'static Signal<NoopRawMutex, ()>,
+) {
+    loop {
+        let (next_hop, frame) = outbox.receive().await;
+        if next_hop == BROADCAST {
+            if let Err(e) = transport.broadcast(&frame).await {
+                println!("Error broadcasting: {e:?}");
+            }
+            continue;
+        }
+        if let Err(e) = transport.send(&next_hop, &frame).await {
+            println!("Lost the link to {next_hop:x?}: {e:?}");
+            let now = Instant::now().as_millis();
+            router.lock(|r| r.borrow_mut().link_failed(now, &next_hop));
+            routes_changed.signal(());
+            let _ = manager.remove_peer(&next_hop);
+        }
+    }
+}
+
+/// Reports to the gateway now and then, and floods an alarm when the BOOT
+/// button is pressed.
+#[embassy_executor::task]
+async fn sensor_task(
+    router: &'static SharedRouter,
+    outbox: &'static Outbox,
+    mut button: Input<'static>,
+) {
+    let mut ticker = Ticker::every(READING_INTERVAL);
+    let mut count = 0u32;
+    let mut out = [0; MAX_PAYLOAD_LEN];
+    loop {
+        let alarm = match select(ticker.next(), button.wait_for_falling_edge()).await {
+            Either::First(()) => false,
+            Either::Second(()) => true,
+        };
+        let sent = router.lock(|r| {
+            let mut r = r.borrow_mut();
+            if alarm {
+                r.broadcast(b"alarm", &mut out)
+                    .map(|(_, len)| (BROADCAST, len))
+            } else {
+                count += 1;
+                let mut reading = heapless::String::<32>::new();
+                let _ = write!(reading, "reading {count}");
+                r.send_to_gateway(reading.as_bytes(), &mut out)
+            }
+        });
+        match sent {
+            Ok((next_hop, len)) => {
+                let frame = heapless::Vec::from_slice(&out[..len]).unwrap();
+                outbox.send((next_hop, frame)).await;
+            }
+            Err(e) => println!("Can't send: {e:?}"),
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use esp_now_stack::ota::{
+    self, CHUNK_LEN, Manifest, SECTOR_LEN, SELECT_ENTRY_LEN, TRAILER_VERIFIED, Trailer,
+};
+use esp_println::println;
+use esp_storage::FlashStorage;
+use sha2::{Digest, Sha256};
+
+/// Where the OTA slots are, see `partitions.csv`.
+const SLOTS: [u32; 2] = [0x10000, 0x1d0000];
+const SLOT_LEN: u32 = 0x1c0000;
+/// The OTA data partition, with one entry at the start of each sector.
+const OTADATA: u32 = 0xd000;
+
+/// How much of a slot an image may take up, the rest is the trailer.
+pub(crate) const CAPACITY: u32 = SLOT_LEN - SECTOR_LEN;
+
+/// The OTA slots, and which one the bootloader picks.
+pub(crate) struct Flash {
+    flash: FlashStorage,
+}
+
+impl Flash {
+    pub(crate) fn new() -> Self {
+        Self {
+            flash: FlashStorage::new(),
+        }
+    }
+
+    /// The sequence number in each OTA data sector, if valid.
+    fn select_entries(&mut self) -> [Option<u32>; 2] {
+        [0, 1].map(|i| {
+            let mut entry = [0; SELECT_ENTRY_LEN];
+            self.flash
+                .read(OTADATA + i * SECTOR_LEN, &mut entry)
+                .ok()
+                .and_then(|()| ota::parse_select_entry(&entry))
+        })
+    }
+
+    /// The slot we run from, the way the bootloader picked it: the one the
+    /// highest valid sequence number points at, or the first one when
+    /// there is none, as after `espflash flash`.
+    pub(crate) fn running_slot(&mut self) -> usize {
+        let seq = self.select_entries().into_iter().flatten().max();
+        seq.map_or(0, |seq| (seq - 1) as usize % SLOTS.len())
+    }
+
+    /// Tells the bootloader to boot `slot` from now on, writing to the
+    /// sector that doesn't hold the current entry, so that a power cut
+    /// leaves that one in place.
+    pub(crate) fn boot_from(&mut self, slot: usize) -> bool {
+        let entries = self.select_entries();
+        let current = entries.into_iter().flatten().max();
+        let seq = ota::next_seq(current, slot as u32, SLOTS.len() as u32);
+        let sector = match entries {
+            [Some(a), Some(b)] if a > b => 1,
+            [Some(_), None] => 1,
+            _ => 0,
+        };
+        let offset = OTADATA + sector * SECTOR_LEN;
+        let result = self
+            .flash
+            .erase(offset, offset + SECTOR_LEN)
+            .and_then(|()| self.flash.write(offset, &ota::select_entry(seq)));
+        if let Err(e) = result {
+            println!("Error writing the OTA data: {e:?}");
+            return false;
+        }
+        true
+    }
+
+    fn trailer_offset(slot: usize) -> u32 {
+        SLOTS[slot] + CAPACITY
+    }
+
+    pub(crate) fn trailer(&mut self, slot: usize) -> Option<Trailer> {
+        let mut sector = [0; SECTOR_LEN as usize];
+        self.flash
+            .read(Self::trailer_offset(slot), &mut sector)
+            .ok()?;
+        Trailer::decode(&sector)
+    }
+
+    /// Starts a transfer into `slot` afresh.
+    pub(crate) fn begin(&mut self, slot: usize, manifest: &Manifest) {
+        let offset = Self::trailer_offset(slot);
+        let result = self
+            .flash
+            .erase(offset, offset + SECTOR_LEN)
+            .and_then(|()| self.flash.write(offset, &Trailer::header(manifest)));
+        if let Err(e) = result {
+            println!("Error starting the trailer: {e:?}");
+        }
+    }
+
+    /// Forgets whatever is in `slot`, so that the next transfer starts
+    /// afresh.
+    pub(crate) fn discard(&mut self, slot: usize) {
+        let offset = Self::trailer_offset(slot);
+        if let Err(e) = self.flash.erase(offset, offset + SECTOR_LEN) {
+            println!("Error erasing the trailer: {e:?}");
+        }
+    }
+
+    /// Writes a chunk, erasing its sector first when it's the first one in
+    /// it.
+    pub(crate) fn write_chunk(&mut self, slot: usize, offset: u32, data: &[u8]) {
+        let offset = SLOTS[slot] + offset;
+        if offset.is_multiple_of(SECTOR_LEN) {
+            if let Err(e) = self.flash.erase(offset, offset + SECTOR_LEN) {
+                println!("Error erasing at {offset:#x}: {e:?}");
+            }
+        }
+        // Writes have to be whole words, erased flash is all ones
+        let mut padded = [0xff; CHUNK_LEN];
+        padded[..data.len()].copy_from_slice(data);
+        let len = data.len().next_multiple_of(4);
+        if let Err(e) = self.flash.write(offset, &padded[..len]) {
+            println!("Error writing at {offset:#x}: {e:?}");
+        }
+    }
+
+    /// Clears the word at `offset` in the trailer of `slot`.
+    fn mark(&mut self, slot: usize, offset: u32) {
+        let offset = Self::trailer_offset(slot) + offset;
+        if let Err(e) = self.flash.write(offset, &[0; 4]) {
+            println!("Error updating the trailer: {e:?}");
+        }
+    }
+
+    pub(crate) fn mark_written(&mut self, slot: usize, sector: u32) {
+        self.mark(slot, Trailer::progress_offset(sector));
+    }
+
+    pub(crate) fn mark_verified(&mut self, slot: usize) {
+        self.mark(slot, TRAILER_VERIFIED);
+    }
+
+    /// Reads chunk `index` of the image in `slot`, and returns how long it
+    /// is.
+    pub(crate) fn read_chunk(
+        &mut self,
+        slot: usize,
+        manifest: &Manifest,
+        index: u32,
+        buf: &mut [u8; CHUNK_LEN],
+    ) -> Option<usize> {
+        let span = manifest.chunk_span(index);
+        let len = span.len();
+        // Reads have to be whole words too
+        let aligned = len.next_multiple_of(4);
+        match self
+            .flash
+            .read(SLOTS[slot] + span.start, &mut buf[..aligned])
+        {
+            Ok(()) => Some(len),
+            Err(e) => {
+                println!("Error reading chunk {index}: {e:?}");
+                None
+            }
+        }
+    }
+
+    /// Whether the image in `slot` hashes to what its manifest says.
+    pub(crate) fn check(&mut self, slot: usize, manifest: &Manifest) -> bool {
+        let mut hasher = Sha256::new();
+        let mut buf = [0; 1024];
+        let mut offset = 0;
+        while offset < manifest.len {
+            let len = (manifest.len - offset).min(buf.len() as u32) as usize;
+            let aligned = len.next_multiple_of(4);
+            if self
+                .flash
+                .read(SLOTS[slot] + offset, &mut buf[..aligned])
+                .is_err()
+            {
+                return false;
+            }
+            hasher.update(&buf[..len]);
+            offset += len as u32;
+        }
+        hasher.finalize().as_slice() == manifest.digest
+    }
+}
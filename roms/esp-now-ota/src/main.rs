@@ -0,0 +1,313 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+mod macros;
+mod flash;
+
+use embassy_executor::Spawner;
+use embassy_futures::select::{Either, select};
+use embassy_time::{Duration, Instant, Timer};
+use esp_backtrace as _;
+use esp_hal::{clock::CpuClock, rng::Rng, timer::timg::TimerGroup};
+use esp_now_stack::{
+    Mac,
+    ota::{self, CHUNK_LEN, Config, Event, Manifest, Message, Receiver},
+};
+use esp_println::println;
+use esp_wifi::{
+    EspWifiController,
+    esp_now::{BROADCAST_ADDRESS, EspNowManager, EspNowSender, PeerInfo},
+};
+
+use flash::{CAPACITY, Flash};
+
+/// Only images signed with the secret key that goes with this one are
+/// taken, see `tools/esp-now-ota`.
+const PUBLIC_KEY: &str = env!("OTA_PUBLIC_KEY");
+
+/// How often a node whose image has a trailer offers it to the others.
+const OFFER_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Leaves room for the broadcast peer.
+const MAX_PEERS: usize = 19;
+
+#[esp_hal_embassy::main]
+async fn main(_spawner: Spawner) -> ! {
+    esp_println::logger::init_logger_from_env();
+    let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
+    let peripherals = esp_hal::init(config);
+
+    esp_alloc::heap_allocator!(size: 72 * 1024);
+
+    let timg0 = TimerGroup::new(peripherals.TIMG0);
+    let timg1 = TimerGroup::new(peripherals.TIMG1);
+    let rng = Rng::new(peripherals.RNG);
+
+    esp_hal_embassy::init(timg1.timer0);
+
+    let init = &*mk_static!(
+        EspWifiController<'static>,
+        esp_wifi::init(timg0.timer0, rng, peripherals.RADIO_CLK).unwrap()
+    );
+
+    let esp_now = esp_wifi::esp_now::EspNow::new(&init, peripherals.WIFI).unwrap();
+    let (manager, sender, mut receiver) = esp_now.split();
+
+    let public_key = ota::parse_key(PUBLIC_KEY).expect("OTA_PUBLIC_KEY should be 64 hex digits");
+    let mut flash = Flash::new();
+    let running = flash.running_slot();
+    // Only an image with a trailer can be handed on, one that was flashed
+    // over USB without one is version 0, and takes any update
+    let own = flash
+        .trailer(running)
+        .filter(|t| t.verified)
+        .map(|t| t.manifest);
+    match &own {
+        Some(own) => println!(
+            "Running version {} from slot {running}, seeding it",
+            own.version
+        ),
+        None => println!("Running an image without a trailer from slot {running}"),
+    }
+
+    let mut node = Node {
+        flash,
+        running,
+        own,
+        download: Receiver::new(Config::default(), public_key, own.map_or(0, |m| m.version)),
+        peers: Peers {
+            manager,
+            added: heapless::Deque::new(),
+        },
+        sender,
+    };
+    let mut next_offer = Instant::now();
+    loop {
+        let deadline = node
+            .download
+            .next_deadline()
+            .map_or(Instant::MAX, Instant::from_millis);
+        let deadline = match node.own {
+            Some(_) => deadline.min(next_offer),
+            None => deadline,
+        };
+        match select(receiver.receive_async(), Timer::at(deadline)).await {
+            Either::First(received) => {
+                node.handle(received.info.src_address, received.data())
+                    .await
+            }
+            Either::Second(()) => {
+                if node.own.is_some() && Instant::now() >= next_offer {
+                    node.offer().await;
+                    next_offer += OFFER_INTERVAL;
+                }
+                node.poll().await;
+            }
+        }
+    }
+}
+
+/// The ESP-NOW peers we talk to, the oldest of which make room for new
+/// ones.
+struct Peers {
+    manager: EspNowManager<'static>,
+    added: heapless::Deque<Mac, MAX_PEERS>,
+}
+
+impl Peers {
+    fn add(&mut self, mac: Mac) -> bool {
+        if self.manager.peer_exists(&mac) {
+            return true;
+        }
+        if self.added.is_full() {
+            let oldest = self.added.pop_front().unwrap();
+            let _ = self.manager.remove_peer(&oldest);
+        }
+        let peer = PeerInfo {
+            peer_address: mac,
+            lmk: None,
+            channel: None,
+            encrypt: false,
+        };
+        match self.manager.add_peer(peer) {
+            Ok(()) => {
+                let _ = self.added.push_back(mac);
+                true
+            }
+            Err(e) => {
+                println!("Error adding peer {mac:x?}: {e:?}");
+                false
+            }
+        }
+    }
+}
+
+/// Seeds the image we run, if it has a trailer, and downloads newer ones
+/// into the other slot.
+struct Node {
+    flash: Flash,
+    running: usize,
+    own: Option<Manifest>,
+    download: Receiver,
+    peers: Peers,
+    sender: EspNowSender<'static>,
+}
+
+impl Node {
+    fn other_slot(&self) -> usize {
+        1 - self.running
+    }
+
+    async fn offer(&mut self) {
+        let Some(own) = self.own else {
+            return;
+        };
+        let offer = Message::Offer(own).encode();
+        if let Err(e) = self.sender.send_async(&BROADCAST_ADDRESS, &offer).await {
+            println!("Error sending offer: {e:?}");
+        }
+    }
+
+    /// Asks again for chunks that are overdue.
+    async fn poll(&mut self) {
+        let was_downloading = self.download.progress().is_some();
+        if let Some((seeder, request)) = self.download.poll(Instant::now().as_millis()) {
+            let _ = self.sender.send_async(&seeder, &request).await;
+        } else if was_downloading && self.download.progress().is_none() {
+            println!("The seeder went quiet, waiting for another offer");
+        }
+    }
+
+    async fn handle(&mut self, src: Mac, payload: &[u8]) {
+        let Some(message) = Message::decode(payload) else {
+            return;
+        };
+        if let Message::Request { .. } = message {
+            self.serve(src, &message).await;
+            return;
+        }
+
+        let now = Instant::now().as_millis();
+        let slot = self.other_slot();
+        let request = match self.download.handle(now, src, payload) {
+            Event::Offered(manifest) if manifest.len > CAPACITY => {
+                println!("Version {} does not fit in a slot", manifest.version);
+                None
+            }
+            Event::Offered(manifest) => {
+                let trailer = self.flash.trailer(slot);
+                let first = match Receiver::resume_from(&manifest, trailer.as_ref()) {
+                    Some(first) => first,
+                    None => {
+                        self.flash.begin(slot, &manifest);
+                        0
+                    }
+                };
+                println!(
+                    "Downloading version {} from {src:x?}, {} of {} chunks to go",
+                    manifest.version,
+                    manifest.chunks() - first,
+                    manifest.chunks()
+                );
+                if !self.peers.add(src) {
+                    None
+                } else {
+                    let request = self.download.start(now, src, manifest, first);
+                    if self.download.is_complete() {
+                        self.finish();
+                    }
+                    request
+                }
+            }
+            Event::BadSignature { src } => {
+                println!("Ignored an offer from {src:x?} with a bad signature");
+                None
+            }
+            Event::Chunk {
+                offset,
+                data,
+                sector_written,
+                complete,
+                request,
+            } => {
+                self.flash.write_chunk(slot, offset, data);
+                if let Some(sector) = sector_written {
+                    self.flash.mark_written(slot, sector);
+                    if let Some((_, manifest, _)) = self.download.progress() {
+                        if sector % 16 == 15 {
+                            println!("{} of {} sectors", sector + 1, manifest.sectors());
+                        }
+                    }
+                }
+                if complete {
+                    self.finish();
+                }
+                request
+            }
+            Event::Missed { request } => Some(request),
+            Event::Ignored => None,
+        };
+        if let Some(request) = request {
+            let _ = self.sender.send_async(&src, &request).await;
+        }
+    }
+
+    /// Sends the chunks of our image that `src` asks for.
+    async fn serve(&mut self, src: Mac, request: &Message<'_>) {
+        let Some(own) = self.own else {
+            return;
+        };
+        let Some(chunks) = ota::serve(&own, Config::default().window, request) else {
+            return;
+        };
+        if chunks.start == 0 {
+            println!("{src:x?} is downloading version {}", own.version);
+        }
+        if !self.peers.add(src) {
+            return;
+        }
+        let mut buf = [0; CHUNK_LEN];
+        for index in chunks {
+            let Some(len) = self.flash.read_chunk(self.running, &own, index, &mut buf) else {
+                return;
+            };
+            let frame = Message::Chunk {
+                id: own.id(),
+                index,
+                data: &buf[..len],
+            }
+            .encode();
+            // Whatever doesn't make it is asked for again
+            if self.sender.send_async(&src, &frame).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Checks a complete image, and boots it when it checks out.
+    fn finish(&mut self) {
+        let slot = self.other_slot();
+        let Some(trailer) = self.flash.trailer(slot) else {
+            println!("The trailer is gone, starting over");
+            self.download.reset();
+            return;
+        };
+        if !self.flash.check(slot, &trailer.manifest) {
+            println!("The image does not match its manifest, starting over");
+            self.flash.discard(slot);
+            self.download.reset();
+            return;
+        }
+        self.flash.mark_verified(slot);
+        if !self.flash.boot_from(slot) {
+            self.download.reset();
+            return;
+        }
+        println!(
+            "Version {} checks out, rebooting into it",
+            trailer.manifest.version
+        );
+        esp_hal::system::software_reset()
+    }
+}
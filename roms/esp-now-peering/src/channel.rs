@@ -0,0 +1,74 @@
+use core::cell::RefCell;
+
+use embassy_futures::select::select;
+use embassy_sync::{
+    blocking_mutex::{NoopMutex, raw::NoopRawMutex},
+    signal::Signal,
+};
+use embassy_time::{Instant, Timer};
+use esp_hal::rng::Rng;
+use esp_now_stack::{
+    channel::{ChannelManager, Config},
+    transport::Transport,
+};
+use esp_println::println;
+use esp_wifi::esp_now::EspNowManager;
+
+use crate::discovery::{self, SharedDiscovery};
+
+pub(crate) type SharedChannels = NoopMutex<RefCell<ChannelManager>>;
+
+/// Wakes up `channel_task` when something other than it changed what the
+/// manager is up to.
+pub(crate) type ChannelChanged = Signal<NoopRawMutex, ()>;
+
+/// We start hopping from channel 1, which is what the driver starts out on.
+/// With a station, the channel only becomes fixed once it is connected and
+/// we know the AP's, until then there is nothing to tell the others.
+pub(crate) fn new() -> ChannelManager {
+    ChannelManager::new(Config::default(), Instant::now().as_millis(), 1, false)
+}
+
+/// Moves the radio to `channel`, and lets whoever is there know we are.
+pub(crate) async fn switch(
+    channel: u8,
+    manager: &EspNowManager<'_>,
+    transport: &Transport<'_>,
+    discovery: &SharedDiscovery,
+    channels: &SharedChannels,
+) {
+    if let Err(e) = manager.set_channel(channel) {
+        println!("Error switching to channel {channel}: {e:?}");
+        return;
+    }
+    discovery::say_hello(transport, discovery, channels).await;
+}
+
+/// Hops and sweeps when the manager says so. Does nothing while a station
+/// decides the channel.
+#[embassy_executor::task]
+pub(crate) async fn channel_task(
+    mut rng: Rng,
+    manager: &'static EspNowManager<'static>,
+    transport: &'static Transport<'static>,
+    discovery: &'static SharedDiscovery,
+    channels: &'static SharedChannels,
+    changed: &'static ChannelChanged,
+) {
+    loop {
+        let now = Instant::now().as_millis();
+        let (next, deadline) = channels.lock(|c| {
+            let mut c = c.borrow_mut();
+            (c.poll(now, rng.random()), c.next_deadline())
+        });
+        if let Some(channel) = next {
+            switch(channel, manager, transport, discovery, channels).await;
+        }
+        match deadline {
+            Some(deadline) => {
+                select(Timer::at(Instant::from_millis(deadline)), changed.wait()).await;
+            }
+            None => changed.wait().await,
+        }
+    }
+}
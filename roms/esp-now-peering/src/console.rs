@@ -0,0 +1,115 @@
+use embassy_time::Instant;
+use embedded_io_async::Read;
+use esp_hal::{Async, gpio::Input, usb_serial_jtag::UsbSerialJtagRx};
+use esp_println::println;
+
+use crate::{
+    channel::SharedChannels,
+    discovery::SharedDiscovery,
+    pairing::{Command, Commands, SharedStore},
+};
+
+const MAX_LINE_LEN: usize = 32;
+
+const HELP: &str = "Commands:
+  pair    look for nodes to pair with, they have to be pairing as well
+  forget  forget all paired peers
+  peers   list the paired peers
+  nearby  list the nodes we have heard from recently
+  channel show the channel we are on, and why
+  help    show this message";
+
+/// Reads commands from the USB serial console, one per line.
+#[embassy_executor::task]
+pub(crate) async fn console(
+    mut usb_rx: UsbSerialJtagRx<'static, Async>,
+    store: &'static SharedStore,
+    discovery: &'static SharedDiscovery,
+    channels: &'static SharedChannels,
+    commands: &'static Commands,
+) {
+    let mut buf = [0; 32];
+    let mut line = heapless::Vec::<u8, MAX_LINE_LEN>::new();
+    loop {
+        let Ok(n) = usb_rx.read(&mut buf).await;
+        for &c in &buf[..n] {
+            match c {
+                b'\r' | b'\n' => {
+                    handle_line(&line, store, discovery, channels, commands).await;
+                    line.clear();
+                }
+                // Backspace and delete
+                0x08 | 0x7f => {
+                    line.pop();
+                }
+                c => {
+                    let _ = line.push(c);
+                }
+            }
+        }
+    }
+}
+
+async fn handle_line(
+    line: &[u8],
+    store: &SharedStore,
+    discovery: &SharedDiscovery,
+    channels: &SharedChannels,
+    commands: &Commands,
+) {
+    match line.trim_ascii() {
+        b"" => (),
+        b"pair" => commands.send(Command::Pair).await,
+        b"forget" => commands.send(Command::Forget).await,
+        b"peers" => store.lock(|s| {
+            let s = s.borrow();
+            if s.peers().is_empty() {
+                println!("Not paired with anybody");
+            }
+            for peer in s.peers() {
+                println!("{:x?}", peer.mac);
+            }
+        }),
+        b"nearby" => discovery.lock(|d| {
+            let d = d.borrow();
+            if d.table().is_empty() {
+                println!("Nobody around");
+            }
+            let now = Instant::now().as_millis();
+            for peer in d.table().peers() {
+                let paired = store.lock(|s| s.borrow().get(&peer.mac).is_some());
+                let channel = peer.announcement.channel;
+                println!(
+                    "{:x?} {:16} rssi={:4} seen {} ms ago, capabilities={:#06x}, channel={}{}{}",
+                    peer.mac,
+                    peer.announcement.name,
+                    peer.rssi,
+                    now - peer.last_seen_ms,
+                    peer.announcement.capabilities.0,
+                    channel.map_or(0, |c| c.number),
+                    if channel.is_some_and(|c| c.fixed) {
+                        " (fixed)"
+                    } else {
+                        ""
+                    },
+                    if paired { ", paired" } else { "" }
+                );
+            }
+        }),
+        b"channel" => channels.lock(|c| {
+            let c = c.borrow();
+            println!("On channel {}, {:?}", c.channel(), c.state());
+        }),
+        b"help" => println!("{HELP}"),
+        _ => println!("Unknown command, try `help`"),
+    }
+}
+
+/// Starts pairing when the BOOT button is pressed.
+#[embassy_executor::task]
+pub(crate) async fn button(mut button: Input<'static>, commands: &'static Commands) {
+    loop {
+        button.wait_for_falling_edge().await;
+        commands.send(Command::Pair).await;
+    }
+}
@@ -0,0 +1,175 @@
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::NoopMutex;
+use embassy_time::{Instant, Timer};
+use esp_hal::rng::Rng;
+use esp_now_stack::{
+    Mac,
+    discovery::{Announcement, Capabilities, Discovery, Update},
+    transport::{Message, Transport},
+};
+use esp_println::println;
+use esp_wifi::esp_now::{EspNowManager, PeerInfo};
+
+use crate::{
+    HelloQueue,
+    channel::{self, ChannelChanged, SharedChannels},
+    pairing::SharedStore,
+};
+
+/// How many nodes we keep track of. Together with the paired peers this
+/// stays below the 20 peers ESP-NOW can have.
+pub(crate) const MAX_DISCOVERED: usize = 12;
+
+/// Nodes say hello every one to two seconds, and are forgotten after
+/// missing a few.
+const HELLO_INTERVAL_MS: u64 = 1000;
+const MAX_AGE_MS: u64 = 5 * HELLO_INTERVAL_MS;
+
+pub(crate) const NAME: &str = match option_env!("DEVICE_NAME") {
+    Some(name) => name,
+    None => "esp-now-peering",
+};
+
+pub(crate) type SharedDiscovery = NoopMutex<RefCell<Discovery<MAX_DISCOVERED>>>;
+
+pub(crate) fn new() -> Discovery<MAX_DISCOVERED> {
+    let capabilities = Capabilities::RELIABLE
+        .union(Capabilities::FRAGMENTS)
+        .union(Capabilities::PAIRING);
+    Discovery::new(Announcement::new(NAME, capabilities), MAX_AGE_MS)
+}
+
+/// Removes a node we no longer track from ESP-NOW, unless we are paired
+/// with it, in which case it stays to keep its key.
+fn forget(manager: &EspNowManager<'_>, store: &SharedStore, mac: &Mac) {
+    if store.lock(|s| s.borrow().get(mac).is_none()) {
+        let _ = manager.remove_peer(mac);
+    }
+}
+
+/// Broadcasts a hello, telling the others which channel we are on.
+pub(crate) async fn say_hello(
+    transport: &Transport<'_>,
+    discovery: &SharedDiscovery,
+    channels: &SharedChannels,
+) {
+    let info = channels.lock(|c| c.borrow().info());
+    let hello = discovery.lock(|d| {
+        let mut d = d.borrow_mut();
+        d.set_channel(info);
+        d.hello()
+    });
+    if let Err(e) = transport.broadcast(&hello).await {
+        println!("Error sending hello: {e:?}");
+    }
+}
+
+/// Answers a hello, keeps the peer table and ESP-NOW's peers in step, and
+/// moves to the channel the sender says we should be on.
+pub(crate) async fn handle(
+    message: &Message,
+    manager: &EspNowManager<'_>,
+    transport: &Transport<'_>,
+    discovery: &SharedDiscovery,
+    store: &SharedStore,
+    hellos: &HelloQueue,
+    channels: &SharedChannels,
+    changed: &ChannelChanged,
+) {
+    let now = Instant::now().as_millis();
+    let info = channels.lock(|c| c.borrow().info());
+    let Some(handled) = discovery.lock(|d| {
+        let mut d = d.borrow_mut();
+        d.set_channel(info);
+        d.handle(now, message.src, message.rssi, &message.payload)
+    }) else {
+        return;
+    };
+    let theirs = discovery.lock(|d| {
+        d.borrow()
+            .table()
+            .get(&message.src)
+            .and_then(|p| p.announcement.channel)
+    });
+    let (moved, state_changed) = channels.lock(|c| {
+        let mut c = c.borrow_mut();
+        let before = c.state();
+        let moved = c.heard(now, theirs);
+        (moved, c.state() != before)
+    });
+    if state_changed {
+        changed.signal(());
+    }
+
+    if let Update::New {
+        evicted: Some(evicted),
+    } = handled.update
+    {
+        forget(manager, store, &evicted);
+    }
+    // Paired peers are already there, encrypted. Others are added the first
+    // time we hear from them, or again after being forgotten.
+    let paired = store.lock(|s| s.borrow().get(&message.src).is_some());
+    if !paired && !manager.peer_exists(&message.src) {
+        let peer = PeerInfo {
+            peer_address: message.src,
+            lmk: None,
+            channel: None,
+            encrypt: false,
+        };
+        if let Err(e) = manager.add_peer(peer) {
+            println!("Error adding peer {:x?}: {e:?}", message.src);
+            return;
+        }
+    }
+    if let Update::New { .. } = handled.update {
+        discovery.lock(|d| {
+            let d = d.borrow();
+            let peer = d.table().get(&message.src).unwrap();
+            println!(
+                "Found {:?} at {:x?}, rssi={}",
+                peer.announcement.name, peer.mac, peer.rssi
+            );
+        });
+        if paired {
+            let _ = hellos.try_send(message.src);
+        }
+    }
+    match moved {
+        Some(to) => {
+            println!("Moving to channel {to} to join {:x?}", message.src);
+            channel::switch(to, manager, transport, discovery, channels).await;
+        }
+        None => {
+            if let Some(reply) = handled.reply {
+                let _ = transport.send_unreliable(&message.src, &reply).await;
+            }
+        }
+    }
+}
+
+/// Says hello to whoever is around, and forgets the nodes that stopped
+/// answering.
+#[embassy_executor::task]
+pub(crate) async fn announce_task(
+    mut rng: Rng,
+    manager: &'static EspNowManager<'static>,
+    transport: &'static Transport<'static>,
+    discovery: &'static SharedDiscovery,
+    store: &'static SharedStore,
+    channels: &'static SharedChannels,
+) {
+    loop {
+        say_hello(transport, discovery, channels).await;
+
+        let now = Instant::now().as_millis();
+        while let Some(peer) = discovery.lock(|d| d.borrow_mut().table_mut().expire(now)) {
+            println!("Lost {:?} at {:x?}", peer.announcement.name, peer.mac);
+            forget(manager, store, &peer.mac);
+        }
+
+        let jitter = rng.random() as u64 % HELLO_INTERVAL_MS;
+        Timer::after_millis(HELLO_INTERVAL_MS + jitter).await;
+    }
+}
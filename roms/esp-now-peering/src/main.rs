@@ -3,17 +3,45 @@
 
 #[macro_use]
 mod macros;
+mod channel;
+mod console;
+mod discovery;
+mod pairing;
+mod store;
+mod wifi;
+
+use core::cell::RefCell;
 
 use embassy_executor::Spawner;
-use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex};
-use embassy_time::Timer;
+use embassy_sync::{
+    blocking_mutex::{NoopMutex, raw::NoopRawMutex},
+    channel::Channel,
+    signal::Signal,
+};
+use embassy_time::{Instant, Timer};
 use esp_backtrace as _;
-use esp_hal::{clock::CpuClock, rng::Rng, timer::timg::TimerGroup};
-use esp_println::println;
-use esp_wifi::{
-    EspWifiController,
-    esp_now::{BROADCAST_ADDRESS, EspNowSender, PeerInfo},
+use esp_hal::{
+    clock::CpuClock,
+    efuse::Efuse,
+    gpio::{Input, InputConfig, Pull},
+    rng::Rng,
+    timer::timg::TimerGroup,
+    usb_serial_jtag::UsbSerialJtag,
 };
+use esp_now_messages::Message;
+use esp_now_stack::{
+    Mac, discovery::is_discovery_message, header::MAX_PAYLOAD_LEN, pairing::is_pairing_message,
+    reliable::Config, transport::Transport,
+};
+use esp_println::println;
+use esp_wifi::{EspWifiController, esp_now::EspNowManager};
+
+use channel::{ChannelChanged, SharedChannels};
+use discovery::{NAME, SharedDiscovery};
+use pairing::{Commands, PAIRING_CODE, PairingMessages, SharedStore};
+
+/// Paired peers we have found and want to say hello to.
+pub(crate) type HelloQueue = Channel<NoopRawMutex, Mac, 4>;
 
 #[esp_hal_embassy::main]
 async fn main(spawner: Spawner) -> ! {
@@ -34,58 +62,164 @@ async fn main(spawner: Spawner) -> ! {
         esp_wifi::init(timg0.timer0, rng, peripherals.RADIO_CLK).unwrap()
     );
 
-    let esp_now = esp_wifi::esp_now::EspNow::new(&init, peripherals.WIFI).unwrap();
+    let channels = &*mk_static!(SharedChannels, NoopMutex::new(RefCell::new(channel::new())));
+    let channel_changed = &*mk_static!(ChannelChanged, Signal::new());
+
+    let esp_now = match wifi::STATION {
+        Some((ssid, _)) => {
+            println!("Connecting to {ssid:?} as well, ESP-NOW stays on its channel");
+            wifi::init_station(
+                &spawner,
+                init,
+                peripherals.WIFI,
+                rng,
+                channels,
+                channel_changed,
+            )
+        }
+        None => {
+            println!("SSID and PASSWORD were not set at build time, hopping channels");
+            esp_wifi::esp_now::EspNow::new(&init, peripherals.WIFI).unwrap()
+        }
+    };
     println!("ESP-NOW version: {:?}", esp_now.version().unwrap());
 
     let (manager, sender, mut receiver) = esp_now.split();
+    let manager = &*mk_static!(EspNowManager<'static>, manager);
 
-    let sender = mk_static!(Mutex<NoopRawMutex, EspNowSender<'static>>, Mutex::new(sender));
+    // Every node has to use the same PMK, or the LMKs can't be decrypted
+    match PAIRING_CODE {
+        Some(code) => manager
+            .set_pmk(&esp_now_stack::pairing::pmk(code.as_bytes()))
+            .unwrap(),
+        None => println!("PAIRING_CODE was not set at build time, pairing is disabled"),
+    }
+
+    let mut flash = store::Flash::new();
+    let peers = flash.load();
+    for peer in peers.peers() {
+        pairing::add_encrypted_peer(manager, peer.mac, peer.lmk);
+    }
+    println!("{} paired peer(s)", peers.peers().len());
+    let peers = &*mk_static!(SharedStore, NoopMutex::new(RefCell::new(peers)));
+
+    let transport = &*mk_static!(
+        Transport<'static>,
+        Transport::new(sender, Config::default(), rng.random() as u16)
+    );
+    let hellos = &*mk_static!(HelloQueue, Channel::new());
+    let discovery = &*mk_static!(
+        SharedDiscovery,
+        NoopMutex::new(RefCell::new(discovery::new()))
+    );
+    let commands = &*mk_static!(Commands, Channel::new());
+    let pairing_messages = &*mk_static!(PairingMessages, Channel::new());
+
+    let (usb_rx, _) = UsbSerialJtag::new(peripherals.USB_DEVICE)
+        .into_async()
+        .split();
+    let button = Input::new(
+        peripherals.GPIO9,
+        InputConfig::default().with_pull(Pull::Up),
+    );
+    let own_mac = Efuse::read_base_mac_address();
 
     Timer::after_millis((rng.random() % 1000) as u64).await;
 
-    spawner.spawn(broadcast_task(rng, sender)).unwrap();
+    spawner
+        .spawn(discovery::announce_task(
+            rng, manager, transport, discovery, peers, channels,
+        ))
+        .unwrap();
+    spawner
+        .spawn(channel::channel_task(
+            rng,
+            manager,
+            transport,
+            discovery,
+            channels,
+            channel_changed,
+        ))
+        .unwrap();
+    spawner.spawn(hello_task(transport, hellos)).unwrap();
+    spawner
+        .spawn(pairing::pairing_task(
+            rng,
+            own_mac,
+            flash,
+            manager,
+            transport,
+            peers,
+            commands,
+            pairing_messages,
+        ))
+        .unwrap();
+    spawner
+        .spawn(console::console(
+            usb_rx, peers, discovery, channels, commands,
+        ))
+        .unwrap();
+    spawner.spawn(console::button(button, commands)).unwrap();
     loop {
-        let r = receiver.receive_async().await;
-        let mut s = heapless::String::<32>::new();
-        for &c in r.data() {
-            for e in core::ascii::escape_default(c) {
-                let _ = s.push(e as char);
+        // This also sends the acknowledgements `hello_task` is waiting for
+        let r = transport.receive(&mut receiver).await;
+        if is_pairing_message(&r.payload) {
+            if let Ok(message) = heapless::Vec::from_slice(&r.payload) {
+                let _ = pairing_messages.try_send((r.src, message));
             }
+            continue;
         }
-        println!(
-            "Got packet: src={:x?} dst={:x?} data={:?}",
-            r.info.src_address, r.info.dst_address, s
-        );
-        if r.info.dst_address == BROADCAST_ADDRESS && r.data() == b"peering test" {
-            if !manager.peer_exists(&r.info.src_address) {
-                manager
-                    .add_peer(PeerInfo {
-                        peer_address: r.info.src_address,
-                        lmk: None,
-                        channel: None,
-                        encrypt: false,
-                    })
-                    .unwrap();
+        if is_discovery_message(&r.payload) {
+            discovery::handle(
+                &r,
+                manager,
+                transport,
+                discovery,
+                peers,
+                hellos,
+                channels,
+                channel_changed,
+            )
+            .await;
+            continue;
+        }
+        let now = Instant::now().as_millis();
+        discovery.lock(|d| d.borrow_mut().table_mut().touch(now, &r.src, r.rssi));
+
+        match Message::decode(&r.payload) {
+            Ok(message) => println!(
+                "Got {message:?}: src={:x?} dst={:x?} rssi={} reliable={}",
+                r.src, r.dst, r.rssi, r.reliable
+            ),
+            Err(err) => {
+                let mut s = heapless::String::<32>::new();
+                for &c in &r.payload {
+                    for e in core::ascii::escape_default(c) {
+                        let _ = s.push(e as char);
+                    }
+                }
+                println!(
+                    "Got packet ({err:?}): src={:x?} dst={:x?} rssi={} reliable={} data={:?}",
+                    r.src, r.dst, r.rssi, r.reliable, s
+                );
             }
-            let status = sender
-                .lock()
-                .await
-                .send_async(&r.info.src_address, b"HELLO!")
-                .await;
-            println!("Status from sending hello: {status:?}");
         }
     }
 }
 
+/// Says hello to each paired peer we find, and waits for it to arrive.
 #[embassy_executor::task]
-async fn broadcast_task(mut rng: Rng, sender: &'static Mutex<NoopRawMutex, EspNowSender<'static>>) {
+async fn hello_task(transport: &'static Transport<'static>, hellos: &'static HelloQueue) {
     loop {
-        let status = sender
-            .lock()
-            .await
-            .send_async(&BROADCAST_ADDRESS, b"peering test")
-            .await;
-        println!("Status from sending ping: {status:?}");
-        Timer::after_millis(1000 + (rng.random() % 1000) as u64).await;
+        let peer = hellos.receive().await;
+        let mut buf = [0; MAX_PAYLOAD_LEN];
+        let Ok(n) = Message::Hello { name: NAME }.encode(&mut buf) else {
+            println!("{NAME:?} is too long to say hello with");
+            continue;
+        };
+        match transport.send(&peer, &buf[..n]).await {
+            Ok(attempts) => println!("Hello delivered to {peer:x?} after {attempts} attempt(s)"),
+            Err(e) => println!("Hello to {peer:x?} failed: {e:?}"),
+        }
     }
 }
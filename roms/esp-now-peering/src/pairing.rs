@@ -0,0 +1,149 @@
+use core::cell::RefCell;
+
+use embassy_futures::select::{Either, select};
+use embassy_sync::{
+    blocking_mutex::{NoopMutex, raw::NoopRawMutex},
+    channel::Channel,
+};
+use embassy_time::{Duration, Instant, Timer};
+use esp_hal::rng::Rng;
+use esp_now_stack::{
+    Mac,
+    pairing::{self, Action, KEY_LEN, Pairing},
+    peer_store::{PairedPeer, PeerStore},
+    transport::Transport,
+};
+use esp_println::println;
+use esp_wifi::esp_now::{EspNowManager, PeerInfo};
+
+use crate::store::Flash;
+
+/// The code every node has to be built with to pair. See `pairing` in
+/// `esp-now-stack` for why it should be long.
+pub(crate) const PAIRING_CODE: Option<&str> = option_env!("PAIRING_CODE");
+
+/// How long we look for nodes to pair with after being asked to.
+const PAIRING_WINDOW: Duration = Duration::from_secs(30);
+/// How long we keep answering after pairing, so the other node gets our
+/// confirmation as well.
+const PAIRED_GRACE: Duration = Duration::from_secs(3);
+const OFFER_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Command {
+    Pair,
+    Forget,
+}
+
+pub(crate) type Commands = Channel<NoopRawMutex, Command, 2>;
+pub(crate) type PairingMessages =
+    Channel<NoopRawMutex, (Mac, heapless::Vec<u8, { pairing::MAX_MESSAGE_LEN }>), 4>;
+pub(crate) type SharedStore = NoopMutex<RefCell<PeerStore>>;
+
+pub(crate) fn add_encrypted_peer(manager: &EspNowManager<'_>, mac: Mac, lmk: [u8; KEY_LEN]) {
+    if manager.peer_exists(&mac) {
+        let _ = manager.remove_peer(&mac);
+    }
+    let peer = PeerInfo {
+        peer_address: mac,
+        lmk: Some(lmk),
+        channel: None,
+        encrypt: true,
+    };
+    if let Err(e) = manager.add_peer(peer) {
+        println!("Error adding peer {mac:x?}: {e:?}");
+    }
+}
+
+/// Runs pairing sessions and forgets peers when asked to.
+#[embassy_executor::task]
+pub(crate) async fn pairing_task(
+    mut rng: Rng,
+    own_mac: Mac,
+    mut flash: Flash,
+    manager: &'static EspNowManager<'static>,
+    transport: &'static Transport<'static>,
+    store: &'static SharedStore,
+    commands: &'static Commands,
+    messages: &'static PairingMessages,
+) {
+    loop {
+        match commands.receive().await {
+            Command::Pair => {
+                let Some(code) = PAIRING_CODE else {
+                    println!("Can't pair, PAIRING_CODE was not set at build time");
+                    continue;
+                };
+                let mut secret = [0; 32];
+                rng.read(&mut secret);
+                let session = Pairing::new(own_mac, secret, code.as_bytes());
+                run_session(session, &mut flash, manager, transport, store, messages).await;
+            }
+            Command::Forget => {
+                store.lock(|s| {
+                    let mut s = s.borrow_mut();
+                    for peer in s.peers() {
+                        let _ = manager.remove_peer(&peer.mac);
+                    }
+                    s.clear();
+                });
+                store.lock(|s| flash.save(&s.borrow()));
+                println!("Forgot all paired peers");
+            }
+        }
+    }
+}
+
+async fn run_session(
+    mut session: Pairing,
+    flash: &mut Flash,
+    manager: &EspNowManager<'_>,
+    transport: &Transport<'_>,
+    store: &SharedStore,
+    messages: &PairingMessages,
+) {
+    println!("Pairing for {} s", PAIRING_WINDOW.as_secs());
+    // Whatever was sent to a session before this one is of no use
+    while messages.try_receive().is_ok() {}
+
+    let mut end = Instant::now() + PAIRING_WINDOW;
+    let mut next_offer = Instant::now();
+    while Instant::now() < end {
+        let wake = next_offer.min(end);
+        let (src, message) = match select(Timer::at(wake), messages.receive()).await {
+            Either::First(()) => {
+                if Instant::now() >= next_offer {
+                    let _ = transport.broadcast(&session.offer()).await;
+                    next_offer += OFFER_INTERVAL;
+                }
+                continue;
+            }
+            Either::Second(received) => received,
+        };
+
+        match session.handle(src, &message) {
+            Action::None => (),
+            Action::Reply(reply) => {
+                let _ = transport.broadcast(&reply).await;
+            }
+            Action::Paired { peer, lmk } => {
+                if store.lock(|s| s.borrow().get(&peer).map(|p| p.lmk)) == Some(lmk) {
+                    continue;
+                }
+                add_encrypted_peer(manager, peer, lmk);
+                let evicted = store.lock(|s| s.borrow_mut().insert(PairedPeer { mac: peer, lmk }));
+                if let Some(evicted) = evicted {
+                    let _ = manager.remove_peer(&evicted.mac);
+                    println!("Forgot {:x?} to make room", evicted.mac);
+                }
+                store.lock(|s| flash.save(&s.borrow()));
+                println!("Paired with {peer:x?}");
+                end = end.min(Instant::now() + PAIRED_GRACE);
+            }
+            Action::Rejected { peer } => {
+                println!("Rejected {peer:x?}, it was probably built with a different pairing code");
+            }
+        }
+    }
+    println!("Pairing finished");
+}
@@ -0,0 +1,40 @@
+use embedded_storage::{ReadStorage, Storage};
+use esp_now_stack::peer_store::{PeerStore, STORE_LEN};
+use esp_println::println;
+use esp_storage::FlashStorage;
+
+/// Where in flash the paired peers are kept. This is the start of the `nvs`
+/// partition in the default partition table, which nothing else in these
+/// ROMs uses.
+const STORE_OFFSET: u32 = 0x9000;
+
+/// Keeps the paired peers across reboots.
+pub(crate) struct Flash {
+    flash: FlashStorage,
+}
+
+impl Flash {
+    pub(crate) fn new() -> Self {
+        Self {
+            flash: FlashStorage::new(),
+        }
+    }
+
+    /// Reads the stored peers. Erased or corrupted flash reads as no peers.
+    pub(crate) fn load(&mut self) -> PeerStore {
+        let mut buf = [0; STORE_LEN];
+        match self.flash.read(STORE_OFFSET, &mut buf) {
+            Ok(()) => PeerStore::decode(&buf),
+            Err(e) => {
+                println!("Error reading paired peers: {e:?}");
+                PeerStore::new()
+            }
+        }
+    }
+
+    pub(crate) fn save(&mut self, store: &PeerStore) {
+        if let Err(e) = self.flash.write(STORE_OFFSET, &store.encode()) {
+            println!("Error saving paired peers: {e:?}");
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use embassy_executor::Spawner;
+use embassy_futures::select::{Either, select};
+use embassy_net::{Runner, StackResources};
+use embassy_time::{Duration, Instant, Timer};
+use esp_hal::{peripherals::WIFI, rng::Rng};
+use esp_now_stack::channel::State;
+use esp_println::println;
+use esp_wifi::{
+    EspWifiController,
+    esp_now::EspNow,
+    wifi::{ClientConfiguration, Configuration, WifiController, WifiDevice, WifiEvent},
+};
+
+use crate::channel::{ChannelChanged, SharedChannels};
+
+/// When both are set at build time, we also connect to this AP as a
+/// station, and ESP-NOW has to stay on whichever channel it is on.
+pub(crate) const STATION: Option<(&str, &str)> =
+    match (option_env!("SSID"), option_env!("PASSWORD")) {
+        (Some(ssid), Some(password)) => Some((ssid, password)),
+        _ => None,
+    };
+
+/// How often we check which channel the AP is on, since it may move.
+const AP_CHANNEL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Brings up the station, and hands ESP-NOW the same radio.
+pub(crate) fn init_station(
+    spawner: &Spawner,
+    init: &'static EspWifiController<'static>,
+    wifi: WIFI,
+    mut rng: Rng,
+    channels: &'static SharedChannels,
+    changed: &'static ChannelChanged,
+) -> EspNow<'static> {
+    let (controller, interfaces) = esp_wifi::wifi::new(init, wifi).unwrap();
+
+    let config = embassy_net::Config::dhcpv4(Default::default());
+    let seed = (rng.random() as u64) << 32 | rng.random() as u64;
+    let (stack, runner) = embassy_net::new(
+        interfaces.sta,
+        config,
+        mk_static!(StackResources<2>, StackResources::new()),
+        seed,
+    );
+
+    spawner
+        .spawn(connection(controller, channels, changed))
+        .unwrap();
+    spawner.spawn(net_task(runner)).unwrap();
+    spawner.spawn(address_task(stack)).unwrap();
+
+    interfaces.esp_now
+}
+
+#[embassy_executor::task]
+async fn connection(
+    mut controller: WifiController<'static>,
+    channels: &'static SharedChannels,
+    changed: &'static ChannelChanged,
+) {
+    let (ssid, password) = STATION.unwrap();
+    let client_config = Configuration::Client(ClientConfiguration {
+        ssid: ssid.try_into().unwrap(),
+        password: password.try_into().unwrap(),
+        ..Default::default()
+    });
+    controller.set_configuration(&client_config).unwrap();
+    println!("Starting wifi");
+    controller.start_async().await.unwrap();
+
+    loop {
+        println!("Connecting to {ssid:?}...");
+        match controller.connect_async().await {
+            Ok(()) => println!("Wifi connected!"),
+            Err(e) => {
+                println!("Failed to connect to wifi: {e:?}");
+                Timer::after(Duration::from_millis(5000)).await;
+                continue;
+            }
+        }
+        follow_ap(&mut controller, channels, changed).await;
+        println!("Wifi disconnected");
+    }
+}
+
+/// Keeps ESP-NOW on the AP's channel until we get disconnected, and then
+/// lets go of it.
+async fn follow_ap(
+    controller: &mut WifiController<'static>,
+    channels: &SharedChannels,
+    changed: &ChannelChanged,
+) {
+    loop {
+        if let Some(channel) = ap_channel() {
+            let moved = channels.lock(|c| {
+                let mut c = c.borrow_mut();
+                let moved = c.channel() != channel || c.state() != State::Fixed;
+                c.set_fixed(Instant::now().as_millis(), Some(channel));
+                moved
+            });
+            if moved {
+                println!("AP is on channel {channel}, ESP-NOW follows");
+                changed.signal(());
+            }
+        }
+        match select(
+            controller.wait_for_event(WifiEvent::StaDisconnected),
+            Timer::after(AP_CHANNEL_INTERVAL),
+        )
+        .await
+        {
+            Either::First(()) => break,
+            Either::Second(()) => (),
+        }
+    }
+    // Without the AP, nothing keeps us on its channel any more
+    channels.lock(|c| c.borrow_mut().set_fixed(Instant::now().as_millis(), None));
+    changed.signal(());
+}
+
+/// The channel of the AP we are connected to.
+fn ap_channel() -> Option<u8> {
+    use esp_wifi_sys::include::{esp_wifi_sta_get_ap_info, wifi_ap_record_t};
+
+    // SAFETY: `wifi_ap_record_t` is a plain C struct, for which all zeroes is
+    // a valid value, and the driver only writes to it while we wait.
+    let mut info: wifi_ap_record_t = unsafe { core::mem::zeroed() };
+    if unsafe { esp_wifi_sta_get_ap_info(&mut info) } != 0 {
+        return None;
+    }
+    Some(info.primary)
+}
+
+/// Shows the address the station got over DHCP.
+#[embassy_executor::task]
+async fn address_task(stack: embassy_net::Stack<'static>) {
+    loop {
+        stack.wait_config_up().await;
+        if let Some(config) = stack.config_v4() {
+            println!("Got IP address {}", config.address);
+        }
+        stack.wait_config_down().await;
+    }
+}
+
+#[embassy_executor::task]
+async fn net_task(mut runner: Runner<'static, WifiDevice<'static>>) {
+    runner.run().await
+}
@@ -0,0 +1,206 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+mod macros;
+
+use core::cell::Cell;
+
+use embassy_executor::Spawner;
+use embassy_sync::{
+    blocking_mutex::{NoopMutex, raw::NoopRawMutex},
+    channel::Channel,
+};
+use embassy_time::{Duration, Timer};
+use esp_backtrace as _;
+use esp_hal::{
+    clock::CpuClock,
+    gpio::{Input, InputConfig, Pin, Pull},
+    rng::Rng,
+    timer::timg::TimerGroup,
+};
+use esp_now_stack::{
+    Mac,
+    reliable::Config,
+    remote::{Command, Message, Rgb, State},
+    transport::Transport,
+};
+use esp_println::println;
+use esp_wifi::{
+    EspWifiController,
+    esp_now::{EspNowManager, PeerInfo},
+};
+
+/// The levels the level button steps through, in percent.
+const LEVELS: [u8; 4] = [25, 50, 75, 100];
+
+/// The colors the color button steps through.
+const COLORS: [Rgb; 6] = [
+    Rgb::WHITE,
+    Rgb::new(255, 0, 0),
+    Rgb::new(255, 128, 0),
+    Rgb::new(0, 255, 0),
+    Rgb::new(0, 0, 255),
+    Rgb::new(128, 0, 255),
+];
+
+/// Buttons bounce for a few milliseconds after they are pressed.
+const DEBOUNCE: Duration = Duration::from_millis(50);
+
+#[derive(Copy, Clone, Debug)]
+enum Button {
+    Toggle,
+    Level,
+    Color,
+}
+
+type Presses = Channel<NoopRawMutex, Button, 4>;
+
+/// The actuator we found, and the state it last told us about.
+type SharedActuator = NoopMutex<Cell<Option<(Mac, State)>>>;
+
+#[esp_hal_embassy::main]
+async fn main(spawner: Spawner) -> ! {
+    esp_println::logger::init_logger_from_env();
+    let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
+    let peripherals = esp_hal::init(config);
+
+    esp_alloc::heap_allocator!(size: 72 * 1024);
+
+    let timg0 = TimerGroup::new(peripherals.TIMG0);
+    let timg1 = TimerGroup::new(peripherals.TIMG1);
+    let mut rng = Rng::new(peripherals.RNG);
+
+    esp_hal_embassy::init(timg1.timer0);
+
+    let init = &*mk_static!(
+        EspWifiController<'static>,
+        esp_wifi::init(timg0.timer0, rng, peripherals.RADIO_CLK).unwrap()
+    );
+
+    let esp_now = esp_wifi::esp_now::EspNow::new(&init, peripherals.WIFI).unwrap();
+    let (manager, sender, mut receiver) = esp_now.split();
+    let transport = &*mk_static!(
+        Transport<'static>,
+        Transport::new(sender, Config::default(), rng.random() as u16)
+    );
+
+    let actuator = &*mk_static!(SharedActuator, NoopMutex::new(Cell::new(None)));
+    let presses = &*mk_static!(Presses, Channel::new());
+
+    // The BOOT button, and two more that pull GPIO4 and GPIO5 to ground
+    let buttons = [
+        (peripherals.GPIO9.degrade(), Button::Toggle),
+        (peripherals.GPIO4.degrade(), Button::Level),
+        (peripherals.GPIO5.degrade(), Button::Color),
+    ];
+    for (pin, button) in buttons {
+        let input = Input::new(pin, InputConfig::default().with_pull(Pull::Up));
+        spawner.spawn(button_task(input, button, presses)).unwrap();
+    }
+    spawner
+        .spawn(command_task(transport, actuator, presses))
+        .unwrap();
+    println!("BOOT toggles, GPIO4 steps the level, GPIO5 the color");
+
+    loop {
+        // This also sends the acknowledgements `command_task` is waiting for
+        let r = transport.receive(&mut receiver).await;
+        let Some(Message::State(state)) = Message::decode(&r.payload) else {
+            continue;
+        };
+        let known = actuator.lock(|a| a.get()).map(|(mac, _)| mac);
+        if known != Some(r.src) {
+            // Its first reply can't be acknowledged until it's a peer, but
+            // the retries can
+            if !add_peer(&manager, r.src) {
+                continue;
+            }
+            println!("Controlling {:x?}", r.src);
+        }
+        println!("{state:?}");
+        actuator.lock(|a| a.set(Some((r.src, state))));
+    }
+}
+
+fn add_peer(manager: &EspNowManager<'static>, mac: Mac) -> bool {
+    if manager.peer_exists(&mac) {
+        return true;
+    }
+    let peer = PeerInfo {
+        peer_address: mac,
+        lmk: None,
+        channel: None,
+        encrypt: false,
+    };
+    match manager.add_peer(peer) {
+        Ok(()) => true,
+        Err(e) => {
+            println!("Error adding peer {mac:x?}: {e:?}");
+            false
+        }
+    }
+}
+
+#[embassy_executor::task(pool_size = 3)]
+async fn button_task(mut input: Input<'static>, button: Button, presses: &'static Presses) {
+    loop {
+        input.wait_for_falling_edge().await;
+        presses.send(button).await;
+        Timer::after(DEBOUNCE).await;
+        input.wait_for_high().await;
+        Timer::after(DEBOUNCE).await;
+    }
+}
+
+/// What a button press asks for, given the state the actuator is in.
+fn command(button: Button, state: &State) -> Command {
+    match button {
+        Button::Toggle => Command::Toggle,
+        Button::Level => {
+            let next = LEVELS.iter().find(|&&l| l > state.level);
+            Command::Level(*next.unwrap_or(&LEVELS[0]))
+        }
+        Button::Color => {
+            let next = match COLORS.iter().position(|&c| c == state.color) {
+                Some(i) => (i + 1) % COLORS.len(),
+                None => 0,
+            };
+            Command::Color(COLORS[next])
+        }
+    }
+}
+
+/// Turns button presses into commands, and sends them to the actuator.
+/// Until we know of one, they are broadcast to any that is listening.
+#[embassy_executor::task]
+async fn command_task(
+    transport: &'static Transport<'static>,
+    actuator: &'static SharedActuator,
+    presses: &'static Presses,
+) {
+    loop {
+        let button = presses.receive().await;
+        let known = actuator.lock(|a| a.get());
+        let state = known.map_or(State::default(), |(_, state)| state);
+        let command = command(button, &state);
+        let message = Message::Command(command).encode();
+        match known {
+            Some((mac, mut state)) => {
+                // Presses in quick succession build on each other, even
+                // before the actuator tells us where it ended up
+                state.apply(command);
+                actuator.lock(|a| a.set(Some((mac, state))));
+                if let Err(e) = transport.send(&mac, &message).await {
+                    println!("{command:?} to {mac:x?} failed: {e:?}");
+                }
+            }
+            None => {
+                println!("No actuator yet, broadcasting {command:?}");
+                if let Err(e) = transport.broadcast(&message).await {
+                    println!("Error broadcasting {command:?}: {e:?}");
+                }
+            }
+        }
+    }
+}
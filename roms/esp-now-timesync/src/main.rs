@@ -0,0 +1,187 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+mod macros;
+
+use core::cell::RefCell;
+
+use embassy_executor::Spawner;
+use embassy_futures::select::select;
+use embassy_sync::{
+    blocking_mutex::{NoopMutex, raw::NoopRawMutex},
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant, Ticker, Timer};
+use esp_backtrace as _;
+use esp_hal::{
+    clock::CpuClock,
+    efuse::Efuse,
+    gpio::{Input, InputConfig, Pull},
+    rng::Rng,
+    timer::timg::TimerGroup,
+};
+use esp_now_stack::timesync::{self, Event, Role, TimeSync};
+use esp_println::println;
+use esp_wifi::{
+    EspWifiController,
+    esp_now::{BROADCAST_ADDRESS, EspNowSender},
+};
+
+const STATS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Half a minute's worth of beacons.
+type SharedSync = NoopMutex<RefCell<TimeSync<32>>>;
+
+/// The common clock, as an `Instant`.
+fn synced_now(sync: &SharedSync) -> Instant {
+    let local = Instant::now().as_micros();
+    Instant::from_micros(sync.lock(|s| s.borrow().now(local)))
+}
+
+#[esp_hal_embassy::main]
+async fn main(spawner: Spawner) -> ! {
+    esp_println::logger::init_logger_from_env();
+    let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
+    let peripherals = esp_hal::init(config);
+
+    esp_alloc::heap_allocator!(size: 72 * 1024);
+
+    let timg0 = TimerGroup::new(peripherals.TIMG0);
+    let timg1 = TimerGroup::new(peripherals.TIMG1);
+    let rng = Rng::new(peripherals.RNG);
+
+    esp_hal_embassy::init(timg1.timer0);
+
+    let init = &*mk_static!(
+        EspWifiController<'static>,
+        esp_wifi::init(timg0.timer0, rng, peripherals.RADIO_CLK).unwrap()
+    );
+
+    let esp_now = esp_wifi::esp_now::EspNow::new(&init, peripherals.WIFI).unwrap();
+    let (_manager, sender, mut receiver) = esp_now.split();
+
+    let own_mac = Efuse::read_base_mac_address();
+    println!("Time sync node {own_mac:x?}");
+
+    let sync = &*mk_static!(
+        SharedSync,
+        NoopMutex::new(RefCell::new(TimeSync::new(
+            own_mac,
+            timesync::Config::default(),
+            Instant::now().as_micros(),
+        )))
+    );
+    let role_changed = &*mk_static!(Signal<NoopRawMutex, ()>, Signal::new());
+
+    let button = Input::new(
+        peripherals.GPIO9,
+        InputConfig::default().with_pull(Pull::Up),
+    );
+    spawner
+        .spawn(beacon_task(sender, sync, role_changed))
+        .unwrap();
+    spawner.spawn(stats_task(sync)).unwrap();
+    spawner.spawn(button_task(button, sync)).unwrap();
+
+    loop {
+        let received = receiver.receive_async().await;
+        // Stamped as early as we can, everything until here is part of the
+        // delay that every follower shares
+        let now = Instant::now().as_micros();
+        let src = received.info.src_address;
+        let (event, role) = sync.lock(|s| {
+            let mut s = s.borrow_mut();
+            let before = s.role();
+            let event = s.handle(now, src, received.data());
+            (event, (s.role() != before).then(|| s.role()))
+        });
+        match event {
+            Event::Outlier { residual_us } => {
+                println!("Ignored a beacon from {src:x?}, {residual_us} us off")
+            }
+            Event::Synced { .. } | Event::NotOurLeader | Event::Ignored => (),
+        }
+        if let Some(role) = role {
+            println!("Now {role:x?}");
+            role_changed.signal(());
+        }
+    }
+}
+
+/// Takes over when the leader goes quiet, and broadcasts beacons while we
+/// lead.
+#[embassy_executor::task]
+async fn beacon_task(
+    mut sender: EspNowSender<'static>,
+    sync: &'static SharedSync,
+    role_changed: &'static Signal<NoopRawMutex, ()>,
+) {
+    loop {
+        let (beacon, deadline, took_over) = sync.lock(|s| {
+            let mut s = s.borrow_mut();
+            let before = s.role();
+            let beacon = s.poll(Instant::now().as_micros());
+            let took_over = before != Role::Leader && s.role() == Role::Leader;
+            (beacon, s.next_deadline(), took_over)
+        });
+        if took_over {
+            println!("Nobody is leading, taking over");
+        }
+        if let Some(beacon) = beacon {
+            if let Err(e) = sender.send_async(&BROADCAST_ADDRESS, &beacon).await {
+                println!("Error sending beacon: {e:?}");
+            }
+        }
+        select(
+            Timer::at(Instant::from_micros(deadline)),
+            role_changed.wait(),
+        )
+        .await;
+    }
+}
+
+/// Shows how well we are keeping up with the leader.
+#[embassy_executor::task]
+async fn stats_task(sync: &'static SharedSync) {
+    let mut ticker = Ticker::every(STATS_INTERVAL);
+    loop {
+        ticker.next().await;
+        let now = synced_now(sync);
+        sync.lock(|s| {
+            let s = s.borrow();
+            let stats = s.estimator().stats();
+            let local = Instant::now().as_micros();
+            match s.role() {
+                Role::Listening => println!("Listening for a leader"),
+                Role::Leader => println!("Leading, time is {} us", now.as_micros()),
+                Role::Follower { leader } => println!(
+                    "Following {leader:x?}, time is {} us, offset {} us, drift {:.2} ppm, \
+                     max residual {} us over {} beacons, {} ignored",
+                    now.as_micros(),
+                    s.now(local) as i64 - local as i64,
+                    stats.drift_ppm,
+                    stats.max_residual_us,
+                    stats.samples,
+                    stats.outliers,
+                ),
+            }
+        });
+    }
+}
+
+/// Stamps BOOT button presses with the common clock, so they can be lined
+/// up with the ones on other nodes.
+#[embassy_executor::task]
+async fn button_task(mut button: Input<'static>, sync: &'static SharedSync) {
+    loop {
+        button.wait_for_falling_edge().await;
+        let at = synced_now(sync);
+        let synced = sync.lock(|s| s.borrow().is_synced());
+        println!(
+            "Button pressed at {} us{}",
+            at.as_micros(),
+            if synced { "" } else { " (not synced)" }
+        );
+    }
+}
@@ -0,0 +1,29 @@
+use modbus::rtu::{MAX_ADDRESS, MIN_ADDRESS};
+
+/// Modbus RTU runs 8E1 unless the devices agree otherwise, at 19200 baud by
+/// default, as does the modbus-slave ROM.
+const DEFAULT_CONFIG: &str = "baud 19200; format 8E1";
+
+/// The device polled unless `MODBUS_ADDRESS` says otherwise.
+const DEFAULT_ADDRESS: u8 = 1;
+
+/// The DE/RE line of the transceiver.
+const DE_PIN: u8 = 4;
+
+fn main() {
+    println!("cargo:rustc-link-arg=-Tlinkall.x");
+
+    uart_config::check_build_config(DEFAULT_CONFIG, &[(DE_PIN, "DE/RE")]);
+
+    println!("cargo:rerun-if-env-changed=MODBUS_ADDRESS");
+    let address = match std::env::var("MODBUS_ADDRESS") {
+        Ok(address) => match address.trim().parse::<u8>() {
+            Ok(address) if (MIN_ADDRESS..=MAX_ADDRESS).contains(&address) => address,
+            _ => {
+                panic!("Invalid MODBUS_ADDRESS {address:?}, must be {MIN_ADDRESS} to {MAX_ADDRESS}")
+            }
+        },
+        Err(_) => DEFAULT_ADDRESS,
+    };
+    println!("cargo:rustc-env=MODBUS_ADDRESS={address}");
+}
@@ -0,0 +1,202 @@
+#![no_std]
+#![no_main]
+
+use core::fmt::Write as _;
+
+use embassy_executor::Spawner;
+use embassy_time::{Duration, Ticker};
+use embedded_io_async::Write;
+use esp_hal::{
+    clock::CpuClock,
+    gpio::{Level, Output, OutputConfig},
+    uart::Uart,
+    usb_serial_jtag::UsbSerialJtag,
+};
+use esp_hal_embassy::main;
+use modbus::{
+    Error, Rtu,
+    pdu::{Registers, Request, Response, ResponseError},
+    rtu::Timing,
+};
+use uart_config::UartParams;
+
+const POLL_PERIOD: Duration = Duration::from_secs(1);
+
+/// How long the slave gets to answer each request.
+const TIMEOUT: Duration = Duration::from_millis(200);
+
+/// How many input registers and discrete inputs the modbus-slave ROM has.
+const INPUT_REGISTERS: u16 = 6;
+const DISCRETE_INPUTS: u16 = 3;
+
+#[panic_handler]
+fn panic(_: &core::panic::PanicInfo) -> ! {
+    esp_hal::system::software_reset()
+}
+
+/// The requests that failed since boot, by how.
+#[derive(Default)]
+struct Failures {
+    timeouts: u32,
+    exceptions: u32,
+    /// Bad frames and responses that don't fit the request.
+    errors: u32,
+}
+
+impl Failures {
+    fn count(&mut self, e: Error) {
+        match e {
+            Error::Timeout => self.timeouts += 1,
+            Error::Response(ResponseError::Exception(_)) => self.exceptions += 1,
+            _ => self.errors += 1,
+        }
+    }
+}
+
+#[main]
+async fn main(_spawner: Spawner) {
+    let peripherals = esp_hal::init(esp_hal::Config::default().with_cpu_clock(CpuClock::max()));
+
+    let timer0 = esp_hal::timer::timg::TimerGroup::new(peripherals.TIMG1);
+    esp_hal_embassy::init(timer0.timer0);
+
+    let mut usb = UsbSerialJtag::new(peripherals.USB_DEVICE).into_async();
+
+    // Checked by build.rs
+    let params = UartParams::default()
+        .from_config(env!("UART_BUILD_CONFIG"))
+        .unwrap();
+    let address: u8 = env!("MODBUS_ADDRESS").parse().unwrap();
+    // SAFETY: build.rs keeps them off DE/RE, and nothing else here uses any
+    // GPIOs
+    let (tx, rx) = unsafe { params.pins() };
+
+    // The RX threshold of the config doesn't apply, see `rx_config`
+    let config = params.uart_config().with_rx(modbus::rx_config());
+    let uart = Uart::new(peripherals.UART0, config)
+        .unwrap()
+        .with_tx(tx)
+        .with_rx(rx)
+        .into_async();
+    let de = Output::new(peripherals.GPIO4, Level::Low, OutputConfig::default());
+    let mut rtu = Rtu::new(
+        uart,
+        Some(de),
+        Timing::new(params.baud, params.frame_bits()),
+    );
+
+    let mut failures = Failures::default();
+    let mut coil = false;
+    let mut report = heapless::String::<256>::new();
+    let mut ticker = Ticker::every(POLL_PERIOD);
+    for polls in 1u32.. {
+        ticker.next().await;
+        report.clear();
+        let _ = write!(report, "#{polls} device {address}: ");
+        match poll(&mut rtu, address, polls, &mut coil, &mut report).await {
+            Ok(()) => (),
+            Err(e) => {
+                failures.count(e);
+                let _ = write!(report, "failed, {e:?}");
+            }
+        }
+        let _ = writeln!(
+            report,
+            " | {} timeouts, {} exceptions, {} errors",
+            failures.timeouts, failures.exceptions, failures.errors
+        );
+        let Ok(_) = usb.write_all(report.as_bytes()).await;
+    }
+}
+
+/// Goes through the tables of the modbus-slave ROM: reads the inputs and
+/// counters, toggles coil 0, and writes the poll number to the first two
+/// holding registers and reads it back.
+async fn poll(
+    rtu: &mut Rtu,
+    address: u8,
+    polls: u32,
+    coil: &mut bool,
+    report: &mut heapless::String<256>,
+) -> Result<(), Error> {
+    let mut registers = [0; INPUT_REGISTERS as usize];
+    let request = Request::ReadInputRegisters {
+        address: 0,
+        count: INPUT_REGISTERS,
+    };
+    read_registers(rtu, address, &request, &mut registers).await?;
+    let [
+        uptime_hi,
+        uptime_lo,
+        requests,
+        exceptions,
+        frame_errors,
+        other_devices,
+    ] = registers;
+    let uptime = ((uptime_hi as u32) << 16) | uptime_lo as u32;
+    let _ = write!(
+        report,
+        "up {uptime} s, {requests} requests, {exceptions} exceptions, \
+         {frame_errors} frame errors, {other_devices} for others, inputs "
+    );
+
+    let request = Request::ReadDiscreteInputs {
+        address: 0,
+        count: DISCRETE_INPUTS,
+    };
+    match rtu.request(address, &request, TIMEOUT).await? {
+        Response::Bits(inputs) => {
+            for input in inputs.iter() {
+                let _ = report.push(if input { '1' } else { '0' });
+            }
+        }
+        _ => return Err(Error::Response(ResponseError::Malformed)),
+    }
+
+    let request = Request::WriteSingleCoil {
+        address: 0,
+        value: !*coil,
+    };
+    rtu.request(address, &request, TIMEOUT).await?;
+    *coil = !*coil;
+    let _ = write!(report, ", coil 0 {}", if *coil { "on" } else { "off" });
+
+    let written = [(polls >> 16) as u16, polls as u16];
+    let mut buf = [0; 4];
+    let request = Request::WriteMultipleRegisters {
+        address: 0,
+        values: Registers::pack(&written, &mut buf),
+    };
+    rtu.request(address, &request, TIMEOUT).await?;
+    let mut read = [0; 2];
+    let request = Request::ReadHoldingRegisters {
+        address: 0,
+        count: 2,
+    };
+    read_registers(rtu, address, &request, &mut read).await?;
+    let _ = write!(
+        report,
+        ", holding {}",
+        if read == written { "ok" } else { "MISMATCH" }
+    );
+    Ok(())
+}
+
+/// Sends a read of holding or input registers, and copies what comes back
+/// to `out`.
+async fn read_registers(
+    rtu: &mut Rtu,
+    address: u8,
+    request: &Request<'_>,
+    out: &mut [u16],
+) -> Result<(), Error> {
+    match rtu.request(address, request, TIMEOUT).await? {
+        Response::Registers(registers) => {
+            for (out, value) in out.iter_mut().zip(registers.iter()) {
+                *out = value;
+            }
+            Ok(())
+        }
+        _ => Err(Error::Response(ResponseError::Malformed)),
+    }
+}
@@ -0,0 +1,38 @@
+use modbus::rtu::{MAX_ADDRESS, MIN_ADDRESS};
+
+/// Modbus RTU runs 8E1 unless the devices agree otherwise, at 19200 baud by
+/// default.
+const DEFAULT_CONFIG: &str = "baud 19200; format 8E1";
+
+/// The device address unless `MODBUS_ADDRESS` says otherwise.
+const DEFAULT_ADDRESS: u8 = 1;
+
+/// The DE/RE line, the coils and the discrete inputs, see src/device.rs.
+const RESERVED_PINS: [(u8, &str); 8] = [
+    (4, "DE/RE"),
+    (5, "a coil"),
+    (6, "a coil"),
+    (7, "a coil"),
+    (10, "a coil"),
+    (0, "an input"),
+    (1, "an input"),
+    (3, "an input"),
+];
+
+fn main() {
+    println!("cargo:rustc-link-arg=-Tlinkall.x");
+
+    uart_config::check_build_config(DEFAULT_CONFIG, &RESERVED_PINS);
+
+    println!("cargo:rerun-if-env-changed=MODBUS_ADDRESS");
+    let address = match std::env::var("MODBUS_ADDRESS") {
+        Ok(address) => match address.trim().parse::<u8>() {
+            Ok(address) if (MIN_ADDRESS..=MAX_ADDRESS).contains(&address) => address,
+            _ => {
+                panic!("Invalid MODBUS_ADDRESS {address:?}, must be {MIN_ADDRESS} to {MAX_ADDRESS}")
+            }
+        },
+        Err(_) => DEFAULT_ADDRESS,
+    };
+    println!("cargo:rustc-env=MODBUS_ADDRESS={address}");
+}
@@ -0,0 +1,151 @@
+use embassy_time::Instant;
+use esp_hal::gpio::{Input, Level, Output};
+use modbus::{
+    pdu::{Bits, Exception, Registers},
+    slave::DataModel,
+};
+
+pub(crate) const HOLDING_REGISTERS: usize = 8;
+
+/// Input registers 0 and 1, the seconds since boot, high word first.
+const UPTIME_HI: u16 = 0;
+const UPTIME_LO: u16 = 1;
+/// The counters from 2 on, in the order of [`Counters::registers`].
+const COUNTERS: u16 = 2;
+
+/// What went over the bus, for the input registers. They wrap around.
+#[derive(Default)]
+pub(crate) struct Counters {
+    /// Requests for this device, broadcasts included.
+    pub(crate) requests: u16,
+    /// Answers that were exceptions.
+    pub(crate) exceptions: u16,
+    /// Frames that didn't come in whole, or had a bad CRC.
+    pub(crate) frame_errors: u16,
+    /// Frames for the other devices on the bus.
+    pub(crate) other_devices: u16,
+}
+
+impl Counters {
+    fn registers(&self) -> [u16; 4] {
+        [
+            self.requests,
+            self.exceptions,
+            self.frame_errors,
+            self.other_devices,
+        ]
+    }
+}
+
+/// The data tables of the device:
+///
+/// | Table             | Address | What                                      |
+/// |-------------------|---------|-------------------------------------------|
+/// | Coils             | 0..=3   | GPIO5, 6, 7 and 10, as outputs            |
+/// | Discrete inputs   | 0..=2   | GPIO0, 1 and 3, pulled up, high is `1`    |
+/// | Holding registers | 0..=7   | Scratch space, zero at boot               |
+/// | Input registers   | 0..=1   | Seconds since boot, high word first       |
+/// |                   | 2..=5   | Requests, exceptions, frame errors and    |
+/// |                   |         | frames for other devices                  |
+pub(crate) struct Device {
+    coils: [Output<'static>; 4],
+    inputs: [Input<'static>; 3],
+    holding: [u16; HOLDING_REGISTERS],
+    pub(crate) counters: Counters,
+}
+
+impl Device {
+    pub(crate) fn new(coils: [Output<'static>; 4], inputs: [Input<'static>; 3]) -> Self {
+        Self {
+            coils,
+            inputs,
+            holding: [0; HOLDING_REGISTERS],
+            counters: Counters::default(),
+        }
+    }
+}
+
+/// Checks that `count` items from `address` are all within a table of
+/// `len`.
+fn check_range(address: u16, count: usize, len: usize) -> Result<(), Exception> {
+    if address as usize + count > len {
+        return Err(Exception::IllegalDataAddress);
+    }
+    Ok(())
+}
+
+impl DataModel for Device {
+    fn read_coil(&mut self, address: u16) -> Result<bool, Exception> {
+        let coil = self
+            .coils
+            .get(address as usize)
+            .ok_or(Exception::IllegalDataAddress)?;
+        Ok(coil.is_set_high())
+    }
+
+    fn read_discrete_input(&mut self, address: u16) -> Result<bool, Exception> {
+        let input = self
+            .inputs
+            .get(address as usize)
+            .ok_or(Exception::IllegalDataAddress)?;
+        Ok(input.is_high())
+    }
+
+    fn read_holding_register(&mut self, address: u16) -> Result<u16, Exception> {
+        self.holding
+            .get(address as usize)
+            .copied()
+            .ok_or(Exception::IllegalDataAddress)
+    }
+
+    fn read_input_register(&mut self, address: u16) -> Result<u16, Exception> {
+        let uptime = Instant::now().as_secs() as u32;
+        match address {
+            UPTIME_HI => Ok((uptime >> 16) as u16),
+            UPTIME_LO => Ok(uptime as u16),
+            _ => self
+                .counters
+                .registers()
+                .get(address.wrapping_sub(COUNTERS) as usize)
+                .copied()
+                .ok_or(Exception::IllegalDataAddress),
+        }
+    }
+
+    fn write_coil(&mut self, address: u16, value: bool) -> Result<(), Exception> {
+        let coil = self
+            .coils
+            .get_mut(address as usize)
+            .ok_or(Exception::IllegalDataAddress)?;
+        coil.set_level(Level::from(value));
+        Ok(())
+    }
+
+    fn write_register(&mut self, address: u16, value: u16) -> Result<(), Exception> {
+        let register = self
+            .holding
+            .get_mut(address as usize)
+            .ok_or(Exception::IllegalDataAddress)?;
+        *register = value;
+        Ok(())
+    }
+
+    // Both check the whole range first, so that a write out of range
+    // changes nothing
+
+    fn write_coils(&mut self, address: u16, values: Bits) -> Result<(), Exception> {
+        check_range(address, values.len(), self.coils.len())?;
+        for (i, value) in values.iter().enumerate() {
+            self.write_coil(address + i as u16, value)?;
+        }
+        Ok(())
+    }
+
+    fn write_registers(&mut self, address: u16, values: Registers) -> Result<(), Exception> {
+        check_range(address, values.len(), self.holding.len())?;
+        for (i, value) in values.iter().enumerate() {
+            self.write_register(address + i as u16, value)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+#![no_std]
+#![no_main]
+
+mod device;
+
+use device::Device;
+use embassy_executor::Spawner;
+use esp_hal::{
+    clock::CpuClock,
+    gpio::{Input, InputConfig, Level, Output, OutputConfig, Pull},
+    uart::Uart,
+};
+use esp_hal_embassy::main;
+use modbus::{Error, Rtu, Served, rtu::Timing};
+use uart_config::UartParams;
+
+#[panic_handler]
+fn panic(_: &core::panic::PanicInfo) -> ! {
+    esp_hal::system::software_reset()
+}
+
+#[main]
+async fn main(_spawner: Spawner) {
+    let peripherals = esp_hal::init(esp_hal::Config::default().with_cpu_clock(CpuClock::max()));
+
+    let timer0 = esp_hal::timer::timg::TimerGroup::new(peripherals.TIMG1);
+    esp_hal_embassy::init(timer0.timer0);
+
+    // Checked by build.rs
+    let params = UartParams::default()
+        .from_config(env!("UART_BUILD_CONFIG"))
+        .unwrap();
+    let address: u8 = env!("MODBUS_ADDRESS").parse().unwrap();
+    // SAFETY: build.rs keeps them off the GPIOs below
+    let (tx, rx) = unsafe { params.pins() };
+
+    // The RX threshold of the config doesn't apply, see `rx_config`
+    let config = params.uart_config().with_rx(modbus::rx_config());
+    let uart = Uart::new(peripherals.UART0, config)
+        .unwrap()
+        .with_tx(tx)
+        .with_rx(rx)
+        .into_async();
+    let de = Output::new(peripherals.GPIO4, Level::Low, OutputConfig::default());
+    let mut rtu = Rtu::new(
+        uart,
+        Some(de),
+        Timing::new(params.baud, params.frame_bits()),
+    );
+
+    let coils = [
+        Output::new(peripherals.GPIO5, Level::Low, OutputConfig::default()),
+        Output::new(peripherals.GPIO6, Level::Low, OutputConfig::default()),
+        Output::new(peripherals.GPIO7, Level::Low, OutputConfig::default()),
+        Output::new(peripherals.GPIO10, Level::Low, OutputConfig::default()),
+    ];
+    let pull_up = InputConfig::default().with_pull(Pull::Up);
+    let inputs = [
+        Input::new(peripherals.GPIO0, pull_up),
+        Input::new(peripherals.GPIO1, pull_up),
+        Input::new(peripherals.GPIO3, pull_up),
+    ];
+    let mut device = Device::new(coils, inputs);
+
+    loop {
+        let served = rtu.serve(address, &mut device).await;
+        let counters = &mut device.counters;
+        match served {
+            Ok(Served::Answered { exception }) => {
+                counters.requests = counters.requests.wrapping_add(1);
+                if exception {
+                    counters.exceptions = counters.exceptions.wrapping_add(1);
+                }
+            }
+            Ok(Served::Broadcast) => counters.requests = counters.requests.wrapping_add(1),
+            Ok(Served::OtherDevice) => {
+                counters.other_devices = counters.other_devices.wrapping_add(1)
+            }
+            Err(Error::Frame(_)) => counters.frame_errors = counters.frame_errors.wrapping_add(1),
+            // Only a failed send, which the master will retry
+            Err(_) => (),
+        }
+    }
+}
@@ -0,0 +1,12 @@
+/// Modbus RTU runs 8E1 unless the devices agree otherwise, at 19200 baud by
+/// default, as do the modbus-slave and modbus-master ROMs.
+const DEFAULT_CONFIG: &str = "baud 19200; format 8E1";
+
+/// The DE/RE line of the transceiver.
+const DE_PIN: u8 = 4;
+
+fn main() {
+    println!("cargo:rustc-link-arg=-Tlinkall.x");
+
+    uart_config::check_build_config(DEFAULT_CONFIG, &[(DE_PIN, "DE/RE")]);
+}
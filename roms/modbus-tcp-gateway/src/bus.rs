@@ -0,0 +1,33 @@
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_time::Duration;
+use esp_println::println;
+use modbus::{Rtu, tcp};
+
+use crate::wifi::MAX_CONNECTIONS;
+
+/// How long a device on the bus gets to answer. Clients usually wait a
+/// second or more for the gateway, this leaves them room for a few requests
+/// queued ahead of theirs.
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// Where the clients line up for the bus.
+pub(crate) type Queue = tcp::Queue<NoopRawMutex, MAX_CONNECTIONS>;
+
+/// The devices on the bus, which tell what went wrong when they don't
+/// answer.
+struct Devices(Rtu);
+
+impl tcp::Bus for Devices {
+    async fn transact(&mut self, address: u8, pdu: &[u8]) -> Option<&[u8]> {
+        self.0
+            .transact(address, pdu, RESPONSE_TIMEOUT)
+            .await
+            .inspect_err(|e| println!("Device {address}: {e:?}"))
+            .ok()
+    }
+}
+
+#[embassy_executor::task]
+pub(crate) async fn bus(rtu: Rtu, queue: &'static Queue) {
+    queue.run(&mut Devices(rtu)).await
+}
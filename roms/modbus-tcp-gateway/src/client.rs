@@ -0,0 +1,38 @@
+use embassy_net::{IpListenEndpoint, Stack, tcp::TcpSocket};
+use embassy_time::Duration;
+use esp_println::println;
+use modbus::tcp::{self, MAX_ADU_LEN};
+
+use crate::{bus::Queue, wifi::MAX_CONNECTIONS};
+
+/// Clients that send nothing for this long are dropped, to make room for
+/// others.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Serves one client connection after another.
+#[embassy_executor::task(pool_size = MAX_CONNECTIONS)]
+pub(crate) async fn client(stack: Stack<'static>, queue: &'static Queue, id: usize) {
+    let mut rx_buffer = [0; 2 * MAX_ADU_LEN];
+    let mut tx_buffer = [0; 2 * MAX_ADU_LEN];
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+    loop {
+        let endpoint = IpListenEndpoint {
+            addr: None,
+            port: tcp::PORT,
+        };
+        if let Err(e) = socket.accept(endpoint).await {
+            println!("Client {id}: error accepting: {e:?}");
+            socket.abort();
+            continue;
+        }
+        socket.set_timeout(Some(IDLE_TIMEOUT));
+        println!("Client {id}: {:?} connected", socket.remote_endpoint());
+        match tcp::serve(&mut socket, queue, id).await {
+            Ok(()) => println!("Client {id}: disconnected"),
+            Err(e) => println!("Client {id}: dropped, {e:?}"),
+        }
+        socket.close();
+        let _ = socket.flush().await;
+        socket.abort();
+    }
+}
@@ -0,0 +1,78 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+mod macros;
+mod bus;
+mod client;
+mod wifi;
+
+use core::future;
+
+use bus::Queue;
+use embassy_executor::Spawner;
+use esp_backtrace as _;
+use esp_hal::{
+    clock::CpuClock,
+    gpio::{Level, Output, OutputConfig},
+    rng::Rng,
+    timer::timg::TimerGroup,
+    uart::Uart,
+};
+use esp_println::println;
+use modbus::{Rtu, rtu::Timing};
+use uart_config::UartParams;
+use wifi::MAX_CONNECTIONS;
+
+#[esp_hal_embassy::main]
+async fn main(spawner: Spawner) -> ! {
+    esp_println::logger::init_logger_from_env();
+    let peripherals = esp_hal::init(esp_hal::Config::default().with_cpu_clock(CpuClock::max()));
+
+    esp_alloc::heap_allocator!(size: 72 * 1024);
+
+    let timg0 = TimerGroup::new(peripherals.TIMG0);
+    let timg1 = TimerGroup::new(peripherals.TIMG1);
+    let rng = Rng::new(peripherals.RNG);
+
+    esp_hal_embassy::init(timg1.timer0);
+
+    // Checked by build.rs
+    let params = UartParams::default()
+        .from_config(env!("UART_BUILD_CONFIG"))
+        .unwrap();
+    // SAFETY: build.rs keeps them off DE/RE, and nothing else here uses any
+    // GPIOs
+    let (tx, rx) = unsafe { params.pins() };
+
+    // The RX threshold of the config doesn't apply, see `rx_config`
+    let config = params.uart_config().with_rx(modbus::rx_config());
+    let uart = Uart::new(peripherals.UART0, config)
+        .unwrap()
+        .with_tx(tx)
+        .with_rx(rx)
+        .into_async();
+    let de = Output::new(peripherals.GPIO4, Level::Low, OutputConfig::default());
+    let rtu = Rtu::new(
+        uart,
+        Some(de),
+        Timing::new(params.baud, params.frame_bits()),
+    );
+    println!("Bus: {params}");
+
+    let queue = &*mk_static!(Queue, Queue::new());
+    spawner.spawn(bus::bus(rtu, queue)).unwrap();
+
+    let stack = wifi::init_wifi(
+        &spawner,
+        timg0.timer0,
+        rng,
+        peripherals.RADIO_CLK,
+        peripherals.WIFI,
+    );
+    for id in 0..MAX_CONNECTIONS {
+        spawner.spawn(client::client(stack, queue, id)).unwrap();
+    }
+
+    future::pending().await
+}
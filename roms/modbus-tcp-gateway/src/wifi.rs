@@ -0,0 +1,96 @@
+use embassy_executor::Spawner;
+use embassy_net::{Runner, Stack, StackResources};
+use embassy_time::{Duration, Timer};
+use esp_hal::{
+    peripheral::Peripheral,
+    peripherals::{RADIO_CLK, WIFI},
+    rng::Rng,
+};
+use esp_println::println;
+use esp_wifi::{
+    EspWifiController,
+    wifi::{ClientConfiguration, Configuration, WifiController, WifiDevice, WifiEvent},
+};
+
+/// How many Modbus TCP clients can be connected at once.
+pub const MAX_CONNECTIONS: usize = 4;
+
+const SSID: &str = env!("SSID");
+const PASSWORD: &str = env!("PASSWORD");
+
+pub(crate) fn init_wifi(
+    spawner: &Spawner,
+    timer: esp_hal::timer::timg::Timer,
+    mut rng: Rng,
+    radio_clk: impl Peripheral<P = RADIO_CLK> + 'static,
+    wifi: impl Peripheral<P = WIFI> + 'static,
+) -> Stack<'static> {
+    let init = mk_static!(
+        EspWifiController<'static>,
+        esp_wifi::init(timer, rng, radio_clk).unwrap()
+    );
+
+    let (controller, wifi_interfaces) = esp_wifi::wifi::new(init, wifi).unwrap();
+
+    let config = embassy_net::Config::dhcpv4(Default::default());
+    let seed = (rng.random() as u64) << 32 | rng.random() as u64;
+    let (stack, runner) = embassy_net::new(
+        wifi_interfaces.sta,
+        config,
+        mk_static!(
+            StackResources<{ 2 + MAX_CONNECTIONS }>,
+            StackResources::new()
+        ),
+        seed,
+    );
+
+    spawner.spawn(connection(controller)).unwrap();
+    spawner.spawn(net_task(runner)).unwrap();
+    spawner.spawn(address_task(stack)).unwrap();
+
+    stack
+}
+
+#[embassy_executor::task]
+async fn connection(mut controller: WifiController<'static>) {
+    let client_config = Configuration::Client(ClientConfiguration {
+        ssid: SSID.try_into().unwrap(),
+        password: PASSWORD.try_into().unwrap(),
+        ..Default::default()
+    });
+    controller.set_configuration(&client_config).unwrap();
+    println!("Starting wifi");
+    controller.start_async().await.unwrap();
+
+    loop {
+        println!("Connecting to {SSID:?}...");
+        match controller.connect_async().await {
+            Ok(()) => println!("Wifi connected!"),
+            Err(e) => {
+                println!("Failed to connect to wifi: {e:?}");
+                Timer::after(Duration::from_millis(5000)).await;
+                continue;
+            }
+        }
+        controller.wait_for_event(WifiEvent::StaDisconnected).await;
+        println!("Wifi disconnected");
+    }
+}
+
+/// Shows the address the station got over DHCP, for the clients to
+/// connect to.
+#[embassy_executor::task]
+async fn address_task(stack: Stack<'static>) {
+    loop {
+        stack.wait_config_up().await;
+        if let Some(config) = stack.config_v4() {
+            println!("Got IP address {}, serving Modbus TCP", config.address);
+        }
+        stack.wait_config_down().await;
+    }
+}
+
+#[embassy_executor::task]
+async fn net_task(mut runner: Runner<'static, WifiDevice<'static>>) {
+    runner.run().await
+}
@@ -0,0 +1,60 @@
+use core::fmt::Write as _;
+
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, signal::Signal};
+use embedded_io_async::{Read, Write};
+use esp_hal::{Async, usb_serial_jtag::UsbSerialJtag};
+use uart_config::UartParams;
+
+const MAX_LINE_LEN: usize = 64;
+
+/// Carries the new UART parameters from the console to whoever owns the
+/// UART.
+pub(crate) type ParamsChanged = Signal<NoopRawMutex, UartParams>;
+
+/// Reads commands from the USB serial console, one per line, and passes the
+/// UART parameters they change on to `changed`.
+#[embassy_executor::task]
+pub(crate) async fn console(
+    usb: UsbSerialJtag<'static, Async>,
+    mut params: UartParams,
+    changed: &'static ParamsChanged,
+) {
+    let (mut usb_rx, mut usb_tx) = usb.split();
+    let mut buf = [0; 64];
+    let mut line = heapless::Vec::<u8, MAX_LINE_LEN>::new();
+    let mut overflowed = false;
+    // Large enough for the help
+    let mut reply = heapless::String::<512>::new();
+    loop {
+        let Ok(n) = usb_rx.read(&mut buf).await;
+        for &c in &buf[..n] {
+            match c {
+                b'\r' | b'\n' => {
+                    reply.clear();
+                    if overflowed {
+                        let _ = writeln!(reply, "Error: line is too long");
+                    } else if let Ok(line) = core::str::from_utf8(&line) {
+                        if params.execute(line, &mut reply) == Ok(true) {
+                            changed.signal(params);
+                        }
+                    } else {
+                        let _ = writeln!(reply, "Error: command is not valid UTF-8");
+                    }
+                    let Ok(_) = usb_tx.write_all(reply.as_bytes()).await;
+                    let Ok(()) = usb_tx.flush().await;
+                    line.clear();
+                    overflowed = false;
+                }
+                // Backspace and delete
+                0x08 | 0x7f => {
+                    line.pop();
+                }
+                c => {
+                    if line.push(c).is_err() {
+                        overflowed = true;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,3 +1,8 @@
+/// The defaults of `UartParams` suit this ROM, `UART_CONFIG` goes on top.
+const DEFAULT_CONFIG: &str = "";
+
 fn main() {
     println!("cargo:rustc-link-arg=-Tlinkall.x");
+
+    uart_config::check_build_config(DEFAULT_CONFIG, &[]);
 }
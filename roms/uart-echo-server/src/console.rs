@@ -0,0 +1,109 @@
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, signal::Signal};
+use embassy_time::Instant;
+use esp_hal::{Async, usb_serial_jtag::UsbSerialJtag};
+use shell::{Arg, Args, Command, Output, Shell};
+use uart_config::{Command as UartCommand, MAX_BAUD, MAX_RX_FIFO_THRESHOLD, MIN_BAUD, UartParams};
+
+/// Carries the new UART parameters from the console to whoever owns the
+/// UART.
+pub(crate) type ParamsChanged = Signal<NoopRawMutex, UartParams>;
+
+struct Console {
+    params: UartParams,
+    changed: &'static ParamsChanged,
+}
+
+type Result = core::result::Result<(), &'static str>;
+
+const COMMANDS: &[Command<Console>] = &[
+    Command {
+        name: "baud",
+        args: &[Arg::int("rate", MIN_BAUD as i64, MAX_BAUD as i64)],
+        help: "Set the baud rate",
+        run: baud,
+    },
+    Command {
+        name: "format",
+        args: &[Arg::word("format")],
+        help: "Set the data bits, parity and stop bits, e.g. `8E1`",
+        run: format,
+    },
+    Command {
+        name: "rx-threshold",
+        args: &[Arg::int("bytes", 1, MAX_RX_FIFO_THRESHOLD as i64)],
+        help: "How many bytes to collect before echoing them",
+        run: rx_threshold,
+    },
+    Command {
+        name: "show",
+        args: &[],
+        help: "Show the UART parameters",
+        run: show,
+    },
+    Command {
+        name: "uptime",
+        args: &[],
+        help: "How long since the ROM started",
+        run: uptime,
+    },
+    Command {
+        name: "reset",
+        args: &[],
+        help: "Restart the ROM",
+        run: |_, _, _| esp_hal::system::software_reset(),
+    },
+];
+
+/// Runs a shell on the USB serial console, and passes the UART parameters
+/// its commands change on to `changed`.
+#[embassy_executor::task]
+pub(crate) async fn console(
+    mut usb: UsbSerialJtag<'static, Async>,
+    params: UartParams,
+    changed: &'static ParamsChanged,
+) {
+    let mut shell = Shell::new(COMMANDS, "echo> ");
+    let mut console = Console { params, changed };
+    loop {
+        let Ok(()) = shell.run(&mut usb, &mut console).await;
+    }
+}
+
+fn apply(console: &mut Console, command: UartCommand, out: &mut Output) -> Result {
+    console.params.apply(command)?;
+    console.changed.signal(console.params);
+    writeln!(out, "{}", console.params);
+    Ok(())
+}
+
+fn baud(console: &mut Console, args: &Args, out: &mut Output) -> Result {
+    let baud = args.int(0).unwrap() as u32;
+    apply(console, UartCommand::Baud(baud), out)
+}
+
+fn format(console: &mut Console, args: &Args, out: &mut Output) -> Result {
+    let format = uart_config::parse_format(args.str(0).unwrap())?;
+    apply(console, format, out)
+}
+
+fn rx_threshold(console: &mut Console, args: &Args, out: &mut Output) -> Result {
+    let threshold = args.int(0).unwrap() as u16;
+    apply(console, UartCommand::RxThreshold(threshold), out)
+}
+
+fn show(console: &mut Console, _: &Args, out: &mut Output) -> Result {
+    writeln!(out, "{}", console.params);
+    Ok(())
+}
+
+fn uptime(_: &mut Console, _: &Args, out: &mut Output) -> Result {
+    let secs = Instant::now().as_secs();
+    writeln!(
+        out,
+        "{}h {:02}m {:02}s",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    );
+    Ok(())
+}
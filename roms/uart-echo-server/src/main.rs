@@ -1,12 +1,21 @@
 #![no_std]
 #![no_main]
 
+#[macro_use]
+mod macros;
+mod console;
+
+use console::ParamsChanged;
 use embassy_executor::Spawner;
+use embassy_futures::select::{Either, select};
+use embassy_sync::signal::Signal;
 use embedded_io_async::Write;
-use esp_hal::clock::CpuClock;
+use esp_hal::{clock::CpuClock, usb_serial_jtag::UsbSerialJtag};
 use esp_hal_embassy::main;
+use uart_config::UartParams;
 
-const READ_BUF_SIZE: usize = 64;
+/// As large as the RX FIFO gets.
+const READ_BUF_SIZE: usize = 128;
 
 #[panic_handler]
 fn panic(_: &core::panic::PanicInfo) -> ! {
@@ -14,25 +23,42 @@ fn panic(_: &core::panic::PanicInfo) -> ! {
 }
 
 #[main]
-async fn main(_spawner: Spawner) {
+async fn main(spawner: Spawner) {
     let peripherals = esp_hal::init(esp_hal::Config::default().with_cpu_clock(CpuClock::max()));
 
     let timer0 = esp_hal::timer::timg::TimerGroup::new(peripherals.TIMG1);
     esp_hal_embassy::init(timer0.timer0);
 
-    let config = esp_hal::uart::Config::default()
-        .with_rx(esp_hal::uart::RxConfig::default().with_fifo_full_threshold(READ_BUF_SIZE as u16));
+    // Checked by build.rs
+    let params = UartParams::default()
+        .from_config(env!("UART_BUILD_CONFIG"))
+        .unwrap();
+    // SAFETY: nothing else here uses any GPIOs
+    let (tx, rx) = unsafe { params.pins() };
 
-    let mut uart0 = esp_hal::uart::Uart::new(peripherals.UART0, config)
+    let mut uart0 = esp_hal::uart::Uart::new(peripherals.UART0, params.uart_config())
         .unwrap()
-        .with_tx(peripherals.GPIO21)
-        .with_rx(peripherals.GPIO20)
+        .with_tx(tx)
+        .with_rx(rx)
         .into_async();
 
+    let params_changed = &*mk_static!(ParamsChanged, Signal::new());
+    let usb = UsbSerialJtag::new(peripherals.USB_DEVICE).into_async();
+    spawner
+        .spawn(console::console(usb, params, params_changed))
+        .unwrap();
+
     let mut buf = [0; READ_BUF_SIZE];
     loop {
-        let Ok(n) = uart0.read_async(&mut buf).await else {
-            continue;
+        let event = select(uart0.read_async(&mut buf), params_changed.wait()).await;
+        let n = match event {
+            Either::First(Ok(n)) => n,
+            Either::First(Err(_)) => continue,
+            Either::Second(params) => {
+                // The console only passes on parameters that check out
+                let _ = uart0.apply_config(&params.uart_config());
+                continue;
+            }
         };
         for b in &mut buf[..n] {
             *b = b.wrapping_add(5);
@@ -0,0 +1,9 @@
+/// Both UARTs only receive, so `pins` is the line from device A, then the
+/// one from device B.
+const DEFAULT_CONFIG: &str = "pins 20 21";
+
+fn main() {
+    println!("cargo:rustc-link-arg=-Tlinkall.x");
+
+    uart_config::check_build_config(DEFAULT_CONFIG, &[]);
+}
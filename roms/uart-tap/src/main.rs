@@ -0,0 +1,267 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+mod macros;
+
+use core::fmt::Write as _;
+
+use embassy_executor::Spawner;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::{Duration, Instant};
+use embedded_io_async::Write;
+use esp_hal::{
+    Async,
+    clock::CpuClock,
+    pac::uart0::RegisterBlock,
+    peripherals::{UART0, UART1},
+    uart::{RxError, UartRx},
+    usb_serial_jtag::UsbSerialJtag,
+};
+use esp_hal_embassy::main;
+use uart_config::UartParams;
+
+/// Every message sent to the host is COBS encoded and terminated by a zero
+/// byte. The first byte of the decoded message tells what it contains:
+///
+/// ```text
+/// MSG_DATA   0x00 | direction | timestamp (u64) | flags | duration (u32) | bytes
+/// MSG_ERROR  0x01 | direction | timestamp (u64) | error
+/// MSG_LOG    0x02 | UTF-8 text
+/// MSG_BREAK  0x03 | direction | timestamp (u64)
+/// ```
+///
+/// Numbers are little endian, times in µs. The timestamp is the time since
+/// boot. For data it is when the first of the bytes came in, worked out
+/// from when the read returned and how long the bytes took at the baud
+/// rate, which is the duration. Errors and breaks are stamped when the read
+/// returned, as the UART doesn't tell which byte they came with.
+///
+/// The UART also receives a zero byte for a break, which may show up in
+/// the next `MSG_DATA`.
+const MSG_DATA: u8 = 0;
+const MSG_ERROR: u8 = 1;
+const MSG_LOG: u8 = 2;
+const MSG_BREAK: u8 = 3;
+
+/// What device A sent, on the first pin.
+const DIRECTION_A: u8 = 0;
+/// What device B sent, on the second pin.
+const DIRECTION_B: u8 = 1;
+
+/// The line went quiet after these bytes, rather than the FIFO filling up.
+const FLAG_IDLE: u8 = 1 << 0;
+
+/// We didn't keep up, and bytes were lost.
+const ERROR_OVERFLOW: u8 = 0;
+/// No stop bit where there should have been one, other than for a break:
+/// a baud rate or format that doesn't match the line.
+const ERROR_FRAMING: u8 = 1;
+const ERROR_PARITY: u8 = 2;
+/// A pulse too short to be a bit, i.e. noise.
+const ERROR_GLITCH: u8 = 3;
+
+/// How many characters long the line has to be quiet for a read to return
+/// before the FIFO fills up, esp-hal's default.
+const RX_TIMEOUT_CHARS: u64 = 10;
+
+/// As large as the RX FIFO gets.
+const READ_BUF_SIZE: usize = 128;
+
+const MAX_MESSAGE_LEN: usize = 1 + 1 + 8 + 1 + 4 + READ_BUF_SIZE;
+const MAX_ENCODED_LEN: usize = MAX_MESSAGE_LEN + MAX_MESSAGE_LEN / 254 + 2;
+
+enum Captured {
+    Data {
+        direction: u8,
+        at: Instant,
+        took: Duration,
+        idle: bool,
+        bytes: heapless::Vec<u8, READ_BUF_SIZE>,
+    },
+    Error {
+        direction: u8,
+        at: Instant,
+        error: u8,
+    },
+    Break {
+        direction: u8,
+        at: Instant,
+    },
+}
+
+static CAPTURED: Channel<CriticalSectionRawMutex, Captured, 8> = Channel::new();
+
+#[panic_handler]
+fn panic(_: &core::panic::PanicInfo) -> ! {
+    esp_hal::system::software_reset()
+}
+
+#[main]
+async fn main(spawner: Spawner) {
+    let peripherals = esp_hal::init(esp_hal::Config::default().with_cpu_clock(CpuClock::max()));
+
+    let timer0 = esp_hal::timer::timg::TimerGroup::new(peripherals.TIMG1);
+    esp_hal_embassy::init(timer0.timer0);
+
+    let (_, mut usb_tx) = UsbSerialJtag::new(peripherals.USB_DEVICE)
+        .into_async()
+        .split();
+
+    // Checked by build.rs
+    let params = UartParams::default()
+        .from_config(env!("UART_BUILD_CONFIG"))
+        .unwrap();
+    // SAFETY: nothing else here uses any GPIOs
+    let (a, b) = unsafe { params.pins() };
+
+    // Only receiving, the tap never drives either line
+    let rx_a = UartRx::new(peripherals.UART0, params.uart_config())
+        .unwrap()
+        .with_rx(a)
+        .into_async();
+    let rx_b = UartRx::new(peripherals.UART1, params.uart_config())
+        .unwrap()
+        .with_rx(b)
+        .into_async();
+    let line = Line {
+        threshold: params.rx_fifo_threshold as usize,
+        char_ns: params.frame_bits() as u64 * 1_000_000_000 / params.baud as u64,
+    };
+    spawner
+        .spawn(tap(rx_a, UART0::regs(), DIRECTION_A, line))
+        .unwrap();
+    spawner
+        .spawn(tap(rx_b, UART1::regs(), DIRECTION_B, line))
+        .unwrap();
+
+    let message = mk_static!([u8; MAX_MESSAGE_LEN], [0; MAX_MESSAGE_LEN]);
+    let encoded = mk_static!([u8; MAX_ENCODED_LEN], [0; MAX_ENCODED_LEN]);
+
+    let mut log = heapless::String::<128>::new();
+    let _ = write!(
+        log,
+        "Tapping A on GPIO{}, B on GPIO{}, {params}",
+        params.tx_pin, params.rx_pin
+    );
+    message[0] = MSG_LOG;
+    message[1..][..log.len()].copy_from_slice(log.as_bytes());
+    let mut len = 1 + log.len();
+
+    loop {
+        let n = cobs::encode(&message[..len], &mut encoded[..]);
+        encoded[n] = 0;
+        let Ok(()) = usb_tx.write_all(&encoded[..n + 1]).await;
+
+        len = match CAPTURED.receive().await {
+            Captured::Data {
+                direction,
+                at,
+                took,
+                idle,
+                bytes,
+            } => {
+                message[0] = MSG_DATA;
+                message[1] = direction;
+                message[2..10].copy_from_slice(&at.as_micros().to_le_bytes());
+                message[10] = if idle { FLAG_IDLE } else { 0 };
+                message[11..15].copy_from_slice(&(took.as_micros() as u32).to_le_bytes());
+                message[15..][..bytes.len()].copy_from_slice(&bytes);
+                15 + bytes.len()
+            }
+            Captured::Error {
+                direction,
+                at,
+                error,
+            } => {
+                message[0] = MSG_ERROR;
+                message[1] = direction;
+                message[2..10].copy_from_slice(&at.as_micros().to_le_bytes());
+                message[10] = error;
+                11
+            }
+            Captured::Break { direction, at } => {
+                message[0] = MSG_BREAK;
+                message[1] = direction;
+                message[2..10].copy_from_slice(&at.as_micros().to_le_bytes());
+                10
+            }
+        };
+    }
+}
+
+/// What the taps need to know about the line.
+#[derive(Copy, Clone)]
+struct Line {
+    /// The RX FIFO threshold, a read that returns less stopped because the
+    /// line went quiet.
+    threshold: usize,
+    /// How long a character takes on the line.
+    char_ns: u64,
+}
+
+/// Reads what one device sends. If the host doesn't keep up, this waits,
+/// and the FIFO overflowing is reported in turn.
+#[embassy_executor::task(pool_size = 2)]
+async fn tap(
+    mut rx: UartRx<'static, Async>,
+    regs: &'static RegisterBlock,
+    direction: u8,
+    line: Line,
+) {
+    let mut buf = [0; READ_BUF_SIZE];
+    // Left over from before we were listening
+    took_break(regs);
+    loop {
+        let result = rx.read_async(&mut buf).await;
+        let at = Instant::now();
+        let captured = match result {
+            Ok(0) => continue,
+            Ok(n) => {
+                let idle = n < line.threshold;
+                // A read that stopped on the line going quiet returns a
+                // while after the last byte came in
+                let took = Duration::from_micros(n as u64 * line.char_ns / 1000);
+                let timeout = match idle {
+                    true => Duration::from_micros(RX_TIMEOUT_CHARS * line.char_ns / 1000),
+                    false => Duration::MIN,
+                };
+                Captured::Data {
+                    direction,
+                    at: at.checked_sub(took + timeout).unwrap_or(Instant::MIN),
+                    took,
+                    idle,
+                    bytes: heapless::Vec::from_slice(&buf[..n]).unwrap(),
+                }
+            }
+            Err(RxError::FrameFormatViolated) if took_break(regs) => {
+                Captured::Break { direction, at }
+            }
+            Err(e) => Captured::Error {
+                direction,
+                at,
+                error: error_code(e),
+            },
+        };
+        CAPTURED.send(captured).await;
+    }
+}
+
+/// Whether the line was held low for longer than a character since we last
+/// looked. The UART flags that in its raw interrupt status, whether or not
+/// the interrupt is enabled, and we clear it again.
+fn took_break(regs: &RegisterBlock) -> bool {
+    let taken = regs.int_raw().read().brk_det().bit_is_set();
+    regs.int_clr().write(|w| w.brk_det().clear_bit_by_one());
+    taken
+}
+
+fn error_code(e: RxError) -> u8 {
+    match e {
+        RxError::FifoOverflowed => ERROR_OVERFLOW,
+        RxError::FrameFormatViolated => ERROR_FRAMING,
+        RxError::ParityMismatch => ERROR_PARITY,
+        // A glitch, or whatever else esp-hal comes up with
+        _ => ERROR_GLITCH,
+    }
+}
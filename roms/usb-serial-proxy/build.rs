@@ -1,3 +1,11 @@
+/// The UART is on GPIO1 and GPIO0 unless `UART_CONFIG` moves it.
+const DEFAULT_CONFIG: &str = "pins 1 0";
+
+/// RTS and DTR, see src/control.rs.
+const CONTROL_PINS: [(u8, &str); 2] = [(4, "the RTS line"), (5, "the DTR line")];
+
 fn main() {
     println!("cargo:rustc-link-arg=-Tlinkall.x");
+
+    uart_config::check_build_config(DEFAULT_CONFIG, &CONTROL_PINS);
 }
@@ -0,0 +1,59 @@
+use embassy_time::Timer;
+use esp_hal::gpio::{Level, Output};
+
+/// Two GPIOs standing in for the RTS and DTR lines of a USB serial adapter,
+/// to drive the usual auto-reset circuit in front of another ESP: RTS pulls
+/// its EN low, DTR its GPIO0 (GPIO9 on a C3), but only while the other line
+/// is not asserted.
+///
+/// Like on an adapter, the lines are active low.
+///
+/// To flash the target through the proxy, `~~boot` it, then
+/// `espflash flash --before no-reset --after no-reset` it, without `--baud`
+/// since the proxy wouldn't follow it to another one, and `~~reset` it.
+pub(crate) struct ControlLines {
+    rts: Output<'static>,
+    dtr: Output<'static>,
+}
+
+impl ControlLines {
+    pub(crate) fn new(rts: Output<'static>, dtr: Output<'static>) -> Self {
+        let mut lines = Self { rts, dtr };
+        lines.set_rts(false);
+        lines.set_dtr(false);
+        lines
+    }
+
+    pub(crate) fn set_rts(&mut self, asserted: bool) {
+        self.rts.set_level(level(asserted));
+    }
+
+    pub(crate) fn set_dtr(&mut self, asserted: bool) {
+        self.dtr.set_level(level(asserted));
+    }
+
+    /// Resets the target, like `esptool --after hard_reset`.
+    pub(crate) async fn reset(&mut self) {
+        self.set_dtr(false);
+        self.set_rts(true);
+        Timer::after_millis(100).await;
+        self.set_rts(false);
+    }
+
+    /// Resets the target into its serial bootloader, like esptool's classic
+    /// reset.
+    pub(crate) async fn enter_bootloader(&mut self) {
+        self.set_dtr(false);
+        self.set_rts(true);
+        Timer::after_millis(100).await;
+        // GPIO0 goes low as EN comes back up, and is sampled then
+        self.set_dtr(true);
+        self.set_rts(false);
+        Timer::after_millis(50).await;
+        self.set_dtr(false);
+    }
+}
+
+fn level(asserted: bool) -> Level {
+    if asserted { Level::Low } else { Level::High }
+}
@@ -0,0 +1,89 @@
+use embassy_time::{Duration, Instant};
+
+/// What a command to the proxy itself starts with.
+pub(crate) const PREFIX: &[u8] = b"~~";
+
+/// How long the host has to be quiet before a command.
+const GUARD: Duration = Duration::from_millis(500);
+
+const MAX_COMMAND_LEN: usize = 64;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum State {
+    Data,
+    /// This much of the prefix came in after the guard time, and is held
+    /// back until we know whether it's a command.
+    Prefix(usize),
+    Command,
+}
+
+/// Picks the commands to the proxy out of the bytes from the host, and
+/// passes everything else on to the UART as is.
+///
+/// A command is a line that starts with [`PREFIX`], sent after the host
+/// has been quiet for a while, e.g. `printf '~~boot\n' > /dev/ttyACM0`.
+/// Whatever a flasher sends goes through untouched, unless it happens to
+/// start with `~~` after a pause, so it needn't know about the proxy.
+pub(crate) struct Escape {
+    state: State,
+    last_byte: Option<Instant>,
+    line: heapless::Vec<u8, MAX_COMMAND_LEN>,
+    overflowed: bool,
+}
+
+impl Escape {
+    pub(crate) const fn new() -> Self {
+        Self {
+            state: State::Data,
+            last_byte: None,
+            line: heapless::Vec::new(),
+            overflowed: false,
+        }
+    }
+
+    /// Sorts a byte from the host. Bytes for the UART go to `data`, and a
+    /// command is returned once its line is complete.
+    pub(crate) fn push(
+        &mut self,
+        now: Instant,
+        byte: u8,
+        data: &mut impl Extend<u8>,
+    ) -> Option<Result<&str, &'static str>> {
+        let quiet = self.last_byte.is_none_or(|last| now - last >= GUARD);
+        self.last_byte = Some(now);
+
+        match self.state {
+            State::Data if quiet && byte == PREFIX[0] => self.state = State::Prefix(1),
+            State::Data => data.extend([byte]),
+            State::Prefix(n) if byte == PREFIX[n] => {
+                self.state = if n + 1 == PREFIX.len() {
+                    self.line.clear();
+                    self.overflowed = false;
+                    State::Command
+                } else {
+                    State::Prefix(n + 1)
+                };
+            }
+            State::Prefix(n) => {
+                data.extend(PREFIX[..n].iter().copied().chain([byte]));
+                self.state = State::Data;
+            }
+            State::Command if byte == b'\n' => {
+                self.state = State::Data;
+                // Another command may follow right away
+                self.last_byte = None;
+                if self.overflowed {
+                    return Some(Err("command is too long"));
+                }
+                let line = self.line.strip_suffix(b"\r").unwrap_or(&self.line);
+                return Some(core::str::from_utf8(line).map_err(|_| "command is not valid UTF-8"));
+            }
+            State::Command => {
+                if self.line.push(byte).is_err() {
+                    self.overflowed = true;
+                }
+            }
+        }
+        None
+    }
+}
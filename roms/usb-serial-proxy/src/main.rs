@@ -1,19 +1,65 @@
 #![no_std]
 #![no_main]
 
+#[macro_use]
+mod macros;
+mod control;
+mod escape;
+
+use core::{cell::Cell, fmt::Write as _};
+
+use control::ControlLines;
 use embassy_executor::{Spawner, task};
-use embassy_time::Timer;
+use embassy_futures::select::{Either, select};
+use embassy_sync::{
+    blocking_mutex::{NoopMutex, raw::NoopRawMutex},
+    mutex::Mutex,
+    signal::Signal,
+};
+use embassy_time::{Instant, Timer};
 use embedded_io_async::{Read, Write};
+use escape::{Escape, PREFIX};
 use esp_hal::{
     Async,
     clock::CpuClock,
-    gpio::GpioPin,
-    uart::UartTx,
-    usb_serial_jtag::{UsbSerialJtag, UsbSerialJtagRx},
+    gpio::{Level, Output, OutputConfig},
+    rmt::{Channel, Rmt, RxChannelCreatorAsync},
+    time::Rate,
+    uart::{Uart, UartRx, UartTx},
+    usb_serial_jtag::{UsbSerialJtag, UsbSerialJtagRx, UsbSerialJtagTx},
 };
 use esp_hal_embassy::main;
+use uart_config::{UartParams, autobaud};
+
+/// As large as the RX FIFO gets.
+const READ_BUF_SIZE: usize = 128;
+
+/// Both directions write to the USB serial: what the target sends, and the
+/// replies to commands.
+type UsbTx = Mutex<NoopRawMutex, UsbSerialJtagTx<'static, Async>>;
 
-const READ_BUF_SIZE: usize = 64;
+/// The UART parameters, as the commands and autobaud leave them.
+type SharedParams = NoopMutex<Cell<UartParams>>;
+
+/// What the commands ask of the task that owns the UART's RX half, which
+/// the baud rate and format are set through.
+#[derive(Copy, Clone, Debug)]
+enum Change {
+    /// Apply the shared parameters.
+    Params,
+    /// Work out the baud rate, and apply it.
+    Autobaud,
+}
+
+type UartChanges = Signal<NoopRawMutex, Change>;
+
+const HELP: &str = "\
+Proxy commands, each on a line of its own starting with `~~`, after 500 ms of quiet:
+  rts 0|1               Assert RTS, which holds the target in reset
+  dtr 0|1               Assert DTR, which pulls the target's boot pin low
+  reset                 Reset the target
+  boot                  Reset the target into its serial bootloader
+  autobaud              Work out the target's baud rate from what it sends";
 
 #[panic_handler]
 fn panic(_: &core::panic::PanicInfo) -> ! {
@@ -27,62 +73,221 @@ async fn main(spawner: Spawner) {
     let timer0 = esp_hal::timer::timg::TimerGroup::new(peripherals.TIMG1);
     esp_hal_embassy::init(timer0.timer0);
 
-    let (usb_rx, mut usb_tx) = UsbSerialJtag::new(peripherals.USB_DEVICE)
+    let (usb_rx, usb_tx) = UsbSerialJtag::new(peripherals.USB_DEVICE)
         .into_async()
         .split();
 
-    let u1_rx: GpioPin<0> = peripherals.GPIO0;
-    let u1_tx: GpioPin<1> = peripherals.GPIO1;
+    let control = ControlLines::new(
+        Output::new(peripherals.GPIO4, Level::High, OutputConfig::default()),
+        Output::new(peripherals.GPIO5, Level::High, OutputConfig::default()),
+    );
 
-    let config = esp_hal::uart::Config::default()
-        .with_rx(esp_hal::uart::RxConfig::default().with_fifo_full_threshold(READ_BUF_SIZE as u16));
+    // Checked by build.rs
+    let params = UartParams::default()
+        .from_config(env!("UART_BUILD_CONFIG"))
+        .unwrap();
+    // SAFETY: build.rs keeps them off the control lines, and nothing else
+    // here uses any GPIOs
+    let (tx, rx) = unsafe { params.pins() };
 
-    let (mut uart_rx, uart_tx) = esp_hal::uart::Uart::new(peripherals.UART0, config)
+    let (mut uart_rx, uart_tx) = Uart::new(peripherals.UART0, params.uart_config())
         .unwrap()
-        .with_tx(u1_tx)
-        .with_rx(u1_rx)
+        .with_tx(tx)
+        .with_rx(rx)
         .into_async()
         .split();
 
-    spawner.spawn(sender(uart_tx, usb_rx)).unwrap();
+    // SAFETY: the RMT only listens on the RX pin, along with the UART
+    let (_, rx) = unsafe { params.pins() };
+    let rmt = Rmt::new(peripherals.RMT, Rate::from_mhz(80))
+        .unwrap()
+        .into_async();
+    let mut rmt = rmt.channel2.configure(rx, autobaud::rmt_config()).unwrap();
+
+    let usb_tx = &*mk_static!(UsbTx, Mutex::new(usb_tx));
+    let params = &*mk_static!(SharedParams, NoopMutex::new(Cell::new(params)));
+    let changes = &*mk_static!(UartChanges, Signal::new());
+    spawner
+        .spawn(sender(usb_rx, uart_tx, control, usb_tx, params, changes))
+        .unwrap();
+
+    if cfg!(feature = "autobaud") {
+        detect_baud(&mut uart_rx, &mut rmt, params, usb_tx).await;
+    }
 
     let mut buf = [0; READ_BUF_SIZE];
-    let mut s = heapless::String::<64>::new();
+    let mut error = heapless::String::<64>::new();
     loop {
-        match uart_rx.read_async(&mut buf).await {
-            Ok(0) => {
+        let event = select(uart_rx.read_async(&mut buf), changes.wait()).await;
+        match event {
+            Either::First(Ok(0)) => {
                 Timer::after_millis(50).await;
             }
-            Err(e) => {
-                use core::fmt::Write;
-                s.clear();
-                let _ = write!(&mut s, "Error: {e:?}\n");
-                let Ok(_) = usb_tx.write_all(&s.as_bytes()).await;
-                let Ok(()) = usb_tx.flush().await;
+            Either::First(Ok(n)) => write_usb(usb_tx, &buf[..n]).await,
+            Either::First(Err(e)) => {
+                error.clear();
+                let _ = writeln!(error, "Error: {e:?}");
+                write_usb(usb_tx, error.as_bytes()).await;
                 Timer::after_millis(50).await;
             }
-            Ok(n) => {
-                let Ok(_) = usb_tx.write_all(&buf[..n]).await;
-                let Ok(()) = usb_tx.flush().await;
+            Either::Second(Change::Params) => {
+                // Sets the baud rate and format of the TX half as well
+                let config = params.lock(Cell::get).uart_config();
+                if let Err(e) = uart_rx.apply_config(&config) {
+                    error.clear();
+                    let _ = writeln!(error, "Error: {e:?}");
+                    write_usb(usb_tx, error.as_bytes()).await;
+                }
+            }
+            Either::Second(Change::Autobaud) => {
+                detect_baud(&mut uart_rx, &mut rmt, params, usb_tx).await;
             }
         }
     }
 }
 
+/// Passes what comes in over the USB serial on to the UART, and runs the
+/// commands in between.
 #[task]
-async fn sender(mut uart_tx: UartTx<'static, Async>, mut usb_rx: UsbSerialJtagRx<'static, Async>) {
-    let mut buf = [0; READ_BUF_SIZE];
+async fn sender(
+    mut usb_rx: UsbSerialJtagRx<'static, Async>,
+    mut uart_tx: UartTx<'static, Async>,
+    mut control: ControlLines,
+    usb_tx: &'static UsbTx,
+    params: &'static SharedParams,
+    changes: &'static UartChanges,
+) {
+    let mut from_usb = [0; READ_BUF_SIZE];
+    let mut escape = Escape::new();
+    let mut data = heapless::Vec::<u8, { READ_BUF_SIZE + PREFIX.len() }>::new();
+    // Large enough for the help
+    let mut reply = heapless::String::<1024>::new();
     loop {
-        let Ok(n) = usb_rx.read(&mut buf).await;
+        let Ok(n) = usb_rx.read(&mut from_usb).await;
         if n == 0 {
             Timer::after_millis(50).await;
             continue;
         }
-        if uart_tx.write_all(&buf[..n]).await.is_err() {
-            Timer::after_millis(50).await;
-        }
-        if uart_tx.flush_async().await.is_err() {
-            Timer::after_millis(50).await;
+        for &byte in &from_usb[..n] {
+            let Some(command) = escape.push(Instant::now(), byte, &mut data) else {
+                continue;
+            };
+            // Whatever came before the command goes out first
+            write_uart(&mut uart_tx, &data).await;
+            data.clear();
+            reply.clear();
+            match command {
+                Ok(line) => {
+                    let mut changed = params.lock(Cell::get);
+                    if let Some(change) = run(line, &mut changed, &mut control, &mut reply).await {
+                        params.lock(|p| p.set(changed));
+                        changes.signal(change);
+                    }
+                }
+                Err(e) => {
+                    let _ = writeln!(reply, "Error: {e}");
+                }
+            }
+            write_usb(usb_tx, reply.as_bytes()).await;
         }
+        write_uart(&mut uart_tx, &data).await;
+        data.clear();
     }
 }
+
+async fn write_uart(uart_tx: &mut UartTx<'static, Async>, data: &[u8]) {
+    if data.is_empty() {
+        return;
+    }
+    if uart_tx.write_all(data).await.is_err() || uart_tx.flush_async().await.is_err() {
+        Timer::after_millis(50).await;
+    }
+}
+
+async fn write_usb(usb_tx: &UsbTx, data: &[u8]) {
+    let mut usb_tx = usb_tx.lock().await;
+    let Ok(_) = usb_tx.write_all(data).await;
+    let Ok(()) = usb_tx.flush().await;
+}
+
+/// Works out the baud rate, and tells the host what came of it.
+async fn detect_baud(
+    uart_rx: &mut UartRx<'static, Async>,
+    rmt: &mut Channel<Async, 2>,
+    params: &SharedParams,
+    usb_tx: &UsbTx,
+) {
+    let mut detected = params.lock(Cell::get);
+    let result = autobaud::detect(uart_rx, rmt, &mut detected).await;
+    params.lock(|p| p.set(detected));
+    let mut reply = heapless::String::<256>::new();
+    write_autobaud(result, &detected, &mut reply);
+    write_usb(usb_tx, reply.as_bytes()).await;
+}
+
+fn write_autobaud(
+    result: Result<u32, autobaud::Error>,
+    params: &UartParams,
+    reply: &mut impl core::fmt::Write,
+) {
+    let _ = match result {
+        Ok(baud) => writeln!(reply, "Detected {baud} baud: {params}"),
+        Err(autobaud::Error::Unverified(baud)) => writeln!(
+            reply,
+            "Going with {baud} baud, the target went quiet before it could be verified: {params}"
+        ),
+        Err(e) => writeln!(reply, "Error: no baud rate found, {e:?}: {params}"),
+    };
+}
+
+/// Runs a command to the proxy itself, and writes the reply to `reply`.
+/// Anything that isn't about the control lines changes the UART, which is
+/// left to the task that owns its RX half.
+async fn run(
+    line: &str,
+    params: &mut UartParams,
+    control: &mut ControlLines,
+    reply: &mut impl core::fmt::Write,
+) -> Option<Change> {
+    let line = line.trim();
+    let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+    let _ = match (name, args.trim()) {
+        (name @ ("rts" | "dtr"), level) => {
+            let asserted = match level {
+                "1" => true,
+                "0" => false,
+                _ => {
+                    let _ = writeln!(reply, "Error: {name} takes 1 or 0");
+                    return None;
+                }
+            };
+            if name == "rts" {
+                control.set_rts(asserted);
+            } else {
+                control.set_dtr(asserted);
+            }
+            writeln!(reply, "{name}={level}")
+        }
+        ("reset", "") => {
+            control.reset().await;
+            writeln!(reply, "Target reset")
+        }
+        ("boot", "") => {
+            control.enter_bootloader().await;
+            writeln!(reply, "Target in its bootloader")
+        }
+        ("autobaud", "") => {
+            let _ = writeln!(reply, "Listening for the target's baud rate");
+            return Some(Change::Autobaud);
+        }
+        ("help", "") => {
+            let _ = writeln!(reply, "{HELP}");
+            params.execute(line, reply).map(|_| ())
+        }
+        _ => match params.execute(line, reply) {
+            Ok(true) => return Some(Change::Params),
+            _ => Ok(()),
+        },
+    };
+    None
+}
@@ -1,4 +1,5 @@
 use embassy_executor::Spawner;
+use embassy_futures::select::{Either, select};
 use embassy_net::{Runner, Stack, StackResources};
 use embassy_time::{Duration, Timer};
 use esp_hal::{
@@ -9,14 +10,25 @@ use esp_hal::{
 use esp_println::println;
 use esp_wifi::{
     EspWifiController,
-    wifi::{ClientConfiguration, Configuration, WifiController, WifiDevice, WifiEvent, WifiState},
+    wifi::{
+        ClientConfiguration, Configuration, ScanConfig, WifiController, WifiDevice, WifiEvent,
+        WifiState,
+    },
 };
+use wifi_analysis::link::{LinkEvent, LinkMonitor, LinkSample, PhyMode};
 
 pub const MAX_CONNECTIONS: usize = 4;
 
 const SSID: &str = env!("SSID");
 const PASSWORD: &str = env!("PASSWORD");
 
+/// How often we look at the signal of the AP we are connected to.
+const LINK_SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How much stronger (in dB) another AP with our SSID needs to be before we
+/// bother roaming to it.
+const ROAM_MARGIN: i8 = 8;
+
 pub(crate) fn init_wifi(
     spawner: &Spawner,
     timer: esp_hal::timer::timg::Timer,
@@ -59,8 +71,8 @@ async fn connection(mut controller: WifiController<'static>) {
     loop {
         match esp_wifi::wifi::wifi_state() {
             WifiState::StaConnected => {
-                // wait until we're no longer connected
-                controller.wait_for_event(WifiEvent::StaDisconnected).await;
+                // keep an eye on the link until we're no longer connected
+                monitor_link(&mut controller).await;
                 Timer::after(Duration::from_millis(5000)).await
             }
             _ => {}
@@ -88,6 +100,149 @@ async fn connection(mut controller: WifiController<'static>) {
     }
 }
 
+/// Samples the link quality until we get disconnected, or until we decide to
+/// roam to a better access point.
+async fn monitor_link(controller: &mut WifiController<'static>) {
+    let mut monitor = LinkMonitor::new();
+    loop {
+        match select(
+            controller.wait_for_event(WifiEvent::StaDisconnected),
+            Timer::after(LINK_SAMPLE_INTERVAL),
+        )
+        .await
+        {
+            Either::First(()) => return,
+            Either::Second(()) => (),
+        }
+
+        let Some((sample, bssid)) = sample_link() else {
+            continue;
+        };
+        let event = monitor.record(sample);
+        println!(
+            "Link: bssid={:02x?} rssi={} (avg={} min={} max={}) channel={} phy={:?}",
+            bssid,
+            sample.rssi,
+            monitor.average_rssi().unwrap_or(sample.rssi),
+            monitor.min_rssi().unwrap_or(sample.rssi),
+            monitor.max_rssi().unwrap_or(sample.rssi),
+            sample.channel,
+            sample.phy,
+        );
+
+        match event {
+            None => (),
+            Some(LinkEvent::Degraded { rssi }) => {
+                println!("WARNING: Signal is weak ({rssi} dBm)");
+            }
+            Some(LinkEvent::Recovered { rssi }) => {
+                println!("Signal recovered ({rssi} dBm)");
+            }
+            Some(LinkEvent::ChannelChanged { from, to }) => {
+                println!("WARNING: AP moved from channel {from} to {to}");
+            }
+            Some(LinkEvent::Roam) => {
+                println!("WARNING: Signal has been weak for a while, looking for a better AP");
+                if try_roam(controller, bssid, sample.rssi).await {
+                    return;
+                }
+                monitor.reset();
+            }
+        }
+    }
+}
+
+/// Scans for other APs with our SSID, and if one of them is noticeably better
+/// than the one we're on, reconfigure the station to use it and disconnect.
+/// The connection task will then reconnect to the new AP.
+///
+/// Returns true if we disconnected.
+async fn try_roam(controller: &mut WifiController<'static>, current: [u8; 6], rssi: i8) -> bool {
+    let scan_config = ScanConfig {
+        ssid: Some(SSID),
+        ..Default::default()
+    };
+    let best = match controller.scan_with_config_async::<8>(scan_config).await {
+        Ok((res, _)) => res
+            .into_iter()
+            .filter(|ap| ap.bssid != current)
+            .max_by_key(|ap| ap.signal_strength),
+        Err(e) => {
+            println!("Error while scanning {e:?}");
+            return false;
+        }
+    };
+
+    let Some(best) = best else {
+        println!("No other AP found");
+        return false;
+    };
+    if best.signal_strength < rssi.saturating_add(ROAM_MARGIN) {
+        println!(
+            "Best other AP {:02x?} is not much better ({} dBm)",
+            best.bssid, best.signal_strength
+        );
+        return false;
+    }
+
+    println!(
+        "Roaming to {:02x?} on channel {} ({} dBm)",
+        best.bssid, best.channel, best.signal_strength
+    );
+    let client_config = Configuration::Client(ClientConfiguration {
+        ssid: SSID.try_into().unwrap(),
+        password: PASSWORD.try_into().unwrap(),
+        bssid: Some(best.bssid),
+        channel: Some(best.channel),
+        ..Default::default()
+    });
+    if let Err(e) = controller.set_configuration(&client_config) {
+        println!("Failed to set configuration: {e:?}");
+        return false;
+    }
+    if let Err(e) = controller.disconnect_async().await {
+        println!("Failed to disconnect: {e:?}");
+        return false;
+    }
+    true
+}
+
+/// Asks the driver about the AP we are currently connected to.
+///
+/// The driver does not tell us the negotiated rate, so we settle for the PHY
+/// mode the AP supports.
+fn sample_link() -> Option<(LinkSample, [u8; 6])> {
+    use esp_wifi_sys::include::{esp_wifi_sta_get_ap_info, wifi_ap_record_t};
+
+    // SAFETY: `wifi_ap_record_t` is a plain C struct, for which all zeroes is
+    // a valid value, and the driver only writes to it while we wait.
+    let mut info: wifi_ap_record_t = unsafe { core::mem::zeroed() };
+    if unsafe { esp_wifi_sta_get_ap_info(&mut info) } != 0 {
+        return None;
+    }
+
+    let phy = if info.phy_lr() != 0 {
+        PhyMode::LongRange
+    } else if info.phy_11n() != 0 {
+        PhyMode::N
+    } else if info.phy_11g() != 0 {
+        PhyMode::G
+    } else if info.phy_11b() != 0 {
+        PhyMode::B
+    } else {
+        PhyMode::Unknown
+    };
+
+    Some((
+        LinkSample {
+            rssi: info.rssi,
+            channel: info.primary,
+            phy,
+        },
+        info.bssid,
+    ))
+}
+
 #[embassy_executor::task]
 async fn net_task(mut runner: Runner<'static, WifiDevice<'static>>) {
     runner.run().await
@@ -0,0 +1,14 @@
+fn main() {
+    println!("cargo:rustc-link-arg=-Tlinkall.x");
+
+    // Our networks, as comma separated lists of SSIDs and of BSSIDs written
+    // as `aa:bb:cc:dd:ee:ff`. The ROM reads them at boot, checking them here
+    // fails the build on a typo instead
+    println!("cargo:rerun-if-env-changed=MONITOR_SSIDS");
+    println!("cargo:rerun-if-env-changed=MONITOR_BSSIDS");
+    let ssids = std::env::var("MONITOR_SSIDS").unwrap_or_default();
+    let bssids = std::env::var("MONITOR_BSSIDS").unwrap_or_default();
+    if let Err((network, e)) = wifi_analysis::detect::Networks::from_config(&ssids, &bssids) {
+        panic!("Invalid network {network:?} in MONITOR_SSIDS or MONITOR_BSSIDS: {e}");
+    }
+}
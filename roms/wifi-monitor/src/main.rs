@@ -0,0 +1,127 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+mod macros;
+
+use embassy_executor::Spawner;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::{Instant, Timer};
+use esp_backtrace as _;
+use esp_hal::{clock::CpuClock, rng::Rng, timer::timg::TimerGroup};
+use esp_println::println;
+use esp_wifi::{
+    EspWifiController,
+    wifi::{PromiscuousPkt, WifiMode},
+};
+use esp_wifi_sys::include::{
+    WIFI_PROMIS_FILTER_MASK_MGMT, esp_wifi_set_channel, esp_wifi_set_promiscuous_filter,
+    wifi_promiscuous_filter_t, wifi_second_chan_t_WIFI_SECOND_CHAN_NONE,
+};
+use wifi_analysis::{
+    detect::{Detector, Networks},
+    frame,
+};
+
+/// How long we listen on each channel before moving on.
+const DWELL_MS: u64 = 300;
+const MAX_CHANNEL: u8 = 13;
+
+/// Everything we look at is in the first few bytes of the frame; the rest
+/// is thrown away as early as possible.
+const MAX_FRAME_LEN: usize = 96;
+
+struct Captured {
+    timestamp_ms: u64,
+    channel: u8,
+    rssi: i8,
+    data: heapless::Vec<u8, MAX_FRAME_LEN>,
+}
+
+static FRAMES: Channel<CriticalSectionRawMutex, Captured, 16> = Channel::new();
+
+#[esp_hal_embassy::main]
+async fn main(spawner: Spawner) -> ! {
+    esp_println::logger::init_logger_from_env();
+    let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
+    let peripherals = esp_hal::init(config);
+
+    esp_alloc::heap_allocator!(size: 72 * 1024);
+
+    let timg0 = TimerGroup::new(peripherals.TIMG0);
+    let timg1 = TimerGroup::new(peripherals.TIMG1);
+    let rng = Rng::new(peripherals.RNG);
+
+    esp_hal_embassy::init(timg1.timer0);
+
+    // Checked by build.rs
+    let networks = Networks::from_config(
+        option_env!("MONITOR_SSIDS").unwrap_or(""),
+        option_env!("MONITOR_BSSIDS").unwrap_or(""),
+    )
+    .unwrap();
+    println!(
+        "Monitoring SSIDs {:?} with known BSSIDs {:02x?}",
+        networks.ssids, networks.bssids
+    );
+    let mut detector = Detector::new(networks);
+
+    let init = &*mk_static!(
+        EspWifiController<'static>,
+        esp_wifi::init(timg0.timer0, rng, peripherals.RADIO_CLK).unwrap()
+    );
+
+    let (mut controller, interfaces) = esp_wifi::wifi::new(&init, peripherals.WIFI).unwrap();
+    controller.set_mode(WifiMode::Sta).unwrap();
+    controller.start_async().await.unwrap();
+
+    // We only care about management frames
+    let filter = wifi_promiscuous_filter_t {
+        filter_mask: WIFI_PROMIS_FILTER_MASK_MGMT,
+    };
+    // SAFETY: The driver copies the filter before returning.
+    unsafe { esp_wifi_set_promiscuous_filter(&filter) };
+
+    let mut sniffer = interfaces.sniffer;
+    sniffer.set_receive_cb(on_frame);
+    sniffer.set_promiscuous_mode(true).unwrap();
+
+    spawner.spawn(channel_hopper()).unwrap();
+    println!("Monitor started");
+
+    loop {
+        let captured = FRAMES.receive().await;
+        let frame = frame::parse(&captured.data);
+        if let Some(alert) = detector.on_frame(captured.timestamp_ms, &frame) {
+            println!(
+                "[{} ms] ALERT channel={} rssi={} {alert}",
+                captured.timestamp_ms, captured.channel, captured.rssi
+            );
+        }
+    }
+}
+
+/// Called by the driver for every captured frame.
+fn on_frame(pkt: PromiscuousPkt<'_>) {
+    let len = pkt.data.len().min(MAX_FRAME_LEN);
+    let captured = Captured {
+        timestamp_ms: Instant::now().as_millis(),
+        channel: pkt.rx_cntl.channel as u8,
+        rssi: pkt.rx_cntl.rssi as i8,
+        data: heapless::Vec::from_slice(&pkt.data[..len]).unwrap(),
+    };
+    // If we can't keep up, we are probably being flooded, which the frames
+    // we already have will tell us about.
+    let _ = FRAMES.try_send(captured);
+}
+
+#[embassy_executor::task]
+async fn channel_hopper() {
+    let mut channel = 1;
+    loop {
+        // SAFETY: Only changes the driver's configuration.
+        unsafe { esp_wifi_set_channel(channel, wifi_second_chan_t_WIFI_SECOND_CHAN_NONE) };
+        channel = channel % MAX_CHANNEL + 1;
+        Timer::after_millis(DWELL_MS).await;
+    }
+}
@@ -1,3 +1,25 @@
 fn main() {
     println!("cargo:rustc-link-arg=-Tlinkall.x");
+
+    // The features pick the output format, which `SCAN_CONFIG` can still
+    // change
+    let format = if std::env::var_os("CARGO_FEATURE_JSON").is_some() {
+        "format json"
+    } else if std::env::var_os("CARGO_FEATURE_CSV").is_some() {
+        "format csv"
+    } else {
+        ""
+    };
+
+    // The ROM applies the same commands at boot, checking them here fails
+    // the build on a typo instead
+    println!("cargo:rerun-if-env-changed=SCAN_CONFIG");
+    let config = format!(
+        "{format}; {}",
+        std::env::var("SCAN_CONFIG").unwrap_or_default()
+    );
+    if let Err((command, e)) = wifi_config::scan::ScanParams::default().from_config(&config) {
+        panic!("Invalid SCAN_CONFIG command {command:?}: {e}");
+    }
+    println!("cargo:rustc-env=SCAN_BUILD_CONFIG={config}");
 }
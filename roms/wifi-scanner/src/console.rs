@@ -0,0 +1,72 @@
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex, signal::Signal};
+use embedded_io_async::Read;
+use esp_hal::{Async, usb_serial_jtag::UsbSerialJtagRx};
+use esp_println::println;
+use wifi_config::scan::{Command, HELP, ScanParams};
+
+const MAX_LINE_LEN: usize = 128;
+
+/// Reads commands from the USB serial console, one per line, and applies
+/// them to the scan parameters.
+///
+/// Output goes through `esp_println` like everything else, so we only ever
+/// read from the USB serial here.
+#[embassy_executor::task]
+pub(crate) async fn console(
+    mut usb_rx: UsbSerialJtagRx<'static, Async>,
+    params: &'static Mutex<NoopRawMutex, ScanParams>,
+    params_changed: &'static Signal<NoopRawMutex, ()>,
+) {
+    let mut buf = [0; 64];
+    let mut line = heapless::Vec::<u8, MAX_LINE_LEN>::new();
+    let mut overflowed = false;
+    loop {
+        let Ok(n) = usb_rx.read(&mut buf).await;
+        for &c in &buf[..n] {
+            match c {
+                b'\r' | b'\n' => {
+                    if overflowed {
+                        println!("Error: line is too long");
+                    } else {
+                        handle_line(&line, params, params_changed).await;
+                    }
+                    line.clear();
+                    overflowed = false;
+                }
+                // Backspace and delete
+                0x08 | 0x7f => {
+                    line.pop();
+                }
+                c => {
+                    if line.push(c).is_err() {
+                        overflowed = true;
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn handle_line(
+    line: &[u8],
+    params: &'static Mutex<NoopRawMutex, ScanParams>,
+    params_changed: &'static Signal<NoopRawMutex, ()>,
+) {
+    let Ok(line) = core::str::from_utf8(line) else {
+        println!("Error: command is not valid UTF-8");
+        return;
+    };
+    match Command::parse(line) {
+        Ok(Command::Nothing) => (),
+        Ok(Command::Help) => println!("{HELP}"),
+        Ok(Command::Show) => println!("{}", params.lock().await),
+        Ok(Command::Scan) => params_changed.signal(()),
+        Ok(command) => {
+            let mut params = params.lock().await;
+            params.apply(command);
+            println!("{}", params);
+            params_changed.signal(());
+        }
+        Err(e) => println!("Error: {e}"),
+    }
+}
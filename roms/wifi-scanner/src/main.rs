@@ -3,14 +3,18 @@
 
 #[macro_use]
 mod macros;
+mod console;
+mod output;
 mod wifi;
 
 use embassy_executor::Spawner;
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex, signal::Signal};
 use esp_backtrace as _;
-use esp_hal::{clock::CpuClock, rng::Rng, timer::timg::TimerGroup};
+use esp_hal::{clock::CpuClock, rng::Rng, timer::timg::TimerGroup, usb_serial_jtag::UsbSerialJtag};
+use wifi_config::scan::ScanParams;
 
 #[esp_hal_embassy::main]
-async fn main(_spawner: Spawner) -> ! {
+async fn main(spawner: Spawner) -> ! {
     esp_println::logger::init_logger_from_env();
     let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
     let peripherals = esp_hal::init(config);
@@ -23,5 +27,27 @@ async fn main(_spawner: Spawner) -> ! {
 
     esp_hal_embassy::init(timg1.timer0);
 
-    wifi::run_scanner(timg0.timer0, rng, peripherals.RADIO_CLK, peripherals.WIFI).await;
+    // Checked by build.rs
+    let params = ScanParams::default()
+        .from_config(env!("SCAN_BUILD_CONFIG"))
+        .unwrap();
+    let params = mk_static!(Mutex<NoopRawMutex, ScanParams>, Mutex::new(params));
+    let params_changed = mk_static!(Signal<NoopRawMutex, ()>, Signal::new());
+
+    let (usb_rx, _) = UsbSerialJtag::new(peripherals.USB_DEVICE)
+        .into_async()
+        .split();
+    spawner
+        .spawn(console::console(usb_rx, params, params_changed))
+        .unwrap();
+
+    wifi::run_scanner(
+        timg0.timer0,
+        rng,
+        peripherals.RADIO_CLK,
+        peripherals.WIFI,
+        params,
+        params_changed,
+    )
+    .await;
 }
@@ -0,0 +1,154 @@
+use core::fmt::{self, Write};
+
+use esp_println::println;
+use wifi_config::scan::Format;
+
+use crate::wifi::ApRecord;
+
+#[cfg(all(feature = "json", feature = "csv"))]
+compile_error!("The `json` and `csv` features can't be enabled together, pick one");
+
+pub const CSV_HEADER: &str = "scan,bssid,ssid,hidden,channel,secondary,rssi,auth,country";
+
+pub fn print_header(format: Format) {
+    if format == Format::Csv {
+        println!("{CSV_HEADER}");
+    }
+}
+
+pub fn print_scan(format: Format, scan: u32, count: usize, shown: usize) {
+    match format {
+        Format::Text => println!("Got {count} results"),
+        Format::JsonLines => {
+            println!(r#"{{"type":"scan","scan":{scan},"count":{count},"shown":{shown}}}"#)
+        }
+        // CSV only has room for access points; the scan number is part of
+        // every row instead.
+        Format::Csv => (),
+    }
+}
+
+pub fn print_ap(format: Format, scan: u32, ap: &ApRecord) {
+    let bssid = Mac(ap.bssid);
+    let country = Country(ap.country);
+    match format {
+        Format::Text => println!(
+            "bssid={bssid} ssid={:?} hidden={} strength={} channel={} secondary={} auth={} country={country}",
+            Escaped(&ap.ssid),
+            ap.is_hidden(),
+            ap.rssi,
+            ap.channel,
+            ap.secondary,
+            ap.auth,
+        ),
+        Format::JsonLines => println!(
+            r#"{{"type":"ap","scan":{scan},"bssid":"{bssid}","ssid":"{}","hidden":{},"channel":{},"secondary":"{}","rssi":{},"auth":"{}","country":"{country}"}}"#,
+            Escaped(&ap.ssid),
+            ap.is_hidden(),
+            ap.channel,
+            ap.secondary,
+            ap.rssi,
+            ap.auth,
+        ),
+        Format::Csv => println!(
+            r#"{scan},{bssid},"{}",{},{},{},{},{},{country}"#,
+            CsvEscaped(&ap.ssid),
+            ap.is_hidden(),
+            ap.channel,
+            ap.secondary,
+            ap.rssi,
+            ap.auth,
+        ),
+    }
+}
+
+/// Prints a tracking event, which is already formatted as text.
+pub fn print_event(format: Format, scan: u32, event: &str) {
+    match format {
+        Format::Text | Format::Csv => println!("Event: {event}"),
+        Format::JsonLines => println!(
+            r#"{{"type":"event","scan":{scan},"event":"{}"}}"#,
+            Escaped(event.as_bytes())
+        ),
+    }
+}
+
+struct Mac([u8; 6]);
+
+impl fmt::Display for Mac {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+
+struct Country([u8; 2]);
+
+impl fmt::Display for Country {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for &c in &self.0 {
+            if c.is_ascii_alphanumeric() {
+                f.write_char(c as char)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// SSIDs are arbitrary bytes, though usually UTF-8. This prints them as a
+/// JSON string body: valid UTF-8 as is, control characters escaped as
+/// `\uXXXX`, and bytes that aren't UTF-8 as `\u00XX`, since JSON has no
+/// way to carry raw bytes.
+struct Escaped<'a>(&'a [u8]);
+
+impl fmt::Display for Escaped<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for chunk in self.0.utf8_chunks() {
+            for c in chunk.valid().chars() {
+                match c {
+                    '"' => f.write_str("\\\"")?,
+                    '\\' => f.write_str("\\\\")?,
+                    c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+                    c => f.write_char(c)?,
+                }
+            }
+            for &b in chunk.invalid() {
+                write!(f, "\\u{b:04x}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Escaped<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{self}\"")
+    }
+}
+
+/// Like `Escaped`, but for the inside of a quoted CSV field, where the only
+/// special character is the quote itself. Control characters and bytes that
+/// aren't UTF-8 are written as `\xNN`, byte by byte.
+struct CsvEscaped<'a>(&'a [u8]);
+
+impl fmt::Display for CsvEscaped<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for chunk in self.0.utf8_chunks() {
+            for c in chunk.valid().chars() {
+                match c {
+                    '"' => f.write_str("\"\"")?,
+                    c if c.is_control() => {
+                        for &b in c.encode_utf8(&mut [0; 4]).as_bytes() {
+                            write!(f, "\\x{b:02x}")?;
+                        }
+                    }
+                    c => f.write_char(c)?,
+                }
+            }
+            for &b in chunk.invalid() {
+                write!(f, "\\x{b:02x}")?;
+            }
+        }
+        Ok(())
+    }
+}
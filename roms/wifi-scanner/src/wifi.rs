@@ -1,53 +1,368 @@
-use embassy_time::{Duration, Timer};
+use core::fmt::{self, Write};
+
+use embassy_futures::select::select;
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex, signal::Signal};
+use embassy_time::{Duration, Instant, Timer};
 use esp_hal::{
     peripheral::Peripheral,
     peripherals::{RADIO_CLK, WIFI},
     rng::Rng,
 };
 use esp_println::println;
-use esp_wifi::{EspWifiController, wifi::ScanConfig};
+use esp_wifi::{
+    EspWifiController,
+    wifi::{WifiController, WifiEvent},
+};
+use esp_wifi_sys::include::{
+    esp_wifi_clear_ap_list, esp_wifi_scan_get_ap_num, esp_wifi_scan_get_ap_records,
+    esp_wifi_scan_start, wifi_active_scan_time_t, wifi_ap_record_t,
+    wifi_auth_mode_t_WIFI_AUTH_ENTERPRISE, wifi_auth_mode_t_WIFI_AUTH_OPEN,
+    wifi_auth_mode_t_WIFI_AUTH_OWE, wifi_auth_mode_t_WIFI_AUTH_WAPI_PSK,
+    wifi_auth_mode_t_WIFI_AUTH_WEP, wifi_auth_mode_t_WIFI_AUTH_WPA_PSK,
+    wifi_auth_mode_t_WIFI_AUTH_WPA_WPA2_PSK, wifi_auth_mode_t_WIFI_AUTH_WPA2_PSK,
+    wifi_auth_mode_t_WIFI_AUTH_WPA2_WPA3_PSK, wifi_auth_mode_t_WIFI_AUTH_WPA3_PSK,
+    wifi_scan_config_t, wifi_scan_time_t, wifi_scan_type_t_WIFI_SCAN_TYPE_ACTIVE,
+    wifi_scan_type_t_WIFI_SCAN_TYPE_PASSIVE, wifi_second_chan_t_WIFI_SECOND_CHAN_ABOVE,
+    wifi_second_chan_t_WIFI_SECOND_CHAN_BELOW,
+};
+use wifi_analysis::tracker::{Sighting, TrackEvent, Tracker};
+use wifi_config::scan::{Format, ScanParams, ScanType};
+
+use crate::output;
+
+/// The maximum number of access points we report per scan. If more are
+/// found, only the total count is reported for the rest.
+const MAX_RESULTS: usize = 64;
+
+/// The maximum number of access points we keep track of across scans.
+const MAX_TRACKED: usize = 128;
+
+/// The most tracking events a single scan can produce. Every access point
+/// in it can appear and push another one out of the table, or change both
+/// its channel and security. After that, every one left in the table can
+/// disappear.
+const MAX_EVENTS: usize = 2 * MAX_RESULTS + MAX_TRACKED;
+
+/// The channel tracking events are broadcast on over ESP-NOW. The collector
+/// has to listen on the same channel.
+#[cfg(feature = "esp-now")]
+const ESP_NOW_CHANNEL: u8 = 1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SecondaryChannel {
+    None,
+    Above,
+    Below,
+}
+
+impl fmt::Display for SecondaryChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SecondaryChannel::None => "none",
+            SecondaryChannel::Above => "above",
+            SecondaryChannel::Below => "below",
+        })
+    }
+}
+
+/// A single access point from a scan.
+///
+/// We don't use `esp_wifi::wifi::AccessPointInfo` since it leaves out the
+/// country information.
+#[derive(Clone, Debug)]
+pub struct ApRecord {
+    pub bssid: [u8; 6],
+    pub ssid: heapless::Vec<u8, 32>,
+    pub channel: u8,
+    pub secondary: SecondaryChannel,
+    pub rssi: i8,
+    pub auth: &'static str,
+    pub country: [u8; 2],
+}
+
+impl ApRecord {
+    pub fn is_hidden(&self) -> bool {
+        self.ssid.is_empty()
+    }
+
+    fn from_raw(raw: &wifi_ap_record_t) -> Self {
+        let ssid_len = raw
+            .ssid
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(raw.ssid.len());
+        let ssid_len = ssid_len.min(32);
+
+        #[allow(non_upper_case_globals)]
+        let auth = match raw.authmode {
+            wifi_auth_mode_t_WIFI_AUTH_OPEN => "open",
+            wifi_auth_mode_t_WIFI_AUTH_WEP => "wep",
+            wifi_auth_mode_t_WIFI_AUTH_WPA_PSK => "wpa-psk",
+            wifi_auth_mode_t_WIFI_AUTH_WPA2_PSK => "wpa2-psk",
+            wifi_auth_mode_t_WIFI_AUTH_WPA_WPA2_PSK => "wpa-wpa2-psk",
+            wifi_auth_mode_t_WIFI_AUTH_ENTERPRISE => "enterprise",
+            wifi_auth_mode_t_WIFI_AUTH_WPA3_PSK => "wpa3-psk",
+            wifi_auth_mode_t_WIFI_AUTH_WPA2_WPA3_PSK => "wpa2-wpa3-psk",
+            wifi_auth_mode_t_WIFI_AUTH_WAPI_PSK => "wapi-psk",
+            wifi_auth_mode_t_WIFI_AUTH_OWE => "owe",
+            _ => "unknown",
+        };
+
+        #[allow(non_upper_case_globals)]
+        let secondary = match raw.second {
+            wifi_second_chan_t_WIFI_SECOND_CHAN_ABOVE => SecondaryChannel::Above,
+            wifi_second_chan_t_WIFI_SECOND_CHAN_BELOW => SecondaryChannel::Below,
+            _ => SecondaryChannel::None,
+        };
+
+        Self {
+            bssid: raw.bssid,
+            ssid: heapless::Vec::from_slice(&raw.ssid[..ssid_len]).unwrap(),
+            channel: raw.primary,
+            secondary,
+            rssi: raw.rssi,
+            auth,
+            country: [raw.country.cc[0] as u8, raw.country.cc[1] as u8],
+        }
+    }
+}
 
 pub(crate) async fn run_scanner(
     timer: esp_hal::timer::timg::Timer,
     rng: Rng,
     radio_clk: impl Peripheral<P = RADIO_CLK> + 'static,
     wifi: impl Peripheral<P = WIFI> + 'static,
+    params: &'static Mutex<NoopRawMutex, ScanParams>,
+    params_changed: &'static Signal<NoopRawMutex, ()>,
 ) -> ! {
     let init = &*mk_static!(
         EspWifiController<'static>,
         esp_wifi::init(timer, rng, radio_clk).unwrap()
     );
 
-    let (mut controller, _) = esp_wifi::wifi::new(&init, wifi).unwrap();
+    #[cfg_attr(not(feature = "esp-now"), allow(unused_variables))]
+    let (mut controller, interfaces) = esp_wifi::wifi::new(&init, wifi).unwrap();
+    #[cfg(feature = "esp-now")]
+    let mut esp_now = interfaces.esp_now;
+
+    let raw_records = mk_static!(
+        [wifi_ap_record_t; MAX_RESULTS],
+        // SAFETY: `wifi_ap_record_t` is a plain C struct, for which all
+        // zeroes is a valid value.
+        unsafe { core::mem::zeroed() }
+    );
 
     println!("start connection task");
     println!("Device capabilities: {:?}", controller.capabilities());
     controller.set_mode(esp_wifi::wifi::WifiMode::Sta).unwrap();
+
+    let tracker = mk_static!(Tracker<MAX_TRACKED>, Tracker::new());
+    let events = mk_static!(
+        heapless::Vec<TrackEvent, MAX_EVENTS>,
+        heapless::Vec::new()
+    );
+
+    let mut previous_params: Option<ScanParams> = None;
+    let mut scan_number: u32 = 0;
     loop {
+        let params = params.lock().await.clone();
+        if let Some(previous) = &previous_params {
+            if previous.format != params.format {
+                output::print_header(params.format);
+            }
+            // Access points might drop out of the scan because of the new
+            // parameters, which would look like they disappeared.
+            if (&previous.channels, &previous.ssid, &previous.bssid)
+                != (&params.channels, &params.ssid, &params.bssid)
+            {
+                tracker.clear();
+            }
+        } else {
+            output::print_header(params.format);
+        }
+        previous_params = Some(params.clone());
+
         if !matches!(controller.is_started(), Ok(true)) {
             println!("Starting wifi");
             controller.start_async().await.unwrap();
             println!("Wifi started!");
         }
 
-        println!("Starting scan...");
-        match controller
-            .scan_with_config_async::<20>(ScanConfig::default())
-            .await
-        {
-            Ok((res, count)) => {
-                println!("Got {count} results");
-                for r in res {
-                    println!(
-                        "ssid={:?} strength={} channel={} auth={:?}",
-                        r.ssid, r.signal_strength, r.channel, r.auth_method
-                    );
+        if params.format == Format::Text {
+            println!("Starting scan...");
+        }
+        match scan(&mut controller, &params, raw_records).await {
+            Ok((records, count)) => {
+                output::print_scan(params.format, scan_number, count, records.len());
+                let now_ms = Instant::now().as_millis();
+                for raw in records.iter() {
+                    let ap = ApRecord::from_raw(raw);
+                    output::print_ap(params.format, scan_number, &ap);
+                    let sighting = Sighting {
+                        bssid: ap.bssid,
+                        ssid: &ap.ssid,
+                        channel: ap.channel,
+                        rssi: ap.rssi,
+                        auth: ap.auth,
+                    };
+                    tracker.observe(now_ms, &sighting, |e| events.push(e).unwrap());
                 }
+                tracker.end_scan(|e| events.push(e).unwrap());
             }
             Err(e) => {
                 println!("Error while scanning {e:?}");
             }
         }
-        Timer::after(Duration::from_millis(5000)).await
+
+        for event in events.drain(..) {
+            let mut text = heapless::String::<192>::new();
+            let _ = write!(text, "{event}");
+            output::print_event(params.format, scan_number, &text);
+
+            #[cfg(feature = "esp-now")]
+            {
+                // Scanning leaves the radio on whatever channel it scanned last
+                if let Err(e) = esp_now.set_channel(ESP_NOW_CHANNEL) {
+                    println!("Error while setting channel: {e:?}");
+                }
+                if let Err(e) = esp_now
+                    .send_async(&esp_wifi::esp_now::BROADCAST_ADDRESS, text.as_bytes())
+                    .await
+                {
+                    println!("Error while sending event: {e:?}");
+                }
+            }
+        }
+        scan_number = scan_number.wrapping_add(1);
+
+        // Changing the parameters starts a new scan right away
+        select(
+            Timer::after(Duration::from_millis(params.interval_ms as u64)),
+            params_changed.wait(),
+        )
+        .await;
+    }
+}
+
+/// Scans the channels in `params`, including hidden networks.
+///
+/// This talks to the driver directly instead of going through
+/// `WifiController::scan_with_config_async`, since that does not give us
+/// access to the full access point records, and only lets us pick either a
+/// single channel or all of them.
+///
+/// Returns the records we had room for, together with the total number of
+/// access points found.
+async fn scan<'a>(
+    controller: &mut WifiController<'static>,
+    params: &ScanParams,
+    records: &'a mut [wifi_ap_record_t],
+) -> Result<(&'a [wifi_ap_record_t], usize), ScanError> {
+    // The driver wants a NUL-terminated SSID
+    let mut ssid = heapless::Vec::<u8, 33>::new();
+    if let Some(s) = &params.ssid {
+        ssid.extend_from_slice(s.as_bytes()).unwrap();
+        ssid.push(0).unwrap();
+    }
+    let mut bssid = params.bssid;
+
+    let (scan_type, scan_time) = match params.scan_type {
+        ScanType::Active { min_ms, max_ms } => (
+            wifi_scan_type_t_WIFI_SCAN_TYPE_ACTIVE,
+            wifi_scan_time_t {
+                active: wifi_active_scan_time_t {
+                    min: min_ms,
+                    max: max_ms,
+                },
+                passive: 0,
+            },
+        ),
+        ScanType::Passive { dwell_ms } => (
+            wifi_scan_type_t_WIFI_SCAN_TYPE_PASSIVE,
+            wifi_scan_time_t {
+                active: wifi_active_scan_time_t { min: 0, max: 0 },
+                passive: dwell_ms,
+            },
+        ),
+    };
+
+    let mut config = wifi_scan_config_t {
+        ssid: if ssid.is_empty() {
+            core::ptr::null_mut()
+        } else {
+            ssid.as_mut_ptr()
+        },
+        bssid: match &mut bssid {
+            Some(bssid) => bssid.as_mut_ptr(),
+            None => core::ptr::null_mut(),
+        },
+        channel: 0,
+        show_hidden: true,
+        scan_type,
+        scan_time,
+        // SAFETY: The remaining fields are plain integers.
+        ..unsafe { core::mem::zeroed() }
+    };
+
+    if params.channels.is_all() {
+        let (n, total) = scan_channel(controller, &config, records).await?;
+        return Ok((&records[..n], total));
+    }
+
+    let mut found = 0;
+    let mut total = 0;
+    for channel in params.channels.iter() {
+        config.channel = channel;
+        let (n, t) = scan_channel(controller, &config, &mut records[found..]).await?;
+        found += n;
+        total += t;
+    }
+    Ok((&records[..found], total))
+}
+
+/// Runs a single scan with the given configuration, where a channel of 0
+/// means all channels.
+///
+/// Returns how many records were written to `records`, and how many access
+/// points were found in total.
+async fn scan_channel(
+    controller: &mut WifiController<'static>,
+    config: &wifi_scan_config_t,
+    records: &mut [wifi_ap_record_t],
+) -> Result<(usize, usize), ScanError> {
+    // SAFETY: The driver copies the configuration, including the SSID and
+    // BSSID it points to, before returning.
+    check(unsafe { esp_wifi_scan_start(config, false) })?;
+    controller.wait_for_event(WifiEvent::ScanDone).await;
+
+    let mut total: u16 = 0;
+    // SAFETY: `total` is a valid pointer for the duration of the call.
+    check(unsafe { esp_wifi_scan_get_ap_num(&mut total) })?;
+
+    if records.is_empty() {
+        // We are out of room, but the driver still holds on to the results
+        // until we ask for them.
+        // SAFETY: Just frees the driver's list of results.
+        check(unsafe { esp_wifi_clear_ap_list() })?;
+        return Ok((0, total as usize));
+    }
+
+    let mut n = records.len().min(u16::MAX as usize) as u16;
+    // SAFETY: `records` has room for `n` entries. The driver writes at most
+    // that many, and updates `n` with how many it actually wrote.
+    check(unsafe { esp_wifi_scan_get_ap_records(&mut n, records.as_mut_ptr()) })?;
+
+    Ok((n as usize, total as usize))
+}
+
+/// An `esp_err_t` returned by the driver.
+#[derive(Copy, Clone, Debug)]
+pub struct ScanError(#[allow(dead_code)] i32);
+
+fn check(code: i32) -> Result<(), ScanError> {
+    if code == 0 {
+        Ok(())
+    } else {
+        Err(ScanError(code))
     }
 }
@@ -0,0 +1,12 @@
+fn main() {
+    println!("cargo:rustc-link-arg=-Tlinkall.x");
+
+    // The ROM applies the same commands at boot, checking them here fails
+    // the build on a typo instead
+    println!("cargo:rerun-if-env-changed=SNIFF_CONFIG");
+    let config = std::env::var("SNIFF_CONFIG").unwrap_or_default();
+    if let Err((command, e)) = wifi_config::sniff::Settings::default().from_config(&config) {
+        panic!("Invalid SNIFF_CONFIG command {command:?}: {e}");
+    }
+    println!("cargo:rustc-env=SNIFF_BUILD_CONFIG={config}");
+}
@@ -0,0 +1,246 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+mod macros;
+mod pcap;
+
+use core::{
+    cell::Cell,
+    fmt::{self, Write as _},
+};
+
+use embassy_executor::Spawner;
+use embassy_futures::select::{Either, select};
+use embassy_sync::{
+    blocking_mutex::{self, raw::CriticalSectionRawMutex},
+    channel::Channel,
+    signal::Signal,
+};
+use embassy_time::{Instant, Timer};
+use embedded_io_async::{Read, Write};
+use esp_backtrace as _;
+use esp_hal::{
+    Async,
+    clock::CpuClock,
+    rng::Rng,
+    timer::timg::TimerGroup,
+    usb_serial_jtag::{UsbSerialJtag, UsbSerialJtagRx},
+};
+use esp_wifi::{
+    EspWifiController,
+    wifi::{PromiscuousPkt, WifiMode},
+};
+use esp_wifi_sys::include::{
+    WIFI_PROMIS_FILTER_MASK_CTRL, WIFI_PROMIS_FILTER_MASK_DATA, WIFI_PROMIS_FILTER_MASK_MGMT,
+    WIFI_PROMIS_FILTER_MASK_MISC, esp_wifi_set_channel, esp_wifi_set_promiscuous_filter,
+    wifi_promiscuous_filter_t, wifi_second_chan_t_WIFI_SECOND_CHAN_NONE,
+};
+use pcap::{FrameInfo, MAX_RECORD_LEN, SNAPLEN};
+use wifi_config::sniff::{self, ChannelMode, FrameTypes, Settings};
+
+/// Every message sent to the host is COBS encoded and terminated by a zero
+/// byte. The first byte of the decoded message tells what it contains.
+///
+/// A PCAP record, see `pcap::write_record`.
+const MSG_PACKET: u8 = 0;
+/// A line of UTF-8 text meant for a human.
+const MSG_LOG: u8 = 1;
+
+const MAX_MESSAGE_LEN: usize = 1 + MAX_RECORD_LEN;
+
+struct Captured {
+    info: FrameInfo,
+    data: heapless::Vec<u8, SNAPLEN>,
+}
+
+static FRAMES: Channel<CriticalSectionRawMutex, Captured, 8> = Channel::new();
+static LOGS: Channel<CriticalSectionRawMutex, heapless::String<96>, 4> = Channel::new();
+static SETTINGS_CHANGED: Signal<CriticalSectionRawMutex, Settings> = Signal::new();
+
+/// Frames we had to throw away because the host did not keep up.
+static DROPPED: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<u32>> =
+    blocking_mutex::Mutex::new(Cell::new(0));
+
+/// We can't print anything with `esp_println`, since that would end up in the
+/// middle of the capture. Instead log messages are sent to the host in their
+/// own kind of message.
+fn log(args: fmt::Arguments<'_>) {
+    let mut s = heapless::String::new();
+    let _ = s.write_fmt(args);
+    let _ = LOGS.try_send(s);
+}
+
+#[esp_hal_embassy::main]
+async fn main(spawner: Spawner) -> ! {
+    let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
+    let peripherals = esp_hal::init(config);
+
+    esp_alloc::heap_allocator!(size: 72 * 1024);
+
+    let timg0 = TimerGroup::new(peripherals.TIMG0);
+    let timg1 = TimerGroup::new(peripherals.TIMG1);
+    let rng = Rng::new(peripherals.RNG);
+
+    esp_hal_embassy::init(timg1.timer0);
+
+    let (usb_rx, mut usb_tx) = UsbSerialJtag::new(peripherals.USB_DEVICE)
+        .into_async()
+        .split();
+
+    let init = &*mk_static!(
+        EspWifiController<'static>,
+        esp_wifi::init(timg0.timer0, rng, peripherals.RADIO_CLK).unwrap()
+    );
+
+    let (mut controller, interfaces) = esp_wifi::wifi::new(&init, peripherals.WIFI).unwrap();
+    controller.set_mode(WifiMode::Sta).unwrap();
+    controller.start_async().await.unwrap();
+
+    let mut sniffer = interfaces.sniffer;
+    sniffer.set_receive_cb(on_frame);
+    sniffer.set_promiscuous_mode(true).unwrap();
+
+    // Checked by build.rs
+    let settings = Settings::default()
+        .from_config(env!("SNIFF_BUILD_CONFIG"))
+        .unwrap();
+    spawner.spawn(radio_task(settings)).unwrap();
+    spawner.spawn(console(usb_rx, settings)).unwrap();
+    log(format_args!("Sniffer started: {settings:?}"));
+
+    let message = mk_static!([u8; MAX_MESSAGE_LEN], [0; MAX_MESSAGE_LEN]);
+    let encoded = mk_static!(
+        [u8; MAX_MESSAGE_LEN + MAX_MESSAGE_LEN / 254 + 2],
+        [0; MAX_MESSAGE_LEN + MAX_MESSAGE_LEN / 254 + 2]
+    );
+    let record = mk_static!([u8; MAX_RECORD_LEN], [0; MAX_RECORD_LEN]);
+
+    loop {
+        let len = match select(FRAMES.receive(), LOGS.receive()).await {
+            Either::First(frame) => {
+                let n = pcap::write_record(&frame.info, &frame.data, record);
+                message[0] = MSG_PACKET;
+                message[1..][..n].copy_from_slice(&record[..n]);
+                1 + n
+            }
+            Either::Second(line) => {
+                message[0] = MSG_LOG;
+                message[1..][..line.len()].copy_from_slice(line.as_bytes());
+                1 + line.len()
+            }
+        };
+
+        let n = cobs::encode(&message[..len], &mut encoded[..]);
+        encoded[n] = 0;
+        let Ok(()) = usb_tx.write_all(&encoded[..n + 1]).await;
+
+        let dropped = DROPPED.lock(|d| d.replace(0));
+        if dropped > 0 {
+            log(format_args!("Dropped {dropped} frames"));
+        }
+    }
+}
+
+/// Called by the driver for every captured frame.
+fn on_frame(pkt: PromiscuousPkt<'_>) {
+    let len = pkt.data.len().min(SNAPLEN);
+    let frame = Captured {
+        info: FrameInfo {
+            timestamp_us: Instant::now().as_micros(),
+            channel: pkt.rx_cntl.channel as u8,
+            rssi: pkt.rx_cntl.rssi as i8,
+            noise_floor: pkt.rx_cntl.noise_floor as i8,
+            orig_len: pkt.data.len(),
+        },
+        data: heapless::Vec::from_slice(&pkt.data[..len]).unwrap(),
+    };
+    if FRAMES.try_send(frame).is_err() {
+        DROPPED.lock(|d| d.set(d.get() + 1));
+    }
+}
+
+fn set_channel(channel: u8) {
+    // SAFETY: Only changes the driver's configuration.
+    let res = unsafe { esp_wifi_set_channel(channel, wifi_second_chan_t_WIFI_SECOND_CHAN_NONE) };
+    if res != 0 {
+        log(format_args!("Error setting channel {channel}: {res}"));
+    }
+}
+
+fn set_filter(filter: FrameTypes) {
+    let mut filter_mask = 0;
+    if filter.mgmt {
+        filter_mask |= WIFI_PROMIS_FILTER_MASK_MGMT;
+    }
+    if filter.ctrl {
+        filter_mask |= WIFI_PROMIS_FILTER_MASK_CTRL;
+    }
+    if filter.data {
+        filter_mask |= WIFI_PROMIS_FILTER_MASK_DATA;
+    }
+    if filter.misc {
+        filter_mask |= WIFI_PROMIS_FILTER_MASK_MISC;
+    }
+    let filter = wifi_promiscuous_filter_t { filter_mask };
+    // SAFETY: The driver copies the filter before returning.
+    let res = unsafe { esp_wifi_set_promiscuous_filter(&filter) };
+    if res != 0 {
+        log(format_args!("Error setting filter: {res}"));
+    }
+}
+
+/// Keeps the radio on the channel(s) we want to listen to.
+#[embassy_executor::task]
+async fn radio_task(mut settings: Settings) {
+    let mut channel = 1;
+    loop {
+        set_filter(settings.filter);
+        let changed = match settings.channel {
+            ChannelMode::Fixed(c) => {
+                set_channel(c);
+                SETTINGS_CHANGED.wait().await
+            }
+            ChannelMode::Hop { dwell_ms } => {
+                set_channel(channel);
+                channel = channel % sniff::MAX_CHANNEL + 1;
+                match select(
+                    Timer::after_millis(dwell_ms as u64),
+                    SETTINGS_CHANGED.wait(),
+                )
+                .await
+                {
+                    Either::First(()) => continue,
+                    Either::Second(changed) => changed,
+                }
+            }
+        };
+        settings = changed;
+    }
+}
+
+/// Reads commands from the host, one per line. See `sniff::Settings`.
+#[embassy_executor::task]
+async fn console(mut usb_rx: UsbSerialJtagRx<'static, Async>, mut settings: Settings) {
+    let mut buf = [0; 64];
+    let mut line = heapless::Vec::<u8, 64>::new();
+    loop {
+        let Ok(n) = usb_rx.read(&mut buf).await;
+        for &c in &buf[..n] {
+            if c != b'\n' && c != b'\r' {
+                let _ = line.push(c);
+                continue;
+            }
+            let command = core::str::from_utf8(&line).unwrap_or("invalid");
+            match settings.apply(command) {
+                Ok(()) if command.trim().is_empty() => (),
+                Ok(()) => {
+                    log(format_args!("{settings:?}"));
+                    SETTINGS_CHANGED.signal(settings);
+                }
+                Err(e) => log(format_args!("Error: {e}")),
+            }
+            line.clear();
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! Turns captured frames into PCAP records with a radiotap header.
+//!
+//! Only the per-packet records are produced here. The PCAP file header is
+//! written by the host tool, since it only appears once per file.
+
+/// The most we keep of each frame. Anything beyond this is cut off, but the
+/// original length is still recorded.
+pub const SNAPLEN: usize = 512;
+
+const RECORD_HEADER_LEN: usize = 16;
+const RADIOTAP_LEN: usize = 16;
+
+pub const MAX_RECORD_LEN: usize = RECORD_HEADER_LEN + RADIOTAP_LEN + SNAPLEN;
+
+// Radiotap fields we include, see https://www.radiotap.org/fields/defined
+const RADIOTAP_FLAGS: u32 = 1 << 1;
+const RADIOTAP_CHANNEL: u32 = 1 << 3;
+const RADIOTAP_DBM_ANTSIGNAL: u32 = 1 << 5;
+const RADIOTAP_DBM_ANTNOISE: u32 = 1 << 6;
+
+/// The frame includes the 4 byte FCS at the end.
+const FLAGS_FCS: u8 = 0x10;
+const CHANNEL_2GHZ: u16 = 0x0080;
+
+#[derive(Copy, Clone, Debug)]
+pub struct FrameInfo {
+    pub timestamp_us: u64,
+    pub channel: u8,
+    pub rssi: i8,
+    pub noise_floor: i8,
+    /// The length of the frame as it was received, including the FCS.
+    pub orig_len: usize,
+}
+
+fn channel_to_mhz(channel: u8) -> u16 {
+    match channel {
+        14 => 2484,
+        c => 2407 + 5 * c as u16,
+    }
+}
+
+/// Writes a PCAP record for `data` to `out`, and returns the number of
+/// bytes written. `data` is cut off at `SNAPLEN` bytes.
+pub fn write_record(info: &FrameInfo, data: &[u8], out: &mut [u8; MAX_RECORD_LEN]) -> usize {
+    let data = &data[..data.len().min(SNAPLEN)];
+    let incl_len = (RADIOTAP_LEN + data.len()) as u32;
+    let orig_len = (RADIOTAP_LEN + info.orig_len) as u32;
+
+    let ts_sec = (info.timestamp_us / 1_000_000) as u32;
+    let ts_usec = (info.timestamp_us % 1_000_000) as u32;
+
+    out[0..4].copy_from_slice(&ts_sec.to_le_bytes());
+    out[4..8].copy_from_slice(&ts_usec.to_le_bytes());
+    out[8..12].copy_from_slice(&incl_len.to_le_bytes());
+    out[12..16].copy_from_slice(&orig_len.to_le_bytes());
+
+    let radiotap = &mut out[RECORD_HEADER_LEN..][..RADIOTAP_LEN];
+    let present =
+        RADIOTAP_FLAGS | RADIOTAP_CHANNEL | RADIOTAP_DBM_ANTSIGNAL | RADIOTAP_DBM_ANTNOISE;
+    // Version and padding
+    radiotap[0] = 0;
+    radiotap[1] = 0;
+    radiotap[2..4].copy_from_slice(&(RADIOTAP_LEN as u16).to_le_bytes());
+    radiotap[4..8].copy_from_slice(&present.to_le_bytes());
+    radiotap[8] = FLAGS_FCS;
+    // The channel field has to be aligned to 2 bytes
+    radiotap[9] = 0;
+    radiotap[10..12].copy_from_slice(&channel_to_mhz(info.channel).to_le_bytes());
+    radiotap[12..14].copy_from_slice(&CHANNEL_2GHZ.to_le_bytes());
+    radiotap[14] = info.rssi as u8;
+    radiotap[15] = info.noise_floor as u8;
+
+    let start = RECORD_HEADER_LEN + RADIOTAP_LEN;
+    out[start..][..data.len()].copy_from_slice(data);
+    start + data.len()
+}
@@ -0,0 +1,346 @@
+//! Connects the `esp-now-gateway` ROM to programs on the host, through a UDP
+//! socket or through stdin and stdout.
+//!
+//! Usage:
+//!   esp-now-bridge --port PORT [--udp ADDR [--to ADDR]]
+//!
+//! Without `--udp`, every line read from stdin is sent, and every frame the
+//! gateway receives is written to stdout:
+//!
+//! ```text
+//! > aa:bb:cc:dd:ee:ff 68656c6c6f
+//! < sent 1 Sent
+//! < rx aa:bb:cc:dd:ee:ff 11:22:33:44:55:66 -52 776f726c64
+//! ```
+//!
+//! A destination of `ff:ff:ff:ff:ff:ff` or `broadcast` broadcasts. A
+//! payload in double quotes is sent as a typed `Text` message, see
+//! `libs/esp-now-messages`, and received typed messages are shown decoded
+//! after their hex:
+//!
+//! ```text
+//! > broadcast "hello"
+//! < sent 2 Sent
+//! < rx aa:bb:cc:dd:ee:ff ff:ff:ff:ff:ff:ff -48 4d5347530100ab02 Ping { seq: 299 }
+//! ```
+//!
+//! With `--udp`, we listen on ADDR. Each datagram is the destination MAC
+//! followed by the payload. Each received frame is sent as the source MAC,
+//! the RSSI and the payload, to `--to` or else to whoever sent us the last
+//! datagram. Failed sends are only reported on stderr.
+
+use std::{
+    io::{self, BufRead, Read, Write},
+    net::{SocketAddr, UdpSocket},
+    process::ExitCode,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use esp_now_messages::MAX_MESSAGE_LEN;
+use esp_now_stack::{
+    Mac,
+    bridge::{Deframer, MAX_ENCODED_LEN, Message, SendStatus},
+};
+
+const BROADCAST: Mac = [0xff; 6];
+
+#[derive(Default)]
+struct Args {
+    port: Option<String>,
+    udp: Option<String>,
+    to: Option<String>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args::default();
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| iter.next())
+                .ok_or_else(|| format!("{name} needs a value"))
+        };
+        match name.as_str() {
+            "--port" => args.port = Some(value()?),
+            "--udp" => args.udp = Some(value()?),
+            "--to" => args.to = Some(value()?),
+            _ => return Err(format!("unknown argument {name}")),
+        }
+    }
+    if args.to.is_some() && args.udp.is_none() {
+        return Err("--to only makes sense with --udp".into());
+    }
+    Ok(args)
+}
+
+fn parse_mac(s: &str) -> Option<Mac> {
+    if s == "broadcast" {
+        return Some(BROADCAST);
+    }
+    let mut mac = [0; 6];
+    let mut parts = s.split(':');
+    for b in &mut mac {
+        *b = u8::from_str_radix(parts.next()?, 16).ok()?;
+    }
+    parts.next().is_none().then_some(mac)
+}
+
+fn format_mac(mac: &Mac) -> String {
+    let parts: Vec<_> = mac.iter().map(|b| format!("{b:02x}")).collect();
+    parts.join(":")
+}
+
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn format_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Hex, or text in double quotes, which goes as a typed message.
+fn parse_payload(s: &str) -> Option<Vec<u8>> {
+    let Some(text) = s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) else {
+        return parse_hex(s);
+    };
+    let mut buf = [0; MAX_MESSAGE_LEN];
+    let n = esp_now_messages::Message::Text(text)
+        .encode(&mut buf)
+        .ok()?;
+    Some(buf[..n].to_vec())
+}
+
+/// The payload in hex, followed by what it says if it's a typed message.
+fn format_payload(payload: &[u8]) -> String {
+    match esp_now_messages::Message::decode(payload) {
+        Ok(message) => format!("{} {message:?}", format_hex(payload)),
+        Err(_) => format_hex(payload),
+    }
+}
+
+/// Where the frames the gateway receives go.
+enum Output {
+    Stdout,
+    Udp {
+        socket: UdpSocket,
+        /// Fixed with `--to`, otherwise whoever we last heard from.
+        peer: Arc<Mutex<Option<SocketAddr>>>,
+    },
+}
+
+impl Output {
+    fn handle(&self, message: Message<'_>) -> io::Result<()> {
+        match (self, message) {
+            (
+                Output::Stdout,
+                Message::Received {
+                    src,
+                    dst,
+                    rssi,
+                    payload,
+                },
+            ) => {
+                let mut out = io::stdout().lock();
+                writeln!(
+                    out,
+                    "rx {} {} {rssi} {}",
+                    format_mac(&src),
+                    format_mac(&dst),
+                    format_payload(payload)
+                )?;
+                out.flush()
+            }
+            (Output::Stdout, Message::SendResult { id, status }) => {
+                let mut out = io::stdout().lock();
+                writeln!(out, "sent {id} {status:?}")?;
+                out.flush()
+            }
+            (
+                Output::Udp { socket, peer },
+                Message::Received {
+                    src, rssi, payload, ..
+                },
+            ) => {
+                let Some(peer) = *peer.lock().unwrap() else {
+                    return Ok(());
+                };
+                let mut datagram = src.to_vec();
+                datagram.push(rssi as u8);
+                datagram.extend_from_slice(payload);
+                socket.send_to(&datagram, peer).map(|_| ())
+            }
+            (Output::Udp { .. }, Message::SendResult { id, status }) => {
+                if status != SendStatus::Sent {
+                    eprintln!("Send {id}: {status:?}");
+                }
+                Ok(())
+            }
+            (_, Message::Log(text)) => {
+                eprintln!("gateway: {text}");
+                Ok(())
+            }
+            (_, Message::Send { .. }) => {
+                eprintln!("Ignoring a message meant for the gateway");
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Reads messages from the gateway until the port fails.
+fn read_port(mut port: Box<dyn serialport::SerialPort>, output: Output) -> io::Result<()> {
+    let mut buf = [0; 4096];
+    let mut deframer = Deframer::<MAX_ENCODED_LEN>::new();
+    loop {
+        let n = match port.read(&mut buf) {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e),
+        };
+        for &b in &buf[..n] {
+            let Some(frame) = deframer.push(b) else {
+                continue;
+            };
+            // The first message is likely the tail end of one we only saw
+            // half of, which fails to decode.
+            match Message::decode(frame) {
+                Ok(message) => output.handle(message)?,
+                Err(e) => eprintln!("Ignoring a bad message: {e:?}"),
+            }
+        }
+    }
+}
+
+struct Sender {
+    port: Box<dyn serialport::SerialPort>,
+    next_id: u16,
+}
+
+impl Sender {
+    /// Returns the ID the result will be reported with.
+    fn send(&mut self, dst: Mac, payload: &[u8]) -> io::Result<u16> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let mut encoded = [0; MAX_ENCODED_LEN];
+        let n = Message::Send { id, dst, payload }.encode(&mut encoded);
+        self.port.write_all(&encoded[..n])?;
+        Ok(id)
+    }
+}
+
+fn run_stdio(mut sender: Sender) -> io::Result<()> {
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (dst, payload) = line.split_once(' ').unwrap_or((line, ""));
+        let (Some(dst), Some(payload)) = (parse_mac(dst), parse_payload(payload.trim())) else {
+            eprintln!("Expected a MAC address and a payload, got {line:?}");
+            continue;
+        };
+        sender.send(dst, &payload)?;
+    }
+    Ok(())
+}
+
+fn run_udp(
+    mut sender: Sender,
+    socket: UdpSocket,
+    peer: Arc<Mutex<Option<SocketAddr>>>,
+    fixed_peer: bool,
+) -> io::Result<()> {
+    let mut buf = [0; 2048];
+    loop {
+        let (n, from) = socket.recv_from(&mut buf)?;
+        if !fixed_peer {
+            *peer.lock().unwrap() = Some(from);
+        }
+        let Some((dst, payload)) = buf[..n].split_first_chunk::<6>() else {
+            eprintln!("Ignoring a datagram of {n} bytes from {from}");
+            continue;
+        };
+        sender.send(*dst, payload)?;
+    }
+}
+
+fn run(args: &Args) -> io::Result<()> {
+    let port_name = args
+        .port
+        .as_deref()
+        .ok_or_else(|| io::Error::other("--port is required"))?;
+    // The baud rate is ignored by the USB-Serial-JTAG peripheral, but we
+    // still need to set one.
+    let port = serialport::new(port_name, 115_200)
+        .timeout(Duration::from_secs(3600))
+        .open()?;
+    let sender = Sender {
+        port: port.try_clone()?,
+        next_id: 0,
+    };
+
+    let (output, udp) = match &args.udp {
+        None => (Output::Stdout, None),
+        Some(addr) => {
+            let socket = UdpSocket::bind(addr)?;
+            let to = match &args.to {
+                Some(to) => Some(
+                    to.parse()
+                        .map_err(|e| io::Error::other(format!("--to: {e}")))?,
+                ),
+                None => None,
+            };
+            let peer = Arc::new(Mutex::new(to));
+            let output = Output::Udp {
+                socket: socket.try_clone()?,
+                peer: peer.clone(),
+            };
+            (output, Some((socket, peer)))
+        }
+    };
+
+    let reader = thread::spawn(move || read_port(port, output));
+    match udp {
+        None => run_stdio(sender)?,
+        Some((socket, peer)) => run_udp(sender, socket, peer, args.to.is_some())?,
+    }
+    // Stdin was closed, but replies may still be on their way
+    thread::sleep(Duration::from_millis(500));
+    if reader.is_finished() {
+        reader.join().unwrap()?;
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! Keys and manifests for the `esp-now-ota` ROM.
+//!
+//! Usage:
+//!   esp-now-ota keygen KEY_FILE
+//!   esp-now-ota sign --key KEY_FILE --version N IMAGE [--output FILE]
+//!
+//! `keygen` writes a new secret key, and prints the public key to build the
+//! ROM with, as `OTA_PUBLIC_KEY`.
+//!
+//! `sign` takes an image as written by `espflash save-image`, and writes the
+//! trailer that goes at the end of the slot it's flashed to, which is what
+//! lets a node running it hand it on. It defaults to IMAGE with `.trailer` appended.
+//!
+//! ```text
+//! espflash save-image --chip esp32c3 target/riscv32imc-unknown-none-elf/release/esp-now-ota ota.bin
+//! esp-now-ota sign --key ota.key --version 2 ota.bin
+//! espflash flash --partition-table roms/esp-now-ota/partitions.csv target/.../esp-now-ota
+//! espflash write-bin 0x1cf000 ota.bin.trailer
+//! ```
+
+use std::{fs, io::Read, process::ExitCode};
+
+use esp_now_stack::ota::{self, KEY_LEN, Manifest, SECTOR_LEN, Trailer};
+
+// See `roms/esp-now-ota/partitions.csv`. `espflash flash` writes to the
+// first slot.
+const SLOT_0_OFFSET: u32 = 0x10000;
+const SLOT_LEN: u32 = 0x1c0000;
+
+#[derive(Default)]
+struct SignArgs {
+    key: Option<String>,
+    version: Option<u32>,
+    image: Option<String>,
+    output: Option<String>,
+}
+
+fn parse_sign_args(args: impl Iterator<Item = String>) -> Result<SignArgs, String> {
+    let mut parsed = SignArgs::default();
+    let mut iter = args;
+    while let Some(arg) = iter.next() {
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| iter.next())
+                .ok_or_else(|| format!("{name} needs a value"))
+        };
+        match name.as_str() {
+            "--key" => parsed.key = Some(value()?),
+            "--version" => {
+                let v = value()?;
+                parsed.version = Some(v.parse().map_err(|_| format!("bad version {v}"))?);
+            }
+            "--output" => parsed.output = Some(value()?),
+            _ if name.starts_with("--") => return Err(format!("unknown argument {name}")),
+            _ if parsed.image.is_none() => parsed.image = Some(name),
+            _ => return Err(format!("unexpected argument {name}")),
+        }
+    }
+    Ok(parsed)
+}
+
+fn read_key(path: &str) -> Result<[u8; KEY_LEN], String> {
+    let key = fs::read(path).map_err(|e| format!("reading {path}: {e}"))?;
+    key.try_into()
+        .map_err(|_| format!("{path} is not a key, it should be {KEY_LEN} bytes"))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn keygen(path: &str) -> Result<(), String> {
+    let mut secret = [0; KEY_LEN];
+    fs::File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut secret))
+        .map_err(|e| format!("reading /dev/urandom: {e}"))?;
+    fs::write(path, secret).map_err(|e| format!("writing {path}: {e}"))?;
+    println!("Wrote the secret key to {path}, keep it to yourself");
+    println!("OTA_PUBLIC_KEY={}", hex(&ota::public_key(&secret)));
+    Ok(())
+}
+
+fn sign(args: SignArgs) -> Result<(), String> {
+    let key = read_key(&args.key.ok_or("--key is required")?)?;
+    let version = args.version.ok_or("--version is required")?;
+    let image_path = args.image.ok_or("the image is required")?;
+    let image = fs::read(&image_path).map_err(|e| format!("reading {image_path}: {e}"))?;
+
+    let capacity = SLOT_LEN - SECTOR_LEN;
+    if image.len() > capacity as usize {
+        return Err(format!(
+            "the image is {} bytes, but only {capacity} fit in a slot",
+            image.len()
+        ));
+    }
+
+    let manifest = Manifest::sign(version, &image, &key);
+    let output = args.output.unwrap_or(format!("{image_path}.trailer"));
+    fs::write(&output, Trailer::flashed(&manifest))
+        .map_err(|e| format!("writing {output}: {e}"))?;
+    println!(
+        "Version {version}, {} bytes, sha256 {}",
+        manifest.len,
+        hex(&manifest.digest)
+    );
+    println!(
+        "Wrote {output}, flash it after the image with: espflash write-bin {:#x} {output}",
+        SLOT_0_OFFSET + capacity
+    );
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let result = match args.next().as_deref() {
+        Some("keygen") => match (args.next(), args.next()) {
+            (Some(path), None) => keygen(&path),
+            _ => Err("keygen needs a file to write the key to".into()),
+        },
+        Some("sign") => parse_sign_args(args).and_then(sign),
+        _ => Err("usage: esp-now-ota keygen KEY_FILE | \
+                  sign --key KEY_FILE --version N IMAGE [--output FILE]"
+            .into()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Pushes `esp_now_stack::bridge` messages through a noisy serial line, and
+//! checks that what comes out the other end is exactly what went in, or
+//! nothing at all.
+
+use esp_now_stack::{
+    Mac,
+    bridge::{Deframer, MAX_ENCODED_LEN, Message, SendStatus},
+    header::MAX_FRAME_LEN,
+};
+use sim_rng::Rng;
+
+const MESSAGES: usize = 2000;
+
+fn bytes(rng: &mut Rng, len: usize) -> Vec<u8> {
+    (0..len).map(|_| rng.next_u64() as u8).collect()
+}
+
+fn mac(rng: &mut Rng) -> Mac {
+    bytes(rng, 6).try_into().unwrap()
+}
+
+/// The payload or text the `n`th message borrows.
+fn contents(rng: &mut Rng, n: usize) -> (Vec<u8>, String) {
+    let len = rng.range(0, MAX_FRAME_LEN as u64) as usize;
+    (bytes(rng, len), format!("log line {n}"))
+}
+
+fn message<'a>(rng: &mut Rng, (payload, text): &'a (Vec<u8>, String)) -> Message<'a> {
+    match rng.range(0, 3) {
+        0 => Message::Received {
+            src: mac(rng),
+            dst: mac(rng),
+            rssi: rng.next_u64() as i8,
+            payload,
+        },
+        1 => Message::SendResult {
+            id: rng.next_u64() as u16,
+            status: [
+                SendStatus::Sent,
+                SendStatus::Failed,
+                SendStatus::TooLong,
+                SendStatus::NoPeer,
+            ][rng.range(0, 3) as usize],
+        },
+        2 => Message::Log(text),
+        _ => Message::Send {
+            id: rng.next_u64() as u16,
+            dst: mac(rng),
+            payload,
+        },
+    }
+}
+
+pub fn run_scenario(seed: u64) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    let contents: Vec<_> = (0..MESSAGES).map(|n| contents(&mut rng, n)).collect();
+    let messages: Vec<_> = contents.iter().map(|c| message(&mut rng, c)).collect();
+
+    // Start with the tail end of a message, like after opening the port
+    let mut line: Vec<u8> = bytes(&mut rng, 37)
+        .into_iter()
+        .filter(|&b| b != 0)
+        .collect();
+    line.push(0);
+    let mut corrupted = Vec::new();
+    for m in &messages {
+        let mut encoded = [0; MAX_ENCODED_LEN];
+        let n = m.encode(&mut encoded);
+        let mut encoded = encoded[..n].to_vec();
+        let corrupt = rng.chance(0.1);
+        if corrupt {
+            let i = rng.range(0, n as u64 - 2) as usize;
+            encoded[i] ^= 1 << rng.range(0, 7);
+        }
+        corrupted.push(corrupt);
+        line.extend_from_slice(&encoded);
+    }
+
+    let mut deframer = Deframer::<MAX_ENCODED_LEN>::new();
+    let mut next = 0;
+    let (mut received, mut rejected) = (0, 0);
+    for b in line {
+        let Some(frame) = deframer.push(b) else {
+            continue;
+        };
+        let Ok(decoded) = Message::decode(frame) else {
+            rejected += 1;
+            continue;
+        };
+        // Messages only ever go missing
+        let Some(i) = messages[next..].iter().position(|m| *m == decoded) else {
+            return Err(format!("decoded a message that wasn't sent: {decoded:?}"));
+        };
+        if let Some(lost) = (next..next + i).find(|&j| !corrupted[j]) {
+            return Err(format!("intact message {lost} was lost"));
+        }
+        if corrupted[next + i] {
+            return Err(format!("corrupted message {} was accepted", next + i));
+        }
+        next += i + 1;
+        received += 1;
+    }
+    if let Some(lost) = (next..messages.len()).find(|&j| !corrupted[j]) {
+        return Err(format!("intact message {lost} was lost"));
+    }
+    println!("  {received} messages made it, {rejected} bad frames were rejected");
+    Ok(())
+}
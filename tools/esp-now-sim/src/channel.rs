@@ -0,0 +1,226 @@
+//! Runs `esp_now_stack::channel` together with discovery on nodes that only
+//! hear each other when they are on the same channel, and checks that they
+//! end up on a common one: the one of a node whose AP dictates it when
+//! there is one, otherwise wherever they meet.
+
+use esp_now_stack::{
+    Mac,
+    channel::{CHANNELS, ChannelManager, Config, State},
+    discovery::{Announcement, Capabilities, Discovery},
+};
+use sim_rng::Rng;
+
+const HELLO_INTERVAL_MS: u64 = 1000;
+const MAX_AGE_MS: u64 = 5 * HELLO_INTERVAL_MS;
+const LOSS: f64 = 0.05;
+/// Long enough for everybody to have swept once or twice.
+const SETTLE_MS: u64 = 75_000;
+
+struct Node {
+    mac: Mac,
+    channels: ChannelManager,
+    discovery: Discovery<8>,
+    next_hello_ms: u64,
+}
+
+struct Frame {
+    at_ms: u64,
+    from: usize,
+    channel: u8,
+    payload: Vec<u8>,
+}
+
+struct Sim {
+    rng: Rng,
+    nodes: Vec<Node>,
+    now_ms: u64,
+    in_flight: Vec<Frame>,
+}
+
+impl Sim {
+    fn new(seed: u64) -> Self {
+        Self {
+            rng: Rng::new(seed),
+            nodes: Vec::new(),
+            now_ms: 0,
+            in_flight: Vec::new(),
+        }
+    }
+
+    /// Adds a node that either stays on `fixed`, or starts hopping from a
+    /// random channel.
+    fn add(&mut self, fixed: Option<u8>) -> usize {
+        let i = self.nodes.len();
+        let channel = fixed.unwrap_or_else(|| self.rng.range(1, *CHANNELS.end() as u64) as u8);
+        let name = format!("node {i}");
+        self.nodes.push(Node {
+            mac: [0x02, 0, 0, 0, 0, i as u8],
+            channels: ChannelManager::new(Config::default(), self.now_ms, channel, fixed.is_some()),
+            discovery: Discovery::new(
+                Announcement::new(&name, Capabilities::default()),
+                MAX_AGE_MS,
+            ),
+            next_hello_ms: self.now_ms,
+        });
+        i
+    }
+
+    fn send(&mut self, from: usize, payload: Vec<u8>) {
+        let channel = self.nodes[from].channels.channel();
+        let at_ms = self.now_ms + self.rng.range(1, 3);
+        self.in_flight.push(Frame {
+            at_ms,
+            from,
+            channel,
+            payload,
+        });
+    }
+
+    fn hello(&mut self, i: usize) {
+        let node = &mut self.nodes[i];
+        node.discovery.set_channel(node.channels.info());
+        let hello = node.discovery.hello().to_vec();
+        node.next_hello_ms = self.now_ms + HELLO_INTERVAL_MS + self.rng.range(0, 500);
+        self.send(i, hello);
+    }
+
+    fn run_until(&mut self, until_ms: u64) {
+        while self.now_ms < until_ms {
+            self.now_ms += 1;
+            for i in 0..self.nodes.len() {
+                let random = self.rng.next_u64() as u32;
+                let node = &mut self.nodes[i];
+                let moved = node.channels.poll(self.now_ms, random).is_some();
+                if moved || self.now_ms >= node.next_hello_ms {
+                    self.hello(i);
+                }
+            }
+
+            let (arrived, rest) = self
+                .in_flight
+                .drain(..)
+                .partition(|f| f.at_ms <= self.now_ms);
+            self.in_flight = rest;
+            for frame in arrived {
+                for to in 0..self.nodes.len() {
+                    if to == frame.from
+                        || self.nodes[to].channels.channel() != frame.channel
+                        || self.rng.chance(LOSS)
+                    {
+                        continue;
+                    }
+                    self.receive(to, &frame);
+                }
+            }
+        }
+    }
+
+    fn receive(&mut self, to: usize, frame: &Frame) {
+        let src = self.nodes[frame.from].mac;
+        let node = &mut self.nodes[to];
+        node.discovery.set_channel(node.channels.info());
+        let Some(handled) = node.discovery.handle(self.now_ms, src, -50, &frame.payload) else {
+            return;
+        };
+        let theirs = node
+            .discovery
+            .table()
+            .get(&src)
+            .unwrap()
+            .announcement
+            .channel;
+        if node.channels.heard(self.now_ms, theirs).is_some() {
+            self.hello(to);
+        } else if let Some(reply) = handled.reply {
+            self.send(to, reply.to_vec());
+        }
+    }
+
+    /// Checks that every node is on `channel`, and whether the ones that
+    /// can move are anchored to a node that can't.
+    fn check(&self, channel: u8, anchored: bool) -> Result<(), String> {
+        for (i, node) in self.nodes.iter().enumerate() {
+            let info = node.channels.info();
+            if info.number != channel {
+                return Err(format!(
+                    "node {i} is on channel {} rather than {channel}, {:?}",
+                    info.number,
+                    node.channels.state()
+                ));
+            }
+            let ok = match node.channels.state() {
+                State::Fixed => true,
+                State::Settled { anchored: a } => a == anchored,
+                State::Sweeping { .. } => !anchored,
+                State::Hopping => false,
+            };
+            if !ok {
+                return Err(format!("node {i} is {:?}", node.channels.state()));
+            }
+        }
+        Ok(())
+    }
+
+    /// The channel most nodes are on.
+    fn common_channel(&self) -> u8 {
+        CHANNELS
+            .max_by_key(|&c| {
+                self.nodes
+                    .iter()
+                    .filter(|n| n.channels.info().number == c)
+                    .count()
+            })
+            .unwrap()
+    }
+}
+
+/// The others find a node whose AP dictates the channel, and follow it when
+/// the AP moves. Once it loses the AP, nothing is fixed any more, and they
+/// stay together where they are.
+fn station(seed: u64) -> Result<(), String> {
+    let mut sim = Sim::new(seed);
+    sim.add(Some(6));
+    for _ in 0..4 {
+        sim.add(None);
+    }
+    // Nodes that can move may meet each other first, and only find the
+    // station on their next sweep
+    sim.run_until(SETTLE_MS);
+    sim.check(6, true)?;
+
+    let now = sim.now_ms;
+    sim.nodes[0].channels.set_fixed(now, Some(11));
+    sim.run_until(now + SETTLE_MS);
+    sim.check(11, true)?;
+
+    let now = sim.now_ms;
+    sim.nodes[0].channels.set_fixed(now, None);
+    sim.run_until(now + SETTLE_MS);
+    let common = sim.common_channel();
+    sim.check(common, false)
+}
+
+/// Without a station, nodes meet somewhere, and move to a station that shows
+/// up later on another channel.
+fn no_station(seed: u64) -> Result<(), String> {
+    let mut sim = Sim::new(seed);
+    for _ in 0..4 {
+        sim.add(None);
+    }
+    sim.run_until(SETTLE_MS);
+    let common = sim.common_channel();
+    sim.check(common, false)?;
+
+    let channel = if common == 1 { 13 } else { common - 1 };
+    sim.add(Some(channel));
+    let now = sim.now_ms;
+    sim.run_until(now + SETTLE_MS);
+    sim.check(channel, true)
+}
+
+pub fn run_scenario(seed: u64) -> Result<(), String> {
+    station(seed)?;
+    no_station(seed)?;
+    println!("  nodes find each other, and the channel of a station wins");
+    Ok(())
+}
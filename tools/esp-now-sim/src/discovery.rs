@@ -0,0 +1,197 @@
+//! Checks `esp_now_stack::discovery`: the peer table on its own, and a few
+//! nodes finding each other over a lossy link and forgetting the ones that
+//! go quiet.
+
+use esp_now_stack::{
+    Mac,
+    discovery::{
+        self, Announcement, Capabilities, Discovery, MAX_NAME_LEN, MessageKind, PeerTable, Update,
+    },
+};
+use sim_rng::Rng;
+
+use crate::link::{Air, LinkQuality};
+
+const HELLO_INTERVAL_MS: u64 = 1000;
+const MAX_AGE_MS: u64 = 3 * HELLO_INTERVAL_MS + 500;
+
+fn announcement(name: &str) -> Announcement {
+    Announcement::new(name, Capabilities::RELIABLE)
+}
+
+fn check_messages() -> Result<(), String> {
+    let caps = Capabilities::RELIABLE
+        .union(Capabilities::PAIRING)
+        .union(Capabilities(0x8000));
+    let own = Announcement::new("kitchen", caps);
+    let encoded = discovery::encode(MessageKind::HelloAck, &own);
+    if discovery::decode(&encoded) != Some((MessageKind::HelloAck, own)) {
+        return Err("announcement did not survive a round trip".into());
+    }
+    if !caps.contains(Capabilities::PAIRING) || caps.contains(Capabilities::FRAGMENTS) {
+        return Err("wrong capabilities".into());
+    }
+
+    // Long names are cut off on a character boundary
+    let long = Announcement::new("ääääääääääääääääää", Capabilities::default());
+    if long.name.len() != MAX_NAME_LEN || long.name.chars().count() != MAX_NAME_LEN / 2 {
+        return Err(format!("long name cut off wrong: {:?}", long.name));
+    }
+
+    let mut truncated = encoded.clone();
+    truncated.pop();
+    for bad in [&truncated[..], &encoded[..7], b"DISC\x07\0\0\0", b"PAIR"] {
+        if discovery::decode(bad).is_some() {
+            return Err(format!("malformed message {bad:?} was accepted"));
+        }
+    }
+    Ok(())
+}
+
+fn check_table() -> Result<(), String> {
+    let mut table = PeerTable::<3>::new(MAX_AGE_MS);
+    for i in 0..3 {
+        if table.update(i * 100, [i as u8; 6], -40, announcement("a"))
+            != (Update::New { evicted: None })
+        {
+            return Err("new peer not reported as new".into());
+        }
+    }
+    // Hearing from the first one again keeps it from being evicted
+    if table.update(500, [0; 6], -50, announcement("renamed")) != Update::Known {
+        return Err("known peer reported as new".into());
+    }
+    match table.update(600, [3; 6], -60, announcement("d")) {
+        Update::New { evicted: Some(mac) } if mac == [1; 6] => {}
+        other => return Err(format!("expected [1; 6] to be evicted, got {other:?}")),
+    }
+    let first = table.get(&[0; 6]).unwrap();
+    if first.rssi != -50 || first.announcement.name != "renamed" || first.first_seen_ms != 0 {
+        return Err("peer not updated".into());
+    }
+
+    // [2; 6] was last seen at 200
+    if table.next_expiry() != Some(200 + MAX_AGE_MS) {
+        return Err("wrong expiry".into());
+    }
+    if table.expire(200 + MAX_AGE_MS - 1).is_some() {
+        return Err("peer expired early".into());
+    }
+    if !table.touch(700, &[2; 6], -70) || table.touch(700, &[9; 6], -70) {
+        return Err("touch did not find the right peers".into());
+    }
+    let now = 600 + MAX_AGE_MS;
+    let mut expired = Vec::new();
+    while let Some(peer) = table.expire(now) {
+        expired.push(peer.mac);
+    }
+    let left: Vec<_> = table.peers().map(|p| p.mac).collect();
+    if expired != [[0; 6], [3; 6]] || left != [[2; 6]] {
+        return Err(format!("wrong peers expired: {expired:x?}"));
+    }
+    Ok(())
+}
+
+struct Node {
+    mac: Mac,
+    discovery: Discovery<8>,
+    online: bool,
+}
+
+/// Runs the nodes until `until_ms`, and returns the number of acks sent.
+fn run(air: &mut Air, nodes: &mut [Node], from_ms: u64, until_ms: u64) -> u64 {
+    let mut acks = 0;
+    for now_ms in from_ms..until_ms {
+        for i in 0..nodes.len() {
+            let node = &nodes[i];
+            if node.online && (now_ms + i as u64 * 37).is_multiple_of(HELLO_INTERVAL_MS) {
+                let hello = node.discovery.hello();
+                for other in nodes.iter().filter(|n| n.mac != node.mac) {
+                    air.transmit(now_ms, node.mac, other.mac, &hello);
+                }
+            }
+        }
+        while let Some(d) = air.receive(now_ms) {
+            let node = nodes.iter_mut().find(|n| n.mac == d.dst).unwrap();
+            if !node.online {
+                continue;
+            }
+            let handled = node.discovery.handle(now_ms, d.src, -40, &d.frame).unwrap();
+            if let Some(reply) = handled.reply {
+                acks += 1;
+                air.transmit(now_ms, d.dst, d.src, &reply);
+            }
+        }
+        for node in nodes.iter_mut() {
+            while node.discovery.table_mut().expire(now_ms).is_some() {}
+        }
+    }
+    acks
+}
+
+fn known(node: &Node) -> Vec<Mac> {
+    let mut macs: Vec<_> = node.discovery.table().peers().map(|p| p.mac).collect();
+    macs.sort();
+    macs
+}
+
+fn check_network(rng: &mut Rng) -> Result<(), String> {
+    let quality = LinkQuality {
+        loss: 0.3,
+        duplicate: 0.05,
+        min_delay_ms: 1,
+        max_delay_ms: 8,
+    };
+    let mut air = Air::new(quality, rng.next_u64());
+    let mut nodes: Vec<_> = (0..4u8)
+        .map(|i| Node {
+            mac: [0x20 + i; 6],
+            discovery: Discovery::new(announcement(&format!("node {i}")), MAX_AGE_MS),
+            online: true,
+        })
+        .collect();
+    let all: Vec<_> = nodes.iter().map(|n| n.mac).collect();
+
+    let acks = run(&mut air, &mut nodes, 0, 10_000);
+    if acks == 0 {
+        return Err("nobody answered a hello".into());
+    }
+    for node in &nodes {
+        let others: Vec<_> = all.iter().copied().filter(|m| *m != node.mac).collect();
+        if known(node) != others {
+            return Err(format!("{:x?} only knows {:x?}", node.mac, known(node)));
+        }
+        let peer = node.discovery.table().get(&others[0]).unwrap();
+        if peer.announcement.name.as_str() != format!("node {}", others[0][0] - 0x20) {
+            return Err("peer has the wrong name".into());
+        }
+    }
+
+    // A node goes quiet and is forgotten by everybody else
+    nodes[3].online = false;
+    run(
+        &mut air,
+        &mut nodes,
+        10_000,
+        10_000 + MAX_AGE_MS + HELLO_INTERVAL_MS,
+    );
+    let gone = nodes[3].mac;
+    for node in &nodes[..3] {
+        if known(node).contains(&gone) {
+            return Err("a node that went quiet was not forgotten".into());
+        }
+        if known(node).len() != 2 {
+            return Err("a node that is still around was forgotten".into());
+        }
+    }
+    Ok(())
+}
+
+pub fn run_scenario(seed: u64) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    check_messages()?;
+    check_table()?;
+    check_network(&mut rng)?;
+    println!("  peers found, updated, evicted when full and forgotten when quiet");
+    Ok(())
+}
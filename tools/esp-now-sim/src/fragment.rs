@@ -0,0 +1,186 @@
+//! Feeds fragments to `esp_now_stack::fragment::Reassembler` shuffled,
+//! duplicated, dropped and interleaved between sources, and checks that
+//! complete messages come out intact and incomplete ones are reported.
+
+use esp_now_stack::fragment::{
+    self, FRAGMENT_DATA_LEN, IncompleteReason, Outcome, Reassembler, Rejected,
+};
+use sim_rng::Rng;
+
+const TIMEOUT_MS: u64 = 500;
+const MAX_LEN: usize = 4096;
+
+type Fragments = Vec<heapless::Vec<u8, { esp_now_stack::header::MAX_PAYLOAD_LEN }>>;
+
+fn message(rng: &mut Rng, len: usize) -> Vec<u8> {
+    (0..len).map(|_| rng.next_u64() as u8).collect()
+}
+
+fn split(msg_id: u16, data: &[u8]) -> Fragments {
+    fragment::split(msg_id, data).unwrap().collect()
+}
+
+fn shuffle<T>(rng: &mut Rng, items: &mut [T]) {
+    for i in (1..items.len()).rev() {
+        items.swap(i, rng.range(0, i as u64) as usize);
+    }
+}
+
+/// Every fragment arrives, in any order and some of them twice.
+fn out_of_order(rng: &mut Rng) -> Result<(), String> {
+    let mut reassembler = Reassembler::<2, MAX_LEN>::new(TIMEOUT_MS);
+    for (msg_id, len) in [
+        0,
+        1,
+        FRAGMENT_DATA_LEN,
+        FRAGMENT_DATA_LEN + 1,
+        1000,
+        MAX_LEN,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let data = message(rng, len);
+        let mut fragments = split(msg_id as u16, &data);
+        let duplicates: Vec<_> = fragments
+            .iter()
+            .filter(|_| rng.chance(0.3))
+            .cloned()
+            .collect();
+        fragments.extend(duplicates);
+        shuffle(rng, &mut fragments);
+
+        let mut complete = None;
+        for (i, f) in fragments.iter().enumerate() {
+            match reassembler.handle(i as u64, [1; 6], f) {
+                Outcome::Complete { data, .. } if complete.is_none() => {
+                    complete = Some(data.to_vec());
+                }
+                Outcome::Complete { .. } => return Err("message completed twice".into()),
+                Outcome::Rejected(r) => return Err(format!("fragment rejected: {r:?}")),
+                Outcome::Pending | Outcome::Duplicate => (),
+            }
+        }
+        if complete.as_ref() != Some(&data) {
+            return Err(format!("{len} byte message came out wrong"));
+        }
+    }
+    if let Some(incomplete) = reassembler.take_incomplete() {
+        return Err(format!("unexpected {incomplete:?}"));
+    }
+    Ok(())
+}
+
+/// A fragment goes missing, and the message is reported once it times out.
+fn missing(rng: &mut Rng) -> Result<(), String> {
+    let mut reassembler = Reassembler::<2, MAX_LEN>::new(TIMEOUT_MS);
+    let data = message(rng, 2000);
+    let mut fragments = split(7, &data);
+    let count = fragments.len() as u8;
+    fragments.remove(rng.range(0, count as u64 - 1) as usize);
+
+    for f in &fragments {
+        if reassembler.handle(100, [1; 6], f) != Outcome::Pending {
+            return Err("incomplete message was not pending".into());
+        }
+    }
+    reassembler.expire(100 + TIMEOUT_MS - 1);
+    if reassembler.take_incomplete().is_some() {
+        return Err("message timed out early".into());
+    }
+    if reassembler.next_deadline() != Some(100 + TIMEOUT_MS) {
+        return Err("wrong deadline".into());
+    }
+    reassembler.expire(100 + TIMEOUT_MS);
+    match reassembler.take_incomplete() {
+        Some(i)
+            if i.msg_id == 7
+                && i.received == count - 1
+                && i.count == count
+                && i.reason == IncompleteReason::TimedOut => {}
+        other => return Err(format!("expected a timed out message, got {other:?}")),
+    }
+    if reassembler.next_deadline().is_some() {
+        return Err("nothing left to time out, but there is a deadline".into());
+    }
+    Ok(())
+}
+
+/// Messages from several sources arrive interleaved, with more sources than
+/// buffers and a source that gives up halfway to send something else.
+fn interleaved(rng: &mut Rng) -> Result<(), String> {
+    let mut reassembler = Reassembler::<2, MAX_LEN>::new(TIMEOUT_MS);
+    let a = message(rng, 1000);
+    let b = message(rng, 1000);
+    let fa = split(1, &a);
+    let fb = split(2, &b);
+    for (x, y) in fa.iter().zip(&fb).take(fa.len() - 1) {
+        reassembler.handle(0, [0xa; 6], x);
+        reassembler.handle(0, [0xb; 6], y);
+    }
+
+    // A third source needs a buffer, and gets the one of the source we heard
+    // from longest ago
+    let c = message(rng, 300);
+    for f in split(3, &c) {
+        reassembler.handle(1, [0xc; 6], &f);
+    }
+    match reassembler.take_incomplete() {
+        Some(i) if i.src == [0xa; 6] && i.reason == IncompleteReason::Evicted => {}
+        other => return Err(format!("expected source A to be evicted, got {other:?}")),
+    }
+
+    // B finishes its message
+    match reassembler.handle(2, [0xb; 6], fb.last().unwrap()) {
+        Outcome::Complete { data, .. } if data == b => {}
+        other => return Err(format!("expected B's message, got {other:?}")),
+    }
+    // A late duplicate is not mistaken for the start of a new message
+    if reassembler.handle(3, [0xb; 6], &fb[0]) != Outcome::Duplicate {
+        return Err("late duplicate not detected".into());
+    }
+
+    // C gives up on a message halfway and starts on another one
+    let c2 = message(rng, 300);
+    reassembler.handle(4, [0xc; 6], &split(4, &c2)[0]);
+    reassembler.handle(5, [0xc; 6], &split(5, &c2)[0]);
+    match reassembler.take_incomplete() {
+        Some(i) if i.msg_id == 4 && i.reason == IncompleteReason::Superseded => {}
+        other => {
+            return Err(format!(
+                "expected C's message to be superseded, got {other:?}"
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn rejected() -> Result<(), String> {
+    let mut reassembler = Reassembler::<1, 1000>::new(TIMEOUT_MS);
+    let large = vec![0; 1001];
+    if reassembler.handle(0, [1; 6], &split(0, &large)[0]) != Outcome::Rejected(Rejected::TooLarge)
+    {
+        return Err("message larger than the buffer not rejected".into());
+    }
+    let mut truncated = split(1, &[0; 600])[0].clone();
+    truncated.pop();
+    if reassembler.handle(0, [1; 6], &truncated) != Outcome::Rejected(Rejected::Malformed) {
+        return Err("truncated fragment not rejected".into());
+    }
+    if fragment::split(0, &vec![0; fragment::MAX_MESSAGE_LEN + 1]).is_ok() {
+        return Err("message too large to fragment was split".into());
+    }
+    Ok(())
+}
+
+pub fn run_scenario(seed: u64) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    for _ in 0..20 {
+        out_of_order(&mut rng)?;
+        missing(&mut rng)?;
+        interleaved(&mut rng)?;
+    }
+    rejected()?;
+    println!("  out of order, missing, interleaved and rejected fragments handled");
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use esp_now_stack::Mac;
+use sim_rng::Rng;
+
+/// How bad the simulated radio is.
+#[derive(Copy, Clone, Debug)]
+pub struct LinkQuality {
+    /// Probability of a frame getting lost.
+    pub loss: f64,
+    /// Probability of a frame arriving twice.
+    pub duplicate: f64,
+    /// Frames take between `min_delay_ms` and `max_delay_ms` to arrive, so
+    /// they can overtake each other.
+    pub min_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Delivery {
+    pub at_ms: u64,
+    /// Keeps frames sent at the same time in order.
+    order: u64,
+    pub src: Mac,
+    pub dst: Mac,
+    pub frame: Vec<u8>,
+}
+
+/// Frames in the air, waiting to be delivered.
+pub struct Air {
+    quality: LinkQuality,
+    rng: Rng,
+    queue: BinaryHeap<Reverse<Delivery>>,
+    sent: u64,
+}
+
+impl Air {
+    pub fn new(quality: LinkQuality, seed: u64) -> Self {
+        Self {
+            quality,
+            rng: Rng::new(seed),
+            queue: BinaryHeap::new(),
+            sent: 0,
+        }
+    }
+
+    /// The number of frames handed to `transmit` so far.
+    pub fn sent(&self) -> u64 {
+        self.sent
+    }
+
+    /// Returns false if the frame got lost, which the sender of a unicast
+    /// frame would find out from the missing acknowledgement.
+    pub fn transmit(&mut self, now_ms: u64, src: Mac, dst: Mac, frame: &[u8]) -> bool {
+        self.sent += 1;
+        if self.rng.chance(self.quality.loss) {
+            return false;
+        }
+        let copies = if self.rng.chance(self.quality.duplicate) {
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let delay = self
+                .rng
+                .range(self.quality.min_delay_ms, self.quality.max_delay_ms);
+            self.queue.push(Reverse(Delivery {
+                at_ms: now_ms + delay,
+                order: self.sent,
+                src,
+                dst,
+                frame: frame.to_vec(),
+            }));
+        }
+        true
+    }
+
+    /// When the next frame arrives.
+    pub fn next_at(&self) -> Option<u64> {
+        self.queue.peek().map(|Reverse(d)| d.at_ms)
+    }
+
+    /// Takes the next frame that has arrived by `now_ms`.
+    pub fn receive(&mut self, now_ms: u64) -> Option<Delivery> {
+        if self.next_at()? > now_ms {
+            return None;
+        }
+        self.queue.pop().map(|Reverse(d)| d)
+    }
+}
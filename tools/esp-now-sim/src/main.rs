@@ -0,0 +1,83 @@
+//! Runs the protocols from `libs/esp-now-stack` on the host against a
+//! simulated radio, and checks that they behave.
+//!
+//! Usage: esp-now-sim [--seed N] [SCENARIO..]
+//!
+//! Without any scenarios, all of them are run. The exit code tells if they
+//! all passed.
+
+mod bridge;
+mod channel;
+mod discovery;
+mod fragment;
+mod link;
+mod mesh;
+mod messages;
+mod ota;
+mod pairing;
+mod proximity;
+mod reliable;
+mod remote;
+mod timesync;
+
+use std::process::ExitCode;
+
+type Scenario = fn(u64) -> Result<(), String>;
+
+const SCENARIOS: &[(&str, Scenario)] = &[
+    ("reliable", reliable::run_scenario),
+    ("fragment", fragment::run_scenario),
+    ("pairing", pairing::run_scenario),
+    ("discovery", discovery::run_scenario),
+    ("mesh", mesh::run_scenario),
+    ("bridge", bridge::run_scenario),
+    ("timesync", timesync::run_scenario),
+    ("channel", channel::run_scenario),
+    ("ota", ota::run_scenario),
+    ("remote", remote::run_scenario),
+    ("messages", messages::run_scenario),
+    ("proximity", proximity::run_scenario),
+];
+
+fn main() -> ExitCode {
+    let mut seed = 1;
+    let mut selected = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--seed" {
+            match args.next().and_then(|s| s.parse().ok()) {
+                Some(s) => seed = s,
+                None => {
+                    eprintln!("Error: --seed needs a number");
+                    return ExitCode::FAILURE;
+                }
+            }
+        } else if SCENARIOS.iter().any(|(name, _)| *name == arg) {
+            selected.push(arg);
+        } else {
+            eprintln!("Error: unknown scenario {arg}");
+            return ExitCode::FAILURE;
+        }
+    }
+
+    let mut failed = false;
+    for (name, scenario) in SCENARIOS {
+        if !selected.is_empty() && !selected.iter().any(|s| s == name) {
+            continue;
+        }
+        println!("{name}:");
+        match scenario(seed) {
+            Ok(()) => println!("{name}: ok"),
+            Err(e) => {
+                println!("{name}: FAILED: {e}");
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
@@ -0,0 +1,364 @@
+//! A discrete-event simulation of `esp_now_stack::mesh` on a virtual
+//! topology: nodes are placed on a grid and can only hear their direct
+//! neighbours. Time jumps from one event to the next, either a frame
+//! arriving or a router wanting to be polled.
+
+use std::collections::HashMap;
+
+use esp_now_stack::{
+    Mac,
+    header::MAX_PAYLOAD_LEN,
+    mesh::{BROADCAST, Config, Dropped, Event, Router},
+};
+use sim_rng::Rng;
+
+use crate::link::{Air, LinkQuality};
+
+/// Unicast hops are sent with `reliable`, which tries this many times.
+const HOP_ATTEMPTS: usize = 6;
+
+struct Node {
+    mac: Mac,
+    pos: (i32, i32),
+    router: Router<32, 64>,
+    up: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Delivery {
+    at: usize,
+    origin: Mac,
+    msg_id: u16,
+    hops: u8,
+}
+
+struct Sim {
+    air: Air,
+    nodes: Vec<Node>,
+    now_ms: u64,
+    delivered: Vec<Delivery>,
+    dropped: Vec<Dropped>,
+}
+
+fn mac(i: usize) -> Mac {
+    [0x02, 0, 0, 0, (i >> 8) as u8, i as u8]
+}
+
+impl Sim {
+    /// Nodes at `positions`, each hearing the ones at most one step away
+    /// horizontally or vertically. The first one is the gateway.
+    fn new(rng: &mut Rng, positions: &[(i32, i32)], loss: f64, config: Config) -> Self {
+        let quality = LinkQuality {
+            loss,
+            duplicate: 0.02,
+            min_delay_ms: 1,
+            max_delay_ms: 8,
+        };
+        let nodes = positions
+            .iter()
+            .enumerate()
+            .map(|(i, &pos)| Node {
+                mac: mac(i),
+                pos,
+                router: Router::new(
+                    mac(i),
+                    Config {
+                        gateway: i == 0,
+                        ..config
+                    },
+                    rng.next_u64() as u16,
+                ),
+                up: true,
+            })
+            .collect();
+        Self {
+            air: Air::new(quality, rng.next_u64()),
+            nodes,
+            now_ms: 0,
+            delivered: Vec::new(),
+            dropped: Vec::new(),
+        }
+    }
+
+    fn index(&self, mac: &Mac) -> Option<usize> {
+        self.nodes.iter().position(|n| n.mac == *mac)
+    }
+
+    fn in_range(&self, a: usize, b: usize) -> bool {
+        let (pa, pb) = (self.nodes[a].pos, self.nodes[b].pos);
+        a != b && (pa.0 - pb.0).abs() + (pa.1 - pb.1).abs() <= 1
+    }
+
+    /// Sends a frame from node `from` to `next_hop`, or to every neighbour
+    /// for [`BROADCAST`].
+    fn transmit(&mut self, from: usize, next_hop: Mac, frame: &[u8]) {
+        let src = self.nodes[from].mac;
+        if next_hop == BROADCAST {
+            for to in 0..self.nodes.len() {
+                if self.in_range(from, to) {
+                    let dst = self.nodes[to].mac;
+                    self.air.transmit(self.now_ms, src, dst, frame);
+                }
+            }
+            return;
+        }
+        let reachable = self
+            .index(&next_hop)
+            .is_some_and(|to| self.in_range(from, to) && self.nodes[to].up);
+        if reachable
+            && (0..HOP_ATTEMPTS).any(|_| self.air.transmit(self.now_ms, src, next_hop, frame))
+        {
+            return;
+        }
+        self.nodes[from].router.link_failed(self.now_ms, &next_hop);
+    }
+
+    fn send(&mut self, from: usize, dst: Option<Mac>, payload: &[u8]) -> bool {
+        let mut out = [0; MAX_PAYLOAD_LEN];
+        let router = &mut self.nodes[from].router;
+        let sent = match dst {
+            Some(BROADCAST) => router
+                .broadcast(payload, &mut out)
+                .map(|(_, len)| (BROADCAST, len)),
+            Some(dst) => router.send(dst, payload, &mut out),
+            None => router.send_to_gateway(payload, &mut out),
+        };
+        match sent {
+            Ok((next_hop, len)) => {
+                self.transmit(from, next_hop, &out[..len]);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn run_until(&mut self, until_ms: u64) {
+        let mut out = [0; MAX_PAYLOAD_LEN];
+        loop {
+            let next_poll = self
+                .nodes
+                .iter()
+                .filter(|n| n.up)
+                .map(|n| n.router.next_deadline())
+                .min();
+            let next = match (self.air.next_at(), next_poll) {
+                (Some(a), Some(b)) => a.min(b),
+                (a, b) => a.or(b).unwrap_or(u64::MAX),
+            };
+            if next > until_ms {
+                self.now_ms = until_ms;
+                return;
+            }
+            self.now_ms = self.now_ms.max(next);
+
+            for i in 0..self.nodes.len() {
+                if !self.nodes[i].up {
+                    continue;
+                }
+                while let Some(len) = self.nodes[i].router.poll(self.now_ms, &mut out) {
+                    self.transmit(i, BROADCAST, &out[..len]);
+                }
+            }
+            while let Some(d) = self.air.receive(self.now_ms) {
+                let Some(i) = self.index(&d.dst).filter(|&i| self.nodes[i].up) else {
+                    continue;
+                };
+                let handled = self.nodes[i]
+                    .router
+                    .handle(self.now_ms, d.src, &d.frame, &mut out);
+                match handled.event {
+                    Event::Delivered {
+                        origin,
+                        msg_id,
+                        hops,
+                        ..
+                    } => self.delivered.push(Delivery {
+                        at: i,
+                        origin,
+                        msg_id,
+                        hops,
+                    }),
+                    Event::Dropped(reason) => self.dropped.push(reason),
+                    _ => (),
+                }
+                if let Some(forward) = handled.forward {
+                    let frame = out[..forward.len].to_vec();
+                    self.transmit(i, forward.next_hop, &frame);
+                }
+            }
+        }
+    }
+
+    fn gateway_hops(&self, i: usize) -> Option<u8> {
+        self.nodes[i].router.gateway().map(|r| r.hops)
+    }
+}
+
+fn line(len: i32) -> Vec<(i32, i32)> {
+    (0..len).map(|x| (x, 0)).collect()
+}
+
+fn grid(size: i32) -> Vec<(i32, i32)> {
+    (0..size * size).map(|i| (i % size, i / size)).collect()
+}
+
+fn distance(a: (i32, i32), b: (i32, i32)) -> u8 {
+    ((a.0 - b.0).abs() + (a.1 - b.1).abs()) as u8
+}
+
+/// Every node finds the shortest way to the gateway, and what it sends
+/// there arrives once.
+fn converge(rng: &mut Rng, positions: &[(i32, i32)], loss: f64) -> Result<Sim, String> {
+    let mut sim = Sim::new(rng, positions, loss, Config::default());
+    sim.run_until(30_000);
+    for (i, &pos) in positions.iter().enumerate().skip(1) {
+        let expected = distance(pos, positions[0]);
+        if sim.gateway_hops(i) != Some(expected) {
+            return Err(format!(
+                "node at {pos:?} has {:?} hops to the gateway, expected {expected}",
+                sim.gateway_hops(i)
+            ));
+        }
+    }
+
+    for i in 1..positions.len() {
+        if !sim.send(i, None, b"reading") {
+            return Err(format!("node {i} could not send to the gateway"));
+        }
+    }
+    sim.run_until(31_000);
+    let mut at_gateway: Vec<_> = sim.delivered.iter().filter(|d| d.at == 0).collect();
+    at_gateway.sort_by_key(|d| d.origin);
+    if at_gateway.len() != positions.len() - 1 {
+        return Err(format!(
+            "{} of {} messages reached the gateway",
+            at_gateway.len(),
+            positions.len() - 1
+        ));
+    }
+    for d in at_gateway {
+        let i = sim.index(&d.origin).unwrap();
+        if d.hops != distance(positions[i], positions[0]) {
+            return Err(format!("message from node {i} took {} hops", d.hops));
+        }
+    }
+    Ok(sim)
+}
+
+/// Floods reach nearly every node despite the loss, each exactly once.
+fn flood(rng: &mut Rng) -> Result<(), String> {
+    let positions = grid(5);
+    let mut sim = Sim::new(rng, &positions, 0.1, Config::default());
+    let floods = 20;
+    let from = positions.len() - 1;
+    for n in 0..floods {
+        sim.send(from, Some(BROADCAST), b"alarm");
+        sim.run_until((n + 1) * 200);
+    }
+
+    let mut counts = HashMap::new();
+    for d in &sim.delivered {
+        *counts.entry((d.at, d.msg_id)).or_insert(0) += 1;
+    }
+    if counts.values().any(|&c| c > 1) {
+        return Err("a flooded message was delivered twice".into());
+    }
+    if counts.keys().any(|&(at, _)| at == from) {
+        return Err("a node received its own flood".into());
+    }
+    let expected = (positions.len() - 1) * floods as usize;
+    if counts.len() * 100 < expected * 90 {
+        return Err(format!(
+            "floods reached {} of {expected} nodes",
+            counts.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Messages don't go further than their TTL.
+fn ttl(rng: &mut Rng) -> Result<(), String> {
+    let config = Config {
+        ttl: 4,
+        ..Config::default()
+    };
+    let mut sim = Sim::new(rng, &line(8), 0.0, config);
+    sim.run_until(30_000);
+    sim.send(0, Some(BROADCAST), b"near");
+    if !sim.send(7, None, b"far") {
+        return Err("no route to the gateway".into());
+    }
+    sim.run_until(31_000);
+    if sim.delivered.iter().any(|d| d.hops > 4) || sim.delivered.len() != 4 {
+        return Err(format!("flood with TTL 4 reached {:?}", sim.delivered));
+    }
+    if sim.dropped != [Dropped::TtlExpired] {
+        return Err(format!(
+            "expected the unicast to expire, got {:?}",
+            sim.dropped
+        ));
+    }
+    Ok(())
+}
+
+/// A node goes down: routes go around it, and where there is no way around
+/// they go away rather than counting to infinity.
+fn failure(rng: &mut Rng) -> Result<(), String> {
+    let positions = grid(4);
+    let mut sim = converge(rng, &positions, 0.1)?;
+    // (1, 1) is on the way to the gateway for several nodes
+    let down = positions.iter().position(|&p| p == (1, 1)).unwrap();
+    sim.nodes[down].up = false;
+    // What is sent right away through the node that is down gets lost, and
+    // lets the senders know
+    for i in 1..positions.len() {
+        if i != down {
+            sim.send(i, None, b"reading");
+        }
+    }
+    sim.run_until(50_000);
+    for i in 1..positions.len() {
+        if i == down {
+            continue;
+        }
+        let Some(route) = sim.nodes[i].router.gateway() else {
+            return Err(format!("node {i} lost its way to the gateway"));
+        };
+        if route.next_hop == sim.nodes[down].mac {
+            return Err(format!("node {i} still goes through the node that is down"));
+        }
+        if route.hops != distance(positions[i], positions[0]) {
+            return Err(format!("node {i} took a detour"));
+        }
+        if !sim.send(i, None, b"reading") {
+            return Err(format!("node {i} could not send to the gateway"));
+        }
+    }
+    sim.delivered.clear();
+    sim.run_until(51_000);
+    let received = sim.delivered.iter().filter(|d| d.at == 0).count();
+    if received != positions.len() - 2 {
+        return Err(format!("only {received} messages reached the gateway"));
+    }
+
+    let mut sim = converge(rng, &line(6), 0.0)?;
+    sim.nodes[2].up = false;
+    sim.run_until(60_000);
+    for i in 3..6 {
+        if let Some(route) = sim.nodes[i].router.gateway() {
+            return Err(format!("node {i} is cut off, but has {route:?}"));
+        }
+    }
+    Ok(())
+}
+
+pub fn run_scenario(seed: u64) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    converge(&mut rng, &line(6), 0.0)?;
+    converge(&mut rng, &grid(5), 0.1)?;
+    flood(&mut rng)?;
+    ttl(&mut rng)?;
+    failure(&mut rng)?;
+    println!("  routes converge and heal, floods reach everybody once, TTLs hold");
+    Ok(())
+}
@@ -0,0 +1,149 @@
+//! Checks that `esp_now_messages` round trip, and that a node gets along
+//! with one that runs a newer schema: it skips the variants it doesn't know,
+//! and reads the ones it does know despite the fields added to them.
+
+use esp_now_messages::{Error, KINDS, MAX_MESSAGE_LEN, Message, VERSION, is_typed_message};
+use serde::Serialize;
+use sim_rng::Rng;
+
+/// `Message` as a later version of the schema might have it.
+#[derive(Serialize)]
+enum NewerMessage<'a> {
+    Ping { seq: u32, uptime_ms: u64 },
+    Hello { name: &'a str, capabilities: u16 },
+    Text(&'a str),
+    Reading { sensor: u8, value: f32 },
+}
+
+/// How a newer node would encode `message`.
+fn encode_newer(message: &NewerMessage<'_>) -> Vec<u8> {
+    // The header is the same, only the body differs
+    let mut payload = b"MSGS".to_vec();
+    payload.push(VERSION);
+    payload.extend(postcard::to_allocvec(message).unwrap());
+    payload
+}
+
+fn random_text(rng: &mut Rng, max_len: u64) -> String {
+    let len = rng.range(0, max_len);
+    (0..len)
+        .map(|_| match rng.range(0, 9) {
+            0 => 'é',
+            1 => '📡',
+            _ => char::from(b'a' + rng.range(0, 25) as u8),
+        })
+        .collect()
+}
+
+fn round_trip(rng: &mut Rng) -> Result<(), String> {
+    let mut buf = [0; MAX_MESSAGE_LEN];
+    let mut kinds = Vec::new();
+    for _ in 0..1000 {
+        let text = random_text(rng, 40);
+        let message = match rng.range(0, 2) {
+            0 => Message::Ping {
+                seq: rng.next_u64() as u32,
+            },
+            1 => Message::Hello { name: &text },
+            _ => Message::Text(&text),
+        };
+        let n = message
+            .encode(&mut buf)
+            .map_err(|e| format!("{message:?} didn't encode: {e:?}"))?;
+        if !is_typed_message(&buf[..n]) || buf[4] != VERSION {
+            return Err(format!("{message:?} has the wrong header"));
+        }
+        kinds.push(buf[5] as u32);
+        let decoded = Message::decode(&buf[..n]);
+        if decoded != Ok(message) {
+            return Err(format!("{message:?} came back as {decoded:?}"));
+        }
+        // Cut short anywhere, it's malformed rather than something else
+        let cut = rng.range(5, n as u64 - 1) as usize;
+        if let Ok(decoded) = Message::decode(&buf[..cut]) {
+            return Err(format!(
+                "{message:?} cut to {cut} bytes decoded as {decoded:?}"
+            ));
+        }
+    }
+    let last = kinds.into_iter().max();
+    if last != Some(KINDS - 1) {
+        return Err(format!(
+            "KINDS is {KINDS}, but the variants go up to {last:?}"
+        ));
+    }
+    Ok(())
+}
+
+fn errors() -> Result<(), String> {
+    let mut buf = [0; MAX_MESSAGE_LEN];
+    let n = Message::Ping { seq: 1 }.encode(&mut buf).unwrap();
+    let mut other_version = buf[..n].to_vec();
+    other_version[4] = VERSION + 1;
+    let cases: [(&[u8], Error); 5] = [
+        (b"test", Error::NotOurs),
+        (b"HELLO!", Error::NotOurs),
+        (b"MSGS", Error::Malformed),
+        (&other_version, Error::Version(VERSION + 1)),
+        // Not UTF-8
+        (b"MSGS\x01\x02\x01\xff", Error::Malformed),
+    ];
+    for (payload, expected) in cases {
+        let decoded = Message::decode(payload);
+        if decoded != Err(expected) {
+            return Err(format!("{payload:02x?} gave {decoded:?}"));
+        }
+    }
+    let long = "x".repeat(MAX_MESSAGE_LEN);
+    let encoded = Message::Text(&long).encode(&mut buf);
+    if encoded != Err(Error::TooLong) {
+        return Err(format!("a message that doesn't fit gave {encoded:?}"));
+    }
+    Ok(())
+}
+
+fn newer_schema() -> Result<(), String> {
+    let cases = [
+        (
+            encode_newer(&NewerMessage::Ping {
+                seq: 7,
+                uptime_ms: 123_456_789,
+            }),
+            Ok(Message::Ping { seq: 7 }),
+        ),
+        (
+            encode_newer(&NewerMessage::Hello {
+                name: "node",
+                capabilities: 0x8001,
+            }),
+            Ok(Message::Hello { name: "node" }),
+        ),
+        (
+            encode_newer(&NewerMessage::Text("hi")),
+            Ok(Message::Text("hi")),
+        ),
+        (
+            encode_newer(&NewerMessage::Reading {
+                sensor: 2,
+                value: 21.5,
+            }),
+            Err(Error::UnknownKind(3)),
+        ),
+    ];
+    for (payload, expected) in cases {
+        let decoded = Message::decode(&payload);
+        if decoded != expected {
+            return Err(format!("{payload:02x?} gave {decoded:?}, not {expected:?}"));
+        }
+    }
+    Ok(())
+}
+
+pub fn run_scenario(seed: u64) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    round_trip(&mut rng)?;
+    errors()?;
+    newer_schema()?;
+    println!("  messages round trip, newer ones are read as far as we know them");
+    Ok(())
+}
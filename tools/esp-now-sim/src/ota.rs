@@ -0,0 +1,393 @@
+//! Runs `esp_now_stack::ota` between a seeder and a few nodes over a radio
+//! that loses and damages frames, with flash that only clears bits unless
+//! erased. One node reboots halfway, and has to carry on where it left off.
+//! Another loses power with the whole image written but not yet checked,
+//! and has to check it without downloading anything. Another runs the seeder's version already, and an impostor offers an
+//! image signed with the wrong key.
+
+use esp_now_stack::{
+    Mac,
+    ota::{
+        self, CHUNK_LEN, Config, Event, Manifest, Message, Receiver, SECTOR_LEN, TRAILER_VERIFIED,
+        Trailer,
+    },
+};
+use sha2::{Digest, Sha256};
+use sim_rng::Rng;
+
+const IMAGE_LEN: usize = 40_000;
+const OFFER_INTERVAL_MS: u64 = 1000;
+const LOSS: f64 = 0.1;
+const CORRUPT: f64 = 0.02;
+const MAX_DELAY_MS: u64 = 4;
+/// The node that reboots does so once it has this many sectors.
+const REBOOT_AFTER_SECTORS: u32 = 4;
+const TIMEOUT_MS: u64 = 120_000;
+
+const SECRET: [u8; 32] = [7; 32];
+const WRONG_SECRET: [u8; 32] = [8; 32];
+
+fn mac(i: usize) -> Mac {
+    [0x02, 0, 0, 0, 0, i as u8]
+}
+
+/// NOR flash: writing clears bits, only erasing sets them again.
+struct Flash(Vec<u8>);
+
+impl Flash {
+    fn new(len: usize) -> Self {
+        Self(vec![0xff; len])
+    }
+
+    fn erase(&mut self, range: std::ops::Range<usize>) {
+        self.0[range].fill(0xff);
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) {
+        for (f, d) in self.0[offset..].iter_mut().zip(data) {
+            *f &= d;
+        }
+    }
+}
+
+struct Node {
+    running_version: u32,
+    receiver: Receiver,
+    slot: Flash,
+    trailer: Flash,
+    /// The chunk each transfer since the last reboot started from.
+    starts: Vec<u32>,
+    bad_signatures: u32,
+    rebooted: bool,
+    /// Reboots once the image is complete, before checking it.
+    reboot_before_check: bool,
+}
+
+impl Node {
+    fn new(running_version: u32) -> Self {
+        Self {
+            running_version,
+            receiver: Receiver::new(Config::default(), ota::public_key(&SECRET), running_version),
+            slot: Flash::new(IMAGE_LEN.next_multiple_of(SECTOR_LEN as usize)),
+            trailer: Flash::new(SECTOR_LEN as usize),
+            starts: Vec::new(),
+            bad_signatures: 0,
+            rebooted: false,
+            reboot_before_check: false,
+        }
+    }
+
+    fn trailer(&self) -> Option<Trailer> {
+        Trailer::decode(&self.trailer.0)
+    }
+
+    /// Forgets everything that isn't in flash.
+    fn reboot(&mut self) {
+        self.receiver = Receiver::new(
+            Config::default(),
+            ota::public_key(&SECRET),
+            self.running_version,
+        );
+        self.starts.clear();
+        self.rebooted = true;
+    }
+
+    /// Checks the complete image against `manifest`, and marks it verified,
+    /// or reboots before it gets to if it is the one to do so.
+    fn check(&mut self, manifest: &Manifest) -> Result<(), String> {
+        if self.reboot_before_check && !self.rebooted {
+            self.reboot();
+            return Ok(());
+        }
+        let image = &self.slot.0[..manifest.len as usize];
+        if Sha256::digest(image).as_slice() != manifest.digest {
+            return Err("got a damaged image".into());
+        }
+        self.trailer.write(TRAILER_VERIFIED as usize, &[0; 4]);
+        Ok(())
+    }
+}
+
+struct Frame {
+    at_ms: u64,
+    src: usize,
+    /// `None` for broadcasts.
+    dst: Option<usize>,
+    payload: Vec<u8>,
+}
+
+struct Sim {
+    rng: Rng,
+    now_ms: u64,
+    image: Vec<u8>,
+    manifest: Manifest,
+    forged: Manifest,
+    /// The seeder is node 0 and the impostor node 1, the rest are here,
+    /// from node 2 on.
+    nodes: Vec<Node>,
+    in_flight: Vec<Frame>,
+    last_at_ms: u64,
+    chunks_sent: u64,
+    damaged: u64,
+}
+
+impl Sim {
+    fn send(&mut self, src: usize, dst: Option<usize>, payload: &[u8]) {
+        let mut payload = payload.to_vec();
+        if self.rng.chance(CORRUPT) {
+            let bit = self.rng.range(0, payload.len() as u64 * 8 - 1);
+            payload[bit as usize / 8] ^= 1 << (bit % 8);
+            self.damaged += 1;
+        }
+        // The radio sends one frame after the other
+        let at_ms = (self.now_ms + self.rng.range(1, MAX_DELAY_MS)).max(self.last_at_ms);
+        self.last_at_ms = at_ms;
+        self.in_flight.push(Frame {
+            at_ms,
+            src,
+            dst,
+            payload,
+        });
+    }
+
+    fn seeder(&mut self, src: usize, payload: &[u8]) {
+        let Some(message) = Message::decode(payload) else {
+            return;
+        };
+        let Some(chunks) = ota::serve(&self.manifest, Config::default().window, &message) else {
+            return;
+        };
+        for index in chunks {
+            let span = self.manifest.chunk_span(index);
+            let data = self.image[span.start as usize..span.end as usize].to_vec();
+            let frame = Message::Chunk {
+                id: self.manifest.id(),
+                index,
+                data: &data,
+            }
+            .encode();
+            self.chunks_sent += 1;
+            self.send(0, Some(src), &frame);
+        }
+    }
+
+    fn receive(&mut self, to: usize, src: usize, payload: &[u8]) -> Result<(), String> {
+        let now = self.now_ms;
+        let node = &mut self.nodes[to - 2];
+        let reply = match node.receiver.handle(now, mac(src), payload) {
+            Event::Offered(manifest) => {
+                if manifest == self.forged {
+                    return Err(format!("node {to} took the forged offer"));
+                }
+                let trailer = node.trailer();
+                let first = match Receiver::resume_from(&manifest, trailer.as_ref()) {
+                    Some(first) => first,
+                    None => {
+                        node.trailer.erase(0..SECTOR_LEN as usize);
+                        node.trailer.write(0, &Trailer::header(&manifest));
+                        0
+                    }
+                };
+                node.starts.push(first);
+                let request = node.receiver.start(now, mac(src), manifest, first);
+                if node.receiver.is_complete() {
+                    node.check(&manifest)
+                        .map_err(|e| format!("node {to} {e}"))?;
+                }
+                request
+            }
+            Event::BadSignature { .. } => {
+                node.bad_signatures += 1;
+                None
+            }
+            Event::Chunk {
+                offset,
+                data,
+                sector_written,
+                complete,
+                request,
+            } => {
+                let offset = offset as usize;
+                if offset.is_multiple_of(SECTOR_LEN as usize) {
+                    node.slot.erase(offset..offset + SECTOR_LEN as usize);
+                }
+                node.slot.write(offset, data);
+                if let Some(sector) = sector_written {
+                    node.trailer
+                        .write(Trailer::progress_offset(sector) as usize, &[0; 4]);
+                }
+                if complete {
+                    node.check(&self.manifest)
+                        .map_err(|e| format!("node {to} {e}"))?;
+                }
+                request
+            }
+            Event::Missed { request } => Some(request),
+            Event::Ignored => None,
+        };
+        if let Some(reply) = reply {
+            self.send(to, Some(src), &reply);
+        }
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<(), String> {
+        self.now_ms += 1;
+        if self.now_ms.is_multiple_of(OFFER_INTERVAL_MS) {
+            let offer = Message::Offer(self.manifest).encode();
+            self.send(0, None, &offer);
+            let forged = Message::Offer(self.forged).encode();
+            self.send(1, None, &forged);
+        }
+        for i in 0..self.nodes.len() {
+            let now = self.now_ms;
+            if let Some((seeder, request)) = self.nodes[i].receiver.poll(now) {
+                let seeder = seeder[5] as usize;
+                self.send(i + 2, Some(seeder), &request);
+            }
+        }
+
+        let (arrived, rest) = self
+            .in_flight
+            .drain(..)
+            .partition(|f| f.at_ms <= self.now_ms);
+        self.in_flight = rest;
+        for frame in arrived {
+            let to: Vec<usize> = match frame.dst {
+                Some(dst) => vec![dst],
+                None => (0..self.nodes.len() + 2)
+                    .filter(|&i| i != frame.src)
+                    .collect(),
+            };
+            for to in to {
+                if self.rng.chance(LOSS) {
+                    continue;
+                }
+                match to {
+                    0 => self.seeder(frame.src, &frame.payload),
+                    1 => (),
+                    _ => self.receive(to, frame.src, &frame.payload)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn transfer(seed: u64) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    let image: Vec<u8> = (0..IMAGE_LEN).map(|_| rng.next_u64() as u8).collect();
+    let manifest = Manifest::sign(2, &image, &SECRET);
+    if !manifest.verify(&ota::public_key(&SECRET)) {
+        return Err("a manifest doesn't verify with its own key".into());
+    }
+    let forged = Manifest::sign(3, &image, &WRONG_SECRET);
+    let mut sim = Sim {
+        rng,
+        now_ms: 0,
+        image,
+        manifest,
+        forged,
+        // The last one is up to date already
+        nodes: vec![
+            Node::new(1),
+            Node::new(1),
+            Node::new(1),
+            Node::new(1),
+            Node::new(2),
+        ],
+        in_flight: Vec::new(),
+        last_at_ms: 0,
+        chunks_sent: 0,
+        damaged: 0,
+    };
+    let rebooting = 1;
+    let unchecked = 2;
+    sim.nodes[unchecked].reboot_before_check = true;
+
+    let updating = 0..sim.nodes.len() - 1;
+    while !updating
+        .clone()
+        .all(|i| sim.nodes[i].trailer().is_some_and(|t| t.verified))
+    {
+        if sim.now_ms >= TIMEOUT_MS {
+            return Err(format!(
+                "not done after {TIMEOUT_MS} ms, at {:?}",
+                sim.nodes
+                    .iter()
+                    .map(|n| n.trailer().map(|t| t.sectors_written))
+                    .collect::<Vec<_>>()
+            ));
+        }
+        sim.step()?;
+        let node = &sim.nodes[rebooting];
+        if !node.rebooted
+            && node
+                .trailer()
+                .is_some_and(|t| t.sectors_written >= REBOOT_AFTER_SECTORS)
+        {
+            sim.nodes[rebooting].reboot();
+        }
+    }
+
+    for i in updating {
+        let node = &sim.nodes[i];
+        if node.slot.0[..IMAGE_LEN] != sim.image[..] {
+            return Err(format!("node {} has a different image", i + 2));
+        }
+    }
+    let resumed = &sim.nodes[rebooting].starts;
+    if resumed.first() < Some(&(REBOOT_AFTER_SECTORS * SECTOR_LEN / CHUNK_LEN as u32)) {
+        return Err(format!("started at chunks {resumed:?} after a reboot"));
+    }
+    let node = &sim.nodes[unchecked];
+    if !node.rebooted || node.starts != [sim.manifest.chunks()] {
+        return Err(format!(
+            "started at chunks {:?} after a reboot before the check",
+            node.starts
+        ));
+    }
+    let idle = sim.nodes.last().unwrap();
+    if !idle.starts.is_empty() || idle.trailer().is_some() {
+        return Err("a node that is up to date downloaded anyway".into());
+    }
+    // The others are busy, and don't look at offers
+    if idle.bad_signatures == 0 {
+        return Err("the forged offer went unnoticed".into());
+    }
+    println!(
+        "  {} chunks sent for {} in the image, {} frames damaged, done in {} ms",
+        sim.chunks_sent,
+        sim.manifest.chunks(),
+        sim.damaged,
+        sim.now_ms
+    );
+    Ok(())
+}
+
+/// The bootloader's own value for the first entry, and the sequence numbers
+/// that pick each slot.
+fn boot_selection() -> Result<(), String> {
+    let entry = ota::select_entry(1);
+    if entry[28..] != 0x4743_989au32.to_le_bytes() || ota::parse_select_entry(&entry) != Some(1) {
+        return Err(format!("entry for sequence 1 is {entry:02x?}"));
+    }
+    if ota::parse_select_entry(&[0xff; 32]).is_some() {
+        return Err("an erased entry parsed".into());
+    }
+    for (current, slot, expected) in [(None, 0, 1), (None, 1, 2), (Some(1), 1, 2), (Some(2), 1, 4)]
+    {
+        let seq = ota::next_seq(current, slot, 2);
+        if seq != expected {
+            return Err(format!("{current:?} to slot {slot} gave {seq}"));
+        }
+    }
+    Ok(())
+}
+
+pub fn run_scenario(seed: u64) -> Result<(), String> {
+    boot_selection()?;
+    transfer(seed)?;
+    println!("  images arrive whole and signed, transfers resume after a reboot");
+    Ok(())
+}
@@ -0,0 +1,171 @@
+//! Pairs nodes over a lossy link with `esp_now_stack::pairing`, and checks
+//! the peer store that keeps the result.
+
+use esp_now_stack::{
+    Mac,
+    crc::crc32,
+    pairing::{self, Action, KEY_LEN, Pairing},
+    peer_store::{MAX_PAIRED, PairedPeer, PeerStore},
+};
+use sim_rng::Rng;
+
+use crate::link::{Air, LinkQuality};
+
+const OFFER_INTERVAL_MS: u64 = 200;
+const WINDOW_MS: u64 = 10_000;
+
+struct Node {
+    mac: Mac,
+    pairing: Pairing,
+    paired: Vec<(Mac, [u8; KEY_LEN])>,
+    rejected: Vec<Mac>,
+}
+
+impl Node {
+    fn new(rng: &mut Rng, mac: Mac, code: &[u8]) -> Self {
+        let mut secret = [0; 32];
+        secret.fill_with(|| rng.next_u64() as u8);
+        Self {
+            mac,
+            pairing: Pairing::new(mac, secret, code),
+            paired: Vec::new(),
+            rejected: Vec::new(),
+        }
+    }
+}
+
+fn broadcast(air: &mut Air, now_ms: u64, nodes: &[Node], src: Mac, frame: &[u8]) {
+    for node in nodes.iter().filter(|n| n.mac != src) {
+        air.transmit(now_ms, src, node.mac, frame);
+    }
+}
+
+/// Runs a pairing window with all nodes offering, and returns them.
+fn pair(rng: &mut Rng, codes: &[&[u8]], loss: f64) -> Vec<Node> {
+    let mut nodes: Vec<_> = codes
+        .iter()
+        .enumerate()
+        .map(|(i, code)| Node::new(rng, [0x10 + i as u8; 6], code))
+        .collect();
+    let quality = LinkQuality {
+        loss,
+        duplicate: 0.05,
+        min_delay_ms: 1,
+        max_delay_ms: 8,
+    };
+    let mut air = Air::new(quality, rng.next_u64());
+
+    for now_ms in 0..WINDOW_MS {
+        if now_ms % OFFER_INTERVAL_MS == 0 {
+            for i in 0..nodes.len() {
+                let offer = nodes[i].pairing.offer();
+                broadcast(&mut air, now_ms, &nodes, nodes[i].mac, &offer);
+            }
+        }
+        while let Some(delivery) = air.receive(now_ms) {
+            let i = nodes.iter().position(|n| n.mac == delivery.dst).unwrap();
+            let node = &mut nodes[i];
+            match node.pairing.handle(delivery.src, &delivery.frame) {
+                Action::None => (),
+                Action::Reply(reply) => broadcast(&mut air, now_ms, &nodes, delivery.dst, &reply),
+                Action::Paired { peer, lmk } => {
+                    if !node.paired.iter().any(|(p, _)| *p == peer) {
+                        node.paired.push((peer, lmk));
+                    }
+                }
+                Action::Rejected { peer } => node.rejected.push(peer),
+            }
+        }
+    }
+    nodes
+}
+
+fn check_pairing(rng: &mut Rng) -> Result<(), String> {
+    for loss in [0.0, 0.3] {
+        let nodes = pair(rng, &[b"correct horse", b"correct horse"], loss);
+        let (a, b) = (&nodes[0], &nodes[1]);
+        match (a.paired.as_slice(), b.paired.as_slice()) {
+            ([(pa, lmk_a)], [(pb, lmk_b)]) if *pa == b.mac && *pb == a.mac => {
+                if lmk_a != lmk_b {
+                    return Err("the two sides derived different keys".into());
+                }
+            }
+            _ => return Err(format!("nodes did not pair at {:.0}% loss", loss * 100.0)),
+        }
+    }
+
+    // A third node with the wrong code pairs with nobody, and the others
+    // still pair with each other
+    let nodes = pair(rng, &[b"correct horse", b"correct horse", b"wrong"], 0.0);
+    if !nodes[2].paired.is_empty() {
+        return Err("node with the wrong code paired".into());
+    }
+    for node in &nodes[..2] {
+        if node.paired.iter().any(|(p, _)| *p == nodes[2].mac) {
+            return Err("paired with the node that has the wrong code".into());
+        }
+        if node.paired.len() != 1 {
+            return Err("nodes with the right code did not pair".into());
+        }
+        if !node.rejected.contains(&nodes[2].mac) {
+            return Err("wrong code was not reported".into());
+        }
+    }
+
+    // Another session between the same nodes ends up with a different key
+    let again = pair(rng, &[b"correct horse", b"correct horse"], 0.0);
+    if again[0].paired[0].1 == nodes[0].paired[0].1 {
+        return Err("two sessions derived the same key".into());
+    }
+
+    if pairing::pmk(b"correct horse") == pairing::pmk(b"wrong") {
+        return Err("different pairing codes derived the same PMK".into());
+    }
+    Ok(())
+}
+
+fn check_store() -> Result<(), String> {
+    if crc32(b"123456789") != 0xcbf4_3926 {
+        return Err("CRC-32 check value is wrong".into());
+    }
+
+    let mut store = PeerStore::new();
+    if PeerStore::decode(&[0xff; esp_now_stack::peer_store::STORE_LEN]) != store {
+        return Err("erased flash did not read as an empty store".into());
+    }
+    for i in 0..=MAX_PAIRED as u8 {
+        let evicted = store.insert(PairedPeer {
+            mac: [i; 6],
+            lmk: [i; KEY_LEN],
+        });
+        if (i as usize == MAX_PAIRED) != (evicted.map(|p| p.mac) == Some([0; 6])) {
+            return Err("the oldest peer was not evicted when full".into());
+        }
+    }
+    // Pairing again with a known peer replaces its key
+    store.insert(PairedPeer {
+        mac: [3; 6],
+        lmk: [0xaa; KEY_LEN],
+    });
+    if store.peers().len() != MAX_PAIRED || store.get(&[3; 6]).unwrap().lmk != [0xaa; KEY_LEN] {
+        return Err("pairing again did not replace the key".into());
+    }
+
+    let mut encoded = store.encode();
+    if PeerStore::decode(&encoded) != store {
+        return Err("store did not survive a round trip".into());
+    }
+    encoded[10] ^= 1;
+    if !PeerStore::decode(&encoded).peers().is_empty() {
+        return Err("corrupted store was not discarded".into());
+    }
+    Ok(())
+}
+
+pub fn run_scenario(seed: u64) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    check_pairing(&mut rng)?;
+    check_store()?;
+    println!("  nodes pair with the right code and only with it, peer store round trips");
+    Ok(())
+}
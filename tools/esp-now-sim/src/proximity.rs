@@ -0,0 +1,153 @@
+//! Tracks two nodes with `esp_now_stack::proximity` from the RSSI of their
+//! beacons, with noise and fading on top of a log-distance path loss. One
+//! walks up, stays a while, walks off and then goes quiet. The other sits
+//! right on the boundary between near and far, and must not flap between
+//! them.
+
+use esp_now_stack::{
+    Mac,
+    proximity::{Config, Event, Tracker, Zone},
+};
+use sim_rng::Rng;
+
+const WALKER: Mac = [0x02, 0, 0, 0, 0, 1];
+const LINGERER: Mac = [0x02, 0, 0, 0, 0, 2];
+
+const BEACON_INTERVAL_MS: u64 = 500;
+const LOSS: f64 = 0.1;
+/// Standard deviation of the RSSI of a frame, in dB.
+const NOISE_DB: f64 = 4.0;
+/// How often a frame comes in much weaker, as when somebody walks between
+/// the nodes, and by how much.
+const FADE: f64 = 0.05;
+const FADE_DB: f64 = 12.0;
+/// The walker goes quiet at this point.
+const SILENT_AT_MS: u64 = 120_000;
+const END_MS: u64 = 150_000;
+
+/// The RSSI in dBm at `distance_m`, for a path loss exponent of 2.5.
+fn path_loss(distance_m: f64) -> f64 {
+    -45.0 - 25.0 * distance_m.log10()
+}
+
+/// Where the boundary between near and far is, in meters.
+fn boundary_m(config: &Config) -> f64 {
+    10f64.powf((-45.0 - config.near_dbm as f64) / 25.0)
+}
+
+/// Far, walking up over ten seconds, near for forty, and back.
+fn walker_distance_m(now_ms: u64) -> f64 {
+    let (far, near) = (12.0, 1.0);
+    let t = now_ms as f64 / 1000.0;
+    match t {
+        t if t < 30.0 => far,
+        t if t < 40.0 => far + (near - far) * (t - 30.0) / 10.0,
+        t if t < 80.0 => near,
+        t if t < 90.0 => near + (far - near) * (t - 80.0) / 10.0,
+        _ => far,
+    }
+}
+
+fn rssi(rng: &mut Rng, distance_m: f64) -> i8 {
+    let mut rssi = path_loss(distance_m) + NOISE_DB * rng.normal();
+    if rng.chance(FADE) {
+        rssi -= FADE_DB;
+    }
+    rssi.round().clamp(-100.0, 0.0) as i8
+}
+
+pub fn run_scenario(seed: u64) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    let config = Config::default();
+    let mut tracker = Tracker::<4>::new(config);
+
+    let mut walker_events = Vec::new();
+    let mut lingerer_events = Vec::new();
+    // What the zone would be going by each frame on its own
+    let mut raw_flips = 0;
+    let mut raw_zone = None;
+    let mut walker_heard_ms = 0;
+    let mut next_beacon_ms = [rng.range(0, BEACON_INTERVAL_MS), 0];
+    next_beacon_ms[1] = rng.range(0, BEACON_INTERVAL_MS);
+
+    for now_ms in 0..END_MS {
+        let mut events = Vec::new();
+        for (i, (mac, distance_m)) in [
+            (WALKER, walker_distance_m(now_ms)),
+            (LINGERER, boundary_m(&config)),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            if now_ms < next_beacon_ms[i] {
+                continue;
+            }
+            next_beacon_ms[i] = now_ms + BEACON_INTERVAL_MS + rng.range(0, 20);
+            if (mac == WALKER && now_ms >= SILENT_AT_MS) || rng.chance(LOSS) {
+                continue;
+            }
+            let rssi = rssi(&mut rng, distance_m);
+            if mac == WALKER {
+                walker_heard_ms = now_ms;
+            } else {
+                let zone = (rssi as f32 >= config.near_dbm).then_some(Zone::Near);
+                if raw_zone.is_some_and(|z| z != zone) {
+                    raw_flips += 1;
+                }
+                raw_zone = Some(zone);
+            }
+            events.extend(tracker.handle(now_ms, mac, rssi));
+        }
+        while let Some(event) = tracker.expire(now_ms) {
+            events.push(event);
+        }
+        for event in events {
+            let (Event::Entered { mac, .. } | Event::Moved { mac, .. } | Event::Left { mac }) =
+                event;
+            if mac == WALKER {
+                walker_events.push((now_ms, event));
+            } else {
+                lingerer_events.push((now_ms, event));
+            }
+        }
+    }
+
+    let zones: Vec<_> = walker_events
+        .iter()
+        .map(|(_, e)| match e {
+            Event::Entered { zone, .. } | Event::Moved { zone, .. } => Some(*zone),
+            Event::Left { .. } => None,
+        })
+        .collect();
+    let expected = [Some(Zone::Far), Some(Zone::Near), Some(Zone::Far), None];
+    if zones != expected {
+        return Err(format!("the walker went {walker_events:?}"));
+    }
+    // It's past the boundary by the hysteresis at about 38.5 and 84.5 s,
+    // the filter takes a few seconds to catch up
+    let at = |i: usize| walker_events[i].0;
+    if !(37_000..44_000).contains(&at(1)) || !(83_000..90_000).contains(&at(2)) {
+        return Err(format!("the walker changed zones late, {walker_events:?}"));
+    }
+    let left_after = at(3) - walker_heard_ms;
+    if left_after != config.timeout_ms {
+        return Err(format!(
+            "the walker left {left_after} ms after its last beacon"
+        ));
+    }
+
+    let moves = lingerer_events
+        .iter()
+        .filter(|(_, e)| matches!(e, Event::Moved { .. }))
+        .count();
+    if moves > 6 || moves * 10 > raw_flips {
+        return Err(format!(
+            "the node on the boundary changed zones {moves} times, single frames {raw_flips}"
+        ));
+    }
+    println!(
+        "  the node on the boundary changed zones {moves} times, single frames would have {raw_flips}"
+    );
+    println!("  nodes enter, move between zones and leave in time, without flapping");
+    Ok(())
+}
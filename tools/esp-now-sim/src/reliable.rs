@@ -0,0 +1,195 @@
+//! Sends a stream of messages over a lossy link with
+//! `esp_now_stack::reliable` and checks that each one arrives at most once,
+//! in order, and that everything reported as delivered actually was.
+
+use esp_now_stack::{
+    Mac,
+    header::MAX_FRAME_LEN,
+    reliable::{Config, Endpoint, Event, Poll},
+};
+
+use crate::link::{Air, LinkQuality};
+
+const A: Mac = [0xa; 6];
+const B: Mac = [0xb; 6];
+
+const MESSAGES: u32 = 500;
+
+#[derive(Default, Debug)]
+struct Stats {
+    delivered: u32,
+    failed: u32,
+    frames: u64,
+}
+
+struct Sender {
+    endpoint: Endpoint<4>,
+    next: u32,
+    in_flight: Option<u32>,
+    wait_until: Option<u64>,
+    delivered: Vec<u32>,
+    failed: u32,
+}
+
+impl Sender {
+    fn new(first_seq: u16) -> Self {
+        Self {
+            endpoint: Endpoint::new(Config::default(), first_seq),
+            next: 0,
+            in_flight: None,
+            wait_until: None,
+            delivered: Vec::new(),
+            failed: 0,
+        }
+    }
+
+    /// Starts the next message and moves the one in flight along.
+    fn step(&mut self, now_ms: u64, air: &mut Air, last: u32) {
+        let mut frame = [0; MAX_FRAME_LEN];
+        if self.in_flight.is_none() && self.next < last {
+            let (_, len) = self
+                .endpoint
+                .send(now_ms, B, &self.next.to_le_bytes(), &mut frame)
+                .unwrap();
+            air.transmit(now_ms, A, B, &frame[..len]);
+            self.in_flight = Some(self.next);
+            self.next += 1;
+        }
+        self.wait_until = None;
+        loop {
+            match self.endpoint.poll(now_ms, &mut frame) {
+                Poll::Idle => return,
+                Poll::Wait { until_ms } => {
+                    self.wait_until = Some(until_ms);
+                    return;
+                }
+                Poll::Retransmit { dst, len } => {
+                    air.transmit(now_ms, A, dst, &frame[..len]);
+                }
+                Poll::Delivered { .. } => {
+                    self.delivered.push(self.in_flight.take().unwrap());
+                    return self.step(now_ms, air, last);
+                }
+                Poll::Failed { .. } => {
+                    self.in_flight = None;
+                    self.failed += 1;
+                    return self.step(now_ms, air, last);
+                }
+            }
+        }
+    }
+}
+
+/// Runs the sender until it has sent the messages up to `last`. Whatever the
+/// receiver gets is added to `received`.
+fn run(
+    now_ms: &mut u64,
+    air: &mut Air,
+    sender: &mut Sender,
+    receiver: &mut Endpoint<4>,
+    received: &mut Vec<u32>,
+    last: u32,
+) -> Result<(), String> {
+    sender.step(*now_ms, air, last);
+    while sender.in_flight.is_some() || air.next_at().is_some() {
+        let next = [air.next_at(), sender.wait_until]
+            .into_iter()
+            .flatten()
+            .min()
+            .unwrap();
+        *now_ms = next.max(*now_ms);
+
+        while let Some(delivery) = air.receive(*now_ms) {
+            if delivery.dst == B {
+                let handled = receiver.handle(delivery.src, &delivery.frame);
+                if let Some(ack) = handled.ack {
+                    air.transmit(*now_ms, B, A, &ack);
+                }
+                if let Event::Received { payload, .. } = handled.event {
+                    let id = u32::from_le_bytes(payload.try_into().unwrap());
+                    if received.contains(&id) {
+                        return Err(format!("message {id} received twice"));
+                    }
+                    if received.last().is_some_and(|&last| last > id) {
+                        return Err(format!("message {id} received out of order"));
+                    }
+                    received.push(id);
+                }
+            } else {
+                sender.endpoint.handle(delivery.src, &delivery.frame);
+            }
+        }
+        sender.step(*now_ms, air, last);
+    }
+    Ok(())
+}
+
+fn simulate(quality: LinkQuality, seed: u64) -> Result<Stats, String> {
+    let mut air = Air::new(quality, seed);
+    let mut now_ms = 0;
+    let mut sender = Sender::new(60_000);
+    let mut receiver = Endpoint::new(Config::default(), 0);
+    let mut received = Vec::new();
+
+    run(
+        &mut now_ms,
+        &mut air,
+        &mut sender,
+        &mut receiver,
+        &mut received,
+        MESSAGES / 2,
+    )?;
+
+    // The sender reboots and starts over with a sequence number that looks
+    // older than what the receiver has seen from it.
+    let mut rebooted = Sender::new(1000);
+    rebooted.next = sender.next;
+    run(
+        &mut now_ms,
+        &mut air,
+        &mut rebooted,
+        &mut receiver,
+        &mut received,
+        MESSAGES,
+    )?;
+    if rebooted.delivered.is_empty() {
+        return Err("nothing delivered after the sender rebooted".into());
+    }
+
+    for id in sender.delivered.iter().chain(&rebooted.delivered) {
+        if !received.contains(id) {
+            return Err(format!(
+                "message {id} reported delivered but never received"
+            ));
+        }
+    }
+
+    Ok(Stats {
+        delivered: (sender.delivered.len() + rebooted.delivered.len()) as u32,
+        failed: sender.failed + rebooted.failed,
+        frames: air.sent(),
+    })
+}
+
+pub fn run_scenario(seed: u64) -> Result<(), String> {
+    for loss in [0.0, 0.1, 0.3, 0.5] {
+        let quality = LinkQuality {
+            loss,
+            duplicate: 0.05,
+            min_delay_ms: 1,
+            max_delay_ms: 8,
+        };
+        let stats = simulate(quality, seed)?;
+        println!(
+            "  loss {:>3.0}%: {} delivered, {} failed, {:.2} frames per message",
+            loss * 100.0,
+            stats.delivered,
+            stats.failed,
+            stats.frames as f64 / MESSAGES as f64
+        );
+        if loss == 0.0 && stats.failed > 0 {
+            return Err("messages failed on a perfect link".into());
+        }
+    }
+    Ok(())
+}
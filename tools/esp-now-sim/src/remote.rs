@@ -0,0 +1,105 @@
+//! Checks the messages of `esp_now_stack::remote` round trip, that anything
+//! malformed is turned away, and that the actuator's state follows the
+//! commands the way the remote expects.
+
+use esp_now_stack::remote::{Command, MAX_LEVEL, Message, Rgb, State, is_remote_message};
+use sim_rng::Rng;
+
+fn random_state(rng: &mut Rng) -> State {
+    State {
+        on: rng.chance(0.5),
+        level: rng.range(1, MAX_LEVEL as u64) as u8,
+        color: Rgb::new(
+            rng.next_u64() as u8,
+            rng.next_u64() as u8,
+            rng.next_u64() as u8,
+        ),
+    }
+}
+
+fn round_trip(rng: &mut Rng) -> Result<(), String> {
+    let mut messages = vec![Message::Command(Command::Toggle)];
+    messages.extend((0..=MAX_LEVEL).map(|l| Message::Command(Command::Level(l))));
+    for _ in 0..1000 {
+        let state = random_state(rng);
+        messages.push(Message::Command(Command::Color(state.color)));
+        messages.push(Message::State(state));
+    }
+    for message in messages {
+        let encoded = message.encode();
+        if !is_remote_message(&encoded) {
+            return Err(format!("{message:?} is not recognized as ours"));
+        }
+        let decoded = Message::decode(&encoded);
+        if decoded != Some(message) {
+            return Err(format!("{message:?} came back as {decoded:?}"));
+        }
+        // Cut short or with something extra, it's malformed
+        if let Some(decoded) = Message::decode(&encoded[..encoded.len() - 1]) {
+            return Err(format!("{message:?} cut short decoded as {decoded:?}"));
+        }
+        let mut longer = encoded.to_vec();
+        longer.push(0);
+        if let Some(decoded) = Message::decode(&longer) {
+            return Err(format!(
+                "{message:?} with a byte more decoded as {decoded:?}"
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn malformed() -> Result<(), String> {
+    let bad: &[&[u8]] = &[
+        b"",
+        b"CTR",
+        b"CTRX\x00",
+        b"CTRL\x04",
+        // Over 100 percent
+        b"CTRL\x01\x65",
+        // `on` is neither 0 nor 1
+        b"CTRL\x03\x02\x32\x00\x00\x00",
+        // A state is never at level 0
+        b"CTRL\x03\x00\x00\x00\x00\x00",
+        b"DISC\x00",
+    ];
+    for payload in bad {
+        if let Some(decoded) = Message::decode(payload) {
+            return Err(format!("{payload:02x?} decoded as {decoded:?}"));
+        }
+    }
+    Ok(())
+}
+
+fn commands() -> Result<(), String> {
+    let red = Rgb::new(255, 0, 0);
+    let steps = [
+        (Command::Toggle, true, 100, Rgb::WHITE),
+        (Command::Level(50), true, 50, Rgb::new(127, 127, 127)),
+        // Off, but the level is kept for later
+        (Command::Level(0), false, 50, Rgb::new(0, 0, 0)),
+        (Command::Toggle, true, 50, Rgb::new(127, 127, 127)),
+        (Command::Toggle, false, 50, Rgb::new(0, 0, 0)),
+        // A color turns it on as well
+        (Command::Color(red), true, 50, Rgb::new(127, 0, 0)),
+        (Command::Level(100), true, 100, red),
+        (Command::Level(200), true, 100, red),
+    ];
+    let mut state = State::default();
+    for (i, (command, on, level, pixel)) in steps.into_iter().enumerate() {
+        state.apply(command);
+        if state.on != on || state.level != level || state.pixel() != pixel {
+            return Err(format!("after step {i}, {command:?}, got {state:?}"));
+        }
+    }
+    Ok(())
+}
+
+pub fn run_scenario(seed: u64) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    round_trip(&mut rng)?;
+    malformed()?;
+    commands()?;
+    println!("  messages round trip, malformed ones are dropped, commands apply");
+    Ok(())
+}
@@ -0,0 +1,273 @@
+//! Runs `esp_now_stack::timesync` on nodes whose clocks started at different
+//! times and run at slightly different rates, and checks that they agree on
+//! the time, and keep agreeing when the leader changes.
+//!
+//! The estimator is checked on its own first, against lines it should fit
+//! exactly.
+
+use esp_now_stack::{
+    Mac,
+    timesync::{Config, Estimator, Role, TimeSync},
+};
+use sim_rng::Rng;
+
+/// Half a minute's worth of beacons. With the jitter below, fewer make for
+/// a noticeably worse drift estimate.
+const SAMPLES: usize = 32;
+/// How far off the drift estimate may be.
+const MAX_DRIFT_ERROR_PPM: f64 = 5.0;
+/// Beacons take this long to arrive, in microseconds.
+const MIN_DELAY_US: u64 = 150;
+const MAX_DELAY_US: u64 = 400;
+/// Now and then a beacon is held up behind something else.
+const LATE: f64 = 0.05;
+const LOSS: f64 = 0.05;
+
+/// How far apart the nodes may be once synced.
+const MAX_ERROR_US: i64 = 500;
+
+fn mac(i: usize) -> Mac {
+    [0x02, 0, 0, 0, 0, i as u8]
+}
+
+/// The estimator finds the offset and drift of a clock that doesn't jitter,
+/// and ignores a sample that is way off.
+fn estimator() -> Result<(), String> {
+    for (offset, drift_ppm) in [(0i64, 0.0), (123_456, 25.0), (-5_000_000, -40.0)] {
+        let mut e = Estimator::<SAMPLES>::new();
+        let remote = |local: u64| local as i64 + offset + (local as f64 * drift_ppm / 1e6) as i64;
+        for i in 0..40 {
+            let local = 1_000_000 + i * 1_000_000;
+            e.add(local, remote(local) as u64, 2000)
+                .map_err(|r| format!("sample {i} rejected with a residual of {r} us"))?;
+        }
+        let local = 50_000_000;
+        let model = e.model().unwrap();
+        let error = model.offset_at(local) - (remote(local) - local as i64);
+        if error.abs() > 1 {
+            return Err(format!("extrapolated offset is {error} us off"));
+        }
+        let stats = e.stats();
+        if (stats.drift_ppm - drift_ppm).abs() > 0.01 || stats.max_residual_us > 1 {
+            return Err(format!("{drift_ppm} ppm fitted as {stats:?}"));
+        }
+
+        let before = *model;
+        let local = 41_000_000;
+        if e.add(local, (remote(local) + 10_000) as u64, 2000).is_ok() {
+            return Err("a sample 10 ms off was taken".into());
+        }
+        if *e.model().unwrap() != before {
+            return Err("a rejected sample moved the line".into());
+        }
+    }
+    Ok(())
+}
+
+struct Node {
+    sync: TimeSync<SAMPLES>,
+    /// When the clock read zero, in the simulation's time.
+    boot_us: u64,
+    skew_ppm: f64,
+    up: bool,
+}
+
+impl Node {
+    fn local(&self, t: u64) -> u64 {
+        let elapsed = t.saturating_sub(self.boot_us) as f64;
+        (elapsed * (1.0 + self.skew_ppm / 1e6)) as u64
+    }
+
+    fn to_sim(&self, local: u64) -> u64 {
+        self.boot_us + (local as f64 / (1.0 + self.skew_ppm / 1e6)) as u64 + 1
+    }
+
+    fn synced(&self, t: u64) -> u64 {
+        self.sync.now(self.local(t))
+    }
+}
+
+struct Sim {
+    rng: Rng,
+    nodes: Vec<Node>,
+    now_us: u64,
+    /// (arrival, from, to, frame)
+    in_flight: Vec<(u64, usize, usize, Vec<u8>)>,
+}
+
+impl Sim {
+    fn new(seed: u64, count: usize) -> Self {
+        let mut rng = Rng::new(seed);
+        let nodes = (0..count)
+            .map(|i| {
+                let boot_us = rng.range(0, 2_000_000);
+                let skew_ppm = rng.range(0, 80) as f64 - 40.0;
+                Node {
+                    sync: TimeSync::new(mac(i), Config::default(), 0),
+                    boot_us,
+                    skew_ppm,
+                    up: false,
+                }
+            })
+            .collect();
+        Self {
+            rng,
+            nodes,
+            now_us: 0,
+            in_flight: Vec::new(),
+        }
+    }
+
+    fn start(&mut self, i: usize) {
+        let node = &mut self.nodes[i];
+        node.boot_us = node.boot_us.max(self.now_us);
+        node.sync = TimeSync::new(mac(i), Config::default(), 0);
+        node.up = true;
+    }
+
+    fn run_until(&mut self, until_us: u64) {
+        loop {
+            let next_poll = self
+                .nodes
+                .iter()
+                .filter(|n| n.up)
+                .map(|n| n.to_sim(n.sync.next_deadline()).max(n.boot_us))
+                .min();
+            let next_arrival = self.in_flight.iter().map(|f| f.0).min();
+            let next = next_poll
+                .into_iter()
+                .chain(next_arrival)
+                .min()
+                .unwrap_or(u64::MAX);
+            if next > until_us {
+                self.now_us = until_us;
+                return;
+            }
+            self.now_us = self.now_us.max(next);
+
+            for i in 0..self.nodes.len() {
+                if !self.nodes[i].up {
+                    continue;
+                }
+                let local = self.nodes[i].local(self.now_us);
+                if let Some(beacon) = self.nodes[i].sync.poll(local) {
+                    self.broadcast(i, &beacon);
+                }
+            }
+            let (arrived, rest) = self.in_flight.drain(..).partition(|f| f.0 <= self.now_us);
+            self.in_flight = rest;
+            for (_, from, to, frame) in arrived {
+                let node = &mut self.nodes[to];
+                if node.up {
+                    let local = node.local(self.now_us);
+                    node.sync.handle(local, mac(from), &frame);
+                }
+            }
+        }
+    }
+
+    fn broadcast(&mut self, from: usize, frame: &[u8]) {
+        for to in 0..self.nodes.len() {
+            if to == from || self.rng.chance(LOSS) {
+                continue;
+            }
+            let mut delay = self.rng.range(MIN_DELAY_US, MAX_DELAY_US);
+            if self.rng.chance(LATE) {
+                delay += self.rng.range(3_000, 20_000);
+            }
+            self.in_flight
+                .push((self.now_us + delay, from, to, frame.to_vec()));
+        }
+    }
+
+    fn leader(&self) -> Result<usize, String> {
+        let leaders: Vec<_> = (0..self.nodes.len())
+            .filter(|&i| self.nodes[i].up && self.nodes[i].sync.role() == Role::Leader)
+            .collect();
+        match leaders[..] {
+            [leader] => Ok(leader),
+            _ => Err(format!("expected one leader, got {leaders:?}")),
+        }
+    }
+
+    /// Checks that everybody is synced to `leader`, within `MAX_ERROR_US`, and
+    /// knows how fast their clock runs compared to the common one.
+    fn check(
+        &self,
+        leader: usize,
+        common_skew_ppm: f64,
+        max_drift_error_ppm: f64,
+    ) -> Result<(), String> {
+        if self.leader()? != leader {
+            return Err(format!("node {} leads instead of {leader}", self.leader()?));
+        }
+        let reference = self.nodes[leader].synced(self.now_us) as i64;
+        for (i, node) in self.nodes.iter().enumerate() {
+            if !node.up || i == leader {
+                continue;
+            }
+            if node.sync.role()
+                != (Role::Follower {
+                    leader: mac(leader),
+                })
+            {
+                return Err(format!("node {i} is {:?}", node.sync.role()));
+            }
+            let error = node.synced(self.now_us) as i64 - reference;
+            if error.abs() > MAX_ERROR_US {
+                return Err(format!("node {i} is {error} us off the leader"));
+            }
+            // The common clock runs at the rate of the first leader's, since
+            // the others carry on with it, and our clock should be estimated
+            // to drift from it by the difference in skew
+            let expected_ppm =
+                (1.0 + common_skew_ppm / 1e6) / (1.0 + node.skew_ppm / 1e6) * 1e6 - 1e6;
+            let stats = node.sync.estimator().stats();
+            if (stats.drift_ppm - expected_ppm).abs() > max_drift_error_ppm {
+                return Err(format!(
+                    "node {i} estimated {:.2} ppm of drift, expected {expected_ppm:.2}",
+                    stats.drift_ppm
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Five nodes come up one after the other, the lowest MAC last, so the
+/// leader changes while they are already synced. Then the leader goes down.
+fn network(seed: u64) -> Result<(), String> {
+    let mut sim = Sim::new(seed, 5);
+    for i in (0..5).rev() {
+        sim.start(i);
+        sim.run_until(sim.now_us + 5_000_000);
+    }
+    sim.run_until(60_000_000);
+    let common_skew_ppm = sim.nodes[0].skew_ppm;
+    sim.check(0, common_skew_ppm, MAX_DRIFT_ERROR_PPM)?;
+
+    // The clock carries on when the leader goes down
+    let before = sim.nodes[0].synced(sim.now_us);
+    sim.nodes[0].up = false;
+    let gone_at = sim.now_us;
+    sim.run_until(120_000_000);
+    // The new leader's drift estimate is off as well
+    sim.check(1, common_skew_ppm, 2.0 * MAX_DRIFT_ERROR_PPM)?;
+    let elapsed = sim.now_us - gone_at;
+    let expected = before as f64 + elapsed as f64 * (1.0 + common_skew_ppm / 1e6);
+    let error = sim.nodes[1].synced(sim.now_us) as f64 - expected;
+    // The new leader can only be as good as its drift estimate
+    if error.abs() > MAX_DRIFT_ERROR_PPM * elapsed as f64 / 1e6 + MAX_ERROR_US as f64 {
+        return Err(format!(
+            "the clock jumped by {error:.0} us when the leader went down"
+        ));
+    }
+    Ok(())
+}
+
+pub fn run_scenario(seed: u64) -> Result<(), String> {
+    estimator()?;
+    network(seed)?;
+    println!("  clocks agree within {MAX_ERROR_US} us, drift is estimated, leaders hand over");
+    Ok(())
+}
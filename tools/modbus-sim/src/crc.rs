@@ -0,0 +1,48 @@
+//! Checks the CRC against the check value of CRC-16/MODBUS and frames from
+//! the spec.
+
+use modbus::crc::crc16;
+use sim_rng::Rng;
+
+/// Frames with their CRC, as they go on the wire.
+const FRAMES: &[&[u8]] = &[
+    // Read holding register 0 of device 1
+    &[0x01, 0x03, 0x00, 0x00, 0x00, 0x01, 0x84, 0x0a],
+    // Write 3 to holding register 1 of device 1
+    &[0x01, 0x06, 0x00, 0x01, 0x00, 0x03, 0x98, 0x0b],
+    // Read holding registers 107 to 109 of device 17, from the serial line
+    // guide
+    &[0x11, 0x03, 0x00, 0x6b, 0x00, 0x03, 0x76, 0x87],
+];
+
+pub fn run_scenario(seed: u64) -> Result<(), String> {
+    let check = crc16(b"123456789");
+    if check != 0x4b37 {
+        return Err(format!("the check value is {check:#06x}, expected 0x4b37"));
+    }
+    for frame in FRAMES {
+        let (data, crc) = frame.split_at(frame.len() - 2);
+        if crc16(data).to_le_bytes() != crc {
+            return Err(format!("wrong CRC for {data:02x?}"));
+        }
+    }
+
+    // With its CRC appended low byte first, a frame checks out to zero, and
+    // any single flipped bit is caught
+    let mut rng = Rng::new(seed);
+    for _ in 0..1000 {
+        let len = rng.range(1, 254) as usize;
+        let mut frame: Vec<u8> = (0..len).map(|_| rng.next_u64() as u8).collect();
+        frame.extend_from_slice(&crc16(&frame).to_le_bytes());
+        if crc16(&frame) != 0 {
+            return Err(format!("{frame:02x?} doesn't check out to zero"));
+        }
+        let bit = rng.range(0, frame.len() as u64 * 8 - 1) as usize;
+        frame[bit / 8] ^= 1 << (bit % 8);
+        if crc16(&frame) == 0 {
+            return Err(format!("bit {bit} flipped in {frame:02x?} went unnoticed"));
+        }
+    }
+    println!("  check value, spec frames and 1000 random frames ok");
+    Ok(())
+}
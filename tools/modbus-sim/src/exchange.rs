@@ -0,0 +1,189 @@
+//! A master sending requests to two slaves over a simulated line, with
+//! noise on it: flipped bits, and stalls in the middle of frames. Every
+//! answer that gets through has to match the tables, and whatever gets
+//! mangled has to be dropped by whoever receives it.
+
+use modbus::{
+    pdu::{Request, Response, ResponseError, WRITE_SINGLE_COIL},
+    rtu::{self, BROADCAST, FrameError, MAX_FRAME_LEN, Timing},
+};
+use sim_rng::Rng;
+
+use crate::{
+    line::{Line, Received},
+    memory::Memory,
+    pdu::{answer, apply, check_response, random_request},
+};
+
+const MASTER: usize = 0;
+/// Devices 1 and 2 on the line.
+const SLAVES: [u8; 2] = [17, 42];
+/// An address nothing answers to.
+const ABSENT: u8 = 3;
+const TABLE_LEN: u16 = 64;
+const REQUESTS: usize = 1000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Noise {
+    None,
+    /// A bit flipped somewhere in the frame.
+    Flip,
+    /// A gap of more than 1.5 characters in the middle of the frame.
+    Stall,
+}
+
+impl Noise {
+    fn random(rng: &mut Rng) -> Self {
+        if rng.chance(0.05) {
+            Noise::Flip
+        } else if rng.chance(0.05) {
+            Noise::Stall
+        } else {
+            Noise::None
+        }
+    }
+
+    /// Checks that a device dropped a frame with this noise, or returns
+    /// the frame to act on if it had none.
+    fn check(self, received: Received) -> Result<Option<Vec<u8>>, String> {
+        match (self, received) {
+            (Noise::None, Some(Ok(frame))) => Ok(Some(frame)),
+            (Noise::Flip, Some(Ok(frame))) if rtu::decode(&frame) == Err(FrameError::BadCrc) => {
+                Ok(None)
+            }
+            (Noise::Stall, Some(Err(FrameError::Interrupted))) => Ok(None),
+            (noise, received) => Err(format!("{received:02x?} received with {noise:?}")),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Tally {
+    answered: usize,
+    exceptions: usize,
+    /// Requests nothing answered: broadcasts, to the absent address, and
+    /// mangled on the way.
+    unanswered: usize,
+    /// Answers mangled on the way back.
+    mangled: usize,
+}
+
+/// Puts `pdu` for `address` on the line from `from`, with `noise`, and a
+/// little jitter in between the bytes otherwise.
+fn send(line: &mut Line, rng: &mut Rng, from: usize, address: u8, pdu: &[u8], noise: Noise) {
+    let mut frame = [0; MAX_FRAME_LEN];
+    let len = rtu::encode(address, pdu, &mut frame);
+    let frame = &mut frame[..len];
+    let timing = line.timing;
+    let (char_us, t1_5, t3_5) = (
+        timing.char_us as u64,
+        timing.t1_5_us as u64,
+        timing.t3_5_us as u64,
+    );
+    let mut gaps: Vec<u64> = (0..len).map(|_| rng.range(0, t1_5 - char_us)).collect();
+    match noise {
+        Noise::None => (),
+        Noise::Flip => {
+            let bit = rng.range(0, len as u64 * 8 - 1) as usize;
+            frame[bit / 8] ^= 1 << (bit % 8);
+        }
+        Noise::Stall => {
+            let at = rng.range(1, len as u64 - 1) as usize;
+            gaps[at] = rng.range(t1_5 - char_us + 1, t3_5 - char_us - 1);
+        }
+    }
+    line.send(from, frame, |i| gaps[i]);
+}
+
+fn run(rng: &mut Rng, timing: Timing, tally: &mut Tally) -> Result<(), String> {
+    let mut line = Line::new(timing, 1 + SLAVES.len());
+    let mut memories: Vec<Memory> = SLAVES
+        .iter()
+        .map(|_| Memory::random(rng, TABLE_LEN as usize))
+        .collect();
+    let mut expected = memories.clone();
+
+    for _ in 0..REQUESTS {
+        let target = match rng.range(0, 9) {
+            0 => BROADCAST,
+            1 => ABSENT,
+            n => SLAVES[n as usize % 2],
+        };
+        // Broadcasts only make sense for writes
+        let pdu = loop {
+            let pdu = random_request(rng, TABLE_LEN);
+            if target != BROADCAST || pdu[0] >= WRITE_SINGLE_COIL {
+                break pdu;
+            }
+        };
+        let request = Request::decode(&pdu).map_err(|e| format!("{pdu:02x?}: {e:?}"))?;
+
+        let noise = Noise::random(rng);
+        send(&mut line, rng, MASTER, target, &pdu, noise);
+        let received = line.settle();
+        let mut reply = None;
+        for (i, &address) in SLAVES.iter().enumerate() {
+            let Some(frame) = noise.check(received[1 + i].clone())? else {
+                continue;
+            };
+            let (to, pdu) = rtu::decode(&frame).map_err(|e| format!("{frame:02x?}: {e:?}"))?;
+            if to != address && to != BROADCAST {
+                continue;
+            }
+            let response = answer(&mut memories[i], pdu);
+            apply(&mut expected[i], &request);
+            if memories[i] != expected[i] {
+                return Err(format!("device {address} didn't do {request:?} right"));
+            }
+            if to == address {
+                reply = Some((i, response));
+            }
+        }
+
+        let Some((i, response)) = reply else {
+            // The master times out
+            if received[MASTER].is_some() {
+                return Err("the master heard its own request".into());
+            }
+            tally.unanswered += 1;
+            continue;
+        };
+        // The slave takes a while to turn around
+        line.now_us += rng.range(0, 2_000);
+        let noise = Noise::random(rng);
+        send(&mut line, rng, 1 + i, SLAVES[i], &response, noise);
+        let received = line.settle();
+        let Some(frame) = noise.check(received[MASTER].clone())? else {
+            tally.mangled += 1;
+            continue;
+        };
+        let (from, pdu) = rtu::decode(&frame).map_err(|e| format!("{frame:02x?}: {e:?}"))?;
+        if from != SLAVES[i] {
+            return Err(format!("answer to {request:?} from device {from}"));
+        }
+        let response = Response::decode(&request, pdu);
+        if let Err(ResponseError::Exception(_)) = response {
+            tally.exceptions += 1;
+        } else {
+            tally.answered += 1;
+        }
+        check_response(&expected[i], &request, response)?;
+    }
+    Ok(())
+}
+
+pub fn run_scenario(seed: u64) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    let mut tally = Tally::default();
+    for (baud, frame_bits) in [(9600, 11), (19_200, 10), (115_200, 11)] {
+        run(&mut rng, Timing::new(baud, frame_bits), &mut tally)?;
+    }
+    if tally.answered == 0 || tally.exceptions == 0 || tally.unanswered == 0 || tally.mangled == 0 {
+        return Err(format!("not everything happened: {tally:?}"));
+    }
+    println!(
+        "  {} answered, {} exceptions, {} unanswered, {} answers mangled, tables in sync",
+        tally.answered, tally.exceptions, tally.unanswered, tally.mangled
+    );
+    Ok(())
+}
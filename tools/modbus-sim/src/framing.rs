@@ -0,0 +1,212 @@
+//! RTU frames: the address and CRC around a PDU, and the silences that
+//! split them on the line.
+
+use modbus::rtu::{self, FrameError, Framer, MAX_FRAME_LEN, Timing};
+use sim_rng::Rng;
+
+fn random_frame(rng: &mut Rng, pdu_len: usize) -> Vec<u8> {
+    let pdu: Vec<u8> = (0..pdu_len).map(|_| rng.next_u64() as u8).collect();
+    let mut frame = vec![0; MAX_FRAME_LEN];
+    let len = rtu::encode(rng.range(0, 247) as u8, &pdu, &mut frame);
+    frame.truncate(len);
+    frame
+}
+
+/// Frames decode to what they were encoded from, and anything else is
+/// rejected.
+fn codec(rng: &mut Rng) -> Result<(), String> {
+    for _ in 0..1000 {
+        let pdu: Vec<u8> = (0..rng.range(1, 253))
+            .map(|_| rng.next_u64() as u8)
+            .collect();
+        let address = rng.range(0, 247) as u8;
+        let mut frame = vec![0; MAX_FRAME_LEN];
+        let len = rtu::encode(address, &pdu, &mut frame);
+        frame.truncate(len);
+        if rtu::decode(&frame) != Ok((address, &pdu[..])) {
+            return Err(format!("{frame:02x?} didn't decode to what it was"));
+        }
+        let bit = rng.range(0, len as u64 * 8 - 1) as usize;
+        frame[bit / 8] ^= 1 << (bit % 8);
+        if rtu::decode(&frame) != Err(FrameError::BadCrc) {
+            return Err(format!("bit {bit} flipped in {frame:02x?} went unnoticed"));
+        }
+    }
+    let cases: &[(&[u8], FrameError)] = &[
+        (&[], FrameError::TooShort),
+        (&[0x01, 0x03, 0x84], FrameError::TooShort),
+        (&[0; MAX_FRAME_LEN + 1], FrameError::TooLong),
+        (
+            &[0x01, 0x03, 0x00, 0x00, 0x00, 0x01, 0x0a, 0x84],
+            FrameError::BadCrc,
+        ),
+    ];
+    for &(frame, expected) in cases {
+        if rtu::decode(frame) != Err(expected) {
+            return Err(format!("{frame:02x?} decoded to {:?}", rtu::decode(frame)));
+        }
+    }
+    Ok(())
+}
+
+/// The gaps follow the baud rate up to 19200, and are fixed above.
+fn timing() -> Result<(), String> {
+    let cases = [
+        ((9600, 11), (1146, 1719, 4011)),
+        ((19_200, 11), (573, 860, 2006)),
+        ((19_200, 10), (521, 782, 1824)),
+        ((38_400, 11), (287, 750, 1750)),
+        ((115_200, 10), (87, 750, 1750)),
+    ];
+    for ((baud, frame_bits), (char_us, t1_5_us, t3_5_us)) in cases {
+        let timing = Timing::new(baud, frame_bits);
+        let expected = Timing {
+            char_us,
+            t1_5_us,
+            t3_5_us,
+        };
+        if timing != expected {
+            return Err(format!(
+                "{baud} baud, {frame_bits} bits: {timing:?}, expected {expected:?}"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Pushes `bytes` back to back from `start_us`, with `gaps[i]` µs more
+/// before byte `i`, and returns when the last one came in.
+fn push(framer: &mut Framer, start_us: u64, bytes: &[u8], gaps: &[(usize, u64)]) -> u64 {
+    let mut at_us = start_us;
+    for (i, &b) in bytes.iter().enumerate() {
+        at_us += framer.timing().char_us as u64;
+        at_us += gaps
+            .iter()
+            .find(|(at, _)| *at == i)
+            .map_or(0, |(_, gap)| *gap);
+        framer.push(at_us, b);
+    }
+    at_us
+}
+
+fn expect(
+    framer: &mut Framer,
+    now_us: u64,
+    expected: Option<Result<&[u8], FrameError>>,
+) -> Result<(), String> {
+    let result = framer.poll(now_us);
+    let got = result.map(|r| r.map(|()| framer.frame()));
+    if got != expected {
+        return Err(format!(
+            "at {now_us} µs got {got:02x?}, expected {expected:02x?}"
+        ));
+    }
+    Ok(())
+}
+
+/// A frame ends after 3.5 characters of silence, and breaks if there is a
+/// gap of more than 1.5 in the middle of it.
+fn silences(rng: &mut Rng) -> Result<(), String> {
+    let timing = Timing::new(9600, 11);
+    let (char_us, t1_5, t3_5) = (
+        timing.char_us as u64,
+        timing.t1_5_us as u64,
+        timing.t3_5_us as u64,
+    );
+    let mut framer = Framer::new(timing);
+    let frame = random_frame(rng, 10);
+
+    // Not over until the line has been quiet for t3.5
+    let end = push(&mut framer, 1_000, &frame, &[]);
+    expect(&mut framer, end + t3_5 - 1, None)?;
+    expect(&mut framer, end + t3_5, Some(Ok(&frame)))?;
+    if framer.deadline().is_some() || framer.frame() != frame {
+        return Err("the frame didn't stay put once polled".into());
+    }
+
+    // A gap of t1.5 is fine, one more µs isn't
+    let gap = t1_5 - char_us;
+    let end = push(&mut framer, end + t3_5, &frame, &[(5, gap)]);
+    expect(&mut framer, end + t3_5, Some(Ok(&frame)))?;
+    let end = push(&mut framer, end + t3_5, &frame, &[(5, gap + 1)]);
+    expect(&mut framer, end + t3_5, Some(Err(FrameError::Interrupted)))?;
+    if !framer.frame().is_empty() {
+        return Err("a broken frame was kept".into());
+    }
+
+    // The rest of a broken frame is dropped up to the next silence, and
+    // the frame after that is fine
+    let end = push(&mut framer, end + t3_5, &frame, &[(3, t3_5 - char_us - 1)]);
+    expect(&mut framer, end + t3_5, Some(Err(FrameError::Interrupted)))?;
+    let end = push(&mut framer, end + t3_5, &frame, &[]);
+    expect(&mut framer, end + t3_5, Some(Ok(&frame)))?;
+
+    // A silence of t3.5 starts a new frame, even if the last one wasn't
+    // polled
+    let other = random_frame(rng, 6);
+    let end = push(&mut framer, end + t3_5, &frame, &[]);
+    let end = push(&mut framer, end + t3_5 - char_us, &other, &[]);
+    expect(&mut framer, end + t3_5, Some(Ok(&other)))?;
+
+    // As long as the buffer, and not a byte more
+    let longest = random_frame(rng, MAX_FRAME_LEN - 3);
+    let end = push(&mut framer, end + t3_5, &longest, &[]);
+    expect(&mut framer, end + t3_5, Some(Ok(&longest)))?;
+    let mut too_long = longest.clone();
+    too_long.push(0);
+    let end = push(&mut framer, end + t3_5, &too_long, &[]);
+    expect(&mut framer, end + t3_5, Some(Err(FrameError::TooLong)))?;
+
+    // A UART error breaks the frame
+    let end = push(&mut framer, end + t3_5, &frame, &[]);
+    framer.interrupt(end);
+    expect(&mut framer, end + t3_5, Some(Err(FrameError::Interrupted)))?;
+
+    // And nothing at all is nothing
+    expect(&mut framer, end + 10 * t3_5, None)?;
+    Ok(())
+}
+
+/// Frames handed over in chunks, as a UART does, come out the same as one
+/// byte at a time, a little late as the executor gets to them.
+fn chunks(rng: &mut Rng) -> Result<(), String> {
+    for baud in [9600, 19_200, 115_200] {
+        let timing = Timing::new(baud, 11);
+        let char_us = timing.char_us as u64;
+        let mut framer = Framer::new(timing);
+        let mut now_us = 0;
+        for _ in 0..200 {
+            let len = rng.range(1, 100) as usize;
+            let frame = random_frame(rng, len);
+            // When each byte comes in, nearly back to back
+            let mut arrivals = Vec::new();
+            for _ in &frame {
+                now_us += char_us + rng.range(0, char_us / 50);
+                arrivals.push(now_us);
+            }
+            let mut i = 0;
+            while i < frame.len() {
+                let n = (rng.range(1, 8) as usize).min(frame.len() - i);
+                let latency = rng.range(0, char_us / 5);
+                framer.push_chunk(arrivals[i + n - 1] + latency, &frame[i..i + n]);
+                i += n;
+            }
+            let end = framer
+                .deadline()
+                .ok_or("no deadline after a frame came in")?;
+            expect(&mut framer, end, Some(Ok(&frame)))?;
+            now_us = end + rng.range(0, 10 * char_us);
+        }
+    }
+    Ok(())
+}
+
+pub fn run_scenario(seed: u64) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    codec(&mut rng)?;
+    timing()?;
+    silences(&mut rng)?;
+    chunks(&mut rng)?;
+    println!("  frames encode and decode, split at t3.5, break past t1.5, and survive chunking");
+    Ok(())
+}
@@ -0,0 +1,424 @@
+//! The Modbus TCP gateway of the modbus-tcp-gateway ROM on the host, in
+//! front of simulated RTU devices, over real sockets. It runs the same
+//! `modbus::tcp::serve` and `Queue` as the ROM, with each client and the bus
+//! in a thread of their own, and the bus taking as long as the line would.
+//!
+//! The scenario has clients hammer it at once, and `--serve` leaves it
+//! running for trying other Modbus TCP clients against.
+
+use std::{
+    future::Future,
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    pin::pin,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+    time::{Duration, Instant},
+};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use modbus::{
+    pdu::{EXCEPTION_FLAG, Exception, Request, Response},
+    rtu::{self, MAX_FRAME_LEN, Timing},
+    tcp::{self, HEADER_LEN, Header, MAX_ADU_LEN},
+};
+use sim_rng::Rng;
+
+use crate::{
+    line::Line,
+    memory::Memory,
+    pdu::{answer, apply, check_response, random_request},
+};
+
+const MASTER: usize = 0;
+const TABLE_LEN: u16 = 64;
+/// How long the devices get to answer, as in the ROM.
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(250);
+/// As in the ROM.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// How many clients can be connected at once, as in the ROM.
+const CLIENTS: usize = 4;
+
+type Queue = tcp::Queue<CriticalSectionRawMutex, CLIENTS>;
+
+/// Devices on a simulated line, which the gateway is the master of.
+pub struct Bus {
+    line: Line,
+    devices: Vec<(u8, Memory)>,
+    response: Vec<u8>,
+    queue: Arc<Queue>,
+    stats: Arc<Stats>,
+}
+
+impl Bus {
+    pub fn new(rng: &mut Rng, timing: Timing, addresses: &[u8]) -> Self {
+        Self {
+            line: Line::new(timing, 1 + addresses.len()),
+            devices: addresses
+                .iter()
+                .map(|&address| (address, Memory::random(rng, TABLE_LEN as usize)))
+                .collect(),
+            response: Vec::new(),
+            queue: Arc::new(Queue::new()),
+            stats: Arc::new(Stats::default()),
+        }
+    }
+
+    pub fn memory(&self, address: u8) -> Option<&Memory> {
+        self.devices
+            .iter()
+            .find(|(a, _)| *a == address)
+            .map(|(_, memory)| memory)
+    }
+
+    /// Sends a request, and returns the response PDU if one comes back, as
+    /// `Rtu::transact` does. Takes as long as that would on the line.
+    fn transact_on_line(&mut self, address: u8, pdu: &[u8]) -> Option<Vec<u8>> {
+        let start_us = self.line.now_us;
+        let mut frame = [0; MAX_FRAME_LEN];
+        let len = rtu::encode(address, pdu, &mut frame);
+        self.line.send(MASTER, &frame[..len], |_| 0);
+        let received = self.line.settle();
+
+        let mut answered = None;
+        for (i, (device, memory)) in self.devices.iter_mut().enumerate() {
+            let Some(Ok(frame)) = &received[1 + i] else {
+                continue;
+            };
+            match rtu::decode(frame) {
+                Ok((to, pdu)) if to == *device => answered = Some((i, answer(memory, pdu))),
+                _ => (),
+            }
+        }
+        let response = match answered {
+            Some((i, response)) => {
+                let len = rtu::encode(address, &response, &mut frame);
+                self.line.send(1 + i, &frame[..len], |_| 0);
+                match &self.line.settle()[MASTER] {
+                    Some(Ok(frame)) => rtu::decode(frame).ok().map(|(_, pdu)| pdu.to_vec()),
+                    _ => None,
+                }
+            }
+            None => {
+                self.line.now_us += RESPONSE_TIMEOUT.as_micros() as u64;
+                None
+            }
+        };
+        thread::sleep(Duration::from_micros(self.line.now_us - start_us));
+        response
+    }
+}
+
+impl tcp::Bus for Bus {
+    async fn transact(&mut self, address: u8, pdu: &[u8]) -> Option<&[u8]> {
+        let response = self.transact_on_line(address, pdu);
+        // What lined up while the bus was busy with this one
+        let waiting = self.queue.waiting();
+        self.stats.most_waiting.fetch_max(waiting, Ordering::SeqCst);
+        self.stats.forwarded.fetch_add(1, Ordering::SeqCst);
+        self.response = response?;
+        Some(&self.response)
+    }
+}
+
+/// How busy the bus got.
+#[derive(Default)]
+pub struct Stats {
+    /// The most requests that were lined up for the bus at once, besides
+    /// the one on it.
+    most_waiting: AtomicUsize,
+    forwarded: AtomicUsize,
+}
+
+/// Runs a future on the current thread, which sleeps until it is woken.
+fn block_on<F: Future>(future: F) -> F::Output {
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+/// A client connection, the way `tcp::serve` reads and writes it. Reads
+/// give up after `IDLE_TIMEOUT`, as in the ROM.
+struct Connection(TcpStream);
+
+impl embedded_io_async::ErrorType for Connection {
+    type Error = embedded_io_async::ErrorKind;
+}
+
+impl embedded_io_async::Read for Connection {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.0
+            .read(buf)
+            .map_err(|_| embedded_io_async::ErrorKind::Other)
+    }
+}
+
+impl embedded_io_async::Write for Connection {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.0
+            .write(buf)
+            .map_err(|_| embedded_io_async::ErrorKind::Other)
+    }
+}
+
+/// Accepts clients on `listener`, up to `CLIENTS` at a time, and forwards
+/// their requests to `bus` in the order they come in.
+pub fn start(listener: TcpListener, mut bus: Bus) -> Arc<Stats> {
+    let queue = bus.queue.clone();
+    let stats = bus.stats.clone();
+    {
+        let queue = queue.clone();
+        thread::spawn(move || block_on(queue.run(&mut bus)));
+    }
+    let listener = Arc::new(listener);
+    for id in 0..CLIENTS {
+        let listener = listener.clone();
+        let queue = queue.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if stream.set_read_timeout(Some(IDLE_TIMEOUT)).is_err() {
+                    continue;
+                }
+                let mut conn = Connection(stream);
+                // Like the ROM, hang up once the client does, or sends
+                // something that isn't Modbus
+                let _ = block_on(tcp::serve(&mut conn, &*queue, id));
+            }
+        });
+    }
+    stats
+}
+
+/// A Modbus TCP client, which can have several requests in flight.
+struct Client {
+    stream: TcpStream,
+    transaction: u16,
+}
+
+impl Client {
+    fn connect(addr: SocketAddr, transaction: u16) -> Result<Self, String> {
+        let stream = TcpStream::connect(addr).map_err(|e| format!("connecting: {e}"))?;
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .map_err(|e| e.to_string())?;
+        Ok(Self {
+            stream,
+            transaction,
+        })
+    }
+
+    /// Sends a request, and returns its transaction id.
+    fn send(&mut self, unit: u8, pdu: &[u8]) -> Result<u16, String> {
+        let header = Header {
+            transaction: self.transaction,
+            unit,
+            pdu_len: pdu.len(),
+        };
+        self.transaction = self.transaction.wrapping_add(1);
+        let mut adu = [0; MAX_ADU_LEN];
+        let len = header.encode(pdu, &mut adu);
+        self.stream
+            .write_all(&adu[..len])
+            .map_err(|e| format!("sending: {e}"))?;
+        Ok(header.transaction)
+    }
+
+    /// Waits for the response to the request with `transaction`, for
+    /// `unit`, which should be the next one.
+    fn receive(&mut self, transaction: u16, unit: u8) -> Result<Vec<u8>, String> {
+        let mut header = [0; HEADER_LEN];
+        self.stream
+            .read_exact(&mut header)
+            .map_err(|e| format!("receiving: {e}"))?;
+        let header = Header::decode(&header).map_err(|e| format!("{e:?}"))?;
+        if header.transaction != transaction || header.unit != unit {
+            return Err(format!(
+                "expected transaction {transaction} for unit {unit}, got {header:?}"
+            ));
+        }
+        let mut pdu = vec![0; header.pdu_len];
+        self.stream
+            .read_exact(&mut pdu)
+            .map_err(|e| format!("receiving: {e}"))?;
+        Ok(pdu)
+    }
+
+    fn request(&mut self, unit: u8, pdu: &[u8]) -> Result<Vec<u8>, String> {
+        let transaction = self.send(unit, pdu)?;
+        self.receive(transaction, unit)
+    }
+
+    /// Whether the gateway hung up.
+    fn closed(&mut self) -> bool {
+        matches!(self.stream.read(&mut [0]), Ok(0) | Err(_))
+    }
+}
+
+/// Sends random requests to the device at `unit`, a few at a time without
+/// waiting in between, and checks the answers against `memory`, which is
+/// what only this client changes.
+fn hammer(addr: SocketAddr, unit: u8, mut memory: Memory, seed: u64) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    // Around the point where the transaction ids wrap
+    let mut client = Client::connect(addr, 0xfff0 + rng.range(0, 15) as u16)?;
+    for _ in 0..25 {
+        let pdus: Vec<Vec<u8>> = (0..rng.range(1, 3))
+            .map(|_| random_request(&mut rng, TABLE_LEN))
+            .collect();
+        let mut transactions = Vec::new();
+        for pdu in &pdus {
+            transactions.push(client.send(unit, pdu)?);
+        }
+        for (pdu, transaction) in pdus.iter().zip(transactions) {
+            let response = client.receive(transaction, unit)?;
+            let request = Request::decode(pdu).map_err(|e| format!("{pdu:02x?}: {e:?}"))?;
+            check_response(&memory, &request, Response::decode(&request, &response))?;
+            apply(&mut memory, &request);
+        }
+    }
+    Ok(())
+}
+
+/// Requests the gateway answers itself, and ones it hangs up on.
+fn refusals(addr: SocketAddr) -> Result<(), String> {
+    let mut client = Client::connect(addr, 0)?;
+    let read = [0x03, 0x00, 0x00, 0x00, 0x01];
+
+    // Nothing at that address, after the timeout
+    let start = Instant::now();
+    let response = client.request(99, &read)?;
+    if response
+        != [
+            0x03 | EXCEPTION_FLAG,
+            Exception::GatewayTargetFailedToRespond.code(),
+        ]
+        || start.elapsed() < RESPONSE_TIMEOUT
+    {
+        return Err(format!(
+            "unit 99 answered {response:02x?} after {:?}",
+            start.elapsed()
+        ));
+    }
+    // Not an address, right away
+    for unit in [0, 248, 255] {
+        let response = client.request(unit, &read)?;
+        if response
+            != [
+                0x03 | EXCEPTION_FLAG,
+                Exception::GatewayPathUnavailable.code(),
+            ]
+        {
+            return Err(format!("unit {unit} answered {response:02x?}"));
+        }
+    }
+    // The device's own exceptions go through
+    let response = client.request(1, &[0x07])?;
+    if response != [0x07 | EXCEPTION_FLAG, Exception::IllegalFunction.code()] {
+        return Err(format!("function 7 answered {response:02x?}"));
+    }
+
+    // Anything that isn't Modbus ends the connection
+    let mut adu = [0; MAX_ADU_LEN];
+    let header = Header {
+        transaction: 1,
+        unit: 1,
+        pdu_len: 5,
+    };
+    let len = header.encode(&read, &mut adu);
+    adu[3] = 1;
+    client
+        .stream
+        .write_all(&adu[..len])
+        .map_err(|e| e.to_string())?;
+    if !client.closed() {
+        return Err("still connected after another protocol".into());
+    }
+    let mut client = Client::connect(addr, 0)?;
+    client
+        .stream
+        .write_all(&[0, 1, 0, 0, 0, 1, 1])
+        .map_err(|e| e.to_string())?;
+    if !client.closed() {
+        return Err("still connected after an empty PDU".into());
+    }
+    Ok(())
+}
+
+pub fn run_scenario(seed: u64) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    let units = [1, 2, 3, 4];
+    let bus = Bus::new(&mut rng, Timing::new(115_200, 11), &units);
+    let memories: Vec<Memory> = units
+        .iter()
+        .map(|&unit| bus.memory(unit).unwrap().clone())
+        .collect();
+    let listener = TcpListener::bind("127.0.0.1:0").map_err(|e| e.to_string())?;
+    let addr = listener.local_addr().map_err(|e| e.to_string())?;
+    let stats = start(listener, bus);
+
+    let clients: Vec<_> = units
+        .iter()
+        .zip(memories)
+        .enumerate()
+        .map(|(i, (&unit, memory))| {
+            let seed = seed.wrapping_add(i as u64);
+            thread::spawn(move || hammer(addr, unit, memory, seed))
+        })
+        .collect();
+    for client in clients {
+        client
+            .join()
+            .map_err(|_| "a client panicked".to_string())??;
+    }
+    refusals(addr)?;
+
+    let most_waiting = stats.most_waiting.load(Ordering::SeqCst);
+    if most_waiting < 2 {
+        return Err("the clients never had to wait for each other".into());
+    }
+    println!(
+        "  {} requests forwarded, up to {most_waiting} waiting for the bus, all answered in order",
+        stats.forwarded.load(Ordering::SeqCst)
+    );
+    Ok(())
+}
+
+/// Runs the gateway on `addr` until killed, in front of devices 1 to 4 at
+/// 19200 baud, with 64 of everything.
+pub fn serve_forever(addr: &str, seed: u64) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    let bus = Bus::new(&mut rng, Timing::new(19_200, 11), &[1, 2, 3, 4]);
+    let listener = TcpListener::bind(addr).map_err(|e| format!("{addr}: {e}"))?;
+    println!(
+        "Serving Modbus TCP on {}, devices 1 to 4 have {TABLE_LEN} of everything",
+        listener.local_addr().map_err(|e| e.to_string())?
+    );
+    let stats = start(listener, bus);
+    let mut forwarded = 0;
+    loop {
+        thread::sleep(Duration::from_secs(1));
+        let now = stats.forwarded.load(Ordering::SeqCst);
+        if now != forwarded {
+            println!("{now} requests forwarded");
+            forwarded = now;
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! An RS-485 line, with a framer for every device on it.
+
+use modbus::rtu::{FrameError, Framer, Timing};
+
+/// What a device made of what went over the line.
+pub type Received = Option<Result<Vec<u8>, FrameError>>;
+
+pub struct Line {
+    pub now_us: u64,
+    pub timing: Timing,
+    pub framers: Vec<Framer>,
+}
+
+impl Line {
+    pub fn new(timing: Timing, devices: usize) -> Self {
+        Self {
+            now_us: 0,
+            timing,
+            framers: vec![Framer::new(timing); devices],
+        }
+    }
+
+    /// Sends `bytes` from device `from`, one character after the other,
+    /// with `gap(i)` µs more of silence before byte `i`. Every other device
+    /// gets each byte as its stop bit ends. The sender ignores its own
+    /// echo, like `Rtu::send`.
+    pub fn send(&mut self, from: usize, bytes: &[u8], mut gap: impl FnMut(usize) -> u64) {
+        for (i, &b) in bytes.iter().enumerate() {
+            self.now_us += self.timing.char_us as u64 + gap(i);
+            for (_, framer) in self
+                .framers
+                .iter_mut()
+                .enumerate()
+                .filter(|(d, _)| *d != from)
+            {
+                framer.push(self.now_us, b);
+            }
+        }
+        self.framers[from].reset();
+    }
+
+    /// Lets the line go quiet for long enough to end a frame, and tells
+    /// what every device got.
+    pub fn settle(&mut self) -> Vec<Received> {
+        self.now_us += self.timing.t3_5_us as u64;
+        let now_us = self.now_us;
+        self.framers
+            .iter_mut()
+            .map(|framer| {
+                let result = framer.poll(now_us)?;
+                Some(result.map(|()| framer.frame().to_vec()))
+            })
+            .collect()
+    }
+}
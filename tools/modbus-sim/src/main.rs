@@ -0,0 +1,94 @@
+//! Runs the Modbus code from `libs/modbus` on the host: the PDU and RTU
+//! codecs, the timing between frames, a master talking to slaves over a
+//! simulated RS-485 line, and a Modbus TCP gateway in front of them.
+//!
+//! Usage: modbus-sim [--seed N] [SCENARIO..]
+//!        modbus-sim [--seed N] --serve ADDR
+//!
+//! Without any scenarios, all of them are run. The exit code tells if they
+//! all passed.
+//!
+//! With `--serve`, it runs the gateway on ADDR instead, for trying other
+//! Modbus TCP clients against, e.g. `--serve 127.0.0.1:5020` and then
+//! `mbpoll -a 1 -r 1 -c 8 -p 5020 127.0.0.1`.
+
+mod crc;
+mod exchange;
+mod framing;
+mod gateway;
+mod line;
+mod mbap;
+mod memory;
+mod pdu;
+
+use std::process::ExitCode;
+
+type Scenario = fn(u64) -> Result<(), String>;
+
+const SCENARIOS: &[(&str, Scenario)] = &[
+    ("crc", crc::run_scenario),
+    ("pdu", pdu::run_scenario),
+    ("framing", framing::run_scenario),
+    ("exchange", exchange::run_scenario),
+    ("mbap", mbap::run_scenario),
+    ("gateway", gateway::run_scenario),
+];
+
+fn main() -> ExitCode {
+    let mut seed = 1;
+    let mut selected = Vec::new();
+    let mut serve = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--seed" {
+            match args.next().and_then(|s| s.parse().ok()) {
+                Some(s) => seed = s,
+                None => {
+                    eprintln!("Error: --seed needs a number");
+                    return ExitCode::FAILURE;
+                }
+            }
+        } else if arg == "--serve" {
+            match args.next() {
+                Some(addr) => serve = Some(addr),
+                None => {
+                    eprintln!("Error: --serve needs an address");
+                    return ExitCode::FAILURE;
+                }
+            }
+        } else if SCENARIOS.iter().any(|(name, _)| *name == arg) {
+            selected.push(arg);
+        } else {
+            eprintln!("Error: unknown scenario {arg}");
+            return ExitCode::FAILURE;
+        }
+    }
+
+    if let Some(addr) = serve {
+        if let Err(e) = gateway::serve_forever(&addr, seed) {
+            eprintln!("Error: {e}");
+        }
+        return ExitCode::FAILURE;
+    }
+
+    let mut failed = false;
+    for (name, scenario) in SCENARIOS {
+        if !selected.is_empty() && !selected.iter().any(|s| s == name) {
+            continue;
+        }
+        println!("{name}:");
+        match scenario(seed) {
+            Ok(()) => println!("{name}: ok"),
+            Err(e) => {
+                println!("{name}: FAILED: {e}");
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
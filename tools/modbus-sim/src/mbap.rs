@@ -0,0 +1,110 @@
+//! The MBAP header of Modbus TCP, and what a gateway answers with when the
+//! bus doesn't.
+
+use modbus::{
+    pdu::{EXCEPTION_FLAG, Exception, MAX_PDU_LEN},
+    tcp::{self, HEADER_LEN, Header, HeaderError, MAX_ADU_LEN},
+};
+use sim_rng::Rng;
+
+/// Headers survive encoding and decoding, and the ones that can't be
+/// Modbus are refused.
+fn headers(rng: &mut Rng) -> Result<(), String> {
+    for _ in 0..1000 {
+        let pdu: Vec<u8> = (0..rng.range(1, MAX_PDU_LEN as u64))
+            .map(|_| rng.next_u64() as u8)
+            .collect();
+        let header = Header {
+            transaction: rng.next_u64() as u16,
+            unit: rng.next_u64() as u8,
+            pdu_len: pdu.len(),
+        };
+        let mut adu = [0; MAX_ADU_LEN];
+        let len = header.encode(&pdu, &mut adu);
+        if len != HEADER_LEN + pdu.len() || adu[HEADER_LEN..len] != pdu[..] {
+            return Err(format!("{header:?} encoded to {:02x?}", &adu[..len]));
+        }
+        let decoded = Header::decode(adu[..HEADER_LEN].try_into().unwrap());
+        if decoded != Ok(header) {
+            return Err(format!("{header:?} decoded to {decoded:?}"));
+        }
+    }
+
+    // From the spec: transaction 0x15 01, unit 0xff, read holding register
+    let adu = [
+        0x15, 0x01, 0x00, 0x00, 0x00, 0x06, 0xff, 0x03, 0x00, 0x04, 0x00, 0x01,
+    ];
+    let expected = Header {
+        transaction: 0x1501,
+        unit: 0xff,
+        pdu_len: 5,
+    };
+    if Header::decode(adu[..HEADER_LEN].try_into().unwrap()) != Ok(expected) {
+        return Err(format!("{adu:02x?} didn't decode to {expected:?}"));
+    }
+
+    let cases: &[([u8; HEADER_LEN], HeaderError)] = &[
+        ([0, 1, 0x00, 0x01, 0, 6, 1], HeaderError::Protocol(1)),
+        ([0, 1, 0x47, 0x45, 0, 6, 1], HeaderError::Protocol(0x4745)),
+        // No function code, or more than a PDU
+        ([0, 1, 0, 0, 0x00, 0x00, 1], HeaderError::Length(0)),
+        ([0, 1, 0, 0, 0x00, 0x01, 1], HeaderError::Length(1)),
+        ([0, 1, 0, 0, 0x00, 0xff, 1], HeaderError::Length(255)),
+        ([0, 1, 0, 0, 0xff, 0xff, 1], HeaderError::Length(0xffff)),
+    ];
+    for (bytes, expected) in cases {
+        if Header::decode(bytes) != Err(*expected) {
+            return Err(format!(
+                "{bytes:02x?} decoded to {:?}",
+                Header::decode(bytes)
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Only unit ids that are RTU addresses go to the bus, and whatever comes
+/// back has to be for the same function.
+fn gateway() -> Result<(), String> {
+    for unit in 0..=255u8 {
+        let expected = match unit {
+            1..=247 => Ok(unit),
+            _ => Err(Exception::GatewayPathUnavailable),
+        };
+        if tcp::route(unit) != expected {
+            return Err(format!("unit {unit} routed to {:?}", tcp::route(unit)));
+        }
+    }
+
+    let request = [0x03, 0x00, 0x00, 0x00, 0x01];
+    let failed = [0x03 | EXCEPTION_FLAG, 0x0b];
+    let cases: &[(Option<&[u8]>, &[u8])] = &[
+        (Some(&[0x03, 0x02, 0x12, 0x34]), &[0x03, 0x02, 0x12, 0x34]),
+        // The device's exceptions go through as they are
+        (Some(&[0x83, 0x02]), &[0x83, 0x02]),
+        // Nothing, or not for this request
+        (None, &failed),
+        (Some(&[]), &failed),
+        (Some(&[0x04, 0x02, 0x12, 0x34]), &failed),
+        (Some(&[0x84, 0x02]), &failed),
+    ];
+    for &(response, expected) in cases {
+        let mut out = [0; MAX_PDU_LEN];
+        let len = tcp::respond(&request, response, &mut out);
+        if out[..len] != *expected {
+            return Err(format!(
+                "answered {response:02x?} with {:02x?}, expected {expected:02x?}",
+                &out[..len]
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub fn run_scenario(seed: u64) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    headers(&mut rng)?;
+    gateway()?;
+    println!("  headers round-trip, broken ones are refused, units and answers routed right");
+    Ok(())
+}
@@ -0,0 +1,87 @@
+//! A device whose tables are plain memory.
+
+use modbus::{
+    pdu::{Bits, Exception, Registers},
+    slave::DataModel,
+};
+use sim_rng::Rng;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Memory {
+    pub coils: Vec<bool>,
+    pub inputs: Vec<bool>,
+    pub holding: Vec<u16>,
+    pub input_registers: Vec<u16>,
+}
+
+impl Memory {
+    /// Tables of `len` entries each, filled at random.
+    pub fn random(rng: &mut Rng, len: usize) -> Self {
+        Self {
+            coils: (0..len).map(|_| rng.chance(0.5)).collect(),
+            inputs: (0..len).map(|_| rng.chance(0.5)).collect(),
+            holding: (0..len).map(|_| rng.next_u64() as u16).collect(),
+            input_registers: (0..len).map(|_| rng.next_u64() as u16).collect(),
+        }
+    }
+}
+
+fn get<T: Copy>(table: &[T], address: u16) -> Result<T, Exception> {
+    table
+        .get(address as usize)
+        .copied()
+        .ok_or(Exception::IllegalDataAddress)
+}
+
+fn check_range<T>(table: &[T], address: u16, count: usize) -> Result<(), Exception> {
+    if address as usize + count > table.len() {
+        return Err(Exception::IllegalDataAddress);
+    }
+    Ok(())
+}
+
+impl DataModel for Memory {
+    fn read_coil(&mut self, address: u16) -> Result<bool, Exception> {
+        get(&self.coils, address)
+    }
+
+    fn read_discrete_input(&mut self, address: u16) -> Result<bool, Exception> {
+        get(&self.inputs, address)
+    }
+
+    fn read_holding_register(&mut self, address: u16) -> Result<u16, Exception> {
+        get(&self.holding, address)
+    }
+
+    fn read_input_register(&mut self, address: u16) -> Result<u16, Exception> {
+        get(&self.input_registers, address)
+    }
+
+    fn write_coil(&mut self, address: u16, value: bool) -> Result<(), Exception> {
+        check_range(&self.coils, address, 1)?;
+        self.coils[address as usize] = value;
+        Ok(())
+    }
+
+    fn write_register(&mut self, address: u16, value: u16) -> Result<(), Exception> {
+        check_range(&self.holding, address, 1)?;
+        self.holding[address as usize] = value;
+        Ok(())
+    }
+
+    fn write_coils(&mut self, address: u16, values: Bits) -> Result<(), Exception> {
+        check_range(&self.coils, address, values.len())?;
+        for (i, value) in values.iter().enumerate() {
+            self.coils[address as usize + i] = value;
+        }
+        Ok(())
+    }
+
+    fn write_registers(&mut self, address: u16, values: Registers) -> Result<(), Exception> {
+        check_range(&self.holding, address, values.len())?;
+        for (i, value) in values.iter().enumerate() {
+            self.holding[address as usize + i] = value;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,347 @@
+//! Encodes and decodes requests and responses, and answers requests from a
+//! device in memory, checking every answer against the tables.
+
+use modbus::{
+    pdu::{
+        Bits, Exception, MAX_PDU_LEN, MAX_READ_BITS, MAX_READ_REGISTERS, MAX_WRITE_BITS,
+        MAX_WRITE_REGISTERS, Registers, Request, Response, ResponseError,
+    },
+    slave,
+};
+use sim_rng::Rng;
+
+use crate::memory::Memory;
+
+/// A request picked at random, encoded. It is mostly within the first `len`
+/// entries of the tables, sometimes well past them.
+pub fn random_request(rng: &mut Rng, len: u16) -> Vec<u8> {
+    let count = rng.range(1, 16) as u16;
+    let address = if rng.chance(0.1) {
+        rng.range(len as u64, 0xfff0) as u16
+    } else {
+        rng.range(0, len.saturating_sub(count) as u64) as u16
+    };
+    let coils: Vec<bool> = (0..count).map(|_| rng.chance(0.5)).collect();
+    let registers: Vec<u16> = (0..count).map(|_| rng.next_u64() as u16).collect();
+    let mut bits_buf = [0; 2];
+    let mut registers_buf = [0; 32];
+    let request = match rng.range(0, 7) {
+        0 => Request::ReadCoils { address, count },
+        1 => Request::ReadDiscreteInputs { address, count },
+        2 => Request::ReadHoldingRegisters { address, count },
+        3 => Request::ReadInputRegisters { address, count },
+        4 => Request::WriteSingleCoil {
+            address,
+            value: coils[0],
+        },
+        5 => Request::WriteSingleRegister {
+            address,
+            value: registers[0],
+        },
+        6 => Request::WriteMultipleCoils {
+            address,
+            values: Bits::pack(&coils, &mut bits_buf),
+        },
+        _ => Request::WriteMultipleRegisters {
+            address,
+            values: Registers::pack(&registers, &mut registers_buf),
+        },
+    };
+    encode(&request)
+}
+
+pub fn encode(request: &Request) -> Vec<u8> {
+    let mut pdu = vec![0; MAX_PDU_LEN];
+    let len = request.encode(&mut pdu);
+    pdu.truncate(len);
+    pdu
+}
+
+/// Answers `pdu` from `memory`, as the slave would.
+pub fn answer(memory: &mut Memory, pdu: &[u8]) -> Vec<u8> {
+    let mut response = vec![0; MAX_PDU_LEN];
+    let (len, _) = slave::handle(memory, pdu, &mut response);
+    response.truncate(len);
+    response
+}
+
+/// Applies a write to `memory` the way the device should, for comparing
+/// with what it did. Returns false if it is out of range, and should have
+/// been refused.
+pub fn apply(memory: &mut Memory, request: &Request) -> bool {
+    let (address, len) = match *request {
+        Request::WriteSingleCoil { address, .. } | Request::WriteMultipleCoils { address, .. } => {
+            (address as usize, memory.coils.len())
+        }
+        Request::WriteSingleRegister { address, .. }
+        | Request::WriteMultipleRegisters { address, .. } => {
+            (address as usize, memory.holding.len())
+        }
+        _ => return true,
+    };
+    match *request {
+        Request::WriteSingleCoil { value, .. } if address < len => {
+            memory.coils[address] = value;
+        }
+        Request::WriteSingleRegister { value, .. } if address < len => {
+            memory.holding[address] = value;
+        }
+        Request::WriteMultipleCoils { values, .. } if address + values.len() <= len => {
+            for (i, value) in values.iter().enumerate() {
+                memory.coils[address + i] = value;
+            }
+        }
+        Request::WriteMultipleRegisters { values, .. } if address + values.len() <= len => {
+            for (i, value) in values.iter().enumerate() {
+                memory.holding[address + i] = value;
+            }
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// Checks the response to a read against `memory`, or that the request was
+/// refused if it is out of range.
+pub fn check_response(
+    memory: &Memory,
+    request: &Request,
+    response: Result<Response, ResponseError>,
+) -> Result<(), String> {
+    let (address, count) = match *request {
+        Request::ReadCoils { address, count }
+        | Request::ReadDiscreteInputs { address, count }
+        | Request::ReadHoldingRegisters { address, count }
+        | Request::ReadInputRegisters { address, count } => (address as usize, count as usize),
+        _ => {
+            let in_range = apply(&mut memory.clone(), request);
+            return match response {
+                Ok(Response::Written) if in_range => Ok(()),
+                Err(ResponseError::Exception(Exception::IllegalDataAddress)) if !in_range => Ok(()),
+                response => Err(format!("{request:?} got {response:?}")),
+            };
+        }
+    };
+    let end = address + count;
+    let expected = match *request {
+        Request::ReadCoils { .. } => memory.coils.get(address..end).map(bits),
+        Request::ReadDiscreteInputs { .. } => memory.inputs.get(address..end).map(bits),
+        Request::ReadHoldingRegisters { .. } => memory.holding.get(address..end).map(registers),
+        _ => memory.input_registers.get(address..end).map(registers),
+    };
+    let got = match response {
+        Ok(Response::Bits(values)) => Some(bits(&values.iter().collect::<Vec<_>>())),
+        Ok(Response::Registers(values)) => Some(registers(&values.iter().collect::<Vec<_>>())),
+        Err(ResponseError::Exception(Exception::IllegalDataAddress)) => None,
+        response => return Err(format!("{request:?} got {response:?}")),
+    };
+    if got != expected {
+        return Err(format!("{request:?} read {got:?}, expected {expected:?}"));
+    }
+    Ok(())
+}
+
+fn bits(values: &[bool]) -> String {
+    values.iter().map(|&v| if v { '1' } else { '0' }).collect()
+}
+
+fn registers(values: &[u16]) -> String {
+    format!("{values:04x?}")
+}
+
+/// Every request survives encoding and decoding, up to the largest ones.
+fn round_trips(rng: &mut Rng) -> Result<(), String> {
+    let mut pdus: Vec<Vec<u8>> = (0..1000).map(|_| random_request(rng, 64)).collect();
+    let bits = vec![true; MAX_WRITE_BITS as usize];
+    let registers = vec![0xbeef; MAX_WRITE_REGISTERS as usize];
+    let mut bits_buf = [0; MAX_PDU_LEN];
+    let mut registers_buf = [0; MAX_PDU_LEN];
+    for request in [
+        Request::ReadCoils {
+            address: 0,
+            count: MAX_READ_BITS,
+        },
+        Request::ReadHoldingRegisters {
+            address: 0xffff - MAX_READ_REGISTERS + 1,
+            count: MAX_READ_REGISTERS,
+        },
+        Request::WriteMultipleCoils {
+            address: 0,
+            values: Bits::pack(&bits, &mut bits_buf),
+        },
+        Request::WriteMultipleRegisters {
+            address: 0,
+            values: Registers::pack(&registers, &mut registers_buf),
+        },
+    ] {
+        pdus.push(encode(&request));
+    }
+    for pdu in &pdus {
+        let request = Request::decode(pdu).map_err(|e| format!("{pdu:02x?}: {e:?}"))?;
+        if encode(&request) != *pdu {
+            return Err(format!("{request:?} encodes to {:02x?}", encode(&request)));
+        }
+    }
+    Ok(())
+}
+
+/// Requests that are broken, and what they are answered with.
+fn decode_errors() -> Result<(), String> {
+    let cases: &[(&[u8], Exception)] = &[
+        (&[], Exception::IllegalFunction),
+        (&[0x07], Exception::IllegalFunction),
+        (&[0x2b, 0x0e, 0x01, 0x00], Exception::IllegalFunction),
+        // Too short, too long
+        (&[0x03, 0x00, 0x00, 0x00], Exception::IllegalDataValue),
+        (
+            &[0x03, 0x00, 0x00, 0x00, 0x01, 0x00],
+            Exception::IllegalDataValue,
+        ),
+        // Counts of 0, and past the limits
+        (&[0x01, 0x00, 0x00, 0x00, 0x00], Exception::IllegalDataValue),
+        (&[0x02, 0x00, 0x00, 0x07, 0xd1], Exception::IllegalDataValue),
+        (&[0x04, 0x00, 0x00, 0x00, 0x7e], Exception::IllegalDataValue),
+        // Past the end of the address space
+        (
+            &[0x03, 0xff, 0xff, 0x00, 0x02],
+            Exception::IllegalDataAddress,
+        ),
+        // A coil is either 0xff00 or 0x0000
+        (&[0x05, 0x00, 0x00, 0x12, 0x34], Exception::IllegalDataValue),
+        // The byte count doesn't match the count, or the data
+        (
+            &[0x0f, 0x00, 0x00, 0x00, 0x09, 0x01, 0xff],
+            Exception::IllegalDataValue,
+        ),
+        (
+            &[0x10, 0x00, 0x00, 0x00, 0x01, 0x02, 0x12],
+            Exception::IllegalDataValue,
+        ),
+        (
+            &[0x10, 0x00, 0x00, 0x00, 0x02, 0x02, 0x12, 0x34],
+            Exception::IllegalDataValue,
+        ),
+    ];
+    for &(pdu, expected) in cases {
+        match Request::decode(pdu) {
+            Err(e) if e == expected => (),
+            result => return Err(format!("{pdu:02x?} decoded to {result:?}")),
+        }
+        // And the slave answers with the exception
+        let response = answer(&mut Memory::random(&mut Rng::new(1), 8), pdu);
+        let function = pdu.first().copied().unwrap_or(0);
+        if response != [function | 0x80, expected.code()] {
+            return Err(format!("{pdu:02x?} was answered with {response:02x?}"));
+        }
+    }
+    Ok(())
+}
+
+/// Responses that don't fit the request they are for.
+fn response_errors() -> Result<(), String> {
+    let read = Request::ReadHoldingRegisters {
+        address: 0,
+        count: 2,
+    };
+    let write = Request::WriteSingleRegister {
+        address: 1,
+        value: 3,
+    };
+    let cases: &[(&Request, &[u8], Result<(), ResponseError>)] = &[
+        (&read, &[0x03, 0x04, 0, 1, 0, 2], Ok(())),
+        (
+            &read,
+            &[0x83, 0x02],
+            Err(ResponseError::Exception(Exception::IllegalDataAddress)),
+        ),
+        (
+            &read,
+            &[0x83, 0x0b],
+            Err(ResponseError::Exception(
+                Exception::GatewayTargetFailedToRespond,
+            )),
+        ),
+        (
+            &read,
+            &[0x83, 0x42],
+            Err(ResponseError::Exception(Exception::Other(0x42))),
+        ),
+        (&read, &[], Err(ResponseError::Malformed)),
+        (&read, &[0x83], Err(ResponseError::Malformed)),
+        // Another function
+        (
+            &read,
+            &[0x04, 0x04, 0, 1, 0, 2],
+            Err(ResponseError::Malformed),
+        ),
+        (&read, &[0x84, 0x02], Err(ResponseError::Malformed)),
+        // Not as many registers as asked for, or a wrong byte count
+        (&read, &[0x03, 0x02, 0, 1], Err(ResponseError::Malformed)),
+        (
+            &read,
+            &[0x03, 0x06, 0, 1, 0, 2],
+            Err(ResponseError::Malformed),
+        ),
+        (&write, &[0x06, 0x00, 0x01, 0x00, 0x03], Ok(())),
+        // Not an echo of the request
+        (
+            &write,
+            &[0x06, 0x00, 0x01, 0x00, 0x04],
+            Err(ResponseError::Malformed),
+        ),
+    ];
+    for &(request, pdu, ref expected) in cases {
+        let result = Response::decode(request, pdu).map(|_| ());
+        if result != *expected {
+            return Err(format!("{pdu:02x?} to {request:?} decoded to {result:?}"));
+        }
+    }
+    Ok(())
+}
+
+/// The slave answers random requests from its tables.
+fn answers(rng: &mut Rng) -> Result<(), String> {
+    let mut memory = Memory::random(rng, 64);
+    for _ in 0..1000 {
+        let pdu = random_request(rng, 64);
+        let request = Request::decode(&pdu).map_err(|e| format!("{pdu:02x?}: {e:?}"))?;
+        let before = memory.clone();
+        let response = answer(&mut memory, &pdu);
+        if response.len() > MAX_PDU_LEN {
+            return Err(format!("{request:?} got a response too long"));
+        }
+        check_response(&before, &request, Response::decode(&request, &response))?;
+        let mut expected = before;
+        apply(&mut expected, &request);
+        if memory != expected {
+            return Err(format!("{request:?} didn't write what it should"));
+        }
+    }
+
+    // The largest reads fit in a response
+    let mut memory = Memory::random(rng, 0x1_0000);
+    for request in [
+        Request::ReadDiscreteInputs {
+            address: 0,
+            count: MAX_READ_BITS,
+        },
+        Request::ReadInputRegisters {
+            address: 0,
+            count: MAX_READ_REGISTERS,
+        },
+    ] {
+        let response = answer(&mut memory, &encode(&request));
+        check_response(&memory, &request, Response::decode(&request, &response))?;
+    }
+    Ok(())
+}
+
+pub fn run_scenario(seed: u64) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    round_trips(&mut rng)?;
+    decode_errors()?;
+    response_errors()?;
+    answers(&mut rng)?;
+    println!("  requests round-trip, bad ones get the right exception, answers match the tables");
+    Ok(())
+}
@@ -0,0 +1,133 @@
+//! Parses arguments of every type, and reports what's wrong with them.
+
+use crate::terminal::Session;
+
+/// Runs `line`, and checks what it called and printed.
+fn expect(
+    session: &mut Session,
+    line: &str,
+    call: Option<&str>,
+    printed: &[&str],
+) -> Result<(), String> {
+    let calls = session.ctx.calls.len();
+    let output = session.run(line);
+    let called = session.ctx.calls.get(calls).map(String::as_str);
+    if called != call || output != printed {
+        return Err(format!(
+            "{line:?} called {called:?} and printed {output:?}, expected {call:?} and {printed:?}"
+        ));
+    }
+    Ok(())
+}
+
+pub fn run_scenario(_seed: u64) -> Result<(), String> {
+    let mut session = Session::new();
+    let s = &mut session;
+
+    expect(s, "", None, &[])?;
+    expect(s, "led on", Some("led true"), &[])?;
+    expect(s, "  led   off  200 ", Some("led false 200"), &[])?;
+    expect(s, "led 1 0x10", Some("led true 16"), &[])?;
+    expect(s, "led false 0", Some("led false 0"), &[])?;
+    expect(s, "set level -10", Some("set level -10"), &[])?;
+    expect(s, "set baud 5000000", Some("set baud 5000000"), &[])?;
+    expect(s, "name foo", Some("name foo"), &[])?;
+    expect(s, "setup", Some("setup"), &[])?;
+    expect(
+        s,
+        "echo  hello   world ",
+        Some("echo hello   world"),
+        &["hello   world"],
+    )?;
+
+    expect(
+        s,
+        "nope",
+        None,
+        &["Error: unknown command `nope`, try `help`"],
+    )?;
+    expect(s, "led", None, &["Error: <on> is missing"])?;
+    expect(s, "led maybe", None, &["Error: <on> must be on or off"])?;
+    expect(
+        s,
+        "led on 256",
+        None,
+        &["Error: <brightness> must be between 0 and 255"],
+    )?;
+    expect(
+        s,
+        "led on -1",
+        None,
+        &["Error: <brightness> must be between 0 and 255"],
+    )?;
+    expect(
+        s,
+        "led on bright",
+        None,
+        &["Error: <brightness> must be a number"],
+    )?;
+    expect(s, "led on 1 2", None, &["Error: too many arguments"])?;
+    expect(
+        s,
+        "set speed 1",
+        None,
+        &["Error: <what> must be one of baud, mode, level"],
+    )?;
+    expect(
+        s,
+        "set level -11",
+        None,
+        &["Error: <value> must be between -10 and 5000000"],
+    )?;
+    expect(s, "setup now", None, &["Error: too many arguments"])?;
+    expect(s, "name a b", None, &["Error: too many arguments"])?;
+    expect(s, "echo", None, &["Error: <text> is missing"])?;
+    expect(s, "fail", None, &["Error: it failed"])?;
+
+    // What doesn't fit is dropped, and the rest of the output still works
+    let output = s.run("spam");
+    if output.last().map(String::as_str) != Some("(output truncated)") || output.len() < 100 {
+        return Err(format!("spam printed {} lines", output.len()));
+    }
+    expect(
+        s,
+        "echo still here",
+        Some("echo still here"),
+        &["still here"],
+    )?;
+
+    let help = s.run("help");
+    let expected = [
+        "  help [command]          List the commands, or explain one",
+        "  history                 List the lines entered before",
+        "  led <on> [brightness]   Turn the LED on or off",
+    ];
+    if help.len() != 2 + 7 || help[..3] != expected {
+        return Err(format!("help printed {help:?}"));
+    }
+    expect(
+        s,
+        "help set",
+        None,
+        &[
+            "set <what> <value>",
+            "  Set something",
+            "  <what>  baud, mode, level",
+            "  <value>  -10 to 5000000",
+        ],
+    )?;
+    expect(
+        s,
+        "help echo",
+        None,
+        &[
+            "echo <text...>",
+            "  Print the text",
+            "  <text>  the rest of the line",
+        ],
+    )?;
+    expect(s, "help nope", None, &["Error: unknown command `nope`"])?;
+
+    println!("  arguments parsed and checked");
+    Ok(())
+}
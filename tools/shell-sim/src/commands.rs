@@ -0,0 +1,88 @@
+//! The commands the scenarios register, which log how they were called.
+
+use shell::{Arg, Args, Command, Output, Value};
+
+pub const PROMPT: &str = "sim> ";
+
+#[derive(Default)]
+pub struct Ctx {
+    /// Every call, as `name arg..`, with `-` for left out arguments.
+    pub calls: Vec<String>,
+}
+
+pub const COMMANDS: &[Command<Ctx>] = &[
+    Command {
+        name: "led",
+        args: &[Arg::bool("on"), Arg::int("brightness", 0, 255).optional()],
+        help: "Turn the LED on or off",
+        run: |ctx, args, _| log(ctx, "led", args),
+    },
+    Command {
+        name: "set",
+        args: &[
+            Arg::choice("what", &["baud", "mode", "level"]),
+            Arg::int("value", -10, 5_000_000),
+        ],
+        help: "Set something",
+        run: |ctx, args, _| log(ctx, "set", args),
+    },
+    Command {
+        name: "setup",
+        args: &[],
+        help: "Set everything up",
+        run: |ctx, args, _| log(ctx, "setup", args),
+    },
+    Command {
+        name: "echo",
+        args: &[Arg::rest("text")],
+        help: "Print the text",
+        run: echo,
+    },
+    Command {
+        name: "name",
+        args: &[Arg::word("name")],
+        help: "Name the device",
+        run: |ctx, args, _| log(ctx, "name", args),
+    },
+    Command {
+        name: "fail",
+        args: &[],
+        help: "Always fails",
+        run: |_, _, _| Err("it failed"),
+    },
+    Command {
+        name: "spam",
+        args: &[],
+        help: "Prints more than fits",
+        run: spam,
+    },
+];
+
+fn log(ctx: &mut Ctx, name: &str, args: &Args) -> Result<(), &'static str> {
+    let mut call = name.to_string();
+    for i in 0..shell::command::MAX_ARGS {
+        match args.get(i) {
+            Some(Value::Int(n)) => call += &format!(" {n}"),
+            Some(Value::Bool(b)) => call += &format!(" {b}"),
+            Some(Value::Str(s)) => call += &format!(" {s}"),
+            None => call += " -",
+        }
+    }
+    ctx.calls.push(call.trim_end_matches(" -").to_string());
+    Ok(())
+}
+
+fn echo(ctx: &mut Ctx, args: &Args, out: &mut Output) -> Result<(), &'static str> {
+    let text = args.str(0).unwrap();
+    ctx.calls.push(format!("echo {text}"));
+    writeln!(out, "{text}");
+    Ok(())
+}
+
+fn spam(ctx: &mut Ctx, _: &Args, out: &mut Output) -> Result<(), &'static str> {
+    ctx.calls.push("spam".into());
+    for i in 0..1000 {
+        writeln!(out, "line {i}");
+    }
+    Ok(())
+}
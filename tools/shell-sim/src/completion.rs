@@ -0,0 +1,72 @@
+//! Completes command names and arguments with Tab.
+
+use crate::terminal::Session;
+
+/// Types `typed` and a Tab into a fresh shell, and checks the line it
+/// completes to, and what it lists if anything.
+fn expect(typed: &str, line: &str, listed: Option<&str>) -> Result<(), String> {
+    let mut session = Session::new();
+    session.send(typed.as_bytes());
+    let row = session.screen.row();
+    let bells = session.screen.bells;
+    session.send(b"\t");
+
+    let completed = session.shell.editor().line();
+    if completed != line {
+        return Err(format!(
+            "{typed:?} completed to {completed:?}, expected {line:?}"
+        ));
+    }
+    session.check_screen()?;
+    let printed = session.screen.rows_since(row + 1);
+    if printed.first().map(String::as_str) != listed {
+        return Err(format!("{typed:?} listed {printed:?}, expected {listed:?}"));
+    }
+    if line == typed && listed.is_none() && session.screen.bells != bells + 1 {
+        return Err(format!("{typed:?} completed to nothing, without a bell"));
+    }
+    Ok(())
+}
+
+pub fn run_scenario(_seed: u64) -> Result<(), String> {
+    // Command names, including the built-in ones
+    expect("l", "led ", None)?;
+    expect("e", "echo ", None)?;
+    expect("hi", "history ", None)?;
+    expect("h", "h", Some("help  history"))?;
+    expect("se", "set", None)?;
+    expect("set", "set", Some("set  setup"))?;
+    expect("setu", "setup ", None)?;
+    expect("x", "x", None)?;
+    expect(
+        "",
+        "",
+        Some("help  history  led  set  setup  echo  name  fail  spam"),
+    )?;
+
+    // Arguments with a fixed set of values
+    expect("set ", "set ", Some("baud  mode  level"))?;
+    expect("set  m", "set  mode ", None)?;
+    expect("led o", "led o", Some("on  off"))?;
+    expect("led of", "led off ", None)?;
+    expect("led on ", "led on ", None)?;
+    expect("set baud ", "set baud ", None)?;
+    expect("name ", "name ", None)?;
+    expect("help s", "help s", Some("set  setup  spam"))?;
+    expect("help le", "help led ", None)?;
+    expect("help led ", "help led ", None)?;
+
+    // Only what's before the cursor counts
+    let mut session = Session::new();
+    session.send(b"led  x\x1b[D\x1b[Dof\t");
+    if session.shell.editor().line() != "led off  x" {
+        return Err(format!(
+            "completing mid-line made {:?}",
+            session.shell.editor().line()
+        ));
+    }
+    session.check_screen()?;
+
+    println!("  command names and arguments completed");
+    Ok(())
+}
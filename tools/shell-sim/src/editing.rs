@@ -0,0 +1,179 @@
+//! Types random keys, in the different ways terminals send them, and checks
+//! that the line and the screen follow a simple model of the editor.
+
+use shell::editor::MAX_LINE_LEN;
+use sim_rng::Rng;
+
+use crate::terminal::Session;
+
+#[derive(Copy, Clone, Debug)]
+enum Key {
+    Char(u8),
+    Left,
+    Right,
+    Home,
+    End,
+    Backspace,
+    Delete,
+    KillToStart,
+    KillToEnd,
+    KillWord,
+}
+
+const KEYS: &[Key] = &[
+    Key::Char(b'a'),
+    Key::Char(b'z'),
+    Key::Char(b' '),
+    Key::Char(b'~'),
+    Key::Left,
+    Key::Right,
+    Key::Home,
+    Key::End,
+    Key::Backspace,
+    Key::Delete,
+    Key::KillToStart,
+    Key::KillToEnd,
+    Key::KillWord,
+];
+
+/// The bytes terminals send for a key. Any of them will do.
+fn encodings(key: Key) -> Vec<Vec<u8>> {
+    let v = |s: &[u8]| s.to_vec();
+    match key {
+        Key::Char(c) => vec![vec![c]],
+        Key::Left => vec![v(b"\x1b[D"), v(b"\x1bOD"), v(b"\x02")],
+        Key::Right => vec![v(b"\x1b[C"), v(b"\x1bOC"), v(b"\x06")],
+        Key::Home => vec![
+            v(b"\x1b[H"),
+            v(b"\x1b[1~"),
+            v(b"\x1b[7~"),
+            v(b"\x1bOH"),
+            v(b"\x01"),
+        ],
+        Key::End => vec![
+            v(b"\x1b[F"),
+            v(b"\x1b[4~"),
+            v(b"\x1b[8~"),
+            v(b"\x1bOF"),
+            v(b"\x05"),
+        ],
+        Key::Backspace => vec![v(b"\x7f"), v(b"\x08")],
+        Key::Delete => vec![v(b"\x1b[3~"), v(b"\x04")],
+        Key::KillToStart => vec![v(b"\x15")],
+        Key::KillToEnd => vec![v(b"\x0b")],
+        Key::KillWord => vec![v(b"\x17")],
+    }
+}
+
+#[derive(Default)]
+struct Model {
+    line: Vec<u8>,
+    cursor: usize,
+}
+
+impl Model {
+    fn key(&mut self, key: Key) {
+        match key {
+            Key::Char(c) if self.line.len() < MAX_LINE_LEN => {
+                self.line.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            Key::Char(_) => (),
+            Key::Left => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right => self.cursor = (self.cursor + 1).min(self.line.len()),
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = self.line.len(),
+            Key::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.line.remove(self.cursor);
+            }
+            Key::Delete if self.cursor < self.line.len() => {
+                self.line.remove(self.cursor);
+            }
+            Key::Backspace | Key::Delete => (),
+            Key::KillToStart => {
+                self.line.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            Key::KillToEnd => self.line.truncate(self.cursor),
+            Key::KillWord => {
+                // Back over any spaces, then over the word
+                let mut start = self.cursor;
+                while start > 0 && self.line[start - 1] == b' ' {
+                    start -= 1;
+                }
+                while start > 0 && self.line[start - 1] != b' ' {
+                    start -= 1;
+                }
+                self.line.drain(start..self.cursor);
+                self.cursor = start;
+            }
+        }
+    }
+}
+
+fn random_edits(rng: &mut Rng) -> Result<(), String> {
+    let mut session = Session::new();
+    let mut model = Model::default();
+    let mut typed = Vec::new();
+    for _ in 0..500 {
+        // Mostly typing, so that the line gets long enough to hit the limit
+        let key = if rng.chance(0.5) {
+            Key::Char(rng.range(b' ' as u64, b'~' as u64) as u8)
+        } else {
+            *rng.pick(KEYS)
+        };
+        let encodings = encodings(key);
+        let bytes: &Vec<u8> = rng.pick(&encodings);
+        session.send(bytes);
+        model.key(key);
+        typed.push(key);
+
+        let editor = session.shell.editor();
+        if editor.line().as_bytes() != model.line || editor.cursor() != model.cursor {
+            return Err(format!(
+                "after {typed:?}, the line is {:?} with the cursor at {}, expected {:?} at {}",
+                editor.line(),
+                editor.cursor(),
+                String::from_utf8_lossy(&model.line),
+                model.cursor
+            ));
+        }
+        session.check_screen()?;
+    }
+    Ok(())
+}
+
+/// Ctrl-C drops the line, and CR LF is one Enter rather than two.
+fn cancel_and_line_endings() -> Result<(), String> {
+    let mut session = Session::new();
+    session.send(b"setup\x03");
+    session.send(b"led on\r\nled off\nled on 3\r\r");
+    session.check_screen()?;
+    let expected = ["led true", "led false", "led true 3"];
+    if session.ctx.calls != expected {
+        return Err(format!(
+            "ran {:?}, expected {expected:?}",
+            session.ctx.calls
+        ));
+    }
+    // Unknown escape sequences and control characters are dropped
+    session.send(b"\x1b[5~\x1b[1;5Cx\x00\x1b[Zy\x1bxz");
+    if session.shell.editor().line() != "xyz" {
+        return Err(format!(
+            "junk got into the line: {:?}",
+            session.shell.editor().line()
+        ));
+    }
+    session.check_screen()
+}
+
+pub fn run_scenario(seed: u64) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    for _ in 0..20 {
+        random_edits(&mut rng)?;
+    }
+    cancel_and_line_endings()?;
+    println!("  random edits match the model and the screen");
+    Ok(())
+}
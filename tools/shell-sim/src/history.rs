@@ -0,0 +1,88 @@
+//! Goes back and forth through the history with Up and Down.
+
+use shell::history::HISTORY_LEN;
+
+use crate::terminal::Session;
+
+const UP: &[u8] = b"\x1b[A";
+const DOWN: &[u8] = b"\x1b[B";
+
+fn expect_line(session: &Session, expected: &str) -> Result<(), String> {
+    let line = session.shell.editor().line();
+    if line != expected {
+        return Err(format!("the line is {line:?}, expected {expected:?}"));
+    }
+    session.check_screen()
+}
+
+fn browse() -> Result<(), String> {
+    let mut session = Session::new();
+    for line in ["setup", "led on", "led on", "  ", "name x"] {
+        session.run(line);
+    }
+    // Repeats and blank lines aren't remembered
+    let history: Vec<_> = session.shell.editor().history().iter().collect();
+    if history != ["setup", "led on", "name x"] {
+        return Err(format!("history is {history:?}"));
+    }
+
+    session.send(b"draft");
+    session.send(UP);
+    expect_line(&session, "name x")?;
+    session.send(UP);
+    session.send(b"\x10");
+    expect_line(&session, "setup")?;
+    let bells = session.screen.bells;
+    session.send(UP);
+    expect_line(&session, "setup")?;
+    if session.screen.bells != bells + 1 {
+        return Err("no bell past the oldest line".into());
+    }
+    session.send(DOWN);
+    expect_line(&session, "led on")?;
+    session.send(b"\x0e");
+    session.send(DOWN);
+    expect_line(&session, "draft")?;
+
+    // A line brought back can be edited before it runs
+    session.send(b"\x15");
+    session.send(UP);
+    session.send(UP);
+    session.send(b"\x7fff");
+    session.send(b"\r");
+    if session.ctx.calls.last().map(String::as_str) != Some("led false") {
+        return Err(format!("ran {:?}", session.ctx.calls.last()));
+    }
+    Ok(())
+}
+
+/// Only the last `HISTORY_LEN` lines are kept.
+fn overflow() -> Result<(), String> {
+    let mut session = Session::new();
+    for i in 0..HISTORY_LEN + 3 {
+        session.run(&format!("led on {i}"));
+    }
+    for _ in 0..HISTORY_LEN + 3 {
+        session.send(UP);
+    }
+    expect_line(&session, "led on 3")?;
+
+    // `history` is in the history by the time it runs
+    session.send(b"\x15");
+    let printed = session.run("history");
+    let mut expected: Vec<_> = (1..HISTORY_LEN)
+        .map(|i| format!("{i:>3}  led on {}", i + 3))
+        .collect();
+    expected.push(format!("{HISTORY_LEN:>3}  history"));
+    if printed != expected {
+        return Err(format!("history printed {printed:?}"));
+    }
+    Ok(())
+}
+
+pub fn run_scenario(_seed: u64) -> Result<(), String> {
+    browse()?;
+    overflow()?;
+    println!("  history browsed, edited and capped at {HISTORY_LEN} lines");
+    Ok(())
+}
@@ -0,0 +1,70 @@
+//! Runs the command shell from `libs/shell` on the host, typing into it
+//! like a terminal would, and checks that it behaves.
+//!
+//! Usage: shell-sim [--seed N] [SCENARIO..]
+//!
+//! Without any scenarios, all of them are run. The exit code tells if they
+//! all passed.
+
+mod arguments;
+mod commands;
+mod completion;
+mod editing;
+mod history;
+mod terminal;
+mod transport;
+
+use std::process::ExitCode;
+
+type Scenario = fn(u64) -> Result<(), String>;
+
+const SCENARIOS: &[(&str, Scenario)] = &[
+    ("editing", editing::run_scenario),
+    ("history", history::run_scenario),
+    ("completion", completion::run_scenario),
+    ("arguments", arguments::run_scenario),
+    ("transport", transport::run_scenario),
+];
+
+fn main() -> ExitCode {
+    let mut seed = 1;
+    let mut selected = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--seed" {
+            match args.next().and_then(|s| s.parse().ok()) {
+                Some(s) => seed = s,
+                None => {
+                    eprintln!("Error: --seed needs a number");
+                    return ExitCode::FAILURE;
+                }
+            }
+        } else if SCENARIOS.iter().any(|(name, _)| *name == arg) {
+            selected.push(arg);
+        } else {
+            eprintln!("Error: unknown scenario {arg}");
+            return ExitCode::FAILURE;
+        }
+    }
+
+    let mut failed = false;
+    for (name, scenario) in SCENARIOS {
+        if !selected.is_empty() && !selected.iter().any(|s| s == name) {
+            continue;
+        }
+        println!("{name}:");
+        match scenario(seed) {
+            Ok(()) => println!("{name}: ok"),
+            Err(e) => {
+                println!("{name}: FAILED: {e}");
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
@@ -0,0 +1,195 @@
+//! What sits on the other side of the shell: a terminal that shows what it
+//! is sent, and the in-memory pipe to it.
+
+use std::{collections::VecDeque, convert::Infallible};
+
+use shell::Shell;
+use sim_rng::Rng;
+
+use crate::commands::Ctx;
+
+/// Just enough of a VT100 for what the shell sends.
+#[derive(Default)]
+pub struct Screen {
+    rows: Vec<Vec<u8>>,
+    row: usize,
+    col: usize,
+    /// An escape sequence that isn't complete yet.
+    escape: Option<Vec<u8>>,
+    pub bells: usize,
+}
+
+impl Screen {
+    pub fn show(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            if let Some(escape) = &mut self.escape {
+                escape.push(b);
+                if escape.len() > 1 && (0x40..=0x7e).contains(&b) {
+                    let escape = self.escape.take().unwrap();
+                    self.csi(&escape);
+                }
+                continue;
+            }
+            match b {
+                0x1b => self.escape = Some(Vec::new()),
+                b'\r' => self.col = 0,
+                b'\n' => self.row += 1,
+                0x07 => self.bells += 1,
+                0x08 => self.col = self.col.saturating_sub(1),
+                b' '..=b'~' => {
+                    let col = self.col;
+                    let row = self.row_mut();
+                    if row.len() <= col {
+                        row.resize(col + 1, b' ');
+                    }
+                    row[col] = b;
+                    self.col += 1;
+                }
+                _ => panic!("unexpected byte {b:#04x} sent to the terminal"),
+            }
+        }
+    }
+
+    /// `escape` starts at the `[`, and ends with the final byte.
+    fn csi(&mut self, escape: &[u8]) {
+        assert_eq!(escape[0], b'[', "unexpected escape sequence");
+        let params = std::str::from_utf8(&escape[1..escape.len() - 1]).unwrap();
+        let n = params.parse().unwrap_or(1);
+        match escape[escape.len() - 1] {
+            b'K' => {
+                let col = self.col;
+                self.row_mut().truncate(col);
+            }
+            b'D' => self.col = self.col.saturating_sub(n),
+            b'C' => self.col += n,
+            b'J' => {
+                self.rows.clear();
+                self.row = 0;
+            }
+            b'H' => (self.row, self.col) = (0, 0),
+            c => panic!("unexpected escape sequence ending in {}", c as char),
+        }
+    }
+
+    fn row_mut(&mut self) -> &mut Vec<u8> {
+        if self.rows.len() <= self.row {
+            self.rows.resize(self.row + 1, Vec::new());
+        }
+        &mut self.rows[self.row]
+    }
+
+    /// The row the cursor is on, with trailing spaces trimmed.
+    pub fn current(&self) -> String {
+        self.rows
+            .get(self.row)
+            .map(|r| String::from_utf8_lossy(r).trim_end().to_string())
+            .unwrap_or_default()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.col
+    }
+
+    /// The rows from `from` up to the one the cursor is on.
+    pub fn rows_since(&self, from: usize) -> Vec<String> {
+        (from..self.row)
+            .map(|i| {
+                let row = self.rows.get(i).map(Vec::as_slice).unwrap_or_default();
+                String::from_utf8_lossy(row).trim_end().to_string()
+            })
+            .collect()
+    }
+
+    pub fn row(&self) -> usize {
+        self.row
+    }
+}
+
+/// A shell with a screen attached, driven a byte at a time.
+pub struct Session {
+    pub shell: Shell<Ctx>,
+    pub ctx: Ctx,
+    pub screen: Screen,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        let mut session = Self {
+            shell: Shell::new(crate::commands::COMMANDS, crate::commands::PROMPT),
+            ctx: Ctx::default(),
+            screen: Screen::default(),
+        };
+        session.shell.start();
+        session.show();
+        session
+    }
+
+    pub fn send(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            if self.shell.feed(b) {
+                self.show();
+                self.shell.execute(&mut self.ctx);
+            }
+            self.show();
+        }
+    }
+
+    /// Sends a line, and returns what it printed.
+    pub fn run(&mut self, line: &str) -> Vec<String> {
+        self.send(line.as_bytes());
+        let from = self.screen.row() + 1;
+        self.send(b"\r");
+        self.screen.rows_since(from)
+    }
+
+    fn show(&mut self) {
+        self.screen.show(self.shell.output());
+        self.shell.clear_output();
+    }
+
+    /// Checks that the screen shows the prompt and the line, with the
+    /// cursor in the right place.
+    pub fn check_screen(&self) -> Result<(), String> {
+        let editor = self.shell.editor();
+        let expected = format!("{}{}", crate::commands::PROMPT, editor.line());
+        let cursor = crate::commands::PROMPT.len() + editor.cursor();
+        if self.screen.current() != expected.trim_end() || self.screen.cursor() != cursor {
+            return Err(format!(
+                "screen shows {:?} with the cursor at {}, expected {expected:?} at {cursor}",
+                self.screen.current(),
+                self.screen.cursor()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Hands the input over in chunks of random size, and takes the output in
+/// writes of random size, the way a UART would.
+pub struct Pipe<'a> {
+    pub input: VecDeque<u8>,
+    pub output: Vec<u8>,
+    pub rng: &'a mut Rng,
+}
+
+impl embedded_io_async::ErrorType for Pipe<'_> {
+    type Error = Infallible;
+}
+
+impl embedded_io_async::Read for Pipe<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+        let n = (self.rng.range(1, buf.len() as u64) as usize).min(self.input.len());
+        for (b, input) in buf.iter_mut().zip(self.input.drain(..n)) {
+            *b = input;
+        }
+        Ok(n)
+    }
+}
+
+impl embedded_io_async::Write for Pipe<'_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
+        let n = (self.rng.range(1, 32) as usize).min(buf.len());
+        self.output.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
@@ -0,0 +1,50 @@
+//! Runs a session through `Shell::run` over an in-memory pipe, with the
+//! input split up and the output taken in pieces at random.
+
+use embassy_futures::block_on;
+use shell::Shell;
+use sim_rng::Rng;
+
+use crate::{
+    commands::{COMMANDS, Ctx, PROMPT},
+    terminal::{Pipe, Screen},
+};
+
+fn session(rng: &mut Rng) -> Result<(), String> {
+    let input = b"led on\r\nset baud 9600\rset mode \x7f\x7f\x7f\x7f\x15\x1b[A\x1b[A\r\
+        sp\tam\x03echo a\x1b[Db\x1b[C\tc\r\nhelp\nspam\r";
+    let mut pipe = Pipe {
+        input: input.iter().copied().collect(),
+        output: Vec::new(),
+        rng,
+    };
+    let mut shell = Shell::new(COMMANDS, PROMPT);
+    let mut ctx = Ctx::default();
+    let Ok(()) = block_on(shell.run(&mut pipe, &mut ctx));
+
+    let expected = ["led true", "set baud 9600", "led true", "echo bac", "spam"];
+    if ctx.calls != expected {
+        return Err(format!("ran {:?}, expected {expected:?}", ctx.calls));
+    }
+
+    // Everything was written, and makes sense to a terminal
+    let mut screen = Screen::default();
+    screen.show(&pipe.output);
+    if screen.current() != PROMPT.trim_end() {
+        return Err(format!("ended with {:?} on the screen", screen.current()));
+    }
+    let text = String::from_utf8_lossy(&pipe.output);
+    if !text.contains("(output truncated)") || !text.contains("Turn the LED on or off") {
+        return Err("output went missing".into());
+    }
+    Ok(())
+}
+
+pub fn run_scenario(seed: u64) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    for _ in 0..50 {
+        session(&mut rng)?;
+    }
+    println!("  sessions over a pipe ran to the end");
+    Ok(())
+}
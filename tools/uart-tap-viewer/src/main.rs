@@ -0,0 +1,274 @@
+//! Renders what the `uart-tap` ROM captures as one timeline, with both
+//! directions interleaved in hex and ASCII.
+//!
+//! Usage:
+//!   uart-tap-viewer --port PORT [--save FILE] [--gap MS]
+//!   uart-tap-viewer --input FILE [--gap MS]
+//!
+//! `--save` keeps what the ROM sends as is, for `--input` to render again
+//! later. Quiet spells of at least `--gap` ms, 10 by default, are marked:
+//!
+//! ```text
+//!    0.000000 A  41 54 0d 0a                                      AT..
+//!           ~  12.7 ms quiet
+//!    0.012700 B  4f 4b 0d 0a                                      OK..
+//!           ~  517.4 ms quiet
+//!    0.530114 A  ! break
+//! ```
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    process::ExitCode,
+    time::Duration,
+};
+
+// See the `uart-tap` ROM for the framing
+const MSG_DATA: u8 = 0;
+const MSG_ERROR: u8 = 1;
+const MSG_LOG: u8 = 2;
+const MSG_BREAK: u8 = 3;
+
+const FLAG_IDLE: u8 = 1 << 0;
+
+const ERROR_OVERFLOW: u8 = 0;
+const ERROR_FRAMING: u8 = 1;
+const ERROR_PARITY: u8 = 2;
+const ERROR_GLITCH: u8 = 3;
+
+const BYTES_PER_LINE: usize = 16;
+
+#[derive(Default)]
+struct Args {
+    port: Option<String>,
+    input: Option<String>,
+    save: Option<String>,
+    gap: Option<String>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args::default();
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| iter.next())
+                .ok_or_else(|| format!("{name} needs a value"))
+        };
+        match name.as_str() {
+            "--port" => args.port = Some(value()?),
+            "--input" => args.input = Some(value()?),
+            "--save" => args.save = Some(value()?),
+            "--gap" => args.gap = Some(value()?),
+            _ => return Err(format!("unknown argument {name}")),
+        }
+    }
+    if args.port.is_some() == args.input.is_some() {
+        return Err("either --port or --input is required".into());
+    }
+    if args.save.is_some() && args.input.is_some() {
+        return Err("--save only makes sense with --port".into());
+    }
+    Ok(args)
+}
+
+fn direction_name(direction: u8) -> char {
+    match direction {
+        0 => 'A',
+        1 => 'B',
+        _ => '?',
+    }
+}
+
+fn error_name(error: u8) -> &'static str {
+    match error {
+        ERROR_OVERFLOW => "overflow, bytes were lost",
+        ERROR_FRAMING => "framing error, the wrong baud rate or format",
+        ERROR_PARITY => "parity error",
+        ERROR_GLITCH => "glitch on the line",
+        _ => "unknown error",
+    }
+}
+
+fn ascii(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+/// Keeps track of where we are on the timeline, to mark the gaps in it.
+struct Timeline {
+    gap_us: u64,
+    /// The first timestamp, which the others are shown relative to.
+    start_us: Option<u64>,
+    /// When the last record ended on the line.
+    last_end_us: u64,
+    /// Whether the line went quiet after the last record, so that the time
+    /// until the next one is a gap rather than a FIFO filling up.
+    last_idle: bool,
+}
+
+impl Timeline {
+    fn new(gap: Duration) -> Self {
+        Self {
+            gap_us: gap.as_micros() as u64,
+            start_us: None,
+            last_end_us: 0,
+            last_idle: true,
+        }
+    }
+
+    /// Prints the gap before a record that starts at `at_us` and takes
+    /// `took_us` on the line, if there is one, and returns the time to show
+    /// for the record.
+    fn advance(
+        &mut self,
+        out: &mut impl Write,
+        at_us: u64,
+        took_us: u64,
+        idle: bool,
+    ) -> io::Result<String> {
+        let first = self.start_us.is_none();
+        let start_us = *self.start_us.get_or_insert(at_us);
+        let quiet_us = at_us.saturating_sub(self.last_end_us);
+        if !first && self.last_idle && quiet_us >= self.gap_us {
+            writeln!(out, "{:>11}  {:.1} ms quiet", "~", quiet_us as f64 / 1000.0)?;
+        }
+        self.last_end_us = self.last_end_us.max(at_us + took_us);
+        self.last_idle = idle;
+        let since = at_us.saturating_sub(start_us);
+        Ok(format!("{}.{:06}", since / 1_000_000, since % 1_000_000))
+    }
+}
+
+fn handle_message(message: &[u8], timeline: &mut Timeline, out: &mut impl Write) -> io::Result<()> {
+    let header = |rest: &[u8]| -> Option<(char, u64)> {
+        let at_us = u64::from_le_bytes(rest.get(1..9)?.try_into().unwrap());
+        Some((direction_name(rest[0]), at_us))
+    };
+    match message.split_first() {
+        Some((&MSG_DATA, rest)) if rest.len() >= 14 => {
+            let (direction, at_us) = header(rest).unwrap();
+            let idle = rest[9] & FLAG_IDLE != 0;
+            let took_us = u32::from_le_bytes(rest[10..14].try_into().unwrap());
+            let time = timeline.advance(out, at_us, took_us as u64, idle)?;
+            for (i, line) in rest[14..].chunks(BYTES_PER_LINE).enumerate() {
+                let hex: Vec<_> = line.iter().map(|b| format!("{b:02x}")).collect();
+                let time = if i == 0 { time.as_str() } else { "" };
+                writeln!(
+                    out,
+                    "{time:>11} {direction}  {:<width$}  {}",
+                    hex.join(" "),
+                    ascii(line),
+                    width = BYTES_PER_LINE * 3 - 1
+                )?;
+            }
+            out.flush()
+        }
+        Some((&MSG_ERROR, rest)) if rest.len() == 10 => {
+            let (direction, at_us) = header(rest).unwrap();
+            let time = timeline.advance(out, at_us, 0, true)?;
+            writeln!(out, "{time:>11} {direction}  ! {}", error_name(rest[9]))?;
+            out.flush()
+        }
+        Some((&MSG_BREAK, rest)) if rest.len() == 9 => {
+            let (direction, at_us) = header(rest).unwrap();
+            let time = timeline.advance(out, at_us, 0, true)?;
+            writeln!(out, "{time:>11} {direction}  ! break")?;
+            out.flush()
+        }
+        Some((&MSG_LOG, text)) => {
+            eprintln!("tap: {}", String::from_utf8_lossy(text));
+            Ok(())
+        }
+        _ => {
+            eprintln!("Ignoring unknown message");
+            Ok(())
+        }
+    }
+}
+
+fn view(args: &Args) -> io::Result<()> {
+    let gap = match &args.gap {
+        Some(gap) => gap
+            .parse()
+            .map_err(|_| io::Error::other("--gap must be a number of ms"))?,
+        None => 10,
+    };
+    let mut input: Box<dyn Read> = match (&args.port, &args.input) {
+        // The baud rate is ignored by the USB-Serial-JTAG peripheral, but we
+        // still need to set one.
+        (Some(port), _) => Box::new(
+            serialport::new(port, 115_200)
+                .timeout(Duration::from_secs(3600))
+                .open()?,
+        ),
+        (None, Some(path)) => Box::new(File::open(path)?),
+        (None, None) => unreachable!(),
+    };
+    let mut save = match &args.save {
+        Some(path) => Some(BufWriter::new(File::create(path)?)),
+        None => None,
+    };
+
+    let mut out = io::stdout().lock();
+    let mut timeline = Timeline::new(Duration::from_millis(gap));
+    let mut buf = [0; 4096];
+    let mut frame = Vec::new();
+    loop {
+        let n = match input.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e),
+        };
+        if let Some(save) = &mut save {
+            save.write_all(&buf[..n])?;
+            save.flush()?;
+        }
+        for &b in &buf[..n] {
+            if b != 0 {
+                frame.push(b);
+                continue;
+            }
+            // Anything before the first zero byte is likely the tail end of
+            // a message we only saw half of, which fails to decode.
+            match cobs::decode_vec(&frame) {
+                Ok(message) => handle_message(&message, &mut timeline, &mut out)?,
+                Err(()) => eprintln!("Ignoring {} bytes of garbage", frame.len()),
+            }
+            frame.clear();
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match view(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! Renders the output of the `wifi-scanner` ROM as a live table.
+//!
+//! The ROM has to be built with either the `json` or the `csv` feature.
+//! Lines that are not scan results (such as log output) are ignored.
+//!
+//! Usage: wifi-scan-viewer [PORT]
+//!
+//! If no port is given, the scan results are read from stdin.
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Read, Write},
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+/// Access points that have not been seen for this many scans are dropped
+/// from the table.
+const FORGET_AFTER_SCANS: u32 = 5;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Message {
+    Scan {
+        scan: u32,
+        count: usize,
+        shown: usize,
+    },
+    Ap(Ap),
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Ap {
+    scan: u32,
+    bssid: String,
+    ssid: String,
+    hidden: bool,
+    channel: u8,
+    secondary: String,
+    rssi: i8,
+    auth: String,
+    country: String,
+}
+
+const CSV_HEADER: &str = "scan,bssid,ssid,hidden,channel,secondary,rssi,auth,country";
+
+fn parse_line(line: &str) -> Option<Message> {
+    let line = line.trim();
+    if line.starts_with('{') {
+        serde_json::from_str(line).ok()
+    } else if line == CSV_HEADER {
+        None
+    } else {
+        parse_csv(line).map(Message::Ap)
+    }
+}
+
+/// Parses a single CSV row. The only quoted field is the SSID.
+fn parse_csv(line: &str) -> Option<Ap> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = line.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+
+    let [
+        scan,
+        bssid,
+        ssid,
+        hidden,
+        channel,
+        secondary,
+        rssi,
+        auth,
+        country,
+    ] = <[String; 9]>::try_from(fields).ok()?;
+    Some(Ap {
+        scan: scan.parse().ok()?,
+        bssid,
+        ssid,
+        hidden: hidden.parse().ok()?,
+        channel: channel.parse().ok()?,
+        secondary,
+        rssi: rssi.parse().ok()?,
+        auth,
+        country,
+    })
+}
+
+#[derive(Default)]
+struct Table {
+    aps: HashMap<String, Ap>,
+    scan: u32,
+    count: usize,
+    shown: usize,
+}
+
+impl Table {
+    /// Returns true if the message belongs to a scan that is newer than the
+    /// one in the table, which means the table is complete and it is time
+    /// to redraw it.
+    fn is_new_scan(&self, message: &Message) -> bool {
+        // CSV output does not have scan messages, so we have to look at the
+        // access points themselves.
+        match message {
+            Message::Scan { scan, .. } => *scan != self.scan,
+            Message::Ap(ap) => ap.scan != self.scan,
+        }
+    }
+
+    fn update(&mut self, message: Message) {
+        match message {
+            Message::Scan { scan, count, shown } => {
+                self.scan = scan;
+                self.count = count;
+                self.shown = shown;
+            }
+            Message::Ap(ap) => {
+                if ap.scan != self.scan {
+                    self.scan = ap.scan;
+                    self.count = 0;
+                    self.shown = 0;
+                }
+                self.aps.insert(ap.bssid.clone(), ap);
+            }
+        }
+    }
+
+    fn render(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let scan = self.scan;
+        self.aps
+            .retain(|_, ap| scan.wrapping_sub(ap.scan) < FORGET_AFTER_SCANS);
+
+        let mut aps: Vec<&Ap> = self.aps.values().collect();
+        aps.sort_by(|a, b| b.rssi.cmp(&a.rssi).then_with(|| a.bssid.cmp(&b.bssid)));
+
+        // Clear the screen and move the cursor to the top left corner
+        write!(out, "\x1b[2J\x1b[H")?;
+        write!(out, "Scan {scan}")?;
+        if self.count > self.shown {
+            write!(
+                out,
+                " ({} of {} access points shown)",
+                self.shown, self.count
+            )?;
+        }
+        writeln!(out)?;
+        writeln!(
+            out,
+            "{:<17}  {:<32}  {:>4}  {:<5}  {:>4}  {:<13}  {:<7}  {:>3}",
+            "BSSID", "SSID", "CH", "2ND", "RSSI", "AUTH", "COUNTRY", "AGE"
+        )?;
+        for ap in aps {
+            let ssid = if ap.hidden { "<hidden>" } else { &ap.ssid };
+            writeln!(
+                out,
+                "{:<17}  {:<32}  {:>4}  {:<5}  {:>4}  {:<13}  {:<7}  {:>3}",
+                ap.bssid,
+                ssid,
+                ap.channel,
+                ap.secondary,
+                ap.rssi,
+                ap.auth,
+                ap.country,
+                scan.wrapping_sub(ap.scan),
+            )?;
+        }
+        out.flush()
+    }
+}
+
+fn open_input() -> io::Result<Box<dyn Read>> {
+    match std::env::args().nth(1) {
+        Some(port) => {
+            // The baud rate is ignored by the USB-Serial-JTAG peripheral, but
+            // we still need to set one.
+            let port = serialport::new(port, 115_200)
+                .timeout(Duration::from_secs(3600))
+                .open()?;
+            Ok(Box::new(port))
+        }
+        None => Ok(Box::new(io::stdin())),
+    }
+}
+
+fn main() -> io::Result<()> {
+    let input = BufReader::new(open_input()?);
+    let mut stdout = io::stdout().lock();
+    let mut table = Table::default();
+
+    for line in input.split(b'\n') {
+        let line = line?;
+        let Some(message) = parse_line(&String::from_utf8_lossy(&line)) else {
+            continue;
+        };
+        if table.is_new_scan(&message) {
+            table.render(&mut stdout)?;
+        }
+        table.update(message);
+    }
+
+    table.render(&mut stdout)
+}
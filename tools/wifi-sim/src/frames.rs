@@ -0,0 +1,307 @@
+//! Management frames the way the sniffer hands them to
+//! `wifi_analysis::frame::parse`, from the frame control field on, and what
+//! the parser makes of them whole, cut short, and mangled.
+
+use sim_rng::Rng;
+use wifi_analysis::frame::{self, Frame};
+
+/// Our access point, and a station associated with it.
+pub const AP: [u8; 6] = [0x24, 0xa4, 0x3c, 0x11, 0x22, 0x33];
+pub const STATION: [u8; 6] = [0x8c, 0x85, 0x90, 0xaa, 0xbb, 0xcc];
+pub const SSID: &str = "HomeNet";
+
+/// A beacon of `AP` for `SSID` on channel 6, with rates, the channel, a TIM
+/// and WPA2.
+const BEACON: &str = "
+    80 00 00 00 ff ff ff ff ff ff 24 a4 3c 11 22 33 24 a4 3c 11 22 33 30 7c
+    0d 5b 3a 9c 05 00 00 00 64 00 11 04
+    00 07 48 6f 6d 65 4e 65 74
+    01 08 82 84 8b 96 0c 12 18 24
+    03 01 06
+    05 04 00 01 00 00
+    30 14 01 00 00 0f ac 04 01 00 00 0f ac 04 01 00 00 0f ac 02 0c 00";
+
+/// The same network answering a probe from `STATION`.
+const PROBE_RESPONSE: &str = "
+    50 00 3a 01 8c 85 90 aa bb cc 24 a4 3c 11 22 33 24 a4 3c 11 22 33 40 7c
+    4f 6e 3a 9c 05 00 00 00 64 00 11 04
+    00 07 48 6f 6d 65 4e 65 74
+    01 08 82 84 8b 96 0c 12 18 24
+    03 01 06";
+
+/// A network that keeps its SSID to itself.
+const HIDDEN_BEACON: &str = "
+    80 00 00 00 ff ff ff ff ff ff 02 11 22 33 44 55 02 11 22 33 44 55 c0 02
+    a0 41 0b 17 00 00 00 00 64 00 31 04
+    00 00
+    01 08 82 84 8b 96 0c 12 18 24
+    03 01 0b";
+
+/// `AP` sending `STATION` away, because it left the network (reason 8).
+const DISASSOC: &str = "
+    a0 00 3a 01 8c 85 90 aa bb cc 24 a4 3c 11 22 33 24 a4 3c 11 22 33 50 7c
+    08 00";
+
+/// What a deauther sends: from `AP` to everyone, class 3 frame from a
+/// nonassociated station (reason 7).
+const DEAUTH: &str = "
+    c0 00 3a 01 ff ff ff ff ff ff 24 a4 3c 11 22 33 24 a4 3c 11 22 33 00 00
+    07 00";
+
+/// Frames that aren't for the monitor: QoS data, an ACK and an action frame.
+const OTHERS: &[&str] = &[
+    "88 41 2c 00 24 a4 3c 11 22 33 8c 85 90 aa bb cc 24 a4 3c 11 22 33 60 7c
+     00 00 aa aa 03 00 00 00 08 00 45 00",
+    "d4 00 00 00 8c 85 90 aa bb cc",
+    "d0 00 3c 00 8c 85 90 aa bb cc 24 a4 3c 11 22 33 24 a4 3c 11 22 33 70 7c
+     03 01 01 02 10 00 00",
+];
+
+pub fn hex(s: &str) -> Vec<u8> {
+    s.split_whitespace()
+        .map(|b| u8::from_str_radix(b, 16).unwrap())
+        .collect()
+}
+
+/// A beacon of `bssid` for `ssid`, on the same template as `BEACON`.
+pub fn beacon(bssid: [u8; 6], ssid: &str) -> Vec<u8> {
+    let mut frame = hex(BEACON);
+    frame[10..16].copy_from_slice(&bssid);
+    frame[16..22].copy_from_slice(&bssid);
+    let ssid_at = 24 + 12;
+    let ssid_len = frame[ssid_at + 1] as usize;
+    let mut element = vec![0, ssid.len() as u8];
+    element.extend_from_slice(ssid.as_bytes());
+    frame.splice(ssid_at..ssid_at + 2 + ssid_len, element);
+    frame
+}
+
+/// A probe response from `bssid` for `ssid`.
+pub fn probe_response(bssid: [u8; 6], ssid: &str) -> Vec<u8> {
+    let mut frame = beacon(bssid, ssid);
+    frame[..24].copy_from_slice(&hex(PROBE_RESPONSE)[..24]);
+    frame[10..16].copy_from_slice(&bssid);
+    frame[16..22].copy_from_slice(&bssid);
+    frame
+}
+
+/// A deauthentication from `src` to `dst`.
+pub fn deauth(src: [u8; 6], dst: [u8; 6], reason: u16) -> Vec<u8> {
+    let mut frame = hex(DEAUTH);
+    frame[4..10].copy_from_slice(&dst);
+    frame[10..16].copy_from_slice(&src);
+    frame[16..22].copy_from_slice(&src);
+    frame[24..26].copy_from_slice(&reason.to_le_bytes());
+    frame
+}
+
+/// Every frame with what it parses to, and how much of it the parser needs
+/// to get there.
+fn fixtures() -> Vec<(&'static str, Vec<u8>, Frame<'static>, usize)> {
+    let announcement = |bssid, ssid, beacon| Frame::Announcement {
+        bssid,
+        ssid,
+        beacon,
+    };
+    let kick = |dst, reason, deauth| Frame::Kick {
+        src: AP,
+        dst,
+        bssid: AP,
+        reason,
+        deauth,
+    };
+    let ssid = SSID.as_bytes();
+    let mut fixtures = vec![
+        (
+            "beacon",
+            hex(BEACON),
+            announcement(AP, ssid, true),
+            24 + 12 + 2 + ssid.len(),
+        ),
+        (
+            "probe response",
+            hex(PROBE_RESPONSE),
+            announcement(AP, ssid, false),
+            24 + 12 + 2 + ssid.len(),
+        ),
+        (
+            "hidden beacon",
+            hex(HIDDEN_BEACON),
+            announcement([0x02, 0x11, 0x22, 0x33, 0x44, 0x55], b"", true),
+            24 + 12 + 2,
+        ),
+        ("disassociation", hex(DISASSOC), kick(STATION, 8, false), 26),
+        (
+            "deauthentication",
+            hex(DEAUTH),
+            kick([0xff; 6], 7, true),
+            26,
+        ),
+    ];
+    for other in OTHERS {
+        fixtures.push(("other", hex(other), Frame::Other, 0));
+    }
+    fixtures
+}
+
+/// The frames parse to what they are, and cut short to anything less than
+/// what the parser needs, they parse to nothing.
+fn whole_and_truncated() -> Result<(), String> {
+    for (name, data, expected, needed) in fixtures() {
+        let parsed = frame::parse(&data);
+        if parsed != expected {
+            return Err(format!(
+                "{name} parsed to {parsed:?}, expected {expected:?}"
+            ));
+        }
+        for len in 0..data.len() {
+            let parsed = frame::parse(&data[..len]);
+            let expected = if len < needed { Frame::Other } else { expected };
+            if parsed != expected {
+                return Err(format!(
+                    "{name} cut to {len} bytes parsed to {parsed:?}, expected {expected:?}"
+                ));
+            }
+        }
+    }
+
+    // The templates for the monitor scenario
+    let twin = [0x02, 0xde, 0xad, 0xbe, 0xef, 0x01];
+    let cases = [
+        (
+            beacon(twin, "Other"),
+            Frame::Announcement {
+                bssid: twin,
+                ssid: b"Other",
+                beacon: true,
+            },
+        ),
+        (
+            probe_response(twin, ""),
+            Frame::Announcement {
+                bssid: twin,
+                ssid: b"",
+                beacon: false,
+            },
+        ),
+        (
+            deauth(twin, STATION, 1),
+            Frame::Kick {
+                src: twin,
+                dst: STATION,
+                bssid: twin,
+                reason: 1,
+                deauth: true,
+            },
+        ),
+    ];
+    for (data, expected) in cases {
+        let parsed = frame::parse(&data);
+        if parsed != expected {
+            return Err(format!(
+                "{data:02x?} parsed to {parsed:?}, expected {expected:?}"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Broken elements in announcements make for nothing, not a panic or an
+/// SSID that isn't there.
+fn malformed() -> Result<(), String> {
+    let ssid_at = 24 + 12;
+    let mut cases = Vec::new();
+
+    // The SSID runs past the end of the frame
+    let mut data = hex(BEACON);
+    data.truncate(ssid_at + 2 + SSID.len());
+    data[ssid_at + 1] += 1;
+    cases.push(("SSID past the end", data));
+
+    let mut data = hex(BEACON);
+    data[ssid_at + 1] = 0xff;
+    cases.push(("SSID of 255 bytes", data));
+
+    // No SSID element at all
+    let mut data = hex(BEACON);
+    data.drain(ssid_at..ssid_at + 2 + SSID.len());
+    cases.push(("no SSID", data));
+
+    // An element before it claims more than is left
+    let mut data = hex(BEACON);
+    data.insert(ssid_at, 0xdd);
+    data.insert(ssid_at + 1, 0xf0);
+    cases.push(("element past the end", data));
+
+    // Only the fixed fields
+    let mut data = hex(PROBE_RESPONSE);
+    data.truncate(ssid_at);
+    cases.push(("no elements", data));
+
+    for (name, data) in cases {
+        let parsed = frame::parse(&data);
+        if parsed != Frame::Other {
+            return Err(format!("{name}: parsed to {parsed:?}"));
+        }
+    }
+
+    // The SSID doesn't have to come first
+    let mut data = hex(BEACON);
+    let rates: Vec<u8> = data
+        .drain(ssid_at + 2 + SSID.len()..ssid_at + 2 + SSID.len() + 10)
+        .collect();
+    data.splice(ssid_at..ssid_at, rates);
+    let expected = Frame::Announcement {
+        bssid: AP,
+        ssid: SSID.as_bytes(),
+        beacon: true,
+    };
+    if frame::parse(&data) != expected {
+        return Err(format!(
+            "SSID after the rates parsed to {:?}",
+            frame::parse(&data)
+        ));
+    }
+    Ok(())
+}
+
+/// Garbage and damaged frames never make the parser panic, and it only
+/// finds something in management frames that are long enough.
+fn fuzz(rng: &mut Rng) -> Result<(), String> {
+    let templates: Vec<Vec<u8>> = fixtures().into_iter().map(|(_, data, ..)| data).collect();
+    for i in 0..100_000 {
+        let mut data = if rng.chance(0.5) {
+            rng.pick(&templates).clone()
+        } else {
+            let len = rng.range(0, 128) as usize;
+            (0..len).map(|_| rng.next_u64() as u8).collect()
+        };
+        if !data.is_empty() {
+            for _ in 0..rng.range(1, 4) {
+                let at = rng.range(0, data.len() as u64 - 1) as usize;
+                data[at] = rng.next_u64() as u8;
+            }
+            data.truncate(rng.range(0, data.len() as u64) as usize);
+        }
+        let parsed = frame::parse(&data);
+        let needed = match parsed {
+            Frame::Announcement { ssid, .. } => 24 + 12 + 2 + ssid.len(),
+            Frame::Kick { .. } => 26,
+            Frame::Other => continue,
+        };
+        // Management frames are type 0
+        if data.len() < needed || data[0] & 0b1100 != 0 {
+            return Err(format!("frame {i}: {data:02x?} parsed to {parsed:?}"));
+        }
+    }
+    Ok(())
+}
+
+pub fn run_scenario(seed: u64) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    whole_and_truncated()?;
+    malformed()?;
+    fuzz(&mut rng)?;
+    println!("  frames parsed whole, cut short, broken and at random as expected");
+    Ok(())
+}
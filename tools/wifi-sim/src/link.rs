@@ -0,0 +1,249 @@
+//! Feeds `wifi_analysis::link::LinkMonitor` the samples a station would
+//! take of its access point: first by hand around the thresholds, then a
+//! noisy signal that fades out and comes back, which must not make it flap.
+
+use sim_rng::Rng;
+use wifi_analysis::link::{
+    HISTORY_LEN, HYSTERESIS, LinkEvent, LinkMonitor, LinkSample, PhyMode, ROAM_AFTER, WEAK_RSSI,
+};
+
+const CHANNEL: u8 = 6;
+/// Standard deviation of a sample, in dB.
+const NOISE_DB: f64 = 2.0;
+const NOISY_SAMPLES: usize = 10_000;
+/// How many samples it takes the signal to fade out and come back.
+const FADE_SAMPLES: usize = 2000;
+
+fn sample(rssi: i8) -> LinkSample {
+    LinkSample {
+        rssi,
+        channel: CHANNEL,
+        phy: PhyMode::N,
+    }
+}
+
+/// Records the samples in turn, and checks what comes out of each.
+fn expect(
+    monitor: &mut LinkMonitor,
+    steps: &[(LinkSample, Option<LinkEvent>)],
+) -> Result<(), String> {
+    for (i, &(sample, expected)) in steps.iter().enumerate() {
+        let event = monitor.record(sample);
+        if event != expected {
+            return Err(format!(
+                "step {i}: {sample:?} gave {event:?}, expected {expected:?}"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Weak is strictly below `WEAK_RSSI`, and good again only from
+/// `HYSTERESIS` above it.
+fn thresholds() -> Result<(), String> {
+    let recovered = WEAK_RSSI + HYSTERESIS;
+    let mut monitor = LinkMonitor::new();
+    expect(
+        &mut monitor,
+        &[
+            (sample(-50), None),
+            (sample(WEAK_RSSI), None),
+            (
+                sample(WEAK_RSSI - 1),
+                Some(LinkEvent::Degraded {
+                    rssi: WEAK_RSSI - 1,
+                }),
+            ),
+            // Still weak anywhere in between
+            (sample(WEAK_RSSI), None),
+            (sample(recovered - 1), None),
+            (sample(WEAK_RSSI - 1), None),
+            (
+                sample(recovered),
+                Some(LinkEvent::Recovered { rssi: recovered }),
+            ),
+            (sample(recovered - 1), None),
+            (sample(WEAK_RSSI), None),
+            (sample(-90), Some(LinkEvent::Degraded { rssi: -90 })),
+            (sample(-40), Some(LinkEvent::Recovered { rssi: -40 })),
+        ],
+    )?;
+
+    // A signal jumping back and forth over the threshold, but never by
+    // more than the hysteresis, is weak once and stays that way
+    let mut monitor = LinkMonitor::new();
+    let mut events = Vec::new();
+    for i in 0..100 {
+        let rssi = if i % 2 == 0 {
+            WEAK_RSSI - 1
+        } else {
+            recovered - 1
+        };
+        events.extend(monitor.record(sample(rssi)));
+    }
+    let flaps = events
+        .iter()
+        .filter(|e| !matches!(e, LinkEvent::Roam))
+        .count();
+    if events.first()
+        != Some(&LinkEvent::Degraded {
+            rssi: WEAK_RSSI - 1,
+        })
+        || flaps != 1
+    {
+        return Err(format!("hovering on the threshold gave {events:?}"));
+    }
+    Ok(())
+}
+
+/// A roam is asked for after `ROAM_AFTER` weak samples, and again after as
+/// many more, until the signal recovers.
+fn roaming() -> Result<(), String> {
+    let weak = sample(WEAK_RSSI - 10);
+    let mut monitor = LinkMonitor::new();
+    let mut steps = vec![(weak, Some(LinkEvent::Degraded { rssi: weak.rssi }))];
+    for round in 0..3 {
+        // The sample that degraded the link counts as the first
+        let first = if round == 0 { 2 } else { 1 };
+        steps.extend((first..ROAM_AFTER).map(|_| (weak, None)));
+        steps.push((weak, Some(LinkEvent::Roam)));
+    }
+    expect(&mut monitor, &steps)?;
+
+    // Recovering starts the count from scratch
+    let mut monitor = LinkMonitor::new();
+    let mut steps = vec![(weak, Some(LinkEvent::Degraded { rssi: weak.rssi }))];
+    steps.extend((2..ROAM_AFTER).map(|_| (weak, None)));
+    steps.push((sample(-50), Some(LinkEvent::Recovered { rssi: -50 })));
+    steps.push((weak, Some(LinkEvent::Degraded { rssi: weak.rssi })));
+    steps.extend((2..ROAM_AFTER).map(|_| (weak, None)));
+    steps.push((weak, Some(LinkEvent::Roam)));
+    expect(&mut monitor, &steps)?;
+
+    // So does a reset after reconnecting
+    let mut monitor = LinkMonitor::new();
+    let mut steps = vec![(weak, Some(LinkEvent::Degraded { rssi: weak.rssi }))];
+    steps.extend((2..ROAM_AFTER).map(|_| (weak, None)));
+    expect(&mut monitor, &steps)?;
+    monitor.reset();
+    steps.push((weak, Some(LinkEvent::Roam)));
+    expect(&mut monitor, &steps)
+}
+
+/// The channel is compared with the previous sample, and loses out to a
+/// change in the signal when both happen at once.
+fn channels() -> Result<(), String> {
+    let on = |channel, rssi| LinkSample {
+        rssi,
+        channel,
+        phy: PhyMode::N,
+    };
+    let mut monitor = LinkMonitor::new();
+    expect(
+        &mut monitor,
+        &[
+            (on(1, -50), None),
+            (on(1, -50), None),
+            (
+                on(11, -50),
+                Some(LinkEvent::ChannelChanged { from: 1, to: 11 }),
+            ),
+            (on(11, -50), None),
+            (on(6, -90), Some(LinkEvent::Degraded { rssi: -90 })),
+            (
+                on(1, -90),
+                Some(LinkEvent::ChannelChanged { from: 6, to: 1 }),
+            ),
+            (on(11, -50), Some(LinkEvent::Recovered { rssi: -50 })),
+        ],
+    )?;
+
+    // After a reset there is nothing to compare with
+    monitor.reset();
+    expect(&mut monitor, &[(on(6, -50), None), (on(6, -50), None)])?;
+
+    // The statistics only cover the last `HISTORY_LEN` samples
+    let mut monitor = LinkMonitor::new();
+    if monitor.average_rssi().is_some() {
+        return Err("a new monitor has an average".into());
+    }
+    monitor.record(sample(-20));
+    for i in 0..HISTORY_LEN as i8 {
+        monitor.record(sample(-60 - i % 5));
+    }
+    let stats = (
+        monitor.min_rssi(),
+        monitor.average_rssi(),
+        monitor.max_rssi(),
+    );
+    if stats != (Some(-64), Some(-61), Some(-60)) {
+        return Err(format!("min/avg/max over the history were {stats:?}"));
+    }
+    Ok(())
+}
+
+/// A signal that fades out over a while and comes back, with noise on top.
+/// Without the hysteresis every sample that crosses the threshold would be
+/// an event.
+fn noisy(rng: &mut Rng) -> Result<(), String> {
+    let mut monitor = LinkMonitor::new();
+    let mut weak = false;
+    let mut weak_run = 0;
+    let mut changes = 0;
+    let mut crossings = 0;
+    let mut previous_below = false;
+    for i in 0..NOISY_SAMPLES {
+        // Down from -60 dBm to -85 dBm and back, a few times
+        let phase = (i % FADE_SAMPLES) as f64 / FADE_SAMPLES as f64;
+        let level = -60.0 - 25.0 * (1.0 - (phase * std::f64::consts::TAU).cos()) / 2.0;
+        let rssi = (level + NOISE_DB * rng.normal()).round().clamp(-100.0, 0.0) as i8;
+
+        let below = rssi < WEAK_RSSI;
+        crossings += (below != previous_below) as usize;
+        previous_below = below;
+
+        let event = monitor.record(sample(rssi));
+        match event {
+            Some(LinkEvent::Degraded { .. }) if !weak && below => {
+                weak = true;
+                weak_run = 1;
+                changes += 1;
+            }
+            Some(LinkEvent::Recovered { .. }) if weak && rssi >= WEAK_RSSI + HYSTERESIS => {
+                weak = false;
+                changes += 1;
+            }
+            Some(LinkEvent::Roam) if weak && below => weak_run = 0,
+            None => (),
+            _ => return Err(format!("sample {i} at {rssi} dBm gave {event:?}")),
+        }
+        if weak && below && event.is_none() {
+            weak_run += 1;
+            if weak_run >= ROAM_AFTER {
+                return Err(format!("no roam after {weak_run} weak samples"));
+            }
+        }
+    }
+    // Every fade goes weak, and it ends on a strong signal. The noise still
+    // gets over the hysteresis now and then, but far less often than it
+    // crosses the threshold.
+    if changes < 2 * NOISY_SAMPLES / FADE_SAMPLES || changes % 2 != 0 || changes * 4 > crossings {
+        return Err(format!(
+            "the link changed {changes} times, for {crossings} crossings of the threshold"
+        ));
+    }
+    println!(
+        "  the link went weak or recovered {changes} times, for {crossings} crossings of the threshold"
+    );
+    Ok(())
+}
+
+pub fn run_scenario(seed: u64) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    thresholds()?;
+    roaming()?;
+    channels()?;
+    noisy(&mut rng)?;
+    println!("  thresholds, hysteresis, roaming and channel changes all as expected");
+    Ok(())
+}
@@ -0,0 +1,67 @@
+//! Runs the code from `libs/wifi-analysis` on the host, against signals and
+//! frames made up to look like what the ROMs get from the radio, and checks
+//! that they behave.
+//!
+//! Usage: wifi-sim [--seed N] [SCENARIO..]
+//!
+//! Without any scenarios, all of them are run. The exit code tells if they
+//! all passed.
+
+mod frames;
+mod link;
+mod monitor;
+mod tracker;
+
+use std::process::ExitCode;
+
+type Scenario = fn(u64) -> Result<(), String>;
+
+const SCENARIOS: &[(&str, Scenario)] = &[
+    ("link", link::run_scenario),
+    ("tracker", tracker::run_scenario),
+    ("frames", frames::run_scenario),
+    ("monitor", monitor::run_scenario),
+];
+
+fn main() -> ExitCode {
+    let mut seed = 1;
+    let mut selected = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--seed" {
+            match args.next().and_then(|s| s.parse().ok()) {
+                Some(s) => seed = s,
+                None => {
+                    eprintln!("Error: --seed needs a number");
+                    return ExitCode::FAILURE;
+                }
+            }
+        } else if SCENARIOS.iter().any(|(name, _)| *name == arg) {
+            selected.push(arg);
+        } else {
+            eprintln!("Error: unknown scenario {arg}");
+            return ExitCode::FAILURE;
+        }
+    }
+
+    let mut failed = false;
+    for (name, scenario) in SCENARIOS {
+        if !selected.is_empty() && !selected.iter().any(|s| s == name) {
+            continue;
+        }
+        println!("{name}:");
+        match scenario(seed) {
+            Ok(()) => println!("{name}: ok"),
+            Err(e) => {
+                println!("{name}: FAILED: {e}");
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
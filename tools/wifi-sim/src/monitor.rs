@@ -0,0 +1,399 @@
+//! Runs `wifi_analysis::detect::Detector` over frames from `frames`, the
+//! way the monitor ROM does: each attack right at its thresholds and the
+//! edges of its window, then an hour of quiet traffic with attacks
+//! somewhere in between, which must be told apart.
+
+use sim_rng::Rng;
+use wifi_analysis::{
+    detect::{
+        Alert, BEACON_THRESHOLD, BEACON_WINDOW_MS, DEAUTH_THRESHOLD, DEAUTH_WINDOW_MS, Detector,
+        MAX_REPORTED_TWINS, Networks, parse_mac,
+    },
+    frame,
+};
+
+use crate::frames::{AP, SSID, STATION, beacon, deauth, probe_response};
+
+const ATTACKER: [u8; 6] = [0x02, 0xde, 0xad, 0xbe, 0xef, 0x00];
+
+/// A BSSID that isn't one of ours.
+fn stranger(n: u32) -> [u8; 6] {
+    let [a, b, c, d] = n.to_be_bytes();
+    [0x02, 0x5e, a, b, c, d]
+}
+
+fn networks() -> Networks {
+    let mut networks = Networks::default();
+    networks.ssids.push(SSID.try_into().unwrap()).unwrap();
+    networks.bssids.push(AP).unwrap();
+    networks
+}
+
+/// Feeds a frame to the detector the way the ROM does.
+fn feed(detector: &mut Detector, now_ms: u64, data: &[u8]) -> Option<Alert> {
+    detector.on_frame(now_ms, &frame::parse(data))
+}
+
+/// Feeds the frames in turn, all at the same time, and returns what the
+/// detector made of the last one. None of the others may raise an alert.
+fn feed_all(
+    detector: &mut Detector,
+    now_ms: u64,
+    frames: impl IntoIterator<Item = Vec<u8>>,
+) -> Result<Option<Alert>, String> {
+    let mut last = None;
+    for data in frames {
+        if let Some(alert) = last.take() {
+            return Err(format!("early {alert:?}"));
+        }
+        last = feed(detector, now_ms, &data);
+    }
+    Ok(last)
+}
+
+fn deauth_flood() -> Result<(), String> {
+    let kick = |n: u32| deauth(ATTACKER, stranger(n), 7);
+
+    // Up to the threshold is fine, one more within the window is a flood
+    let mut detector = Detector::new(networks());
+    let start = 5_000;
+    let alert = feed_all(&mut detector, start, (0..DEAUTH_THRESHOLD).map(kick))?;
+    if alert.is_some() {
+        return Err(format!("{DEAUTH_THRESHOLD} deauths raised {alert:?}"));
+    }
+    let last = deauth(AP, STATION, 8);
+    let alert = feed(&mut detector, start + DEAUTH_WINDOW_MS - 1, &last);
+    let expected = Alert::DeauthFlood {
+        count: DEAUTH_THRESHOLD + 1,
+        src: AP,
+        dst: STATION,
+        reason: 8,
+    };
+    if alert != Some(expected) {
+        return Err(format!("the deauth over the threshold raised {alert:?}"));
+    }
+    // Once per window
+    let alert = feed_all(&mut detector, start + 500, (0..100).map(kick))?;
+    if alert.is_some() {
+        return Err(format!("the same flood raised {alert:?} again"));
+    }
+    // And again in the next one
+    let next = start + DEAUTH_WINDOW_MS;
+    let alert = feed_all(&mut detector, next, (0..=DEAUTH_THRESHOLD).map(kick))?;
+    if !matches!(alert, Some(Alert::DeauthFlood { count, .. }) if count == DEAUTH_THRESHOLD + 1) {
+        return Err(format!("the next window raised {alert:?}"));
+    }
+
+    // Disassociations count as well
+    let mut detector = Detector::new(networks());
+    let disassoc = |_| {
+        let mut data = deauth(ATTACKER, STATION, 3);
+        data[0] = 0xa0;
+        data
+    };
+    let alert = feed_all(&mut detector, start, (0..=DEAUTH_THRESHOLD).map(disassoc))?;
+    if !matches!(alert, Some(Alert::DeauthFlood { .. })) {
+        return Err(format!("disassociations raised {alert:?}"));
+    }
+
+    // The windows are fixed: the last one over the threshold at the start of
+    // the next window is one too late
+    let mut detector = Detector::new(networks());
+    feed_all(&mut detector, start, (0..DEAUTH_THRESHOLD).map(kick))?;
+    let alert = feed(&mut detector, start + DEAUTH_WINDOW_MS, &kick(0));
+    if alert.is_some() {
+        return Err(format!("a deauth in the next window raised {alert:?}"));
+    }
+    Ok(())
+}
+
+fn beacon_flood() -> Result<(), String> {
+    let fake = |n: u32| beacon(stranger(n), &format!("FREE WIFI {n}"));
+    let threshold = BEACON_THRESHOLD as u32;
+
+    // As many access points as the threshold, each beaconing over and over,
+    // are fine
+    let mut detector = Detector::new(networks());
+    let start = 60_000;
+    for round in 0..10 {
+        let now_ms = start + round * 100;
+        let alert = feed_all(&mut detector, now_ms, (0..threshold).map(fake))?;
+        if alert.is_some() {
+            return Err(format!("{threshold} access points raised {alert:?}"));
+        }
+    }
+    // One more is a flood
+    let alert = feed(
+        &mut detector,
+        start + BEACON_WINDOW_MS - 1,
+        &fake(threshold),
+    );
+    let expected = Alert::BeaconFlood {
+        bssids: BEACON_THRESHOLD + 1,
+    };
+    if alert != Some(expected) {
+        return Err(format!(
+            "the access point over the threshold raised {alert:?}"
+        ));
+    }
+    let alert = feed_all(&mut detector, start + 2000, (0..1000).map(fake))?;
+    if alert.is_some() {
+        return Err(format!("the same flood raised {alert:?} again"));
+    }
+
+    // Probe responses don't count, they are answers to our own probes
+    let mut detector = Detector::new(networks());
+    let answer = |n| probe_response(stranger(n), "Cafe");
+    let alert = feed_all(&mut detector, start, (0..1000).map(answer))?;
+    if alert.is_some() {
+        return Err(format!("probe responses raised {alert:?}"));
+    }
+
+    // The windows are fixed here as well
+    let mut detector = Detector::new(networks());
+    feed_all(&mut detector, start, (0..threshold).map(fake))?;
+    let alert = feed(&mut detector, start + BEACON_WINDOW_MS, &fake(threshold));
+    if alert.is_some() {
+        return Err(format!("a beacon in the next window raised {alert:?}"));
+    }
+    Ok(())
+}
+
+/// What a twin of our network with the BSSID `stranger(n)` raises.
+fn twin(n: u32) -> Option<Alert> {
+    Some(Alert::EvilTwin {
+        ssid: SSID.try_into().unwrap(),
+        bssid: stranger(n),
+    })
+}
+
+fn evil_twin() -> Result<(), String> {
+    let mut detector = Detector::new(networks());
+    let cases = [
+        ("our own access point", beacon(AP, SSID), None),
+        ("our own probe response", probe_response(AP, SSID), None),
+        ("someone else's network", beacon(stranger(1), "Cafe"), None),
+        ("a similar name", beacon(stranger(1), "HomeNet2"), None),
+        ("different case", beacon(stranger(1), "homenet"), None),
+        ("a hidden network", beacon(stranger(1), ""), None),
+        ("a twin", beacon(stranger(1), SSID), twin(1)),
+        ("the twin again", beacon(stranger(1), SSID), None),
+        (
+            "the twin answering",
+            probe_response(stranger(1), SSID),
+            None,
+        ),
+        ("another twin", probe_response(stranger(2), SSID), twin(2)),
+    ];
+    for (name, data, expected) in cases {
+        let alert = feed(&mut detector, 1000, &data);
+        if alert != expected {
+            return Err(format!("{name} raised {alert:?}, expected {expected:?}"));
+        }
+    }
+
+    // Twins are remembered up to a point, after which the oldest one is
+    // reported again
+    for n in 3..=MAX_REPORTED_TWINS as u32 {
+        let alert = feed(&mut detector, 2000, &beacon(stranger(n), SSID));
+        if alert != twin(n) {
+            return Err(format!("twin {n} raised {alert:?}"));
+        }
+    }
+    for n in 1..=MAX_REPORTED_TWINS as u32 {
+        let alert = feed(&mut detector, 3000, &beacon(stranger(n), SSID));
+        if alert.is_some() {
+            return Err(format!("twin {n} raised {alert:?} again"));
+        }
+    }
+    let next = MAX_REPORTED_TWINS as u32 + 1;
+    let alert = feed(&mut detector, 4000, &beacon(stranger(next), SSID));
+    if alert != twin(next) {
+        return Err(format!("twin {next} raised {alert:?}"));
+    }
+    let alert = feed(&mut detector, 4000, &beacon(stranger(1), SSID));
+    if alert != twin(1) {
+        return Err(format!("the forgotten twin raised {alert:?}"));
+    }
+
+    // A twin whose beacon is the one that makes for a flood is reported
+    // with its next beacon
+    let mut detector = Detector::new(networks());
+    let fake = |n: u32| beacon(stranger(1000 + n), "FREE WIFI");
+    feed_all(&mut detector, 0, (0..BEACON_THRESHOLD as u32).map(fake))?;
+    let alert = feed(&mut detector, 10, &beacon(stranger(1), SSID));
+    if !matches!(alert, Some(Alert::BeaconFlood { .. })) {
+        return Err(format!("the twin that made a flood raised {alert:?}"));
+    }
+    let alert = feed(&mut detector, 110, &beacon(stranger(1), SSID));
+    if alert != twin(1) {
+        return Err(format!("the twin after the flood raised {alert:?}"));
+    }
+
+    // Without any networks to look after there are no twins
+    let mut detector = Detector::new(Networks::default());
+    if let Some(alert) = feed(&mut detector, 0, &beacon(stranger(1), SSID)) {
+        return Err(format!("no networks, but {alert:?}"));
+    }
+    Ok(())
+}
+
+fn macs() -> Result<(), String> {
+    let cases = [
+        ("24:a4:3c:11:22:33", Some(AP)),
+        ("24:A4:3C:11:22:33", Some(AP)),
+        ("24:a4:3c:11:22", None),
+        ("24:a4:3c:11:22:33:44", None),
+        ("24-a4-3c-11-22-33", None),
+        ("24:a4:3c:11:22:3g", None),
+        ("24:a4:3c:11:22:", None),
+        ("124:a4:3c:11:22:33", None),
+        ("", None),
+    ];
+    for (s, expected) in cases {
+        if parse_mac(s) != expected {
+            return Err(format!("{s:?} parsed to {:02x?}", parse_mac(s)));
+        }
+    }
+    Ok(())
+}
+
+/// `MONITOR_SSIDS` and `MONITOR_BSSIDS` as the build writes them, spaces
+/// and all.
+fn build_config() -> Result<(), String> {
+    let networks =
+        Networks::from_config(" home , ,office", "24:a4:3c:11:22:33, 02:00:00:00:00:01 ,")
+            .map_err(|e| format!("valid networks were refused: {e:?}"))?;
+    let ssids: Vec<&str> = networks.ssids.iter().map(|s| s.as_str()).collect();
+    if ssids != ["home", "office"] {
+        return Err(format!("SSIDs were read as {ssids:?}"));
+    }
+    if networks.bssids != [AP, [0x02, 0, 0, 0, 0, 1]] {
+        return Err(format!("BSSIDs were read as {:02x?}", networks.bssids));
+    }
+
+    let long = "x".repeat(33);
+    let many = "a,b,c,d,e";
+    let cases = [
+        (long.as_str(), "", long.as_str()),
+        (many, "", "e"),
+        ("home", "24:a4:3c:11:22", "24:a4:3c:11:22"),
+    ];
+    for (ssids, bssids, wrong) in cases {
+        match Networks::from_config(ssids, bssids) {
+            Err((s, _)) if s == wrong => (),
+            other => return Err(format!("{ssids:?} and {bssids:?} gave {other:?}")),
+        }
+    }
+    Ok(())
+}
+
+/// An hour of twenty access points beaconing every 102.4 ms, with a few
+/// frames lost, and stations leaving now and then. On top of that one
+/// attack after another: a burst of deauths, a fake access point per
+/// beacon, and a twin of our network.
+fn airtime(rng: &mut Rng) -> Result<(), String> {
+    const HOUR_MS: u64 = 3_600_000;
+    const DEAUTHS_AT_MS: u64 = 600_000;
+    const BEACONS_AT_MS: u64 = 1_800_000;
+    const TWIN_AT_MS: u64 = 3_000_000;
+    const ATTACK_MS: u64 = 30_000;
+
+    // The frames, and when which one goes on the air
+    let mut frames = Vec::new();
+    let mut events = Vec::new();
+    let mut add = |frame: Vec<u8>, times: &mut dyn Iterator<Item = u64>| {
+        events.extend(times.map(|at_ms| (at_ms, frames.len())));
+        frames.push(frame);
+    };
+
+    for n in 0..20 {
+        let frame = match n {
+            0 => beacon(AP, SSID),
+            n => beacon(stranger(n), &format!("Net {n}")),
+        };
+        let first_us = rng.range(0, 102_400);
+        let mut times: Vec<_> = (first_us..HOUR_MS * 1000)
+            .step_by(102_400)
+            .filter(|_| !rng.chance(0.1))
+            .map(|at_us| at_us / 1000)
+            .collect();
+        add(frame, &mut times.drain(..));
+    }
+    // A station leaving about every ten seconds
+    let mut at_ms = 0;
+    while at_ms < HOUR_MS {
+        at_ms += rng.range(1000, 20_000);
+        let station = stranger(100 + rng.range(0, 10) as u32);
+        let reason = *rng.pick(&[3, 8]);
+        add(deauth(AP, station, reason), &mut [at_ms].into_iter());
+    }
+    // 50 deauths a second
+    let mut times = (DEAUTHS_AT_MS..DEAUTHS_AT_MS + ATTACK_MS).step_by(20);
+    add(deauth(AP, [0xff; 6], 7), &mut times);
+    // A fake access point every 10 ms, each beaconing once
+    for (i, at_ms) in (BEACONS_AT_MS..BEACONS_AT_MS + ATTACK_MS)
+        .step_by(10)
+        .enumerate()
+    {
+        let fake = beacon(stranger(1000 + i as u32), "FREE WIFI");
+        add(fake, &mut [at_ms].into_iter());
+    }
+    // A twin beaconing like a real access point
+    let mut times = (TWIN_AT_MS * 10..(TWIN_AT_MS + ATTACK_MS) * 10)
+        .step_by(1024)
+        .map(|at| at / 10);
+    add(beacon(ATTACKER, SSID), &mut times);
+    events.sort();
+
+    let mut detector = Detector::new(networks());
+    let mut alerts = [0; 3];
+    for &(at_ms, frame) in &events {
+        let Some(alert) = feed(&mut detector, at_ms, &frames[frame]) else {
+            continue;
+        };
+        let (kind, attack_ms) = match alert {
+            Alert::DeauthFlood { .. } => (0, DEAUTHS_AT_MS),
+            Alert::BeaconFlood { .. } => (1, BEACONS_AT_MS),
+            Alert::EvilTwin { bssid, .. } if bssid == ATTACKER => (2, TWIN_AT_MS),
+            _ => return Err(format!("{at_ms} ms: {alert}")),
+        };
+        if !(attack_ms..attack_ms + ATTACK_MS).contains(&at_ms) {
+            return Err(format!("{at_ms} ms: {alert}, outside of the attack"));
+        }
+        alerts[kind] += 1;
+    }
+
+    // One per window the attack is over the threshold in, give or take the
+    // windows it starts and ends in
+    let deauth_windows = ATTACK_MS / DEAUTH_WINDOW_MS;
+    let beacon_windows = ATTACK_MS / BEACON_WINDOW_MS;
+    if !(deauth_windows - 1..=deauth_windows + 1).contains(&alerts[0])
+        || !(beacon_windows - 1..=beacon_windows + 1).contains(&alerts[1])
+        || alerts[2] != 1
+    {
+        return Err(format!(
+            "{} deauth floods, {} beacon floods and {} twins reported",
+            alerts[0], alerts[1], alerts[2]
+        ));
+    }
+    println!(
+        "  {} frames in an hour, {} deauth floods, {} beacon floods and an evil twin reported",
+        events.len(),
+        alerts[0],
+        alerts[1]
+    );
+    Ok(())
+}
+
+pub fn run_scenario(seed: u64) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    deauth_flood()?;
+    beacon_flood()?;
+    evil_twin()?;
+    macs()?;
+    build_config()?;
+    airtime(&mut rng)?;
+    println!("  deauth floods, beacon floods and evil twins caught at their thresholds");
+    Ok(())
+}
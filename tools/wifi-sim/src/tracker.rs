@@ -0,0 +1,239 @@
+//! Feeds scans to `wifi_analysis::tracker::Tracker`: first a few by hand,
+//! then a crowd of access points coming and going at random through a
+//! table too small for all of them. Whoever listens to the events has to
+//! hear about every one that leaves.
+
+use std::collections::HashSet;
+
+use sim_rng::Rng;
+use wifi_analysis::tracker::{DISAPPEAR_AFTER_SCANS, Sighting, TrackEvent, Tracker};
+
+const SCAN_INTERVAL_MS: u64 = 10_000;
+const CROWD: usize = 24;
+const TABLE: usize = 16;
+const SCANS: u64 = 500;
+
+fn bssid(n: u8) -> [u8; 6] {
+    [0x02, 0, 0, 0, 0, n]
+}
+
+fn sighting(n: u8, channel: u8, rssi: i8, auth: &'static str) -> Sighting<'static> {
+    Sighting {
+        bssid: bssid(n),
+        ssid: b"net",
+        channel,
+        rssi,
+        auth,
+    }
+}
+
+/// Runs a scan with the sightings, and returns the events in order.
+fn scan<const N: usize>(
+    tracker: &mut Tracker<N>,
+    now_ms: u64,
+    sightings: &[Sighting<'_>],
+) -> Vec<TrackEvent> {
+    let mut events = Vec::new();
+    for sighting in sightings {
+        tracker.observe(now_ms, sighting, |e| events.push(e));
+    }
+    tracker.end_scan(|e| events.push(e));
+    events
+}
+
+fn appeared(n: u8, channel: u8, rssi: i8) -> TrackEvent {
+    TrackEvent::Appeared {
+        bssid: bssid(n),
+        ssid: heapless::Vec::from_slice(b"net").unwrap(),
+        channel,
+        rssi,
+    }
+}
+
+fn check(what: &str, events: Vec<TrackEvent>, expected: &[TrackEvent]) -> Result<(), String> {
+    if events != expected {
+        return Err(format!("{what}: got {events:?}, expected {expected:?}"));
+    }
+    Ok(())
+}
+
+/// Appearing, changing and disappearing, one scan at a time.
+fn by_hand() -> Result<(), String> {
+    let mut tracker = Tracker::<8>::new();
+    let events = scan(&mut tracker, 0, &[sighting(1, 6, -50, "wpa2-psk")]);
+    check("first scan", events, &[appeared(1, 6, -50)])?;
+
+    let events = scan(&mut tracker, 10, &[sighting(1, 6, -60, "wpa2-psk")]);
+    check("seen again", events, &[])?;
+
+    let events = scan(&mut tracker, 20, &[sighting(1, 11, -70, "open")]);
+    let expected = [
+        TrackEvent::ChannelChanged {
+            bssid: bssid(1),
+            from: 6,
+            to: 11,
+        },
+        TrackEvent::SecurityChanged {
+            bssid: bssid(1),
+            from: "wpa2-psk",
+            to: "open",
+        },
+    ];
+    check("changed", events, &expected)?;
+
+    // Missing from a scan or two is normal
+    for _ in 1..DISAPPEAR_AFTER_SCANS {
+        check("missing for a while", scan(&mut tracker, 30, &[]), &[])?;
+    }
+    let events = scan(&mut tracker, 40, &[sighting(1, 11, -50, "open")]);
+    check("back before it was gone", events, &[])?;
+
+    for _ in 1..DISAPPEAR_AFTER_SCANS {
+        check("missing again", scan(&mut tracker, 50, &[]), &[])?;
+    }
+    let events = scan(&mut tracker, 60, &[]);
+    let expected = [TrackEvent::Disappeared {
+        bssid: bssid(1),
+        first_seen_ms: 0,
+        last_seen_ms: 40,
+        min_rssi: -70,
+        max_rssi: -50,
+        avg_rssi: -57,
+    }];
+    check("gone", events, &expected)?;
+
+    let events = scan(&mut tracker, 300, &[sighting(1, 1, -40, "open")]);
+    check("back after it was gone", events, &[appeared(1, 1, -40)])?;
+
+    // Forgetting is quiet, and makes the AP new again
+    tracker.clear();
+    for _ in 0..DISAPPEAR_AFTER_SCANS {
+        check("cleared", scan(&mut tracker, 400, &[]), &[])?;
+    }
+    let events = scan(&mut tracker, 500, &[sighting(1, 1, -40, "open")]);
+    check("seen after clearing", events, &[appeared(1, 1, -40)])
+}
+
+/// A full table makes room by reporting the AP that was seen the longest
+/// time ago as gone, and only that one.
+fn eviction() -> Result<(), String> {
+    let mut tracker = Tracker::<3>::new();
+    let all: Vec<_> = (1..=3).map(|n| sighting(n, 6, -50, "open")).collect();
+    let events = scan(&mut tracker, 0, &all);
+    let expected: Vec<_> = (1..=3).map(|n| appeared(n, 6, -50)).collect();
+    check("filling up", events, &expected)?;
+    // 1 goes missing, but not for long enough to be gone
+    let events = scan(&mut tracker, 10, &all[1..]);
+    check("one missing", events, &[])?;
+
+    let events = scan(&mut tracker, 20, &[sighting(4, 6, -60, "open")]);
+    let expected = [
+        TrackEvent::Disappeared {
+            bssid: bssid(1),
+            first_seen_ms: 0,
+            last_seen_ms: 0,
+            min_rssi: -50,
+            max_rssi: -50,
+            avg_rssi: -50,
+        },
+        appeared(4, 6, -60),
+    ];
+    check("full", events, &expected)?;
+
+    // It doesn't disappear a second time when the others do
+    let mut events = Vec::new();
+    for i in 0..DISAPPEAR_AFTER_SCANS {
+        events.extend(scan(&mut tracker, 30 + i as u64, &[]));
+    }
+    let mut gone: Vec<_> = events
+        .iter()
+        .map(|e| match e {
+            TrackEvent::Disappeared { bssid, .. } => Ok(bssid[5]),
+            e => Err(format!("unexpected {e:?}")),
+        })
+        .collect::<Result<_, _>>()?;
+    gone.sort();
+    if gone != [2, 3, 4] {
+        return Err(format!("after the eviction, {gone:?} disappeared"));
+    }
+    Ok(())
+}
+
+/// More access points than fit in the table come and go over many scans,
+/// and sometimes get missed while they are around. Everything that
+/// appears has to disappear again before it can reappear, and the table
+/// never holds more than it has room for.
+fn crowd(rng: &mut Rng) -> Result<(), String> {
+    let mut tracker = Tracker::<TABLE>::new();
+    let mut present = [false; CROWD];
+    let mut known = HashSet::new();
+    let mut evictions = 0;
+    let mut most_known = 0;
+    for i in 0..SCANS {
+        for p in &mut present {
+            if rng.chance(0.05) {
+                *p = !*p;
+            }
+        }
+        let now_ms = i * SCAN_INTERVAL_MS;
+        let mut sightings = Vec::new();
+        for (n, &present) in present.iter().enumerate() {
+            if present && !rng.chance(0.2) {
+                let rssi = -40 - rng.range(0, 50) as i8;
+                sightings.push(sighting(n as u8, 6, rssi, "open"));
+            }
+        }
+        let events = scan(&mut tracker, now_ms, &sightings);
+
+        for event in &events {
+            match event {
+                TrackEvent::Appeared { bssid, .. } => {
+                    if !known.insert(*bssid) {
+                        return Err(format!("scan {i}: {bssid:02x?} appeared twice"));
+                    }
+                }
+                TrackEvent::Disappeared {
+                    bssid,
+                    last_seen_ms,
+                    ..
+                } => {
+                    if !known.remove(bssid) {
+                        return Err(format!("scan {i}: {bssid:02x?} disappeared twice"));
+                    }
+                    let missed = (now_ms - last_seen_ms) / SCAN_INTERVAL_MS;
+                    if missed < DISAPPEAR_AFTER_SCANS as u64 {
+                        evictions += 1;
+                    }
+                }
+                e => return Err(format!("scan {i}: unexpected {e:?}")),
+            }
+        }
+        if known.len() > TABLE {
+            return Err(format!("scan {i}: {} access points known", known.len()));
+        }
+        most_known = most_known.max(known.len());
+
+        // Everything seen in this scan is known now, unless there were
+        // too many to fit
+        let lost = sightings.iter().find(|s| !known.contains(&s.bssid));
+        if let Some(lost) = lost.filter(|_| sightings.len() <= TABLE) {
+            return Err(format!("scan {i}: {:02x?} was lost", lost.bssid));
+        }
+    }
+    if evictions == 0 || most_known < TABLE {
+        return Err(format!(
+            "the table didn't fill up: {evictions} evictions, at most {most_known} known"
+        ));
+    }
+    println!("  {CROWD} access points through a table of {TABLE}, {evictions} of them pushed out");
+    Ok(())
+}
+
+pub fn run_scenario(seed: u64) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    by_hand()?;
+    eviction()?;
+    crowd(&mut rng)?;
+    println!("  access points appeared, changed and disappeared as expected");
+    Ok(())
+}
@@ -0,0 +1,219 @@
+//! Receives frames from the `wifi-sniffer` ROM and writes them as a PCAP
+//! stream, either to a file, to stdout, or to Wireshark as an extcap.
+//!
+//! Usage:
+//!   wifi-sniffer-capture --port PORT [--output FILE] [--channel N | --hop MS] [--filter TYPES]
+//!
+//! The output defaults to stdout, so it can be piped into `wireshark -k -i -`.
+//! TYPES is a comma separated list of `mgmt`, `ctrl`, `data` and `misc`, or `all`.
+//!
+//! To use it as a Wireshark extcap, symlink the binary into Wireshark's
+//! extcap folder, e.g. `~/.config/wireshark/extcap/`.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    process::ExitCode,
+    time::Duration,
+};
+
+// See the `wifi-sniffer` ROM for the framing
+const MSG_PACKET: u8 = 0;
+const MSG_LOG: u8 = 1;
+
+const LINKTYPE_IEEE802_11_RADIOTAP: u32 = 127;
+// The ROM cuts off frames at 512 bytes, plus 16 bytes of radiotap header
+const SNAPLEN: u32 = 512 + 16;
+
+const EXTCAP_INTERFACE: &str = "esp32c3-wifi";
+
+#[derive(Default)]
+struct Args {
+    port: Option<String>,
+    output: Option<String>,
+    channel: Option<String>,
+    hop: Option<String>,
+    filter: Option<String>,
+    extcap_interfaces: bool,
+    extcap_dlts: bool,
+    extcap_config: bool,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args::default();
+    let mut iter = std::env::args().skip(1).peekable();
+    while let Some(arg) = iter.next() {
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| iter.next())
+                .ok_or_else(|| format!("{name} needs a value"))
+        };
+        match name.as_str() {
+            "--port" => args.port = Some(value()?),
+            "--output" | "--fifo" => args.output = Some(value()?),
+            "--channel" => args.channel = Some(value()?),
+            "--hop" => args.hop = Some(value()?),
+            "--filter" => args.filter = Some(value()?),
+            "--extcap-interfaces" => args.extcap_interfaces = true,
+            "--extcap-dlts" => args.extcap_dlts = true,
+            "--extcap-config" => args.extcap_config = true,
+            // Capturing is what we do unless asked for extcap information
+            "--capture" => {}
+            "--extcap-interface" => {
+                if value()? != EXTCAP_INTERFACE {
+                    return Err("unknown extcap interface".into());
+                }
+            }
+            // Wireshark passes along a few more options we don't care about,
+            // some of them with a value in the next argument
+            name if name.starts_with("--extcap-") => {
+                if inline_value.is_none() {
+                    iter.next_if(|next| !next.starts_with("--"));
+                }
+            }
+            _ => return Err(format!("unknown argument {name}")),
+        }
+    }
+    Ok(args)
+}
+
+fn print_extcap_info(args: &Args) {
+    if args.extcap_interfaces {
+        println!("extcap {{version=0.1.0}}");
+        println!("interface {{value={EXTCAP_INTERFACE}}}{{display=ESP32-C3 Wi-Fi sniffer}}");
+    }
+    if args.extcap_dlts {
+        println!(
+            "dlt {{number={LINKTYPE_IEEE802_11_RADIOTAP}}}{{name=IEEE802_11_RADIOTAP}}{{display=802.11 with radiotap header}}"
+        );
+    }
+    if args.extcap_config {
+        println!(
+            "arg {{number=0}}{{call=--port}}{{display=Serial port}}{{type=string}}{{default=/dev/ttyACM0}}{{required=true}}"
+        );
+        println!(
+            "arg {{number=1}}{{call=--channel}}{{display=Channel}}{{type=integer}}{{range=1,13}}{{default=1}}"
+        );
+        println!(
+            "arg {{number=2}}{{call=--hop}}{{display=Hop channels, dwell time in ms (overrides channel)}}{{type=integer}}"
+        );
+        println!(
+            "arg {{number=3}}{{call=--filter}}{{display=Frame types}}{{type=string}}{{default=mgmt,data}}"
+        );
+    }
+}
+
+fn write_pcap_header(out: &mut impl Write) -> io::Result<()> {
+    out.write_all(&0xa1b2c3d4u32.to_le_bytes())?;
+    // Version 2.4
+    out.write_all(&2u16.to_le_bytes())?;
+    out.write_all(&4u16.to_le_bytes())?;
+    // Timezone and timestamp accuracy, both unused
+    out.write_all(&0u32.to_le_bytes())?;
+    out.write_all(&0u32.to_le_bytes())?;
+    out.write_all(&SNAPLEN.to_le_bytes())?;
+    out.write_all(&LINKTYPE_IEEE802_11_RADIOTAP.to_le_bytes())?;
+    out.flush()
+}
+
+/// Sends the settings to the ROM, using the same commands as its console.
+fn configure(port: &mut dyn Write, args: &Args) -> io::Result<()> {
+    if let Some(filter) = &args.filter {
+        writeln!(port, "filter {filter}")?;
+    }
+    if let Some(hop) = &args.hop {
+        writeln!(port, "hop {hop}")?;
+    } else if let Some(channel) = &args.channel {
+        writeln!(port, "channel {channel}")?;
+    }
+    port.flush()
+}
+
+fn handle_message(message: &[u8], out: &mut impl Write) -> io::Result<()> {
+    match message.split_first() {
+        Some((&MSG_PACKET, record)) => {
+            out.write_all(record)?;
+            out.flush()
+        }
+        Some((&MSG_LOG, text)) => {
+            eprintln!("sniffer: {}", String::from_utf8_lossy(text));
+            Ok(())
+        }
+        _ => {
+            eprintln!("Ignoring unknown message");
+            Ok(())
+        }
+    }
+}
+
+fn capture(args: &Args) -> io::Result<()> {
+    let port_name = args
+        .port
+        .as_deref()
+        .ok_or_else(|| io::Error::other("--port is required"))?;
+    // The baud rate is ignored by the USB-Serial-JTAG peripheral, but we
+    // still need to set one.
+    let mut port = serialport::new(port_name, 115_200)
+        .timeout(Duration::from_secs(3600))
+        .open()?;
+    configure(&mut port, args)?;
+
+    let mut out: Box<dyn Write> = match args.output.as_deref() {
+        None | Some("-") => Box::new(io::stdout().lock()),
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+    };
+    write_pcap_header(&mut out)?;
+
+    let mut buf = [0; 4096];
+    let mut frame = Vec::new();
+    loop {
+        let n = match port.read(&mut buf) {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e),
+        };
+        for &b in &buf[..n] {
+            if b != 0 {
+                frame.push(b);
+                continue;
+            }
+            // Anything before the first zero byte is likely the tail end of
+            // a message we only saw half of, which fails to decode.
+            match cobs::decode_vec(&frame) {
+                Ok(message) => handle_message(&message, &mut out)?,
+                Err(()) => eprintln!("Ignoring {} bytes of garbage", frame.len()),
+            }
+            frame.clear();
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if args.extcap_interfaces || args.extcap_dlts || args.extcap_config {
+        print_extcap_info(&args);
+        return ExitCode::SUCCESS;
+    }
+
+    match capture(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        // Wireshark closes the fifo when the capture is stopped
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}